@@ -0,0 +1,96 @@
+// src/cli.rs
+use std::path::{Path, PathBuf};
+
+/// A file named on the command line and where to put the cursor in it
+#[derive(Debug, PartialEq)]
+pub struct FileTarget {
+    pub path: PathBuf,
+    pub line: Option<usize>, // Counting from 1
+    pub col: Option<usize>,  // Counting from 1
+}
+
+/// Make sense of the file arguments: `FILE`, `FILE:LINE`, `FILE:LINE:COL`
+/// (as compilers print them) and `+LINE`, which applies to the first file.
+/// A suffix is only split off when no file by the whole name `exists`, so
+/// names containing colons still open.
+pub fn parse_file_args(words: &[String], exists: impl Fn(&Path) -> bool) -> Vec<FileTarget> {
+    let mut targets = Vec::new();
+    let mut jump = None;
+    for word in words {
+        if let Some(line) = word.strip_prefix('+').and_then(|line| line.parse().ok()) {
+            jump = Some(line);
+            continue;
+        }
+        targets.push(if exists(Path::new(word)) {
+            FileTarget { path: PathBuf::from(word), line: None, col: None }
+        } else {
+            split_position(word)
+        });
+    }
+
+    if let Some(first) = targets.first_mut() {
+        first.line = jump.or(first.line);
+    }
+    targets
+}
+
+// `src/main.rs:42:7` into the path, line and column
+fn split_position(word: &str) -> FileTarget {
+    let number = |text: &str| text.parse::<usize>().ok();
+    let plain = || FileTarget { path: PathBuf::from(word), line: None, col: None };
+    let Some((rest, last)) = word.rsplit_once(':') else {
+        return plain();
+    };
+    let Some(last) = number(last) else {
+        return plain();
+    };
+    match rest.rsplit_once(':') {
+        Some((path, line)) if number(line).is_some() && !path.is_empty() => FileTarget {
+            path: PathBuf::from(path),
+            line: number(line),
+            col: Some(last),
+        },
+        _ if !rest.is_empty() => FileTarget { path: PathBuf::from(rest), line: Some(last), col: None },
+        _ => plain(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(words: &[&str]) -> Vec<FileTarget> {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        parse_file_args(&words, |path| path == Path::new("odd:1"))
+    }
+
+    fn target(path: &str, line: Option<usize>, col: Option<usize>) -> FileTarget {
+        FileTarget { path: PathBuf::from(path), line, col }
+    }
+
+    #[test]
+    fn test_parse_file_args() {
+        assert_eq!(parse(&[]), vec![]);
+        assert_eq!(parse(&["main.rs"]), vec![target("main.rs", None, None)]);
+        assert_eq!(parse(&["main.rs:42"]), vec![target("main.rs", Some(42), None)]);
+        assert_eq!(parse(&["src/main.rs:42:7"]), vec![target("src/main.rs", Some(42), Some(7))]);
+        assert_eq!(parse(&["+42", "main.rs"]), vec![target("main.rs", Some(42), None)]);
+        assert_eq!(parse(&["main.rs", "+3"]), vec![target("main.rs", Some(3), None)]);
+    }
+
+    #[test]
+    fn test_parse_several_files() {
+        assert_eq!(
+            parse(&["+5", "a.rs", "b.rs:2"]),
+            vec![target("a.rs", Some(5), None), target("b.rs", Some(2), None)]
+        );
+    }
+
+    #[test]
+    fn test_parse_file_args_keeps_real_names() {
+        // An existing file wins over reading its name as a position
+        assert_eq!(parse(&["odd:1"]), vec![target("odd:1", None, None)]);
+        assert_eq!(parse(&["notes:todo"]), vec![target("notes:todo", None, None)]);
+        assert_eq!(parse(&[":12"]), vec![target(":12", None, None)]);
+    }
+}
@@ -1,5 +1,6 @@
 // src/config/mod.rs
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use crossterm::style::Color;
 
@@ -12,10 +13,80 @@ pub struct EditorConfig {
     pub highlight_current_line: bool,
     pub show_whitespace: bool,
     pub word_wrap: bool,
+    #[serde(default = "default_statusline")]
+    pub statusline: String,
+    #[serde(default)]
+    pub autosave_delay: u64, // Seconds of inactivity before auto-saving (0 = off)
+    #[serde(default)]
+    pub spell_file: Option<PathBuf>, // Word list for :set spell (default /usr/share/dict/words)
+    #[serde(default)]
+    pub truecolor: Option<bool>, // Force 24-bit color on or off instead of detecting it
+    #[serde(default)]
+    pub whichwrap: String, // Keys that may cross lines: h l, < > (arrows), [ ] (insert arrows)
+    #[serde(default)]
+    pub virtualedit: String, // Cursor may go past end of line: "block", "all" or "" (off)
+    #[serde(default)]
+    pub indent_guides: bool, // Draw a line down each level of indentation
+    #[serde(default)]
+    pub highlight_trailing_whitespace: bool, // Show whitespace at line ends in the theme's color
+    #[serde(default)]
+    pub colorcolumn: String, // Columns to draw a rule down, counting from 1: "80" or "80,100"
+    #[serde(default = "default_clipboard_history")]
+    pub clipboard_history: usize, // Yanks kept for pasting older ones
+    #[serde(default)]
+    pub fileencoding: Option<String>, // Read files as this encoding instead of detecting it, e.g. "latin1"
+    #[serde(default = "default_leader")]
+    pub leader: String, // Key that starts a leader mapping in normal mode
+    #[serde(default)]
+    pub leader_mappings: BTreeMap<String, String>, // Keys typed after the leader -> command to run, e.g. "w" = ":w"
+    #[serde(default)]
+    pub detect_indent: bool, // Indent opened files with tabs or spaces, and as wide, as they already are
+    #[serde(default = "default_restore_cursor")]
+    pub restore_cursor: bool, // Open files with the cursor where it was last left in them
+    #[serde(default = "default_undolevels")]
+    pub undolevels: i64, // Changes undo can go back through; 0 or less turns undo off
+}
+
+/// Status line format; see `ui::renderer::expand_statusline` for placeholders
+pub const DEFAULT_STATUSLINE: &str = " %m | %f%M%r %y %b | %l:%c %p %S";
+
+fn default_statusline() -> String {
+    DEFAULT_STATUSLINE.to_string()
+}
+
+pub const DEFAULT_CLIPBOARD_HISTORY: usize = 10;
+
+fn default_clipboard_history() -> usize {
+    DEFAULT_CLIPBOARD_HISTORY
+}
+
+pub const DEFAULT_LEADER: &str = " ";
+
+fn default_leader() -> String {
+    DEFAULT_LEADER.to_string()
+}
+
+pub const DEFAULT_UNDOLEVELS: i64 = 1000;
+
+fn default_undolevels() -> i64 {
+    DEFAULT_UNDOLEVELS
+}
+
+fn default_restore_cursor() -> bool {
+    true
+}
+
+fn default_indent_guide() -> ColorDef {
+    ColorDef::Named(NamedColor::BrightBlack)
+}
+
+fn default_trailing_whitespace() -> ColorDef {
+    ColorDef::Named(NamedColor::Red)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Theme {
+    #[serde(default)]
     pub name: String,
     pub background: ColorDef,
     pub foreground: ColorDef,
@@ -24,6 +95,10 @@ pub struct Theme {
     pub search_highlight: ColorDef,
     pub line_numbers: ColorDef,
     pub line_numbers_highlight: ColorDef,
+    #[serde(default = "default_indent_guide")]
+    pub indent_guide: ColorDef,
+    #[serde(default = "default_trailing_whitespace")]
+    pub trailing_whitespace: ColorDef,
     pub status_line: StatusLineTheme,
     pub ui: UiTheme,
 }
@@ -52,6 +127,15 @@ pub enum ColorDef {
     Rgb { r: u8, g: u8, b: u8 },
 }
 
+/// How many colors the terminal can display, from fewest to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorSupport {
+    Monochrome, // NO_COLOR or --no-color: no color escapes at all
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum NamedColor {
@@ -83,6 +167,22 @@ impl Default for EditorConfig {
             highlight_current_line: true,
             show_whitespace: false,
             word_wrap: false,
+            statusline: default_statusline(),
+            autosave_delay: 0,
+            spell_file: None,
+            truecolor: None,
+            whichwrap: String::new(),
+            virtualedit: String::new(),
+            indent_guides: false,
+            highlight_trailing_whitespace: false,
+            colorcolumn: String::new(),
+            clipboard_history: default_clipboard_history(),
+            fileencoding: None,
+            leader: default_leader(),
+            leader_mappings: BTreeMap::new(),
+            detect_indent: false,
+            restore_cursor: default_restore_cursor(),
+            undolevels: default_undolevels(),
         }
     }
 }
@@ -98,12 +198,88 @@ impl Default for Theme {
             search_highlight: ColorDef::Named(NamedColor::Yellow),
             line_numbers: ColorDef::Named(NamedColor::BrightBlack),
             line_numbers_highlight: ColorDef::Named(NamedColor::White),
+            indent_guide: default_indent_guide(),
+            trailing_whitespace: default_trailing_whitespace(),
             status_line: StatusLineTheme::default(),
             ui: UiTheme::default(),
         }
     }
 }
 
+// Shorthand for the RGB colors in the bundled themes
+const fn rgb(r: u8, g: u8, b: u8) -> ColorDef {
+    ColorDef::Rgb { r, g, b }
+}
+
+impl Theme {
+    /// A theme shipped with the editor: "default", "light" or "gruvbox"
+    pub fn bundled(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "light" => Some(Self {
+                name: "light".to_string(),
+                background: ColorDef::Named(NamedColor::White),
+                foreground: ColorDef::Named(NamedColor::Black),
+                cursor: ColorDef::Named(NamedColor::Black),
+                selection: ColorDef::Named(NamedColor::BrightCyan),
+                search_highlight: ColorDef::Named(NamedColor::Yellow),
+                line_numbers: ColorDef::Named(NamedColor::BrightBlack),
+                line_numbers_highlight: ColorDef::Named(NamedColor::Black),
+                indent_guide: ColorDef::Named(NamedColor::BrightBlack),
+                trailing_whitespace: ColorDef::Named(NamedColor::BrightRed),
+                status_line: StatusLineTheme::default(),
+                ui: UiTheme {
+                    background: ColorDef::Named(NamedColor::White),
+                    foreground: ColorDef::Named(NamedColor::Black),
+                    selected: ColorDef::Named(NamedColor::BrightCyan),
+                    active: ColorDef::Named(NamedColor::Black),
+                    inactive: ColorDef::Named(NamedColor::BrightBlack),
+                },
+            }),
+            "gruvbox" => Some(Self {
+                name: "gruvbox".to_string(),
+                background: rgb(40, 40, 40),
+                foreground: rgb(235, 219, 178),
+                cursor: rgb(235, 219, 178),
+                selection: rgb(80, 73, 69),
+                search_highlight: rgb(250, 189, 47),
+                line_numbers: rgb(124, 111, 100),
+                line_numbers_highlight: rgb(250, 189, 47),
+                indent_guide: rgb(60, 56, 54),
+                trailing_whitespace: rgb(204, 36, 29),
+                status_line: StatusLineTheme {
+                    normal: rgb(168, 153, 132),
+                    insert: rgb(184, 187, 38),
+                    visual: rgb(131, 165, 152),
+                    command: rgb(250, 189, 47),
+                },
+                ui: UiTheme {
+                    background: rgb(40, 40, 40),
+                    foreground: rgb(235, 219, 178),
+                    selected: rgb(80, 73, 69),
+                    active: rgb(235, 219, 178),
+                    inactive: rgb(124, 111, 100),
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    /// Look a theme up by name: `themes/{name}.toml` in the config directory
+    /// wins over a bundled theme of the same name
+    pub fn find(name: &str) -> Result<Self, ConfigError> {
+        let path = dirs::config_dir()
+            .map(|dir| dir.join("zorforge").join("themes").join(format!("{}.toml", name)));
+        if let Some(path) = path.filter(|path| path.exists()) {
+            let contents = std::fs::read_to_string(path)?;
+            let mut theme: Theme = toml::from_str(&contents)?;
+            theme.name = name.to_string();
+            return Ok(theme);
+        }
+        Self::bundled(name).ok_or_else(|| ConfigError::UnknownTheme(name.to_string()))
+    }
+}
+
 impl Default for StatusLineTheme {
     fn default() -> Self {
         Self {
@@ -134,6 +310,119 @@ impl ColorDef {
             ColorDef::Rgb { r, g, b } => Color::Rgb { r: *r, g: *g, b: *b },
         }
     }
+
+    /// Like `to_crossterm_color`, but RGB colors are mapped to the nearest
+    /// color the terminal can actually show
+    pub fn to_color(&self, support: ColorSupport) -> Color {
+        support.fit(self.to_crossterm_color())
+    }
+}
+
+impl ColorSupport {
+    /// Guess from the environment the way most terminal programs do. A
+    /// non-empty NO_COLOR (https://no-color.org) turns colors off.
+    pub fn detect() -> Self {
+        if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorSupport::Monochrome;
+        }
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        Self::from_env(&colorterm, &term)
+    }
+
+    /// The nearest color to `color` this terminal can show. Programs in a
+    /// terminal window pick colors without knowing what's drawing them.
+    pub fn fit(self, color: Color) -> Color {
+        match (color, self) {
+            (_, ColorSupport::Monochrome) => Color::Reset,
+            (Color::Rgb { r, g, b }, ColorSupport::Ansi256) => Color::AnsiValue(rgb_to_ansi256(r, g, b)),
+            (Color::Rgb { r, g, b }, ColorSupport::Ansi16) => rgb_to_ansi16(r, g, b),
+            (Color::AnsiValue(index), ColorSupport::Ansi16) => match ANSI16.get(index as usize) {
+                Some(&(named, _)) => named,
+                None => {
+                    let (r, g, b) = ansi256_to_rgb(index);
+                    rgb_to_ansi16(r, g, b)
+                }
+            },
+            _ => color,
+        }
+    }
+
+    fn from_env(colorterm: &str, term: &str) -> Self {
+        if matches!(colorterm, "truecolor" | "24bit") || term.ends_with("-direct") {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        }
+    }
+}
+
+// Channel values of the 6x6x6 color cube at indices 16..=231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// The 16 standard colors with their usual xterm RGB values
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (128, 0, 0)),
+    (Color::DarkGreen, (0, 128, 0)),
+    (Color::DarkYellow, (128, 128, 0)),
+    (Color::DarkBlue, (0, 0, 128)),
+    (Color::DarkMagenta, (128, 0, 128)),
+    (Color::DarkCyan, (0, 128, 128)),
+    (Color::Grey, (192, 192, 192)),
+    (Color::DarkGrey, (128, 128, 128)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (0, 0, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+// Nearest entry of the color cube or the grey ramp (232..=255)
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| (0..6).min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs()).unwrap_or(0);
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = (average.saturating_sub(3) / 10).min(23);
+    let grey = (8 + 10 * grey_index) as u8;
+
+    if distance((grey, grey, grey), (r, g, b)) < distance(cube, (r, g, b)) {
+        232 + grey_index as u8
+    } else {
+        (16 + 36 * ri + 6 * gi + bi) as u8
+    }
+}
+
+// RGB value of a color cube or grey ramp entry
+fn ansi256_to_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI16[index as usize].1,
+        16..=231 => {
+            let i = (index - 16) as usize;
+            (CUBE_LEVELS[i / 36], CUBE_LEVELS[i / 6 % 6], CUBE_LEVELS[i % 6])
+        }
+        _ => {
+            let grey = 8 + 10 * (index - 232);
+            (grey, grey, grey)
+        }
+    }
+}
+
+fn rgb_to_ansi16(r: u8, g: u8, b: u8) -> Color {
+    ANSI16.iter()
+        .min_by_key(|(_, rgb)| distance(*rgb, (r, g, b)))
+        .map_or(Color::Reset, |(color, _)| *color)
 }
 
 impl NamedColor {
@@ -171,16 +460,19 @@ impl EditorConfig {
     }
 
     pub fn save(&self) -> Result<(), ConfigError> {
-        let config_path = Self::get_config_path()?;
-        if let Some(parent) = config_path.parent() {
+        self.save_to_file(&Self::get_config_path()?)
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
             std::fs::create_dir_all(parent)?;
         }
         let contents = toml::to_string_pretty(self)?;
-        std::fs::write(config_path, contents)?;
+        std::fs::write(path, contents)?;
         Ok(())
     }
 
-    fn get_config_path() -> Result<PathBuf, ConfigError> {
+    pub fn get_config_path() -> Result<PathBuf, ConfigError> {
         dirs::config_dir()
             .map(|mut path| {
                 path.push("zorforge");
@@ -195,6 +487,30 @@ impl EditorConfig {
         Ok(toml::from_str(&contents)?)
     }
 
+    /// The key `leader` names; None when it's empty, which turns leader
+    /// mappings off
+    pub fn leader_key(&self) -> Option<char> {
+        self.leader.chars().next()
+    }
+
+    /// The columns `colorcolumn` names, counting from 1. Anything that isn't
+    /// one is left out.
+    pub fn color_columns(&self) -> Vec<usize> {
+        parse_color_columns(&self.colorcolumn).unwrap_or_default()
+    }
+
+    /// Color depth to render with: detected, unless `truecolor` overrides it.
+    /// NO_COLOR wins over both.
+    pub fn color_support(&self) -> ColorSupport {
+        let detected = ColorSupport::detect();
+        match self.truecolor {
+            _ if detected == ColorSupport::Monochrome => detected,
+            Some(true) => ColorSupport::TrueColor,
+            Some(false) => detected.min(ColorSupport::Ansi256),
+            None => detected,
+        }
+    }
+
     pub fn load_default() -> Result<Self, ConfigError> {
         Ok(Self::default())
     }
@@ -208,10 +524,38 @@ impl EditorConfig {
             highlight_current_line: true,
             show_whitespace: false,
             word_wrap: true,
+            statusline: default_statusline(),
+            autosave_delay: 0,
+            spell_file: None,
+            truecolor: None,
+            whichwrap: String::new(),
+            virtualedit: String::new(),
+            indent_guides: false,
+            highlight_trailing_whitespace: false,
+            colorcolumn: String::new(),
+            clipboard_history: default_clipboard_history(),
+            fileencoding: None,
+            leader: default_leader(),
+            leader_mappings: BTreeMap::new(),
+            detect_indent: false,
+            restore_cursor: default_restore_cursor(),
+            undolevels: default_undolevels(),
         }
     }
 }
 
+/// Columns such as "80,100" for `colorcolumn`, sorted; None unless every
+/// one is a number past 0
+pub fn parse_color_columns(value: &str) -> Option<Vec<usize>> {
+    let mut columns = value.split(',')
+        .filter(|column| !column.is_empty())
+        .map(|column| column.trim().parse().ok().filter(|&column| column > 0))
+        .collect::<Option<Vec<usize>>>()?;
+    columns.sort_unstable();
+    columns.dedup();
+    Some(columns)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error("Could not determine config directory")]
@@ -225,4 +569,95 @@ pub enum ConfigError {
     
     #[error("TOML serialization error: {0}")]
     TomlSer(#[from] toml::ser::Error),
-}
\ No newline at end of file
+
+    #[error("Cannot find color scheme '{0}'")]
+    UnknownTheme(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_color_support() {
+        assert_eq!(ColorSupport::from_env("truecolor", "xterm"), ColorSupport::TrueColor);
+        assert_eq!(ColorSupport::from_env("", "xterm-direct"), ColorSupport::TrueColor);
+        assert_eq!(ColorSupport::from_env("", "screen-256color"), ColorSupport::Ansi256);
+        assert_eq!(ColorSupport::from_env("", "linux"), ColorSupport::Ansi16);
+    }
+
+    #[test]
+    fn test_parse_color_columns() {
+        assert_eq!(parse_color_columns("80"), Some(vec![80]));
+        assert_eq!(parse_color_columns("100,80,80"), Some(vec![80, 100]));
+        assert_eq!(parse_color_columns(""), Some(vec![]));
+        assert_eq!(parse_color_columns("80,x"), None);
+        assert_eq!(parse_color_columns("0"), None);
+    }
+
+    #[test]
+    fn test_rgb_downgrade() {
+        let orange = ColorDef::Rgb { r: 255, g: 135, b: 0 };
+        assert_eq!(orange.to_color(ColorSupport::TrueColor), Color::Rgb { r: 255, g: 135, b: 0 });
+        assert_eq!(orange.to_color(ColorSupport::Ansi256), Color::AnsiValue(208));
+        assert_eq!(orange.to_color(ColorSupport::Ansi16), Color::Yellow);
+
+        let grey = ColorDef::Rgb { r: 100, g: 100, b: 100 };
+        assert_eq!(grey.to_color(ColorSupport::Ansi256), Color::AnsiValue(241));
+
+        let named = ColorDef::Named(NamedColor::Blue);
+        assert_eq!(named.to_color(ColorSupport::Ansi16), Color::Blue);
+    }
+
+    #[test]
+    fn test_fit_terminal_colors() {
+        assert_eq!(ColorSupport::Ansi16.fit(Color::AnsiValue(1)), Color::DarkRed);
+        assert_eq!(ColorSupport::Ansi16.fit(Color::AnsiValue(208)), Color::Yellow);
+        assert_eq!(ColorSupport::Ansi256.fit(Color::AnsiValue(208)), Color::AnsiValue(208));
+        assert_eq!(ColorSupport::Ansi256.fit(Color::Rgb { r: 255, g: 135, b: 0 }), Color::AnsiValue(208));
+        assert_eq!(ColorSupport::Monochrome.fit(Color::AnsiValue(1)), Color::Reset);
+    }
+
+    #[test]
+    fn test_monochrome_drops_colors() {
+        let orange = ColorDef::Rgb { r: 255, g: 135, b: 0 };
+        assert_eq!(orange.to_color(ColorSupport::Monochrome), Color::Reset);
+        assert_eq!(ColorDef::Named(NamedColor::Red).to_color(ColorSupport::Monochrome), Color::Reset);
+    }
+
+    #[test]
+    fn test_bundled_themes() {
+        for name in ["default", "light", "gruvbox"] {
+            assert_eq!(Theme::bundled(name).map(|theme| theme.name).as_deref(), Some(name));
+        }
+        assert!(Theme::bundled("nonexistent").is_none());
+    }
+
+    #[test]
+    fn test_theme_from_toml() {
+        let toml = r#"
+            background = "black"
+            foreground = { r = 200, g = 200, b = 200 }
+            cursor = "white"
+            selection = "blue"
+            search_highlight = "yellow"
+            line_numbers = "brightblack"
+            line_numbers_highlight = "white"
+
+            [status_line]
+            normal = "brightblack"
+            insert = "green"
+            visual = "blue"
+            command = "yellow"
+
+            [ui]
+            background = "black"
+            foreground = "white"
+            selected = "blue"
+            active = "white"
+            inactive = "brightblack"
+        "#;
+        let theme: Theme = toml::from_str(toml).unwrap();
+        assert_eq!(theme.foreground.to_crossterm_color(), Color::Rgb { r: 200, g: 200, b: 200 });
+    }
+}
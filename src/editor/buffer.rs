@@ -1,7 +1,19 @@
 // src/editor/buffer.rs
+use std::borrow::Cow;
+use std::io;
 use std::ops::Range;
-use std::collections::HashSet;
-use super::clipboard::Clipboard;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use parking_lot::Mutex;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use super::clipboard::{Clipboard, YankType};
+use super::encoding::Encoding;
+use super::indent;
+use super::large_file::LargeFile;
+use super::mode::VisualVariant;
+use super::modeline::ModelineSettings;
+use super::spell::Dictionary;
 use super::viewport::Viewport;
 
 #[derive(Clone, Debug)]
@@ -14,14 +26,57 @@ pub enum BufferChange {
         position: (usize, usize),
         content: String,
     },
+    // Line `position.0` was broken at `position.1`, with `indent` put at
+    // the start of the new line
     NewLine {
         position: (usize, usize),
-        content: String,
+        indent: String,
     },
     DeleteLine {
         position: usize,
         content: String,
     },
+    // A whole line was added at row `position`
+    InsertLine {
+        position: usize,
+        content: String,
+    },
+    Replace {
+        position: (usize, usize),
+        old: String,
+        new: String,
+    },
+    // Line `position.0` was joined with the next where it used to end, at
+    // `position.1`: `removed` was dropped from the front of the next line and
+    // `separator` put in its place
+    Join {
+        position: (usize, usize),
+        removed: String,
+        separator: String,
+    },
+    // The reverse of a Join, recorded when one is undone
+    Split {
+        position: (usize, usize),
+        removed: String,
+        separator: String,
+    },
+}
+
+impl BufferChange {
+    // The rows the change leaves different, as start..end numbered after
+    // it, and how many lines it added, or took away if negative
+    fn rows(&self) -> (usize, usize, isize) {
+        match *self {
+            BufferChange::Insert { position: (row, _), .. }
+            | BufferChange::Delete { position: (row, _), .. }
+            | BufferChange::Replace { position: (row, _), .. } => (row, row + 1, 0),
+            BufferChange::NewLine { position: (row, _), .. }
+            | BufferChange::Split { position: (row, _), .. } => (row, row + 2, 1),
+            BufferChange::Join { position: (row, _), .. } => (row, row + 1, -1),
+            BufferChange::DeleteLine { position, .. } => (position, position, -1),
+            BufferChange::InsertLine { position, .. } => (position, position + 1, 1),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -31,6 +86,47 @@ pub enum VisualMode {
     Block,  // Block-wise visual mode
 }
 
+impl From<VisualMode> for VisualVariant {
+    fn from(mode: VisualMode) -> Self {
+        match mode {
+            VisualMode::Char => VisualVariant::Char,
+            VisualMode::Line => VisualVariant::Line,
+            VisualMode::Block => VisualVariant::Block,
+        }
+    }
+}
+
+impl From<VisualVariant> for VisualMode {
+    fn from(variant: VisualVariant) -> Self {
+        match variant {
+            VisualVariant::Char => VisualMode::Char,
+            VisualVariant::Line => VisualMode::Line,
+            VisualVariant::Block => VisualMode::Block,
+        }
+    }
+}
+
+// Where the cursor may move past the end of its line
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum VirtualEdit {
+    #[default]
+    Off,
+    Block,  // Only in block-wise visual mode
+    All,    // In every mode
+}
+
+impl VirtualEdit {
+    // Value of the `virtualedit` option
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "" | "none" => Some(VirtualEdit::Off),
+            "block" => Some(VirtualEdit::Block),
+            "all" => Some(VirtualEdit::All),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SelectionType {
     Inner,      // Inside delimiters
@@ -43,18 +139,115 @@ pub struct Buffer {
     cursor_position: (usize, usize),  // (row, column)
     visual_start: Option<(usize, usize)>, // Start of visual selection
     tab_size: usize,                  // Tab size in spaces
+    shift_width: usize,               // Columns added or removed per indent level
+    expand_tab: bool,                 // Indent with spaces instead of tabs
+    text_width: usize,                // Preferred maximum line width (0 = off)
+    filetype: Option<String>,         // Detected or user-set file type, e.g. "rust"
+    encoding: Encoding,               // How the file is stored on disk
+    bom: bool,                        // The file starts with a byte order mark ('bomb')
     search_matches: Vec<(usize, usize, usize)>, // (row, start_col, end_col)
     current_match: Option<usize>,     // Index into search_matches
+    search_forward: bool,             // Direction of the last / or ?, which n follows
+    search_query: Option<(String, bool)>, // The search the matches are for, and whether it was case sensitive
+    search_outdated: Option<(usize, usize)>, // Lines at the start and end unchanged since the matches were found
+    search_line_count: usize,         // Lines there were when the matches were found
     undo_stack: Vec<BufferChangeRecord>, // (change, cursor_position)
     redo_stack: Vec<BufferChangeRecord>,
+    undo_levels: usize,  // Changes kept for undo; older ones are forgotten
+    undo_dropped: usize, // Records forgotten off the bottom of the undo stack, which undo_len counts
+    undo_groups: usize,  // Separate changes on the undo stack, each undone by one u
+    merges_open: usize,  // begin_merge calls not yet ended by merge_changes_since
     visual_mode: Option<VisualMode>,
     visual_bounds: Option<((usize, usize), (usize, usize))>, // Stored selection bounds
+    last_visual_rows: Option<(usize, usize)>, // First and last row of the last selection, for '<,'>
     selection_type: Option<SelectionType>,
     dirty_lines: std::collections::HashSet<usize>,
+    dirty_from: Option<usize>, // Every line from here down changed or moved
+    render_cache: Mutex<Vec<Option<String>>>, // Search-highlighted lines by row
     clipboard: Option<Clipboard>,
     viewport: Viewport,
-    last_save_change_id: usize, // ID of the last change when saved
+    // Every state of the content is identified by the change_id of the top
+    // undo record, or by base_change_id when there's nothing to undo
+    base_change_id: usize,
+    last_save_change_id: Option<usize>, // State last written to disk, if any still is
     change_counter: usize, // Monotonically increase change ID
+    desired_col: usize, // Column to aim for when moving vertically
+    jumps_back: Vec<(usize, usize)>,    // Positions to return to with Ctrl-o
+    jumps_forward: Vec<(usize, usize)>, // Positions to revisit with Ctrl-i
+    large_file: Option<LargeFile>, // Read-only disk backing used instead of `content`
+    spell: Option<Arc<Dictionary>>, // Word list to check against while :set spell is on
+    virtual_edit: VirtualEdit,
+}
+
+// Most text {count}p pastes; a bigger count pastes as many copies as fit,
+// rather than building a string of whatever size was typed
+const MAX_PASTE_BYTES: usize = 16 * 1024 * 1024;
+
+// Keywords that, directly before an identifier, suggest it's being defined
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "fn", "let", "mut", "struct", "enum", "const", "static", "type", "trait", "mod",
+];
+
+// Characters that make up a word for motions and text objects
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Characters gf takes as part of a file name, besides the `:` before a line
+// number
+pub fn is_file_name_char(c: char) -> bool {
+    is_word_char(c) || matches!(c, '/' | '.' | '-' | '~' | '+' | '\\')
+}
+
+// Terminal cells taken by `c` when drawn starting at cell `cell`. A tab
+// reaches to the next multiple of `tab_size`.
+pub fn cell_width(c: char, cell: usize, tab_size: usize) -> usize {
+    match c {
+        '\t' => tab_size.max(1) - cell % tab_size.max(1),
+        _ => c.width().unwrap_or(0),
+    }
+}
+
+// Kinds of character that `iw` and `aw` treat as separate runs
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if is_word_char(c) {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+// A yanked block `count` times side by side, each copy but the last padded
+// out to the block's width so the next lines up
+fn repeat_block(text: &str, count: usize) -> String {
+    let width = text.split('\n').map(UnicodeWidthStr::width).max().unwrap_or(0);
+    text.split('\n')
+        .map(|line| {
+            let padded = format!("{}{}", line, " ".repeat(width - line.width()));
+            padded.repeat(count.saturating_sub(1)) + line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// The first number whose digits start in `line[from..to]`, taking in a `-`
+// just before them, as its byte range and value. An empty range still
+// looks at the character at `from`.
+fn find_number(line: &str, from: usize, to: usize) -> Option<(usize, usize, i64)> {
+    let to = to.max(from + 1).min(line.len());
+    let digits = from + line.get(from..to)?.find(|c: char| c.is_ascii_digit())?;
+    let end = line[digits..].find(|c: char| !c.is_ascii_digit()).map_or(line.len(), |i| digits + i);
+    let start = if line[..digits].ends_with('-') { digits - 1 } else { digits };
+    Some((start, end, line[start..end].parse().ok()?))
 }
 
 #[derive(Clone, Debug)]
@@ -71,22 +264,46 @@ impl Buffer {
             cursor_position: (0, 0),
             visual_start: None,
             tab_size: 4,
+            shift_width: 4,
+            expand_tab: true,
+            text_width: 0,
+            filetype: None,
+            encoding: Encoding::default(),
+            bom: false,
             search_matches: Vec::new(),
             current_match: None,
+            search_forward: true,
+            search_query: None,
+            search_outdated: None,
+            search_line_count: 0,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
+            undo_levels: usize::MAX,
+            undo_dropped: 0,
+            undo_groups: 0,
+            merges_open: 0,
             visual_mode: None,
             visual_bounds: None,
+            last_visual_rows: None,
             selection_type: None,
             dirty_lines: HashSet::new(),
+            dirty_from: None,
+            render_cache: Mutex::new(Vec::new()),
             clipboard: Some(Clipboard::new()),
             viewport: Viewport {
                 start: 0,
                 height: 20,
                 width: 80,
             },
-            last_save_change_id: 0,
+            base_change_id: 0,
+            last_save_change_id: Some(0),
             change_counter: 0,
+            desired_col: 0,
+            jumps_back: Vec::new(),
+            jumps_forward: Vec::new(),
+            large_file: None,
+            spell: None,
+            virtual_edit: VirtualEdit::Off,
         }
     }
 
@@ -97,42 +314,141 @@ impl Buffer {
             cursor: self.cursor_position,
             change_id: self.change_counter,
         };
+        self.outdate_search(&record.change, self.content.len());
         self.undo_stack.push(record);
+        self.undo_groups += 1;
+        self.redo_stack.clear();
+        self.trim_history();
+    }
+
+    // Record `changes` so that a single undo reverts all of them
+    fn record_change_group(&mut self, changes: Vec<BufferChange>) {
+        self.change_counter += 1;
+        if !changes.is_empty() {
+            self.undo_groups += 1;
+        }
+        // Each change's rows are numbered as the lines were just after it
+        let added: isize = changes.iter().map(|change| change.rows().2).sum();
+        let mut len = self.content.len().saturating_add_signed(-added);
+        for change in &changes {
+            len = len.saturating_add_signed(change.rows().2);
+            self.outdate_search(change, len);
+        }
+        for change in changes {
+            self.undo_stack.push(BufferChangeRecord {
+                change,
+                cursor: self.cursor_position,
+                change_id: self.change_counter,
+            });
+        }
         self.redo_stack.clear();
+        self.trim_history();
+    }
+
+    // Note that `change` left the matches on its rows out of date. What's
+    // kept is how many lines at either end are untouched, which edits
+    // elsewhere don't change. `len` is the line count around the change;
+    // some are recorded before they're made, so it may be from either side
+    // of it, and the smaller is taken.
+    fn outdate_search(&mut self, change: &BufferChange, len: usize) {
+        let (start, end, added) = change.rows();
+        let tail = len.min(len.saturating_add_signed(added)).saturating_sub(end);
+        self.search_outdated = Some(match self.search_outdated {
+            Some((head, old_tail)) => (head.min(start), old_tail.min(tail)),
+            None => (start, tail),
+        });
+    }
+
+    // How many changes undo can go back through; 0 turns undo off
+    pub fn set_undo_levels(&mut self, levels: usize) {
+        self.undo_levels = levels;
+        self.trim_history();
+    }
+
+    // Forget the oldest changes past the undo levels, a whole group at a
+    // time. The state before them is then as far back as undo goes. Waits
+    // while a merge is under way, as its records only count as one change
+    // once merged.
+    fn trim_history(&mut self) {
+        if self.merges_open > 0 {
+            return;
+        }
+        while self.undo_groups > self.undo_levels {
+            let oldest = self.undo_stack[0].change_id;
+            let count = self.undo_stack.iter()
+                .take_while(|record| record.change_id == oldest)
+                .count();
+            self.undo_stack.drain(..count);
+            self.undo_dropped += count;
+            self.base_change_id = oldest;
+            self.undo_groups -= 1;
+        }
+    }
+
+    // Number of records recorded so far. Records trimmed off the bottom
+    // still count, so it holds across them.
+    fn undo_len(&self) -> usize {
+        self.undo_dropped + self.undo_stack.len()
+    }
+
+    // Start a merge: what's recorded from here until merge_changes_since
+    // is given the returned length undoes as one change
+    pub fn begin_merge(&mut self) -> usize {
+        self.merges_open += 1;
+        self.undo_len()
+    }
+
+    // Make everything recorded since undo_len was `len` undo as a single
+    // change
+    pub fn merge_changes_since(&mut self, len: usize) {
+        // Changes undone in the meantime may have taken the stack below
+        // `len`
+        let start = len.saturating_sub(self.undo_dropped).min(self.undo_stack.len());
+        let merged = self.undo_stack[start..]
+            .chunk_by(|a, b| a.change_id == b.change_id)
+            .count();
+        for record in &mut self.undo_stack[start..] {
+            record.change_id = self.change_counter;
+        }
+        self.undo_groups -= merged.saturating_sub(1);
+        // A merge inside another leaves the outer one under way
+        self.merges_open = self.merges_open.saturating_sub(1);
+        self.trim_history();
     }
 
     // Add method to mark current state as saved
     pub fn mark_saved(&mut self) {
-        if let Some(record) = self.undo_stack.last() {
-            self.last_save_change_id = record.change_id;
-        } else {
-            self.last_save_change_id = self.change_counter;
-        }
+        self.last_save_change_id = Some(self.current_change_id());
+    }
+
+    // Flag the content as differing from disk without an undoable change,
+    // e.g. after restoring it from a swap file
+    pub fn mark_modified(&mut self) {
+        self.last_save_change_id = None;
     }
 
     // Get the current change ID
     pub fn current_change_id(&self) -> usize {
-        if let Some(record) = self.undo_stack.last() {
-            record.change_id
-        } else {
-            self.change_counter
-        }
+        self.undo_stack.last().map_or(self.base_change_id, |record| record.change_id)
     }
 
-    // Check if there are unsaved changes
+    // Check if there are unsaved changes. Undoing back to the saved
+    // state, or redoing forward to it, counts as unmodified again.
     pub fn has_unsaved_changes(&self) -> bool {
-        // First check if we have any changes at all
-        let has_changes = if let Some(record) = self.undo_stack.last() {
-            record.change_id != self.last_save_change_id
-        } else {
-            false
-        };
-
-        // Then check if this is a new file (no path) with content
-        let is_new_with_content = self.content.len() > 1 || 
-            (self.content.len() == 1 && !self.content[0].is_empty());
+        self.last_save_change_id != Some(self.current_change_id())
+    }
 
-        has_changes || is_new_with_content
+    // Start a fresh history for content that matches what's on disk
+    fn reset_history(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.undo_dropped = 0;
+        self.undo_groups = 0;
+        self.merges_open = 0;
+        self.change_counter += 1;
+        self.base_change_id = self.change_counter;
+        self.last_save_change_id = Some(self.base_change_id);
+        self.search_outdated = Some((0, 0));
     }
 
     // For debugging and testing - get a count of stored changes
@@ -151,20 +467,56 @@ impl Buffer {
     }
 
     pub fn insert_char(&mut self, c: char) {
-        let current_line = &mut self.content[self.cursor_position.0];
+        self.fill_virtual_space();
         let change = BufferChange::Insert {
             position: self.cursor_position,
             content: c.to_string(),
         };
-        current_line.insert(self.cursor_position.1, c);
-        self.cursor_position.1 += 1;
+        let col = self.cursor_position.1;
+        self.current_line_mut().insert(col, c);
+        self.mark_lines_dirty(self.cursor_position.0, self.cursor_position.0);
+        self.cursor_position.1 += c.len_utf8();
+        self.record_change(change);
+    }
+
+    // Swap the `len` bytes before the cursor for `text` as a single change
+    pub fn replace_before_cursor(&mut self, len: usize, text: &str) {
+        self.clamp_virtual_cursor();
+        let (row, col) = self.cursor_position;
+        self.replace_text((row, col - len), len, text);
+        self.cursor_position.1 = col - len + text.len();
+    }
+
+    // Swap the `len` bytes at `position` for `text` as a single change
+    pub fn replace_text(&mut self, (row, col): (usize, usize), len: usize, text: &str) {
+        let change = BufferChange::Replace {
+            position: (row, col),
+            old: self.content[row][col..col + len].to_string(),
+            new: text.to_string(),
+        };
+        self.content[row].replace_range(col..col + len, text);
+        self.mark_lines_dirty(row, row);
         self.record_change(change);
     }
 
+    // First occurrence of `pattern` at or after `from`, no further down
+    // than `end_row`
+    pub fn find_text(&self, from: (usize, usize), end_row: usize, pattern: &str) -> Option<(usize, usize)> {
+        if pattern.is_empty() {
+            return None;
+        }
+        let end_row = end_row.min(self.content.len().saturating_sub(1));
+        (from.0..=end_row).find_map(|row| {
+            let line = &self.content[row];
+            let start = if row == from.0 { from.1.min(line.len()) } else { 0 };
+            line[start..].find(pattern).map(|col| (row, start + col))
+        })
+    }
+
     // Get character before cursor for ctrl+w word deletion
     pub fn get_char_before_cursor(&self) -> Option<char> {
         if self.cursor_position.1 > 0 {
-            self.content[self.cursor_position.0]
+            self.get_current_line()?
                 .chars()
                 .nth(self.cursor_position.1 - 1)
         } else {
@@ -172,36 +524,143 @@ impl Buffer {
         }
     }
 
-    // Word movement operations
+    // Word movement operations. `w`, `b` and `e` stop where a run of word
+    // characters meets punctuation, the WORD versions `W`, `B` and `E` only
+    // at whitespace. All of them carry on across lines, and an empty line
+    // counts as a word of its own for `w` and `b`.
     pub fn move_word_forward(&mut self) {
-        let line = &self.content[self.cursor_position.0];
-        if let Some(next_word) = line[self.cursor_position.1..]
-            .char_indices()
-            .skip_while(|(_, c)| !c.is_whitespace())
-            .skip_while(|(_, c)| c.is_whitespace())
-            .next()
-        {
-            self.cursor_position.1 += next_word.0;
-        } else {
-            self.cursor_position.1 = line.len();
-        }
+        self.word_forward(false);
     }
 
     pub fn move_word_backward(&mut self) {
-        let line = &self.content[self.cursor_position.0];
-        if self.cursor_position.1 > 0 {
-            let reversed: String = line[..self.cursor_position.1].chars().rev().collect();
-            if let Some(prev_word) = reversed
-                .char_indices()
-                .skip_while(|(_, c)| !c.is_whitespace())
-                .skip_while(|(_, c)| c.is_whitespace())
-                .next()
-            {
-                self.cursor_position.1 = self.cursor_position.1.saturating_sub(prev_word.0 + 1);
-            } else {
-                self.cursor_position.1 = 0;
+        self.word_backward(false);
+    }
+
+    pub fn move_word_end(&mut self) {
+        self.word_end(false);
+    }
+
+    pub fn move_big_word_forward(&mut self) {
+        self.word_forward(true);
+    }
+
+    pub fn move_big_word_backward(&mut self) {
+        self.word_backward(true);
+    }
+
+    pub fn move_big_word_end(&mut self) {
+        self.word_end(true);
+    }
+
+    fn word_forward(&mut self, big: bool) {
+        self.clamp_virtual_cursor();
+        let mut pos = self.cursor_position;
+        let class = self.motion_class(pos, big);
+        if class != Some(CharClass::Space) {
+            // Step off the current word, or the empty line
+            pos = match self.next_motion_pos(pos) {
+                Some(next) => next,
+                None => return self.move_to_last_char(),
+            };
+            if class.is_some() {
+                while self.motion_class(pos, big) == class {
+                    pos = match self.next_motion_pos(pos) {
+                        Some(next) => next,
+                        None => return self.move_to_last_char(),
+                    };
+                }
+            }
+        }
+        while self.motion_class(pos, big) == Some(CharClass::Space) {
+            pos = match self.next_motion_pos(pos) {
+                Some(next) => next,
+                None => return self.move_to_last_char(),
+            };
+        }
+        self.cursor_position = pos;
+        self.update_desired_col();
+    }
+
+    fn word_backward(&mut self, big: bool) {
+        self.clamp_virtual_cursor();
+        let mut pos = self.cursor_position;
+        loop {
+            pos = match self.prev_motion_pos(pos) {
+                Some(prev) => prev,
+                None => break,
+            };
+            if self.motion_class(pos, big) != Some(CharClass::Space) {
+                break;
+            }
+        }
+        let class = self.motion_class(pos, big);
+        if class.is_some() {
+            while let Some(prev) = self.prev_motion_pos(pos).filter(|&prev| self.motion_class(prev, big) == class) {
+                pos = prev;
+            }
+        }
+        self.cursor_position = pos;
+        self.update_desired_col();
+    }
+
+    fn word_end(&mut self, big: bool) {
+        self.clamp_virtual_cursor();
+        let mut pos = self.cursor_position;
+        // `e` passes over empty lines rather than stopping on them
+        let is_space = |buffer: &Self, pos| matches!(buffer.motion_class(pos, big), Some(CharClass::Space) | None);
+        loop {
+            pos = match self.next_motion_pos(pos) {
+                Some(next) => next,
+                None => return self.move_to_last_char(),
+            };
+            if !is_space(self, pos) {
+                break;
             }
         }
+        let class = self.motion_class(pos, big);
+        while let Some(next) = self.next_motion_pos(pos).filter(|&next| self.motion_class(next, big) == class) {
+            pos = next;
+        }
+        self.cursor_position = pos;
+        self.update_desired_col();
+    }
+
+    // Class of the character at `pos` for word motions. The end of a line
+    // reads as whitespace, except on an empty line, which is None.
+    fn motion_class(&self, (row, col): (usize, usize), big: bool) -> Option<CharClass> {
+        let line = &self.content[row];
+        match line[col..].chars().next() {
+            Some(c) if big && !c.is_whitespace() => Some(CharClass::Word),
+            Some(c) => Some(char_class(c)),
+            None if line.is_empty() => None,
+            None => Some(CharClass::Space),
+        }
+    }
+
+    // Next position for a word motion, visiting each line's end on the way
+    fn next_motion_pos(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+        match self.content[row][col..].chars().next() {
+            Some(c) => Some((row, col + c.len_utf8())),
+            None if row + 1 < self.content.len() => Some((row + 1, 0)),
+            None => None,
+        }
+    }
+
+    fn prev_motion_pos(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+        match self.content[row][..col].chars().next_back() {
+            Some(c) => Some((row, col - c.len_utf8())),
+            None if row > 0 => Some((row - 1, self.content[row - 1].len())),
+            None => None,
+        }
+    }
+
+    // Where `w` and `e` stop when they run out of buffer
+    fn move_to_last_char(&mut self) {
+        let row = self.content.len() - 1;
+        let line = &self.content[row];
+        let col = line.char_indices().next_back().map_or(0, |(i, _)| i);
+        self.cursor_position = (row, col);
+        self.update_desired_col();
     }
 
     pub fn get_undo_stack(&self) -> &Vec<BufferChangeRecord> {
@@ -209,133 +668,108 @@ impl Buffer {
     }
 
     pub fn undo(&mut self) -> bool {
-        if let Some(record) = self.undo_stack.pop() {
-            let reverse_record = match record.change {
-                BufferChange::Insert { position, content } => {
-                    // For insert, remove the inserted content
-                    let (row, col) = position;
-                    let line = &mut self.content[row];
-                    let end_col = col + content.len();
-                    line.replace_range(col..end_col, "");
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::Delete { position, content },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-                BufferChange::Delete { position, content } => {
-                    // For delete, reinsert the deleted content
-                    let (row, col) = position;
-                    let line = &mut self.content[row];
-                    line.insert_str(col, &content);
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::Insert { position, content },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-                BufferChange::NewLine { position, content } => {
-                    // For newline, join the lines back
-                    let (row, _) = position;
-                    let next_line = self.content.remove(row + 1);
-                    self.content[row].push_str(&next_line);
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::DeleteLine { 
-                            position: row, 
-                            content 
-                        },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-                BufferChange::DeleteLine { position, content } => {
-                    // For line deletion, reinsert the line
-                    self.content.insert(position, content.clone());
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::NewLine { 
-                            position: (position, 0),
-                            content 
-                        },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-            };
-            
-            self.change_counter += 1;
+        let Some(group) = self.undo_stack.last().map(|record| record.change_id) else {
+            return false;
+        };
+        // Changes recorded as one group are undone together, newest first.
+        // They keep their id on the redo stack, so redoing them returns to
+        // the same state.
+        while let Some(record) = self.undo_stack.pop_if(|record| record.change_id == group) {
+            let change = self.reverse_change(record.change);
+            self.outdate_search(&change, self.content.len());
+            self.redo_stack.push(BufferChangeRecord {
+                change,
+                cursor: self.cursor_position,
+                change_id: group,
+            });
             self.cursor_position = record.cursor;
-            self.redo_stack.push(reverse_record);
-            true
-        } else {
-            false
         }
+        self.undo_groups -= 1;
+        true
     }
 
     // Redo last undone change
     pub fn redo(&mut self) -> bool {
-        if let Some(record) = self.redo_stack.pop() {
-            let reverse_record = match record.change {
-                BufferChange::Insert { position, content } => {
-                    let (row, col) = position;
-                    let line = &mut self.content[row];
-                    let end_col = col + content.len();
-                    line.replace_range(col..end_col, "");
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::Delete { position, content },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-                BufferChange::Delete { position, content } => {
-                    let (row, col) = position;
-                    let line = &mut self.content[row];
-                    line.insert_str(col, &content);
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::Insert { position, content },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-                BufferChange::NewLine { position, content } => {
-                    let (row, _) = position;
-                    let next_line = self.content.remove(row + 1);
-                    self.content[row].push_str(&next_line);
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::DeleteLine { 
-                            position: row,
-                            content 
-                        },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-                BufferChange::DeleteLine { position, content } => {
-                    self.content.insert(position, content.clone());
-                    
-                    BufferChangeRecord {
-                        change: BufferChange::NewLine { 
-                            position: (position, 0),
-                            content 
-                        },
-                        cursor: self.cursor_position,
-                        change_id: self.change_counter + 1,
-                    }
-                }
-            };
-            
-            self.change_counter += 1;
+        let Some(group) = self.redo_stack.last().map(|record| record.change_id) else {
+            return false;
+        };
+        while let Some(record) = self.redo_stack.pop_if(|record| record.change_id == group) {
+            let change = self.reverse_change(record.change);
+            self.outdate_search(&change, self.content.len());
+            self.undo_stack.push(BufferChangeRecord {
+                change,
+                cursor: self.cursor_position,
+                change_id: group,
+            });
             self.cursor_position = record.cursor;
-            self.undo_stack.push(reverse_record);
-            true
-        } else {
-            false
+        }
+        self.undo_groups += 1;
+        true
+    }
+
+    // Revert `change` in the content, returning the change that reverts
+    // it back. Undo and redo both go through here.
+    fn reverse_change(&mut self, change: BufferChange) -> BufferChange {
+        match change {
+            BufferChange::Insert { position, content } => {
+                // For insert, remove the inserted content
+                let (row, col) = position;
+                let line = &mut self.content[row];
+                let end_col = col + content.len();
+                line.replace_range(col..end_col, "");
+                self.mark_lines_dirty(row, row);
+                
+                BufferChange::Delete { position, content }
+            }
+            BufferChange::Delete { position, content } => {
+                // For delete, reinsert the deleted content
+                let (row, col) = position;
+                let line = &mut self.content[row];
+                line.insert_str(col, &content);
+                self.mark_lines_dirty(row, row);
+                
+                BufferChange::Insert { position, content }
+            }
+            BufferChange::NewLine { position, indent } => {
+                // For newline, join the lines back
+                self.join_next_line(position.0, indent.len(), "");
+
+                BufferChange::Join {
+                    position,
+                    removed: indent,
+                    separator: String::new(),
+                }
+            }
+            BufferChange::Replace { position, old, new } => {
+                let (row, col) = position;
+                self.content[row].replace_range(col..col + new.len(), &old);
+                self.mark_lines_dirty(row, row);
+
+                BufferChange::Replace { position, old: new, new: old }
+            }
+            BufferChange::Join { position, removed, separator } => {
+                self.split_joined_line(position, &removed, &separator);
+
+                BufferChange::Split { position, removed, separator }
+            }
+            BufferChange::Split { position, removed, separator } => {
+                self.join_next_line(position.0, removed.len(), &separator);
+
+                BufferChange::Join { position, removed, separator }
+            }
+            BufferChange::DeleteLine { position, content } => {
+                // For line deletion, reinsert the line
+                self.content.insert(position, content.clone());
+                self.mark_dirty_from(position);
+
+                BufferChange::InsertLine { position, content }
+            }
+            BufferChange::InsertLine { position, content } => {
+                self.content.remove(position);
+                self.mark_dirty_from(position);
+
+                BufferChange::DeleteLine { position, content }
+            }
         }
     }
 
@@ -352,187 +786,632 @@ impl Buffer {
     pub fn move_page_down(&mut self) {
         // Move down by screen height (configurable)
         for _ in 0..20 { // Default set to 20 lines, could be made configurable
-            if self.cursor_position.0 < self.content.len() - 1 {
+            if self.cursor_position.0 < self.line_count() - 1 {
                 self.move_cursor("down");
             }
         }
     }
 
-    // Indentation operations
-    pub fn indent_line(&mut self, size: usize) {
-        let spaces = " ".repeat(size);
-        self.content[self.cursor_position.0].insert_str(0, &spaces);
-        self.cursor_position.1 += size;
+    // Ctrl-D and Ctrl-U: scroll half a screen, taking the cursor the same
+    // number of lines along so it stays put on screen, in the same column
+    pub fn scroll_half_page(&mut self, down: bool) {
+        let half = (self.viewport.height / 2).max(1);
+        let last = self.line_count() - 1;
+        let (row, start) = (self.cursor_position.0, self.viewport.start);
+        let (row, start) = if down {
+            let last_start = self.line_count().saturating_sub(self.viewport.height);
+            ((row + half).min(last), (start + half).min(last_start).max(start))
+        } else {
+            (row.saturating_sub(half), start.saturating_sub(half))
+        };
+        self.cursor_position.1 = self.vertical_target_col(row);
+        self.cursor_position.0 = row;
+        self.viewport.set_start(start);
+        self.scroll_to_cursor();
     }
 
-    pub fn dedent_line(&mut self, size: usize) {
-        let line = &mut self.content[self.cursor_position.0];
-        let whitespace_count = line.chars()
-            .take_while(|c| c.is_whitespace())
-            .count();
-        let remove_count = whitespace_count.min(size);
-        if remove_count > 0 {
-            line.replace_range(0..remove_count, "");
-            self.cursor_position.1 = self.cursor_position.1.saturating_sub(remove_count);
+    // Ctrl-E and Ctrl-Y: scroll `count` lines, leaving the cursor on its
+    // line unless that would take it off screen
+    pub fn scroll_lines(&mut self, count: usize, down: bool) {
+        let start = self.viewport.start;
+        let start = match down {
+            true => (start + count).min(self.line_count().saturating_sub(self.viewport.height)).max(start),
+            false => start.saturating_sub(count),
+        };
+        self.viewport.set_start(start);
+        let bottom = start + self.viewport.height.max(1) - 1;
+        let row = self.cursor_position.0.clamp(start, bottom.min(self.line_count() - 1));
+        if row != self.cursor_position.0 {
+            self.cursor_position.1 = self.vertical_target_col(row);
+            self.cursor_position.0 = row;
         }
     }
 
-    pub fn delete_word_backward(&mut self) {
-        let start_pos = self.cursor_position.1;
-        self.move_word_backward();
-        let end_pos = self.cursor_position.1;
-        if start_pos > end_pos {
-            self.content[self.cursor_position.0]
-                .replace_range(end_pos..start_pos, "");
-        }
+    // {count}|: the character on screen column `column` of the cursor's
+    // line, counting from 1, or the end of a line too short to reach it
+    pub fn move_to_column(&mut self, column: usize) {
+        let row = self.cursor_position.0;
+        self.cursor_position.1 = self.col_at_display(row, column.saturating_sub(1));
+        self.update_desired_col();
     }
 
-    pub fn delete_to_line_start(&mut self) {
-        let line = &mut self.content[self.cursor_position.0];
-        line.replace_range(0..self.cursor_position.1, "");
-        self.cursor_position.1 = 0;
+    // Jump to the line `percent`% of the way through the file ({count}%)
+    pub fn move_to_percentage(&mut self, percent: usize) {
+        let percent = percent.min(100);
+        let row = (percent * self.line_count()).div_ceil(100).saturating_sub(1);
+        self.set_cursor_position(row, 0);
     }
 
-    // Text insertion at cursor
-    pub fn insert_text(&mut self, text: &str) {
-        let current_line = &mut self.content[self.cursor_position.0];
-        current_line.insert_str(self.cursor_position.1, text);
-        self.cursor_position.1 += text.len();
+    // `}`: to the blank line after the `count`th paragraph below, or the
+    // end of the last line when there isn't one
+    pub fn move_paragraph_forward(&mut self, count: usize) {
+        let last_row = self.line_count() - 1;
+        let is_blank = |row: usize| self.content[row].trim().is_empty();
+        let mut row = self.cursor_position.0.min(last_row);
+        for _ in 0..count {
+            while row < last_row && is_blank(row) {
+                row += 1;
+            }
+            row = self.find_paragraph_end(row) + 1;
+            if row > last_row {
+                self.set_cursor_position(last_row, self.content[last_row].len());
+                return;
+            }
+        }
+        self.set_cursor_position(row, 0);
     }
 
-    pub fn paste_at_cursor(&mut self, text: &str) {
-        for line in text.lines() {
-            self.insert_text(line);
-            if text.contains('\n') {
-                self.insert_newline_auto_indent();
+    // `{`: to the blank line before the `count`th paragraph above, or the
+    // start of the buffer
+    pub fn move_paragraph_backward(&mut self, count: usize) {
+        let is_blank = |row: usize| self.content[row].trim().is_empty();
+        let mut row = self.cursor_position.0.min(self.line_count() - 1);
+        for _ in 0..count {
+            while row > 0 && is_blank(row) {
+                row -= 1;
+            }
+            let start = self.find_paragraph_start(row);
+            if start == 0 {
+                self.set_cursor_position(0, 0);
+                return;
             }
+            row = start - 1;
         }
+        self.set_cursor_position(row, 0);
     }
 
-    // === Insert Mode Entry Preparations ===
-    
-    // Handle 'a' - appen after cursor
-    pub fn prepare_append(&mut self) {
-        if !self.content[self.cursor_position.0].is_empty() {
-            self.move_cursor("right");
+    // `)`: to the start of the `count`th sentence after the cursor, or the
+    // last char of the buffer
+    pub fn move_sentence_forward(&mut self, count: usize) {
+        let starts = self.sentence_starts();
+        let cursor = self.cursor_position;
+        match starts.iter().filter(|&&start| start > cursor).nth(count.max(1) - 1) {
+            Some(&(row, col)) => self.set_cursor_position(row, col),
+            None => self.move_to_last_char(),
         }
     }
 
-    // Handle 'A' - append at end of line
-    pub fn prepare_append_end_of_line(&mut self) {
-        self.cursor_position.1 = self.content[self.cursor_position.0].len();
+    // `(`: to the start of the `count`th sentence before the cursor
+    pub fn move_sentence_backward(&mut self, count: usize) {
+        let starts = self.sentence_starts();
+        let cursor = self.cursor_position;
+        let (row, col) = starts.iter().rev()
+            .filter(|&&start| start < cursor)
+            .nth(count.max(1) - 1)
+            .copied()
+            .unwrap_or((0, 0));
+        self.set_cursor_position(row, col);
     }
 
-    // Handle 'I' - insert at start of line (after whitespace)
-    pub fn prepare_insert_start_of_line(&mut self) {
-        let line = &self.content[self.cursor_position.0];
-        if let Some(first_non_space) = line.chars().position(|c| !c.is_whitespace()) {
-            self.cursor_position.1 = first_non_space;
-        } else {
-            self.cursor_position.1 = 0;
+    // Where each sentence begins: the first char after a `.`, `!` or `?`
+    // (and any closing quotes or brackets) that's followed by whitespace or
+    // the end of the line. A blank line ends a sentence too, and the first
+    // blank line of a run counts as a sentence of its own.
+    fn sentence_starts(&self) -> Vec<(usize, usize)> {
+        let mut starts = Vec::new();
+        let mut at_start = true;
+        for (row, line) in self.content.iter().enumerate() {
+            if line.trim().is_empty() {
+                if row == 0 || !self.content[row - 1].trim().is_empty() {
+                    starts.push((row, 0));
+                }
+                at_start = true;
+                continue;
+            }
+            let mut ended = false;
+            for (col, c) in line.char_indices() {
+                if at_start && !c.is_whitespace() {
+                    starts.push((row, col));
+                    at_start = false;
+                }
+                if matches!(c, '.' | '!' | '?') {
+                    ended = true;
+                } else if ended && c.is_whitespace() {
+                    at_start = true;
+                    ended = false;
+                } else if !matches!(c, ')' | ']' | '"' | '\'') {
+                    ended = false;
+                }
+            }
+            at_start |= ended;
         }
+        starts
     }
 
-    // Handle 'o' - open line below
-    pub fn insert_line_below(&mut self) {
-        let current_indent = self.get_line_indentation(self.cursor_position.0);
-        self.cursor_position.0 += 1;
-        self.content.insert(self.cursor_position.0, current_indent.clone());
-        self.cursor_position.1 = current_indent.len();
+    // Indentation settings
+    pub fn tab_size(&self) -> usize {
+        self.tab_size
     }
 
-    // Handle 'O'- open line above
-    pub fn insert_line_above(&mut self) {
-        let current_indent = self.get_line_indentation(self.cursor_position.0);
-        self.content.insert(self.cursor_position.0, current_indent.clone());
-        self.cursor_position.1 = current_indent.len();
+    pub fn set_tab_size(&mut self, tab_size: usize) {
+        self.tab_size = tab_size;
     }
 
-    // Cursor operations
-    pub fn get_cursor_position(&self) -> (usize, usize) {
-        self.cursor_position
+    pub fn shift_width(&self) -> usize {
+        self.shift_width
     }
 
-    pub fn set_cursor_position(&mut self, row: usize, col: usize) {
-        if row < self.content.len() {
-            self.cursor_position.0 = row;
-            self.cursor_position.1 = col.min(self.content[row].len());
+    pub fn set_shift_width(&mut self, shift_width: usize) {
+        self.shift_width = shift_width;
+    }
+
+    pub fn expand_tab(&self) -> bool {
+        self.expand_tab
+    }
+
+    pub fn text_width(&self) -> usize {
+        self.text_width
+    }
+
+    pub fn filetype(&self) -> Option<&str> {
+        self.filetype.as_deref()
+    }
+
+    pub fn set_filetype(&mut self, filetype: Option<String>) {
+        self.filetype = filetype;
+    }
+
+    // How many yanks the buffer's clipboard keeps
+    pub fn set_clipboard_history(&mut self, max_history: usize) {
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.set_max_history(max_history);
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    pub fn set_bom(&mut self, bom: bool) {
+        self.bom = bom;
+    }
+
+    // Apply per-file settings read from a modeline
+    pub fn spell_enabled(&self) -> bool {
+        self.spell.is_some()
+    }
+
+    pub fn set_spell(&mut self, dictionary: Option<Arc<Dictionary>>) {
+        self.spell = dictionary;
+        self.mark_dirty_from(0);
+    }
+
+    pub fn apply_modeline(&mut self, settings: &ModelineSettings) {
+        if let Some(tab_size) = settings.tab_size {
+            self.tab_size = tab_size;
+        }
+        if let Some(shift_width) = settings.shift_width {
+            self.shift_width = shift_width;
+        }
+        if let Some(expand_tab) = settings.expand_tab {
+            self.expand_tab = expand_tab;
+        }
+        if let Some(text_width) = settings.text_width {
+            self.text_width = text_width;
+        }
+    }
+
+    // Text inserted for one indent level of `size` columns
+    fn indent_unit(&self, size: usize) -> String {
+        if self.expand_tab {
+            " ".repeat(size)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    // Bytes of leading whitespace to strip from `line` for one dedent level
+    fn dedent_amount(line: &str, size: usize) -> usize {
+        if line.starts_with('\t') {
+            return 1;
+        }
+        line.chars()
+            .take_while(|&c| c == ' ')
+            .count()
+            .min(size)
+    }
+
+    // Indentation operations. Both change the indent at the start of the
+    // line, wherever the cursor is, and keep the cursor on its character.
+    pub fn indent_line(&mut self, size: usize) {
+        let indent = self.indent_unit(size);
+        self.current_line_mut().insert_str(0, &indent);
+        let row = self.cursor_position.0;
+        self.mark_lines_dirty(row, row);
+        self.cursor_position.1 += indent.len();
+        self.record_change(BufferChange::Insert { position: (row, 0), content: indent });
+    }
+
+    pub fn dedent_line(&mut self, size: usize) {
+        let remove_count = Self::dedent_amount(self.current_line_mut(), size);
+        let row = self.cursor_position.0;
+        if remove_count > 0 {
+            let content: String = self.content[row].drain(0..remove_count).collect();
+            self.cursor_position.1 = self.cursor_position.1.saturating_sub(remove_count);
+            self.mark_lines_dirty(row, row);
+            self.record_change(BufferChange::Delete { position: (row, 0), content });
+        }
+    }
+
+    pub fn delete_word_backward(&mut self) {
+        self.clamp_virtual_cursor();
+        let (row, start_pos) = self.cursor_position;
+        self.move_word_backward();
+        // Only as far back as the start of the line
+        if self.cursor_position.0 != row {
+            self.cursor_position = (row, 0);
+        }
+        let end_pos = self.cursor_position.1;
+        if start_pos > end_pos {
+            let content: String = self.content[row].drain(end_pos..start_pos).collect();
+            self.mark_lines_dirty(row, row);
+            self.record_change(BufferChange::Delete { position: (row, end_pos), content });
+        }
+    }
+
+    pub fn delete_to_line_start(&mut self) {
+        self.clamp_virtual_cursor();
+        let (row, col) = self.cursor_position;
+        if col > 0 {
+            let content: String = self.content[row].drain(0..col).collect();
+            self.mark_lines_dirty(row, row);
+            self.record_change(BufferChange::Delete { position: (row, 0), content });
+        }
+        self.cursor_position.1 = 0;
+    }
+
+    // Insert `text` on the cursor line, leaving the cursor after it. Like
+    // every column in the buffer the cursor's counts bytes, so it moves on
+    // by the text's length in bytes.
+    pub fn insert_text(&mut self, text: &str) {
+        self.fill_virtual_space();
+        let (row, col) = self.cursor_position;
+        if !text.is_empty() {
+            self.content[row].insert_str(col, text);
+            self.mark_lines_dirty(row, row);
+            self.record_change(BufferChange::Insert { position: (row, col), content: text.to_string() });
+        }
+        self.cursor_position.1 += text.len();
+    }
+
+    // Insert `text` at the cursor exactly as given: pasted lines keep their
+    // own indentation instead of picking up the cursor line's
+    pub fn paste_at_cursor(&mut self, text: &str) {
+        let undo_len = self.begin_merge();
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.insert_line();
+            }
+            self.insert_text(line.strip_suffix('\r').unwrap_or(line));
+        }
+        self.merge_changes_since(undo_len);
+    }
+
+    // === Insert Mode Entry Preparations ===
+    
+    // Handle 'a' - appen after cursor
+    pub fn prepare_append(&mut self) {
+        if !self.current_line_mut().is_empty() {
+            self.move_cursor("right");
+        }
+    }
+
+    // Handle 'A' - append at end of line
+    pub fn prepare_append_end_of_line(&mut self) {
+        self.cursor_position.1 = self.current_line_mut().len();
+    }
+
+    // Handle 'I' - insert at start of line (after whitespace)
+    pub fn prepare_insert_start_of_line(&mut self) {
+        let line = self.current_line_mut();
+        if let Some(first_non_space) = line.chars().position(|c| !c.is_whitespace()) {
+            self.cursor_position.1 = first_non_space;
+        } else {
+            self.cursor_position.1 = 0;
+        }
+    }
+
+    // Handle 'o' - open line below
+    pub fn insert_line_below(&mut self) {
+        self.clamp_cursor_row();
+        let above = self.cursor_position.0;
+        let current_indent = self.new_line_indent(above, self.content[above].len());
+        let row = above + 1;
+        self.record_change(BufferChange::InsertLine {
+            position: row,
+            content: current_indent.clone(),
+        });
+        self.content.insert(row, current_indent.clone());
+        self.mark_dirty_from(row);
+        self.cursor_position = (row, current_indent.len());
+    }
+
+    // Handle 'O'- open line above
+    pub fn insert_line_above(&mut self) {
+        self.clamp_cursor_row();
+        let current_indent = self.get_line_indentation(self.cursor_position.0);
+        self.content.insert(self.cursor_position.0, current_indent.clone());
+        self.mark_dirty_from(self.cursor_position.0);
+        self.cursor_position.1 = current_indent.len();
+        self.record_change(BufferChange::InsertLine {
+            position: self.cursor_position.0,
+            content: current_indent,
+        });
+    }
+
+    // Cursor operations
+    pub fn get_cursor_position(&self) -> (usize, usize) {
+        self.cursor_position
+    }
+
+    // Rows past the end land on the last line, as for a click below the text
+    pub fn set_cursor_position(&mut self, row: usize, col: usize) {
+        let row = row.min(self.line_count().saturating_sub(1));
+        let before = self.cursor_position;
+        self.cursor_position.0 = row;
+        self.cursor_position.1 = col.min(self.line_len(row));
+        self.update_desired_col();
+        log::debug!("cursor: set {:?} -> {:?}", before, self.cursor_position);
+    }
+
+    // Remember the current column as the target for vertical movement
+    fn update_desired_col(&mut self) {
+        self.desired_col = self.cursor_position.1;
+    }
+
+    // Column to land on when moving onto `row`, clamped to that line's length
+    fn vertical_target_col(&mut self, row: usize) -> usize {
+        // The cursor may have been moved horizontally by an edit rather than a
+        // motion; if so, the column we're on now becomes the new target.
+        if self.cursor_position.1 != self.clamp_col(self.cursor_position.0, self.desired_col) {
+            self.update_desired_col();
+        }
+        self.clamp_col(row, self.desired_col)
+    }
+
+    pub fn set_virtual_edit(&mut self, virtual_edit: VirtualEdit) {
+        self.virtual_edit = virtual_edit;
+        if !self.virtual_cursor() {
+            self.clamp_virtual_cursor();
+        }
+    }
+
+    // Whether the cursor may currently go past the end of its line
+    fn virtual_cursor(&self) -> bool {
+        match self.virtual_edit {
+            VirtualEdit::Off => false,
+            VirtualEdit::Block => self.visual_mode == Some(VisualMode::Block),
+            VirtualEdit::All => true,
+        }
+    }
+
+    // Furthest column the cursor may move right to on `row`
+    fn max_col(&self, row: usize) -> usize {
+        if self.virtual_cursor() {
+            usize::MAX
+        } else {
+            self.line_len(row)
+        }
+    }
+
+    // Column `col` as reachable on `row`; the `$` target always means the
+    // real end of line
+    fn clamp_col(&self, row: usize, col: usize) -> usize {
+        if self.virtual_cursor() && col != usize::MAX {
+            col
+        } else {
+            col.min(self.line_len(row))
+        }
+    }
+
+    // Bring a cursor left on a row that no longer exists back onto the
+    // last line, so the methods below can index the cursor's line safely
+    fn clamp_cursor_row(&mut self) {
+        if self.content.is_empty() {
+            self.content.push(String::new());
+        }
+        let last = self.content.len() - 1;
+        if self.cursor_position.0 > last {
+            self.cursor_position = (last, self.cursor_position.1.min(self.content[last].len()));
+        }
+    }
+
+    // The cursor's line, after making sure the cursor is on one
+    fn current_line_mut(&mut self) -> &mut String {
+        self.clamp_cursor_row();
+        &mut self.content[self.cursor_position.0]
+    }
+
+    // Pad the line with spaces out to a cursor that sits in virtual space,
+    // so text can be inserted there
+    fn fill_virtual_space(&mut self) {
+        self.clamp_cursor_row();
+        let (row, col) = self.cursor_position;
+        let len = self.content[row].len();
+        if col > len {
+            let padding = " ".repeat(col - len);
+            self.content[row].push_str(&padding);
+            self.mark_lines_dirty(row, row);
+            self.record_change(BufferChange::Insert {
+                position: (row, len),
+                content: padding,
+            });
         }
     }
 
+    // Bytes in the character at `(row, col)`; 1 in virtual space, where
+    // each step is a column
+    fn char_len_at(&self, (row, col): (usize, usize)) -> usize {
+        self.content[row].get(col..).and_then(|rest| rest.chars().next()).map_or(1, char::len_utf8)
+    }
+
+    // Bytes in the character just before `(row, col)`
+    fn char_len_before(&self, (row, col): (usize, usize)) -> usize {
+        self.content[row].get(..col).and_then(|before| before.chars().next_back()).map_or(1, char::len_utf8)
+    }
+
+    // Bring a cursor in virtual space back to the real end of its line
+    fn clamp_virtual_cursor(&mut self) {
+        self.clamp_cursor_row();
+        let (row, col) = self.cursor_position;
+        self.cursor_position.1 = col.min(self.line_len(row));
+    }
+
     pub fn move_cursor(&mut self, direction: &str) {
+        let before = self.cursor_position;
         match direction {
             "left" => {
                 if self.cursor_position.1 > 0 {
-                    self.cursor_position.1 -= 1;
+                    self.cursor_position.1 -= self.char_len_before(self.cursor_position);
                 }
+                self.update_desired_col();
             }
             "right" => {
-                if self.cursor_position.1 < self.content[self.cursor_position.0].len() {
-                    self.cursor_position.1 += 1;
+                if self.cursor_position.1 < self.max_col(self.cursor_position.0) {
+                    self.cursor_position.1 += self.char_len_at(self.cursor_position);
                 }
+                self.update_desired_col();
             }
             "up" => {
                 if self.cursor_position.0 > 0 {
-                    self.cursor_position.0 -= 1;
-                    self.cursor_position.1 = self.content[self.cursor_position.0]
-                        .len()
-                        .min(self.cursor_position.1);
+                    let row = self.cursor_position.0 - 1;
+                    self.cursor_position.1 = self.vertical_target_col(row);
+                    self.cursor_position.0 = row;
                 }
             }
             "down" => {
-                if self.cursor_position.0 + 1 < self.content.len() {
-                    self.cursor_position.0 += 1;
-                    self.cursor_position.1 = self.content[self.cursor_position.0]
-                        .len()
-                        .min(self.cursor_position.1);
+                if self.cursor_position.0 + 1 < self.line_count() {
+                    let row = self.cursor_position.0 + 1;
+                    self.cursor_position.1 = self.vertical_target_col(row);
+                    self.cursor_position.0 = row;
                 }
             }
             "top" => {
                 self.cursor_position = (0, 0);
+                self.update_desired_col();
             }
             "bottom" => {
-                self.cursor_position.0 = self.content.len().saturating_sub(1);
+                self.cursor_position.0 = self.line_count().saturating_sub(1);
                 self.cursor_position.1 = 0;
+                self.update_desired_col();
             }
             "line_start" => {
                 self.cursor_position.1 = 0;
+                self.update_desired_col();
+            }
+            "first_non_blank" => {
+                self.cursor_position.1 = self.first_non_blank(self.cursor_position.0);
+                self.update_desired_col();
+            }
+            // Home: the first non-blank, or from there the very start
+            "smart_home" => {
+                let first = self.first_non_blank(self.cursor_position.0);
+                self.cursor_position.1 = if self.cursor_position.1 == first { 0 } else { first };
+                self.update_desired_col();
             }
             "line_end" => {
-                self.cursor_position.1 = self.content[self.cursor_position.0].len();
+                self.cursor_position.1 = self.line_len(self.cursor_position.0);
+                // Like vim's `$`, stick to the end of every line we move onto
+                self.desired_col = usize::MAX;
             }
             _ => {}
         }
+        log::debug!("cursor: {} {:?} -> {:?}", direction, before, self.cursor_position);
+    }
+
+    // Column of the first character on `row` that isn't a blank, or the
+    // end of a line of nothing but blanks
+    fn first_non_blank(&self, row: usize) -> usize {
+        let line = &self.content[row];
+        line.len() - line.trim_start().len()
+    }
+
+    // Left or right that continues onto the previous or next line at
+    // either end of the current one
+    pub fn move_cursor_wrapping(&mut self, direction: &str) {
+        let (row, col) = self.cursor_position;
+        match direction {
+            "left" if col == 0 && row > 0 => {
+                self.cursor_position = (row - 1, self.line_len(row - 1));
+                self.update_desired_col();
+            }
+            "right" if col >= self.line_len(row) && row + 1 < self.line_count() => {
+                self.cursor_position = (row + 1, 0);
+                self.update_desired_col();
+            }
+            _ => self.move_cursor(direction),
+        }
     }
 
     // === Enhanced Text Operations ===
 
     // Insert character with replace mode support
     pub fn insert_char_replace(&mut self, c: char) {
-        let current_line = &mut self.content[self.cursor_position.0];
-        if self.cursor_position.1 < current_line.len() {
-            // Replace existing character
-            current_line.replace_range(self.cursor_position.1..=self.cursor_position.1, &c.to_string());
-        } else {
-            // Append if at end of line
-            current_line.push(c);
-        }
-        self.cursor_position.1 += 1;
+        self.fill_virtual_space();
+        let (row, col) = self.cursor_position;
+        // Overwrite the character under the cursor, or append at the end of
+        // the line; undo puts back whatever was there
+        let end = match col < self.content[row].len() {
+            true => col + self.char_len_at((row, col)),
+            false => col,
+        };
+        let old = self.content[row][col..end].to_string();
+        let new = c.to_string();
+        self.content[row].replace_range(col..end, &new);
+        self.mark_lines_dirty(row, row);
+        self.record_change(BufferChange::Replace { position: (row, col), old, new });
+        self.cursor_position.1 += c.len_utf8();
     }
 
-    // Newline handling with auto-indent
-    pub fn insert_newline_auto_indent(&mut self) {
+    // Split the line at the cursor, carrying the line's indentation over to
+    // the new one when `auto_indent` is on, deeper or shallower as the
+    // filetype's rules say
+    pub fn insert_newline(&mut self, auto_indent: bool) {
+        self.clamp_virtual_cursor();
         let current_line = self.cursor_position.0;
-        let current_indent = self.get_line_indentation(current_line);
+        let current_indent = if auto_indent {
+            self.new_line_indent(current_line, self.cursor_position.1)
+        } else {
+            String::new()
+        };
         let remainder = self.content[current_line][self.cursor_position.1..].to_string();
 
         let change = BufferChange::NewLine {
             position: (current_line, self.cursor_position.1),
-            content: remainder.clone(),
+            indent: current_indent.clone(),
         };
 
         // Update current line to end at cursor
         self.content[current_line] = self.content[current_line][..self.cursor_position.1].to_string();
+        self.record_change(change);
 
         // Insert new line with indentation
         self.cursor_position.0 += 1;
@@ -540,9 +1419,26 @@ impl Buffer {
             self.cursor_position.0,
             format!("{}{}", current_indent, remainder)
         );
+        self.mark_dirty_from(current_line);
         self.cursor_position.1 = current_indent.len();
-        
-        self.record_change(change);
+    }
+
+    // Indentation for a line broken off `row` at `col`
+    fn new_line_indent(&self, row: usize, col: usize) -> String {
+        let mut indent = self.get_line_indentation(row);
+        let provider = indent::indent_provider(self.filetype());
+        let levels = provider.indent_after(&self.content[row][..col]);
+        let unit = provider.indent_unit()
+            .map(String::from)
+            .unwrap_or_else(|| self.indent_unit(self.shift_width));
+        for _ in 0..levels.max(0) {
+            indent.push_str(&unit);
+        }
+        for _ in 0..-levels.min(0) {
+            let remove = Self::dedent_amount(&indent, self.shift_width);
+            indent.drain(..remove);
+        }
+        indent
     }
 
     // Helper for getting line indentation
@@ -556,32 +1452,26 @@ impl Buffer {
     }
 
     pub fn delete_char(&mut self) {
+        self.clamp_virtual_cursor();
         if self.cursor_position.1 > 0 {
-            let line = &mut self.content[self.cursor_position.0];
-            let deleted = line.remove(self.cursor_position.1 - 1);
+            let col = self.cursor_position.1 - self.char_len_before(self.cursor_position);
+            let deleted = self.current_line_mut().remove(col);
+            self.mark_lines_dirty(self.cursor_position.0, self.cursor_position.0);
             let change = BufferChange::Delete {
-                position: (self.cursor_position.0, self.cursor_position.1 - 1),
+                position: (self.cursor_position.0, col),
                 content: deleted.to_string(),
             };
-            self.cursor_position.1 -= 1;
+            self.cursor_position.1 = col;
             self.record_change(change);
         } else if self.cursor_position.0 > 0 {
-            let current_line = self.content.remove(self.cursor_position.0);
-            let change = BufferChange::DeleteLine {
-                position: self.cursor_position.0,
-                content: current_line.clone(),
-            };
-            self.cursor_position.0 -= 1;
-            self.cursor_position.1 = self.content[self.cursor_position.0].len();
-            self.content[self.cursor_position.0].push_str(&current_line);
-            self.record_change(change);
+            let row = self.cursor_position.0 - 1;
+            self.cursor_position = (row, self.content[row].len());
+            self.join_at_cursor();
         }
     }
 
     pub fn delete_char_forward(&mut self) {
-        if self.cursor_position.0 >= self.content.len() {
-            return;
-        }
+        self.clamp_virtual_cursor();
     
         let current_row = self.cursor_position.0;
         let current_col = self.cursor_position.1;
@@ -590,6 +1480,7 @@ impl Buffer {
         if current_col < line_length {
             // Delete character at cursor position
             let deleted_char = self.content[current_row].remove(current_col);
+            self.mark_lines_dirty(current_row, current_row);
             
             let change = BufferChange::Delete {
                 position: (current_row, current_col),
@@ -599,106 +1490,269 @@ impl Buffer {
             self.record_change(change);
         } else if current_row < self.content.len() - 1 {
             // If at end of line, join with next line
-            let next_line = self.content.remove(current_row + 1);
-            
-            let change = BufferChange::DeleteLine {
-                position: current_row + 1,
-                content: next_line.clone(),
-            };
-            
-            self.content[current_row].push_str(&next_line);
-            
-            self.record_change(change);
+            self.join_at_cursor();
         }
     }
 
     pub fn cut_char(&mut self) {
+        self.clamp_virtual_cursor();
         if let Some(line) = self.content.get_mut(self.cursor_position.0) {
             if self.cursor_position.1 < line.len() {
                 // Cut character at cursor
                 let cut_char = line.remove(self.cursor_position.1);
+                self.mark_lines_dirty(self.cursor_position.0, self.cursor_position.0);
                 // Store in clipboard
                 if let Some(clipboard) = &mut self.clipboard {
                     clipboard.yank(cut_char.to_string());
                 }
                 // Don't move cursor back since we're cutting at cursor position
+                self.record_change(BufferChange::Delete {
+                    position: self.cursor_position,
+                    content: cut_char.to_string(),
+                });
             } else if self.cursor_position.0 < self.content.len() - 1 {
                 // At end of line, joing with next line if it exists
-                let next_line = self.content.remove(self.cursor_position.0 + 1);
-                self.content[self.cursor_position.0].push_str(&next_line);
+                self.join_at_cursor();
             }
         }
     }
 
+    // `{count}x`: cut up to `count` characters from the cursor to the end of
+    // its line as one change
+    pub fn cut_chars(&mut self, count: usize) {
+        self.clamp_virtual_cursor();
+        let (row, col) = self.cursor_position;
+        let Some(line) = self.content.get_mut(row) else {
+            return;
+        };
+        let col = col.min(line.len());
+        let end = line[col..].char_indices().nth(count).map_or(line.len(), |(i, _)| col + i);
+        if end == col {
+            return;
+        }
+        let content: String = line.drain(col..end).collect();
+        self.mark_lines_dirty(row, row);
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.yank(content.clone());
+        }
+        self.record_change(BufferChange::Delete { position: (row, col), content });
+    }
+
+    /// Cut from the cursor to the end of its line, as D does, leaving the
+    /// cursor on what is now the last character
+    pub fn delete_to_line_end(&mut self) {
+        self.clamp_virtual_cursor();
+        let (row, col) = self.cursor_position;
+        let Some(line) = self.content.get_mut(row) else {
+            return;
+        };
+        if col >= line.len() {
+            return;
+        }
+        let content: String = line.drain(col..).collect();
+        self.cursor_position.1 = line[..col].char_indices().next_back().map_or(0, |(i, _)| i);
+        self.update_desired_col();
+        self.mark_lines_dirty(row, row);
+        if let Some(clipboard) = &mut self.clipboard {
+            clipboard.yank(content.clone());
+        }
+        self.record_change(BufferChange::Delete { position: (row, col), content });
+    }
+
+    /// Clear the cursor's line back to its indentation, as S and cc do,
+    /// leaving the cursor where the text began
+    pub fn clear_line_to_indent(&mut self) {
+        self.clamp_cursor_row();
+        let row = self.cursor_position.0;
+        let indent = self.get_line_indentation(row).len();
+        let Some(line) = self.content.get_mut(row) else {
+            return;
+        };
+        let content: String = line.drain(indent..).collect();
+        self.cursor_position.1 = indent;
+        self.update_desired_col();
+        if content.is_empty() {
+            return;
+        }
+        self.mark_lines_dirty(row, row);
+        self.record_change(BufferChange::Delete { position: (row, indent), content });
+    }
+
     pub fn yank(&mut self) {
-        // Yank the current line
+        // Yank the current line, line-wise so that it's put back on a line
+        // of its own
         if let Some(line) = self.get_current_line().cloned() {
             if let Some(clipboard) = self.clipboard.as_mut() {
-                clipboard.yank(line.clone());
+                clipboard.yank_as(line + "\n", YankType::Line);
             }
         }
     }
 
     pub fn paste(&mut self) {
-        // Paste content from clipboard
-        if let Some(clipboard) = &mut self.clipboard {
-            if let Some(content) = clipboard.peek().cloned() {
-                // Check if we're dealing with multiline content
-                let lines: Vec<&str> = content.lines().collect();
-                
-                if lines.len() > 1 {
-                    // Multiline paste
-                    for (i, line) in lines.iter().enumerate() {
-                        if i == 0 {
-                            // Insert first line at current cursor position
-                            self.insert_text(line);
-                        } else {
-                            // Insert subsequent lines on new lines
-                            self.insert_newline_auto_indent();
-                            self.insert_text(line);
-                        }
-                    }
-                } else {
-                    // Single line paste
-                    self.insert_text(&content);
-                }
+        self.paste_entry(0, 1);
+    }
+
+    // {count}p: the newest clipboard entry `count` times over, as one change
+    pub fn paste_times(&mut self, count: usize) {
+        self.paste_entry(0, count);
+    }
+
+    // Paste the `index`th newest clipboard entry `count` times the way it
+    // was yanked: line-wise text goes in below the cursor's line and a block
+    // at the cursor's column. False if there aren't that many.
+    pub fn paste_entry(&mut self, index: usize, count: usize) -> bool {
+        let Some(entry) = self.clipboard.as_ref().and_then(|c| c.peek_entry(index).cloned()) else {
+            return false;
+        };
+        let count = count.clamp(1, (MAX_PASTE_BYTES / entry.text.len().max(1)).max(1));
+        match entry.kind {
+            YankType::Line if !self.content.is_empty() => {
+                let row = self.cursor_position.0.min(self.content.len() - 1) + 1;
+                let lines = entry.text.strip_suffix('\n').unwrap_or(&entry.text);
+                self.insert_lines_at(row, &vec![lines; count].join("\n"));
+                let indent = self.get_line_indentation(row).len();
+                self.cursor_position = (row, indent);
+                self.update_desired_col();
             }
+            YankType::Block if !self.content.is_empty() => {
+                self.clamp_virtual_cursor();
+                let position = self.cursor_position;
+                self.insert_block_at(position, &repeat_block(&entry.text, count));
+            }
+            _ => self.paste_at_cursor(&entry.text.repeat(count)),
         }
+        true
+    }
+
+    pub fn clipboard(&self) -> Option<&Clipboard> {
+        self.clipboard.as_ref()
+    }
+
+    // The clipboard every yank, cut and paste goes through
+    pub fn clipboard_mut(&mut self) -> &mut Clipboard {
+        self.clipboard.get_or_insert_with(Clipboard::new)
     }
 
     // Method to handle forward delete (Delete Key)
     pub fn delete_char_fn(&mut self) {
+        self.clamp_virtual_cursor();
         if let Some(line) = self.content.get_mut(self.cursor_position.0) {
             if self.cursor_position.1 < line.len() {
                 // Delete character at cursor
-                line.remove(self.cursor_position.1);
+                let deleted = line.remove(self.cursor_position.1);
+                self.mark_lines_dirty(self.cursor_position.0, self.cursor_position.0);
                 // Cursor position stays the same
+                self.record_change(BufferChange::Delete {
+                    position: self.cursor_position,
+                    content: deleted.to_string(),
+                });
             } else if self.cursor_position.0 < self.content.len() - 1 {
                 // At end of line, joing with next line if it exists
-                let next_line = self.content.remove(self.cursor_position.0 + 1);
-                self.content[self.cursor_position.0].push_str(&next_line);
+                self.join_at_cursor();
             }
         }
     }
 
+    // Join the next line onto the cursor's, which the cursor is at the end of
+    fn join_at_cursor(&mut self) {
+        let position = self.cursor_position;
+        self.join_next_line(position.0, 0, "");
+        self.record_change(BufferChange::Join {
+            position,
+            removed: String::new(),
+            separator: String::new(),
+        });
+    }
+
     pub fn insert_line(&mut self) {
-        let current_line = self.content[self.cursor_position.0]
-            .split_off(self.cursor_position.1);
+        self.clamp_virtual_cursor();
+        let change = BufferChange::NewLine {
+            position: self.cursor_position,
+            indent: String::new(),
+        };
+        let col = self.cursor_position.1;
+        let current_line = self.current_line_mut().split_off(col);
         self.content
             .insert(self.cursor_position.0 + 1, current_line);
+        self.mark_dirty_from(self.cursor_position.0);
+        self.record_change(change);
         self.cursor_position.0 += 1;
         self.cursor_position.1 = 0;
     }
 
+    // J / gJ: join `count` lines (at least two) starting at the cursor's,
+    // as one change.
+    // J drops the next line's indent and puts a single space between the
+    // two, unless the first already ends in whitespace or the second is
+    // empty or starts with ')'. gJ (`insert_space` false) joins them as is.
+    pub fn join_lines(&mut self, count: usize, insert_space: bool) {
+        self.clamp_cursor_row();
+        let row = self.cursor_position.0;
+        let undo_len = self.begin_merge();
+        for _ in 1..count.max(2) {
+            if row + 1 >= self.content.len() {
+                break;
+            }
+            let col = self.content[row].len();
+            let next = &self.content[row + 1];
+            let (removed, separator) = if insert_space {
+                let rest = next.trim_start();
+                let removed = next[..next.len() - rest.len()].to_string();
+                let needs_space = !rest.is_empty()
+                    && !rest.starts_with(')')
+                    && !self.content[row].is_empty()
+                    && !self.content[row].ends_with(char::is_whitespace);
+                (removed, if needs_space { " " } else { "" }.to_string())
+            } else {
+                (String::new(), String::new())
+            };
+
+            self.record_change(BufferChange::Join {
+                position: (row, col),
+                removed: removed.clone(),
+                separator: separator.clone(),
+            });
+            self.join_next_line(row, removed.len(), &separator);
+            self.cursor_position = (row, col);
+        }
+        self.merge_changes_since(undo_len);
+        self.update_desired_col();
+    }
+
+    // Append line `row + 1` to `row`, replacing its first `removed_len`
+    // bytes with `separator`
+    fn join_next_line(&mut self, row: usize, removed_len: usize, separator: &str) {
+        let next = self.content.remove(row + 1);
+        self.content[row].push_str(separator);
+        self.content[row].push_str(&next[removed_len..]);
+        self.mark_dirty_from(row);
+    }
+
+    // Undo a join: break the line at `col` again and restore what the
+    // next line started with
+    fn split_joined_line(&mut self, (row, col): (usize, usize), removed: &str, separator: &str) {
+        let rest = self.content[row].split_off(col);
+        self.content.insert(row + 1, format!("{}{}", removed, &rest[separator.len()..]));
+        self.mark_dirty_from(row);
+    }
+
     pub fn delete_line(&mut self) {
+        self.clamp_cursor_row();
         if self.content.len() > 1 {
-            self.content.remove(self.cursor_position.0);
+            let row = self.cursor_position.0;
+            let content = self.content.remove(row);
+            self.mark_dirty_from(row);
+            self.record_change(BufferChange::DeleteLine { position: row, content });
             if self.cursor_position.0 >= self.content.len() {
                 self.cursor_position.0 = self.content.len() - 1;
             }
             self.cursor_position.1 = 0;
         } else {
-            self.content[0].clear();
+            let content = std::mem::take(&mut self.content[0]);
+            self.mark_lines_dirty(0, 0);
+            if !content.is_empty() {
+                self.record_change(BufferChange::Delete { position: (0, 0), content });
+            }
             self.cursor_position = (0, 0);
         }
     }
@@ -719,23 +1773,83 @@ impl Buffer {
         })
     }
 
+    pub fn visual_mode(&self) -> Option<VisualMode> {
+        self.visual_mode
+    }
+
+    // Where the selection begins: its first character, or a block's
+    // top-left corner, whichever end the cursor is at
+    pub fn selection_top_left(&self) -> Option<(usize, usize)> {
+        let (start, end) = self.get_visual_selection()?;
+        Some(match self.visual_mode {
+            Some(VisualMode::Block) => (start.0.min(end.0), start.1.min(end.1)),
+            _ => start.min(end),
+        })
+    }
+
+    // Byte range of `row` that the selection covers, for drawing it. A block
+    // covers the same columns of every row it spans.
+    pub fn selection_span(&self, row: usize) -> Option<(usize, usize)> {
+        let (start, end) = self.get_visual_selection()?;
+        self.region_span(start, end, self.visual_mode.unwrap_or(VisualMode::Char), row)
+    }
+
+    // Byte range of `row` inside the region from `start` to `end` as a
+    // selection of `mode` would cover it
+    pub fn region_span(&self, start: (usize, usize), end: (usize, usize), mode: VisualMode, row: usize) -> Option<(usize, usize)> {
+        let (first, last) = (start.min(end), start.max(end));
+        if row < first.0 || row > last.0 {
+            return None;
+        }
+        let len = self.content.get(row)?.len();
+        let (from, to) = match mode {
+            VisualMode::Line => (0, len),
+            VisualMode::Block => (start.1.min(end.1), start.1.max(end.1)),
+            VisualMode::Char => (
+                if row == first.0 { first.1 } else { 0 },
+                if row == last.0 { last.1 } else { len },
+            ),
+        };
+        Some((from.min(len), to.min(len)))
+    }
+
+    // The selected text. Line-wise selections are whole lines whatever the
+    // columns, ending in a line break to mark them line-wise as vim does.
+    // A block is the slice of its columns from each row, one row per line.
     pub fn get_selected_text(&self) -> Option<String> {
         self.get_visual_selection().map(|(start, end)| {
             let start_row = start.0.min(end.0);
             let end_row = start.0.max(end.0);
+            if self.visual_mode == Some(VisualMode::Line) {
+                let end_row = end_row.min(self.content.len() - 1);
+                return self.content[start_row..=end_row].join("\n") + "\n";
+            }
+            if self.visual_mode == Some(VisualMode::Block) {
+                let end_row = end_row.min(self.content.len() - 1);
+                let rows: Vec<&str> = (start_row..=end_row)
+                    .map(|row| match self.region_span(start, end, VisualMode::Block, row) {
+                        Some((from, to)) => &self.content[row][from..to],
+                        None => "",
+                    })
+                    .collect();
+                return rows.join("\n");
+            }
             let mut selected = String::new();
 
             for row in start_row..=end_row {
+                // Columns may lie in virtual space past the end of the line
+                let line = &self.content[row];
+                let clip = |col: usize| col.min(line.len());
                 if row == start_row && row == end_row {
-                    let start_col = start.1.min(end.1);
-                    let end_col = start.1.max(end.1);
-                    selected.push_str(&self.content[row][start_col..end_col]);
+                    let start_col = clip(start.1.min(end.1));
+                    let end_col = clip(start.1.max(end.1));
+                    selected.push_str(&line[start_col..end_col]);
                 } else if row == start_row {
                     let start_col = if start.0 == start_row { start.1 } else { end.1 };
-                    selected.push_str(&self.content[row][start_col..]);
+                    selected.push_str(&line[clip(start_col)..]);
                 } else if row == end_row {
                     let end_col = if end.0 == end_row { end.1 } else { start.1 };
-                    selected.push_str(&self.content[row][..end_col]);
+                    selected.push_str(&line[..clip(end_col)]);
                 } else {
                     selected.push_str(&self.content[row]);
                 }
@@ -747,13 +1861,46 @@ impl Buffer {
         })
     }
 
-    // Buffer content access
+    // How get_selected_text's text should be pasted back
+    pub fn selection_yank_type(&self) -> YankType {
+        match self.visual_mode {
+            Some(VisualMode::Line) => YankType::Line,
+            Some(VisualMode::Block) => YankType::Block,
+            _ => YankType::Char,
+        }
+    }
+
+    // Buffer content access
     pub fn mark_lines_dirty(&mut self, start: usize, end: usize) {
+        let cache = self.render_cache.get_mut();
         for line_num in start..=end {
             self.dirty_lines.insert(line_num);
+            if let Some(cached) = cache.get_mut(line_num) {
+                *cached = None;
+            }
         }
     }
 
+    pub fn dirty_lines(&self) -> &HashSet<usize> {
+        &self.dirty_lines
+    }
+
+    pub fn dirty_from(&self) -> Option<usize> {
+        self.dirty_from
+    }
+
+    // Called once the screen has been redrawn to match the buffer
+    pub fn clear_dirty_lines(&mut self) {
+        self.dirty_lines.clear();
+        self.dirty_from = None;
+    }
+
+    // Lines were inserted or removed at `row`, so everything below it moved
+    fn mark_dirty_from(&mut self, row: usize) {
+        self.dirty_from = Some(self.dirty_from.map_or(row, |from| from.min(row)));
+        self.render_cache.get_mut().truncate(row);
+    }
+
     pub fn get_line(&self, index: usize) -> Option<&String> {
         self.content.get(index)
     }
@@ -775,19 +1922,110 @@ impl Buffer {
         &self.content
     }
 
+    // Replace the whole buffer, e.g. with a file's lines
+    pub fn set_content(&mut self, lines: Vec<String>) {
+        self.large_file = None;
+        self.content = if lines.is_empty() { vec![String::new()] } else { lines };
+        self.mark_dirty_from(0);
+        self.cursor_position = (0, 0);
+        self.desired_col = 0;
+        self.reset_history();
+    }
+
+    // Add lines after the last one without recording an undoable change
+    pub fn append_lines(&mut self, lines: Vec<String>) {
+        let len = self.content.len();
+        self.mark_dirty_from(len);
+        self.content.extend(lines);
+        self.search_outdated = Some(self.search_outdated.map_or((len, 0), |(head, _)| (head.min(len), 0)));
+    }
+
     pub fn line_count(&self) -> usize {
-        self.content.len()
+        match &self.large_file {
+            Some(file) => file.line_count(),
+            None => self.content.len(),
+        }
+    }
+
+    // Bytes the text takes written out, with a newline between lines
+    pub fn byte_len(&self) -> u64 {
+        match &self.large_file {
+            Some(file) => file.len(),
+            None => {
+                let text: usize = self.content.iter().map(String::len).sum();
+                (text + self.content.len().saturating_sub(1)) as u64
+            }
+        }
+    }
+
+    fn line_len(&self, row: usize) -> usize {
+        match &self.large_file {
+            Some(file) => file.line(row).map_or(0, |line| line.len()),
+            None => self.content.get(row).map_or(0, String::len),
+        }
+    }
+
+    // Text of a line for display, read from disk in large file mode
+    pub fn line_text(&self, row: usize) -> Option<Cow<'_, str>> {
+        match &self.large_file {
+            Some(file) => file.line(row).map(Cow::Owned),
+            None => self.content.get(row).map(|line| Cow::Borrowed(line.as_str())),
+        }
+    }
+
+    // Terminal cells taken by the text before byte column `col` of `row`.
+    // Wide characters such as CJK and most emoji take two cells.
+    pub fn display_col(&self, row: usize, col: usize) -> usize {
+        self.line_text(row).map_or(0, |line| {
+            let text = line.char_indices()
+                .take_while(|&(i, _)| i < col)
+                .fold(0, |cells, (_, c)| cells + cell_width(c, cells, self.tab_size));
+            // One cell per column of virtual space past the end
+            text + col.saturating_sub(line.len())
+        })
+    }
+
+    // Byte column of the character drawn at terminal cell `cells` of `row`
+    pub fn col_at_display(&self, row: usize, cells: usize) -> usize {
+        self.line_text(row).map_or(0, |line| {
+            let mut used = 0;
+            for (i, c) in line.char_indices() {
+                used += cell_width(c, used, self.tab_size);
+                if used > cells {
+                    return i;
+                }
+            }
+            line.len()
+        })
+    }
+
+    // Back the buffer onto a file on disk instead of owned lines. The buffer
+    // can then only be viewed, not edited.
+    pub fn open_large_file(&mut self, path: &Path) -> io::Result<()> {
+        self.large_file = Some(LargeFile::open(path)?);
+        self.content = vec![String::new()];
+        self.mark_dirty_from(0);
+        self.cursor_position = (0, 0);
+        self.desired_col = 0;
+        self.reset_history();
+        Ok(())
+    }
+
+    pub fn is_large_file(&self) -> bool {
+        self.large_file.is_some()
     }
 
     pub fn insert_at(&mut self, row: usize, content: String) {
         if row <= self.content.len() {
             self.content.insert(row, content);
+            self.mark_dirty_from(row);
         }
     }
 
     pub fn replace_line(&mut self, row: usize, content: String) {
         if row < self.content.len() {
             self.content[row] = content;
+            self.mark_lines_dirty(row, row);
         }
     }
 
@@ -796,42 +2034,142 @@ impl Buffer {
         &self.viewport
     }
 
+    pub fn set_viewport_size(&mut self, height: usize, width: usize) {
+        self.viewport.set_height(height);
+        self.viewport.set_width(width);
+    }
+
+    pub fn set_viewport_start(&mut self, start: usize) {
+        self.viewport.set_start(start.min(self.line_count().saturating_sub(1)));
+    }
+
+    // Scroll just enough to bring the cursor row back on screen
+    pub fn scroll_to_cursor(&mut self) {
+        let row = self.cursor_position.0;
+        if row < self.viewport.start {
+            self.viewport.set_start(row);
+        } else if self.viewport.height > 0 && !self.viewport.contains(row) {
+            self.viewport.set_start(row + 1 - self.viewport.height);
+        }
+    }
+
     // Search-related methods
     pub fn search(&mut self, query: &str, case_sensitive: bool) -> usize {
-        self.search_matches.clear();
+        self.search_query = (!query.is_empty()).then(|| (query.to_string(), case_sensitive));
+        self.search_outdated = None;
+        self.search_line_count = self.content.len();
+        self.search_matches = self.find_matches(query, case_sensitive, 0..self.content.len());
         self.current_match = None;
+        self.mark_dirty_from(0);
+
+        // if we found matches, select the first one
+        if !self.search_matches.is_empty() {
+            self.current_match = Some(0);
+            self.jump_to_current_match();
+        }
+
+        self.search_matches.len()
+    }
+
+    // Edits leave the matches pointing at text that has moved, so after
+    // one the last search is run again over the rows edits touched; the
+    // matches below them just move with their lines. The match `n` starts
+    // from stays the first one at or after where it was.
+    pub fn refresh_search(&mut self) {
+        let Some((head, tail)) = self.search_outdated.take() else {
+            return;
+        };
+        let Some((query, case_sensitive)) = self.search_query.clone() else {
+            return;
+        };
+        let (old_len, len) = (self.search_line_count, self.content.len());
+        let head = head.min(old_len).min(len);
+        let tail = tail.min(old_len - head).min(len - head);
+        let (old_end, end) = (old_len - tail, len - tail);
+
+        let at = self.current_match().map(|(row, col, _)| (row, col));
+        let first = self.search_matches.partition_point(|&(row, _, _)| row < head);
+        let last = self.search_matches.partition_point(|&(row, _, _)| row < old_end);
+        let moved: Vec<_> = self.search_matches.drain(last..)
+            .map(|(row, start_col, end_col)| (row - old_end + end, start_col, end_col))
+            .collect();
+        self.search_matches.truncate(first);
+        let found = self.find_matches(&query, case_sensitive, head..end);
+        self.search_matches.extend(found);
+        self.search_matches.extend(moved);
+        self.search_line_count = len;
+
+        self.current_match = at
+            .filter(|_| !self.search_matches.is_empty())
+            .map(|at| self.search_matches.partition_point(|&(row, col, _)| (row, col) < at))
+            .map(|index| if index == self.search_matches.len() { 0 } else { index });
+        if head < end {
+            self.mark_lines_dirty(head, end - 1);
+        }
+    }
 
+    // Every match of `query` in `rows` as (row, start_col, end_col), in order
+    fn find_matches(&self, query: &str, case_sensitive: bool, rows: Range<usize>) -> Vec<(usize, usize, usize)> {
+        let mut matches = Vec::new();
         if query.is_empty() {
-            return 0;
+            return matches;
         }
 
-        for (row, line) in self.content.iter().enumerate() {
+        let query_to_search = if case_sensitive {
+            query.to_string()
+        } else {
+            query.to_lowercase()
+        };
+        for (row, line) in self.content.iter().enumerate().take(rows.end).skip(rows.start) {
             let line_to_search = if case_sensitive {
                 line.to_string()
             } else {
                 line.to_lowercase()
             };
-            let query_to_search = if case_sensitive {
-                query.to_string()
-            } else {
-                query.to_lowercase()
-            };
 
             let mut start_idx = 0;
             while let Some(found_idx) = line_to_search[start_idx..].find(&query_to_search) {
                 let abs_idx = start_idx + found_idx;
-                self.search_matches.push((row, abs_idx, abs_idx + query.len()));
+                matches.push((row, abs_idx, abs_idx + query.len()));
                 start_idx = abs_idx + 1;
             }
         }
+        matches
+    }
 
-        // if we found matches, select the first one
-        if !self.search_matches.is_empty() {
-            self.current_match = Some(0);
-            self.jump_to_current_match();
-        }
+    // `/` and `?`: search for `query` and jump to the nearest match after
+    // the cursor, or before it when searching backward, wrapping around
+    // the ends of the buffer
+    pub fn search_from_cursor(&mut self, query: &str, case_sensitive: bool, forward: bool) -> usize {
+        let cursor = self.cursor_position;
+        let count = self.search(query, case_sensitive);
+        self.search_forward = forward;
+        self.cursor_position = cursor;
+        self.select_match_from_cursor(forward);
+        count
+    }
 
-        self.search_matches.len()
+    // `n` repeats the last search in its own direction and `N` in the
+    // other one. False if there is nothing to repeat.
+    pub fn repeat_search(&mut self, reverse: bool) -> bool {
+        self.select_match_from_cursor(self.search_forward != reverse)
+    }
+
+    fn select_match_from_cursor(&mut self, forward: bool) -> bool {
+        let count = self.search_matches.len();
+        if count == 0 {
+            return false;
+        }
+        let cursor = self.cursor_position;
+        let at = |&(row, col, _): &(usize, usize, usize)| (row, col);
+        let index = if forward {
+            self.search_matches.iter().position(|m| at(m) > cursor).unwrap_or(0)
+        } else {
+            self.search_matches.iter().rposition(|m| at(m) < cursor).unwrap_or(count - 1)
+        };
+        self.current_match = Some(index);
+        self.jump_to_current_match();
+        true
     }
 
     pub fn next_match(&mut self) -> bool {
@@ -864,72 +2202,169 @@ impl Buffer {
         }
     }
 
+    // The match `n` would start from, as (row, start_col, end_col)
+    pub fn current_match(&self) -> Option<(usize, usize, usize)> {
+        self.search_matches.get(self.current_match?).copied()
+    }
+
+    // Which match the cursor is on and how many there are, counting from 1
+    pub fn search_position(&self) -> Option<(usize, usize)> {
+        self.current_match.map(|current| (current + 1, self.search_matches.len()))
+    }
+
+    // Make `found` the only match, e.g. the one `:s///c` is asking about
+    pub fn set_current_match(&mut self, found: Option<(usize, usize, usize)>) {
+        self.search_query = None;
+        let old = std::mem::replace(&mut self.search_matches, found.into_iter().collect());
+        for &(row, _, _) in old.iter().chain(&found) {
+            self.mark_lines_dirty(row, row);
+        }
+        self.current_match = found.map(|_| 0);
+    }
+
     pub fn clear_search(&mut self) {
+        self.search_query = None;
         self.search_matches.clear();
         self.current_match = None;
+        self.mark_dirty_from(0);
+    }
+
+    // Byte ranges of misspelled words in `row`. Only rows being drawn are
+    // checked, so this is empty while spell checking is off.
+    pub fn misspellings(&self, row: usize) -> Vec<(usize, usize)> {
+        match (&self.spell, self.line_text(row)) {
+            (Some(dictionary), Some(line)) => dictionary.misspellings(&line),
+            _ => Vec::new(),
+        }
+    }
+
+    // ]s / [s: move to the next or previous misspelled word, wrapping around
+    // the ends of the buffer
+    pub fn move_to_misspelling(&mut self, forward: bool) -> bool {
+        let (row, col) = self.cursor_position;
+        let lines = self.line_count();
+        // Step i visits the cursor row again at i == lines, for words
+        // on the far side of the cursor
+        let found = (0..=lines).find_map(|i| {
+            let r = if forward { (row + i) % lines } else { (row + lines - i % lines) % lines };
+            let spans = self.misspellings(r);
+            let start = match (i, forward) {
+                (0, true) => spans.iter().map(|&(start, _)| start).find(|&start| start > col),
+                (0, false) => spans.iter().map(|&(start, _)| start).rfind(|&start| start < col),
+                (_, true) => spans.first().map(|&(start, _)| start),
+                (_, false) => spans.last().map(|&(start, _)| start),
+            };
+            start.map(|start| (r, start))
+        });
+
+        match found {
+            Some((row, col)) => {
+                self.set_cursor_position(row, col);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // z=: the word under the cursor with replacements for it, if spell
+    // checking is on
+    pub fn spell_suggestions(&self, limit: usize) -> Option<(String, Vec<String>)> {
+        let dictionary = self.spell.as_ref()?;
+        let (start, end) = self.spell_word_at_cursor()?;
+        let word = self.content[self.cursor_position.0][start..end].to_string();
+        let suggestions = dictionary.suggest(&word, limit);
+        Some((word, suggestions))
+    }
+
+    // Replace the word under the cursor as one change, leaving the cursor at
+    // its start
+    pub fn replace_spell_word(&mut self, text: &str) -> bool {
+        let Some((start, end)) = self.spell_word_at_cursor() else {
+            return false;
+        };
+        self.cursor_position.1 = end;
+        self.replace_before_cursor(end - start, text);
+        self.cursor_position.1 = start;
+        self.update_desired_col();
+        true
+    }
+
+    fn spell_word_at_cursor(&self) -> Option<(usize, usize)> {
+        let (row, col) = self.cursor_position;
+        let line = self.content.get(row)?;
+        super::spell::words(line).find(|&(start, end)| start <= col && col < end)
+    }
+
+    // Line number gutter: enough digits for the last line (min 3) plus " │ "
+    pub fn gutter_width(&self) -> usize {
+        self.gutter_digits() + 3
+    }
+
+    fn gutter_digits(&self) -> usize {
+        self.line_count().to_string().len().max(3)
+    }
+
+    pub fn format_line_number(&self, row: usize) -> String {
+        format!("{:>width$} │ ", row + 1, width = self.gutter_digits())
     }
 
     // Rendering
     pub fn render_lines(&self) -> Vec<String> {
-        let mut rendered = self.content.clone();
-
-        // Add search highlighting
-        for (row, line) in rendered.iter_mut().enumerate() {
-            let mut offset = 0;
-            let matches_in_line: Vec<_> = self.search_matches.iter()
-                .filter(|&&(match_row, _, _)| match_row == row)
-                .collect();
-
-            for &(_, start_col, end_col) in matches_in_line {
-                let start_idx = start_col + offset;
-                let end_idx = end_col + offset;
-                let highlight = if Some(start_col) == self.current_match.map(|i| self.search_matches[i].1) {
-                    "\x1b[43m" // Yellow background for current match
-                } else {
-                    "\x1b[42m" // Green background for other matches
-                };
-                let highlighted = format!("{}{}\x1b[0m",
-                    highlight,
-                    &line[start_col..end_col]
-                );
-                line.replace_range(start_idx..end_idx, &highlighted);
-                offset += highlighted.len() - (end_col - start_col);
-            }
+        (0..self.content.len())
+            .map(|row| format!("{}{}", self.format_line_number(row), self.highlighted_line(row)))
+            .collect()
+    }
+
+    // Search-highlighted text of `row`. Lines are only re-highlighted after
+    // being marked dirty; the cursor's line is always redone since it may hold
+    // the current match.
+    fn highlighted_line(&self, row: usize) -> String {
+        if row == self.cursor_position.0 {
+            return self.highlight_search(row);
         }
+        let mut cache = self.render_cache.lock();
+        if cache.len() < self.content.len() {
+            cache.resize(self.content.len(), None);
+        }
+        cache[row]
+            .get_or_insert_with(|| self.highlight_search(row))
+            .clone()
+    }
 
-        rendered
+    fn highlight_search(&self, row: usize) -> String {
+        let line = &self.content[row];
+        let first = self.search_matches.partition_point(|&(match_row, _, _)| match_row < row);
+        let mut highlighted = String::with_capacity(line.len());
+        let mut last = 0;
+
+        for &(_, start_col, end_col) in self.search_matches[first..]
             .iter()
-            .enumerate()
-            .map(|(i, line)| format!("{:4} | {}", i + 1, line))
-            .collect()
+            .take_while(|&&(match_row, _, _)| match_row == row)
+        {
+            // Overlapping matches ("aa" in "aaa") are already covered
+            if start_col < last {
+                continue;
+            }
+            let highlight = if (row, start_col) == self.cursor_position {
+                "\x1b[43m" // Yellow background for the match under the cursor
+            } else {
+                "\x1b[42m" // Green background for other matches
+            };
+            highlighted.push_str(&line[last..start_col]);
+            highlighted.push_str(highlight);
+            highlighted.push_str(&line[start_col..end_col]);
+            highlighted.push_str("\x1b[0m");
+            last = end_col;
+        }
+        highlighted.push_str(&line[last..]);
+        highlighted
     }
 
     pub fn render_lines_with_visual(&self) -> Vec<String> {
-        let mut rendered = self.content.clone();
-        
         // First, apply search highlighting
-        for (row, line) in rendered.iter_mut().enumerate() {
-            let mut offset = 0;
-            let matches_in_line: Vec<_> = self.search_matches.iter()
-                .filter(|&&(match_row, _, _)| match_row == row)
-                .collect();
-
-            for &(_, start_col, end_col) in matches_in_line {
-                let start_idx = start_col + offset;
-                let end_idx = end_col + offset;
-                let highlight = if Some(start_col) == self.current_match.map(|i| self.search_matches[i].1) {
-                    "\x1b[43m" // Yellow background for current match
-                } else {
-                    "\x1b[42m" // Green background for other matches
-                };
-                let highlighted = format!("{}{}\x1b[0m", 
-                    highlight,
-                    &line[start_col..end_col]
-                );
-                line.replace_range(start_idx..end_idx, &highlighted);
-                offset += highlighted.len() - (end_col - start_col);
-            }
-        }
+        let mut rendered: Vec<String> = (0..self.content.len())
+            .map(|row| self.highlighted_line(row))
+            .collect();
         
         // Then apply visual selection highlighting
         if let Some((start_row, start_col)) = self.visual_start {
@@ -971,11 +2406,19 @@ impl Buffer {
         rendered
             .iter()
             .enumerate()
-            .map(|(i, line)| format!("{:4} | {}", i + 1, line))
+            .map(|(i, line)| format!("{}{}", self.format_line_number(i), line))
             .collect()
     }
 
     // Visual mode management
+    // Select with `mode`, starting at the cursor unless already selecting
+    pub fn set_visual_mode(&mut self, mode: VisualMode) {
+        self.visual_mode = Some(mode);
+        if self.visual_start.is_none() {
+            self.start_visual();
+        }
+    }
+
     pub fn toggle_visual_mode(&mut self, mode: VisualMode) {
         match self.visual_mode {
             Some(current_mode) if current_mode == mode => {
@@ -993,33 +2436,73 @@ impl Buffer {
     }
 
     pub fn clear_visual(&mut self) {
+        if let Some((start, end)) = self.get_visual_selection() {
+            self.last_visual_rows = Some((start.0.min(end.0), start.0.max(end.0)));
+        }
         self.visual_start = None;
         self.visual_mode = None;
         self.visual_bounds = None;
         self.selection_type = None;
+        if !self.virtual_cursor() {
+            self.clamp_virtual_cursor();
+        }
     }
 
     // Selection operations
     fn delete_char_selection(&mut self, start: (usize, usize), end: (usize, usize)) {
-        let start_row = start.0.min(end.0);
-        let end_row = start.0.max(end.0);
-        let start_col = start.1.min(end.1);
-        let end_col = start.1.max(end.1);
+        let ((start_row, start_col), (end_row, end_col)) = (start.min(end), start.max(end));
+        // Columns may lie in virtual space past the end of the line
+        let start_col = start_col.min(self.content[start_row].len());
+        let end_col = end_col.min(self.content[end_row].len());
 
         if start_row == end_row {
             // Single line selection
             let line = &mut self.content[start_row];
-            line.replace_range(start_col..end_col, "");
+            let content: String = line.drain(start_col..end_col).collect();
+            self.mark_lines_dirty(start_row, start_row);
+            self.cursor_position = (start_row, start_col);
+            self.record_change(BufferChange::Delete { position: (start_row, start_col), content });
+        } else {
+            // Cut the tail of the first line, any whole lines in between and
+            // the head of the last, then join what's left of the two
+            let mut changes = Vec::new();
+            let tail: String = self.content[start_row].drain(start_col..).collect();
+            changes.push(BufferChange::Delete { position: (start_row, start_col), content: tail });
+            for row in (start_row + 1..end_row).rev() {
+                let content = self.content.remove(row);
+                changes.push(BufferChange::DeleteLine { position: row, content });
+            }
+            let head: String = self.content[start_row + 1].drain(..end_col).collect();
+            changes.push(BufferChange::Delete { position: (start_row + 1, 0), content: head });
+            self.join_next_line(start_row, 0, "");
+            changes.push(BufferChange::Join {
+                position: (start_row, start_col),
+                removed: String::new(),
+                separator: String::new(),
+            });
+
             self.cursor_position = (start_row, start_col);
+            self.record_change_group(changes);
         }
+        self.update_desired_col();
     }
 
     fn delete_line_selection(&mut self, start_row: usize, end_row: usize) {
         let start = start_row.min(end_row);
         let end = start_row.max(end_row);
 
-        // Remove lines in the range
-        self.content.drain(start..=end);
+        // Remove lines in the range, bottom up so each row is where undo
+        // will put it back
+        let mut changes: Vec<BufferChange> = (start..=end).rev()
+            .map(|row| BufferChange::DeleteLine { position: row, content: self.content.remove(row) })
+            .collect();
+        // The buffer always keeps at least one line
+        if self.content.is_empty() {
+            self.content.push(String::new());
+            changes.push(BufferChange::InsertLine { position: 0, content: String::new() });
+        }
+        self.mark_dirty_from(start);
+        self.record_change_group(changes);
 
         // Adjust cursor position
         self.cursor_position.0 = start.min(self.content.len() - 1);
@@ -1027,21 +2510,31 @@ impl Buffer {
     }
 
     fn delete_block_selection(&mut self, start: (usize, usize), end: (usize, usize)) {
-        let start_row = start.0.min(end.0);
-        let end_row = start.0.max(end.0);
+        let last = self.content.len() - 1;
+        let start_row = start.0.min(end.0).min(last);
+        let end_row = start.0.max(end.0).min(last);
         let start_col = start.1.min(end.1);
         let end_col = start.1.max(end.1);
 
-        // Delete block-wise selection
+        // Delete block-wise selection; lines that end before the block's
+        // left edge are left alone
+        let mut changes = Vec::new();
         for row in start_row..=end_row {
             let line = &mut self.content[row];
             if start_col < line.len() {
                 let actual_end_col = end_col.min(line.len());
-                line.replace_range(start_col..actual_end_col, "");
+                let content: String = line.drain(start_col..actual_end_col).collect();
+                changes.push(BufferChange::Delete { position: (row, start_col), content });
             }
         }
+        if !changes.is_empty() {
+            self.record_change_group(changes);
+        }
+        self.mark_lines_dirty(start_row, end_row);
 
-        self.cursor_position = (start_row, start_col);
+        let col = start_col.min(self.content[start_row].len());
+        self.cursor_position = (start_row, col);
+        self.update_desired_col();
     }
 
     pub fn delete_selection(&mut self) -> bool {
@@ -1058,122 +2551,316 @@ impl Buffer {
     }
 
     pub fn insert_at_cursor(&mut self, content: &str) {
-        let current_line = &mut self.content[self.cursor_position.0];
-        current_line.insert_str(self.cursor_position.1, content);
-        self.cursor_position.1 += content.len();
+        self.insert_text(content);
     }
 
     pub fn insert_lines_at(&mut self, row: usize, content: &str) {
         // Split content into lines and insert them
         let lines: Vec<&str> = content.split('\n').collect();
+        let mut changes = Vec::new();
         for (i, line) in lines.iter().enumerate() {
             self.content.insert(row + i, line.to_string());
+            changes.push(BufferChange::InsertLine { position: row + i, content: line.to_string() });
         }
+        self.mark_dirty_from(row);
+        self.record_change_group(changes);
         self.cursor_position = (row + lines.len() - 1, 0);
     }
 
+    // Insert each line of `content` at the same column on successive rows
     pub fn insert_block_at(&mut self, start: (usize, usize), content: &str) {
+        let (start_row, start_col) = start;
         let lines: Vec<&str> = content.split('\n').collect();
-        let start_row = start.0;
-        let start_col = start.1;
+
+        // A block running past the last line adds new ones
+        let mut changes = Vec::new();
+        let end_row = start_row + lines.len() - 1;
+        if end_row >= self.content.len() {
+            self.mark_dirty_from(self.content.len());
+            for row in self.content.len()..=end_row {
+                changes.push(BufferChange::InsertLine { position: row, content: String::new() });
+            }
+            self.content.resize(end_row + 1, String::new());
+        }
 
         for (i, line) in lines.iter().enumerate() {
             let row = start_row + i;
-            if row < self.content.len() {
-                let current_line = &mut self.content[row];
-                
-                // Ensure the line is long enough to insert at start_col
-                if current_line.len() < start_col {
-                    current_line.push_str(&" ".repeat(start_col - current_line.len()));
-                }
-
-                current_line.insert_str(start_col, line);
-            }
+            let current_line = &mut self.content[row];
+
+            // Pad short lines out to the block's column
+            let padding = " ".repeat(start_col.saturating_sub(current_line.len()));
+            let position = (row, start_col - padding.len());
+            current_line.push_str(&padding);
+            current_line.insert_str(start_col, line);
+            changes.push(BufferChange::Insert { position, content: format!("{}{}", padding, line) });
         }
+        self.mark_lines_dirty(start_row, end_row);
+        self.record_change_group(changes);
 
         self.cursor_position = (start_row, start_col);
     }
 
     pub fn paste_over_selection(&mut self) {
         // First, extract the content and visual selection before any mutations
-        let content = self.clipboard.as_ref().and_then(|c| c.peek().cloned());
+        let entry = self.clipboard.as_ref().and_then(|c| c.peek_entry(0).cloned());
         let visual_selection = self.get_visual_selection();
         let visual_mode = self.visual_mode.unwrap_or(VisualMode::Char);
     
         // Now perform mutations
-        if let (Some(content), Some((start, end))) = (content, visual_selection) {
+        if let (Some(entry), Some((start, end))) = (entry, visual_selection) {
+            let undo_len = self.begin_merge();
+            let content = entry.text;
             // Delete the selection
             self.delete_selection();
-            
-            // Then paste the content
-            match visual_mode {
-                VisualMode::Char => {
-                    let current_line = &mut self.content[self.cursor_position.0];
-                    current_line.insert_str(self.cursor_position.1, &content);
-                    self.cursor_position.1 += content.len();
-                },
-                VisualMode::Line => {
+
+            // Charwise text goes where the selection began, whichever end
+            // the cursor was at and however many lines it spanned
+            if visual_mode == VisualMode::Char {
+                let (row, col) = start.min(end);
+                self.cursor_position = (row, col.min(self.line_len(row)));
+            }
+
+            // Then paste the content: line-wise text stays on lines of its
+            // own and a block keeps its shape whatever was selected
+            match (visual_mode, entry.kind) {
+                (VisualMode::Char, YankType::Char) => self.paste_at_cursor(&content),
+                (VisualMode::Char, YankType::Line) => self.paste_at_cursor(&format!("\n{}", content)),
+                (VisualMode::Char, YankType::Block) => {
+                    let position = self.cursor_position;
+                    self.insert_block_at(position, &content);
+                }
+                (VisualMode::Line, _) => {
                     // Split content into lines and insert at the start row
-                    let lines: Vec<&str> = content.split('\n').collect();
-                    for (i, line) in lines.iter().enumerate() {
-                        self.content.insert(start.0 + i, line.to_string());
-                    }
-                    self.cursor_position = (start.0 + lines.len() - 1, 0);
+                    let lines = content.strip_suffix('\n').unwrap_or(&content);
+                    self.insert_lines_at(start.0.min(end.0), lines);
                 },
-                VisualMode::Block => {
-                    let lines: Vec<&str> = content.split('\n').collect();
-                    let start_row = start.0;
-                    let start_col = start.1;
-    
-                    for (i, line) in lines.iter().enumerate() {
-                        let row = start_row + i;
-                        if row < self.content.len() {
-                            let current_line = &mut self.content[row];
-                            
-                            // Ensure the line is long enough to insert at start_col
-                            if current_line.len() < start_col {
-                                current_line.push_str(&" ".repeat(start_col - current_line.len()));
-                            }
-    
-                            current_line.insert_str(start_col, line);
-                        }
-                    }
-    
-                    self.cursor_position = (start_row, start_col);
+                (VisualMode::Block, _) => {
+                    // The selection may have been made from any corner
+                    let top_left = (start.0.min(end.0), start.1.min(end.1));
+                    let height = start.0.abs_diff(end.0) + 1;
+
+                    // A single yanked line is repeated on every row of the block
+                    let content = match content.split_once('\n') {
+                        None if height > 1 => vec![content.as_str(); height].join("\n"),
+                        _ => content,
+                    };
+                    self.insert_block_at(top_left, &content);
                 }
             }
+            self.merge_changes_since(undo_len);
         }
     }
 
     // Indentation operations
-    pub fn indent_selection(&mut self, size: usize) {
+    // Indent the selected lines by `levels` steps of `size`. The selection
+    // stays active and follows the text, so `>` can be pressed again.
+    pub fn indent_selection(&mut self, size: usize, levels: usize) {
         if let Some((start, end)) = self.get_visual_selection() {
-            let start_row = start.0.min(end.0);
-            let end_row = start.0.max(end.0);
-            
-            for row in start_row..=end_row {
-                let spaces = " ".repeat(size);
-                self.content[row].insert_str(0, &spaces);
-            }
+            let indent_len = self.indent_lines(start.0.min(end.0), start.0.max(end.0), size, levels);
+            self.shift_selection(|_| indent_len as isize);
         }
     }
 
-    pub fn dedent_selection(&mut self, size: usize) {
+    pub fn dedent_selection(&mut self, size: usize, levels: usize) {
         if let Some((start, end)) = self.get_visual_selection() {
-            let start_row = start.0.min(end.0);
-            let end_row = start.0.max(end.0);
-            
-            for row in start_row..=end_row {
-                let whitespace_count = self.content[row]
-                    .chars()
-                    .take_while(|c| c.is_whitespace())
-                    .count();
-                let remove_count = whitespace_count.min(size);
-                if remove_count > 0 {
-                    self.content[row].replace_range(0..remove_count, "");
+            let removed = self.dedent_lines(start.0.min(end.0), start.0.max(end.0), size, levels);
+            self.shift_selection(|row| -(removed[&row] as isize));
+        }
+    }
+
+    // Indent rows `start_row..=end_row` as one change, returning the
+    // length of the indent added to each
+    pub fn indent_lines(&mut self, start_row: usize, end_row: usize, size: usize, levels: usize) -> usize {
+        let indent = self.indent_unit(size).repeat(levels);
+        let mut changes = Vec::new();
+        for row in start_row..=end_row {
+            self.content[row].insert_str(0, &indent);
+            changes.push(BufferChange::Insert { position: (row, 0), content: indent.clone() });
+        }
+        self.record_change_group(changes);
+        self.mark_lines_dirty(start_row, end_row);
+        indent.len()
+    }
+
+    // Dedent rows `start_row..=end_row` as one change, returning how many
+    // bytes came off the front of each
+    pub fn dedent_lines(&mut self, start_row: usize, end_row: usize, size: usize, levels: usize) -> HashMap<usize, usize> {
+        let mut removed = HashMap::new();
+        let mut changes = Vec::new();
+        for row in start_row..=end_row {
+            let mut remove_count = 0;
+            for _ in 0..levels {
+                remove_count += Self::dedent_amount(&self.content[row][remove_count..], size);
+            }
+            if remove_count > 0 {
+                let content = self.content[row][..remove_count].to_string();
+                self.content[row].replace_range(0..remove_count, "");
+                changes.push(BufferChange::Delete { position: (row, 0), content });
+            }
+            removed.insert(row, remove_count);
+        }
+        if !changes.is_empty() {
+            self.record_change_group(changes);
+        }
+        self.mark_lines_dirty(start_row, end_row);
+        removed
+    }
+
+    // First and last row of the most recent visual selection
+    pub fn last_visual_rows(&self) -> Option<(usize, usize)> {
+        self.last_visual_rows
+    }
+
+    // :d - delete rows `start_row..=end_row`
+    pub fn delete_lines(&mut self, start_row: usize, end_row: usize) {
+        self.delete_line_selection(start_row, end_row);
+    }
+
+    // :sort - sort rows `start_row..=end_row`
+    pub fn sort_lines(&mut self, start_row: usize, end_row: usize) {
+        let mut lines = self.content[start_row..=end_row].to_vec();
+        lines.sort();
+        self.replace_lines(start_row, lines);
+    }
+
+    // Ctrl-A and Ctrl-X: add `delta` to the number under or after the
+    // cursor, leaving the cursor on its last digit. False if there's none.
+    pub fn modify_number_at_cursor(&mut self, delta: i64) -> bool {
+        self.clamp_virtual_cursor();
+        let (row, col) = self.cursor_position;
+        let line = &self.content[row];
+        // Start from the beginning of a number the cursor is inside
+        let from = line[..col].trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let Some((start, end, value)) = find_number(line, from, line.len()) else {
+            return false;
+        };
+        let number = value.saturating_add(delta).to_string();
+        self.replace_text((row, start), end - start, &number);
+        self.cursor_position.1 = start + number.len() - 1;
+        self.update_desired_col();
+        true
+    }
+
+    // Ctrl-A and Ctrl-X over a selection: add `delta` to the first number in
+    // the selected part of each row. `progressive` (g Ctrl-A) adds it once
+    // for the first row with a number, twice for the second and so on,
+    // turning a column of equal numbers into a sequence. One undo step;
+    // returns how many numbers changed.
+    pub fn increment_selection(&mut self, delta: i64, progressive: bool) -> usize {
+        let (Some((start, end)), Some(mode)) = (self.get_visual_selection(), self.visual_mode) else {
+            return 0;
+        };
+        let (first, last) = (start.0.min(end.0), start.0.max(end.0).min(self.content.len() - 1));
+        let mut count = 0;
+        let lines = (first..=last)
+            .map(|row| {
+                let line = &self.content[row];
+                let number = self.region_span(start, end, mode, row)
+                    .and_then(|(from, to)| find_number(line, from, to));
+                let Some((from, to, value)) = number else {
+                    return line.clone();
+                };
+                count += 1;
+                let add = if progressive { delta.saturating_mul(count) } else { delta };
+                format!("{}{}{}", &line[..from], value.saturating_add(add), &line[to..])
+            })
+            .collect();
+        let top_left = self.selection_top_left();
+        self.replace_lines(first, lines);
+        if let Some((row, col)) = top_left {
+            self.set_cursor_position(row, col);
+        }
+        count as usize
+    }
+
+    // :align over rows `start_row..=end_row`: pad the text before the first
+    // `delimiter` on each row so that they all line up in one column, a
+    // space past the longest unless none had a space before it. Rows
+    // without it are left alone. Returns how many rows have it.
+    pub fn align_lines(&mut self, start_row: usize, end_row: usize, delimiter: &str) -> usize {
+        if delimiter.is_empty() {
+            return 0;
+        }
+        let tab_size = self.tab_size;
+        let cells = |text: &str| text.chars().fold(0, |used, c| used + cell_width(c, used, tab_size));
+        let splits: Vec<Option<(&str, usize, &str)>> = self.content[start_row..=end_row].iter()
+            .map(|line| {
+                let (before, after) = line.split_at(line.find(delimiter)?);
+                let trimmed = before.trim_end();
+                let gap = usize::from(trimmed.len() < before.len());
+                Some((trimmed, cells(trimmed) + gap, after))
+            })
+            .collect();
+        let column = splits.iter().flatten().map(|&(_, width, _)| width).max().unwrap_or(0);
+        let count = splits.iter().flatten().count();
+        let lines = splits.into_iter()
+            .zip(&self.content[start_row..=end_row])
+            .map(|(split, line)| match split {
+                Some((before, _, after)) => {
+                    format!("{}{}{}", before, " ".repeat(column - cells(before)), after)
+                }
+                None => line.clone(),
+            })
+            .collect();
+        self.replace_lines(start_row, lines);
+        self.clamp_virtual_cursor();
+        count
+    }
+
+    // :s/pattern/replacement/ over rows `start_row..=end_row`: swap the
+    // first literal occurrence of `pattern` on each row, or all of them
+    // with `global`. Returns how many were replaced, leaving the cursor on
+    // the last row changed.
+    pub fn substitute(&mut self, start_row: usize, end_row: usize, pattern: &str, replacement: &str, global: bool) -> usize {
+        if pattern.is_empty() {
+            return 0;
+        }
+        let mut count = 0;
+        let mut last_row = None;
+        let lines = self.content[start_row..=end_row].iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let found = if global { line.matches(pattern).count() } else { usize::from(line.contains(pattern)) };
+                if found > 0 {
+                    count += found;
+                    last_row = Some(start_row + i);
                 }
+                let limit = if global { usize::MAX } else { 1 };
+                line.replacen(pattern, replacement, limit)
+            })
+            .collect();
+        self.replace_lines(start_row, lines);
+        if let Some(row) = last_row {
+            self.set_cursor_position(row, 0);
+        }
+        count
+    }
+
+    // Overwrite whole rows from `start_row` on with `lines`, as one change
+    fn replace_lines(&mut self, start_row: usize, lines: Vec<String>) {
+        let mut changes = Vec::new();
+        for (i, new) in lines.into_iter().enumerate() {
+            let row = start_row + i;
+            if self.content[row] != new {
+                let old = std::mem::replace(&mut self.content[row], new.clone());
+                self.mark_lines_dirty(row, row);
+                changes.push(BufferChange::Replace { position: (row, 0), old, new });
             }
         }
+        if !changes.is_empty() {
+            self.record_change_group(changes);
+        }
+    }
+
+    // Move both ends of the selection along their rows by `delta(row)`
+    // bytes, after indentation changed under them
+    fn shift_selection(&mut self, delta: impl Fn(usize) -> isize) {
+        let shift = |(row, col): (usize, usize)| (row, col.saturating_add_signed(delta(row)));
+        self.cursor_position = shift(self.cursor_position);
+        if let Some(start) = self.visual_start.as_mut() {
+            *start = shift(*start);
+        }
+        self.update_desired_col();
     }
 
     // Text object selection helpers
@@ -1190,20 +2877,27 @@ impl Buffer {
     }
 
     pub fn select_paragraph(&mut self, selection_type: SelectionType) {
-        let row = self.cursor_position.0;
-        let start_row = self.find_paragraph_start(row);
-        let end_row = self.find_paragraph_end(row);
-        
-        match selection_type {
-            SelectionType::Inner => {
-                self.visual_start = Some((start_row + 1, 0));
-                self.cursor_position = (end_row - 1, self.content[end_row - 1].len());
-            }
-            SelectionType::Around => {
-                self.visual_start = Some((start_row, 0));
-                self.cursor_position = (end_row, 0);
+        let row = self.cursor_position.0.min(self.content.len() - 1);
+        let mut start_row = self.find_paragraph_start(row);
+        let mut end_row = self.find_paragraph_end(row);
+
+        // `ap` takes in the blank lines after the paragraph, or the ones
+        // before it when it ends the file
+        if selection_type == SelectionType::Around {
+            let is_blank = |row: usize| self.content[row].trim().is_empty();
+            if end_row + 1 < self.content.len() {
+                while end_row + 1 < self.content.len() && is_blank(end_row + 1) {
+                    end_row += 1;
+                }
+            } else {
+                while start_row > 0 && is_blank(start_row - 1) {
+                    start_row -= 1;
+                }
             }
         }
+
+        self.visual_start = Some((start_row, 0));
+        self.cursor_position = (end_row, self.content[end_row].len());
     }
 
     // Bracket selection helpers
@@ -1223,44 +2917,58 @@ impl Buffer {
     }
 
     // Helper methods for finding text object bounds
+    // Byte range of the run of characters like the one at `col`: a word,
+    // a run of punctuation or, as with vim's `iw`, a run of whitespace. A
+    // cursor past the end of the line counts as being on its last character.
     fn find_word_bounds(&self, line: &str, col: usize) -> (usize, usize) {
-        let chars: Vec<char> = line.chars().collect();
-        let mut start = col;
-        let mut end = col;
-
-        // Move backward to word start
-        while start > 0 && chars[start - 1].is_alphanumeric() {
-            start -= 1;
-        }
-
-        // Move forward to word end
-        while end < chars.len() && chars[end].is_alphanumeric() {
-            end += 1;
-        }
-
+        let Some((col, c)) = line.char_indices().take_while(|&(i, _)| i <= col).last() else {
+            return (0, 0);
+        };
+        let class = char_class(c);
+
+        let start = line[..col].char_indices()
+            .rev()
+            .take_while(|&(_, c)| char_class(c) == class)
+            .last()
+            .map_or(col, |(i, _)| i);
+        let end = Self::run_end(line, col, class);
         (start, end)
     }
 
+    // `aw`: the word and the whitespace after it, or before it if there's
+    // none after. On whitespace, the whitespace and the word that follows.
     fn find_word_bounds_with_spaces(&self, line: &str, col: usize) -> (usize, usize) {
         let (start, end) = self.find_word_bounds(line, col);
-        let chars: Vec<char> = line.chars().collect();
-        
-        let mut space_start = start;
-        let mut space_end = end;
+        let Some(c) = line[start..].chars().next() else {
+            return (start, end);
+        };
 
-        // Include leading spaces
-        while space_start > 0 && chars[space_start - 1].is_whitespace() {
-            space_start -= 1;
+        if c.is_whitespace() {
+            let word_end = line[end..].chars().next()
+                .map_or(end, |next| Self::run_end(line, end, char_class(next)));
+            return (start, word_end);
         }
-
-        // Include trailing spaces
-        while space_end < chars.len() && chars[space_end].is_whitespace() {
-            space_end += 1;
+        let space_end = Self::run_end(line, end, CharClass::Space);
+        if space_end > end {
+            return (start, space_end);
         }
+        let space_start = line[..start].char_indices()
+            .rev()
+            .take_while(|&(_, c)| c.is_whitespace())
+            .last()
+            .map_or(start, |(i, _)| i);
+        (space_start, end)
+    }
 
-        (space_start, space_end)
+    // End of the run of `class` characters starting at byte `col`
+    fn run_end(line: &str, col: usize, class: CharClass) -> usize {
+        col + line[col..].chars()
+            .take_while(|&c| char_class(c) == class)
+            .map(char::len_utf8)
+            .sum::<usize>()
     }
 
+    // First row of the paragraph containing `row`
     fn find_paragraph_start(&self, row: usize) -> usize {
         let mut start = row;
         while start > 0 && !self.content[start - 1].trim().is_empty() {
@@ -1269,12 +2977,13 @@ impl Buffer {
         start
     }
 
+    // Last row of the paragraph containing `row`
     fn find_paragraph_end(&self, row: usize) -> usize {
         let mut end = row;
-        while end < self.content.len() - 1 && !self.content[end + 1].trim().is_empty() {
+        while end + 1 < self.content.len() && !self.content[end + 1].trim().is_empty() {
             end += 1;
         }
-        end + 1
+        end
     }
 
     fn find_matching_pair(&self, open: char, close: char) -> Option<((usize, usize), (usize, usize))> {
@@ -1308,76 +3017,1175 @@ impl Buffer {
         None
     }
 
-    // Selection bounds storage for search operations
-    pub fn store_visual_bounds(&mut self) {
-        self.visual_bounds = self.get_visual_selection();
+    // Jump list
+    pub fn push_jump(&mut self) {
+        self.jumps_back.push(self.cursor_position);
+        self.jumps_forward.clear();
+    }
+
+    pub fn jump_back(&mut self) -> bool {
+        if let Some((row, col)) = self.jumps_back.pop() {
+            self.jumps_forward.push(self.cursor_position);
+            self.set_cursor_position(row, col);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn jump_forward(&mut self) -> bool {
+        if let Some((row, col)) = self.jumps_forward.pop() {
+            self.jumps_back.push(self.cursor_position);
+            self.set_cursor_position(row, col);
+            true
+        } else {
+            false
+        }
+    }
+
+    // gd - jump to where the word under the cursor appears to be defined:
+    // its first occurrence after a definition keyword before the cursor,
+    // otherwise its first occurrence in the file
+    pub fn goto_local_definition(&mut self) -> bool {
+        self.clamp_cursor_row();
+        let (row, col) = self.cursor_position;
+        let line = &self.content[row];
+        let (start, end) = self.find_word_bounds(line, col);
+        if !line[start..end].starts_with(is_word_char) {
+            return false;
+        }
+        let occurrences = self.find_word_occurrences(&line[start..end]);
+
+        let definition = occurrences.iter()
+            .filter(|&&pos| pos < (row, start))
+            .find(|&&(r, c)| {
+                let keyword: String = self.content[r][..c]
+                    .trim_end()
+                    .chars()
+                    .rev()
+                    .take_while(|&c| is_word_char(c))
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .rev()
+                    .collect();
+                DEFINITION_KEYWORDS.contains(&keyword.as_str())
+            });
+
+        match definition.or(occurrences.first()) {
+            Some(&(target_row, target_col)) if (target_row, target_col) != (row, start) => {
+                self.push_jump();
+                self.set_cursor_position(target_row, target_col);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // gf - the file name under the cursor with any `:LINE:COL` that follows
+    // it, as compilers and grep print them
+    pub fn file_name_at_cursor(&self) -> Option<String> {
+        let line = self.content.get(self.cursor_position.0)?;
+        let col = self.cursor_position.1.min(line.len());
+        let in_name = |c: char| is_file_name_char(c) || c == ':';
+        if !line[col..].starts_with(in_name) {
+            return None;
+        }
+        let start = line[..col].char_indices()
+            .rev()
+            .take_while(|&(_, c)| in_name(c))
+            .last()
+            .map_or(col, |(i, _)| i);
+        let end = col + line[col..].chars()
+            .take_while(|&c| in_name(c))
+            .map(char::len_utf8)
+            .sum::<usize>();
+        let name = line[start..end].trim_start_matches(':').trim_end_matches([':', '.']);
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    // Every whole-word occurrence of `word` as (row, col), in file order
+    fn find_word_occurrences(&self, word: &str) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        for (row, line) in self.content.iter().enumerate() {
+            for (col, _) in line.match_indices(word) {
+                let at_start = !line[..col].ends_with(is_word_char);
+                let at_end = !line[col + word.len()..].starts_with(is_word_char);
+                if at_start && at_end {
+                    found.push((row, col));
+                }
+            }
+        }
+        found
+    }
+
+    // Selection bounds storage for search operations
+    pub fn store_visual_bounds(&mut self) {
+        self.visual_bounds = self.get_visual_selection();
+    }
+
+    pub fn restore_visual_bounds(&mut self) {
+        if let Some((start, end)) = self.visual_bounds.take() {
+            self.visual_start = Some(start);
+            self.cursor_position = end;
+        }
+    }
+
+    // The kind of selection a `/` from visual mode is searching inside, stored
+    // until the search is done
+    pub fn stored_visual_mode(&self) -> Option<VisualMode> {
+        self.visual_bounds.map(|_| self.visual_mode.unwrap_or(VisualMode::Char))
+    }
+
+    // `/` from visual mode: find `query` inside the stored selection and
+    // select from where the selection began to its first match there. With
+    // no match inside, the selection is left as it was. False if none.
+    pub fn search_in_selection(&mut self, query: &str) -> bool {
+        let Some((start, end)) = self.visual_bounds else {
+            return false;
+        };
+        let (first, last) = (start.min(end), start.max(end));
+        let mode = self.visual_mode.unwrap_or(VisualMode::Char);
+        let inside = |&(row, col, match_end): &(usize, usize, usize)| match mode {
+            VisualMode::Line => (first.0..=last.0).contains(&row),
+            VisualMode::Block => (first.0..=last.0).contains(&row)
+                && col >= start.1.min(end.1)
+                && match_end <= start.1.max(end.1),
+            VisualMode::Char => (row, col) >= first && (row, match_end) <= last,
+        };
+
+        self.search(query, true);
+        self.search_forward = true;
+        self.restore_visual_bounds();
+        self.current_match = self.search_matches.iter().position(inside);
+        self.jump_to_current_match();
+        self.current_match.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EditorConfig;
+    use crate::editor::Editor;
+    use crate::input::send_keys;
+
+    #[test]
+    fn test_new_buffer() {
+        let buffer = Buffer::new();
+        assert_eq!(buffer.cursor_position, (0, 0));
+        assert_eq!(buffer.content, vec![String::new()]);
+        assert_eq!(buffer.tab_size, 4);
+    }
+
+    #[test]
+    fn test_insert_char() {
+        let mut buffer = Buffer::new();
+        buffer.insert_char('a');
+        assert_eq!(buffer.content[0], "a");
+        assert_eq!(buffer.cursor_position, (0, 1));
+    }
+
+    #[test]
+    fn test_delete_char() {
+        let mut buffer = Buffer::new();
+        buffer.insert_char('a');
+        buffer.delete_char();
+        assert_eq!(buffer.content[0], "");
+        assert_eq!(buffer.cursor_position, (0, 0));
+    }
+
+    #[test]
+    fn test_insert_multibyte() {
+        let mut buffer = Buffer::new();
+        buffer.insert_char('é');
+        buffer.insert_at_cursor("→x");
+        buffer.insert_char('ü');
+        assert_eq!(buffer.content[0], "é→xü");
+        assert_eq!(buffer.cursor_position, (0, "é→xü".len()));
+
+        buffer.move_cursor("left");
+        buffer.move_cursor("left");
+        buffer.delete_char();
+        assert_eq!(buffer.content[0], "éxü");
+        assert_eq!(buffer.cursor_position, (0, "é".len()));
+        buffer.move_cursor("right");
+        assert_eq!(buffer.cursor_position, (0, "éx".len()));
+    }
+
+    #[test]
+    fn test_large_file_navigation() {
+        let path = std::env::temp_dir().join(format!("zorforge_buffer_large_{}", std::process::id()));
+        std::fs::write(&path, "short\nmuch longer line\nend\n").unwrap();
+
+        let mut buffer = Buffer::new();
+        buffer.open_large_file(&path).unwrap();
+        assert!(buffer.is_large_file());
+        assert_eq!(buffer.line_count(), 3);
+        assert_eq!(buffer.line_text(1).as_deref(), Some("much longer line"));
+
+        buffer.move_cursor("down");
+        buffer.move_cursor("line_end");
+        assert_eq!(buffer.get_cursor_position(), (1, 16));
+        buffer.move_cursor("down");
+        assert_eq!(buffer.get_cursor_position(), (2, 3));
+        buffer.move_cursor("down");
+        assert_eq!(buffer.get_cursor_position(), (2, 3));
+
+        // Replacing the content leaves large file mode
+        buffer.set_content(vec!["owned".to_string()]);
+        assert!(!buffer.is_large_file());
+        assert_eq!(buffer.line_count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_display_col_counts_wide_chars() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["你好a".to_string()]);
+        assert_eq!(buffer.display_col(0, 0), 0);
+        assert_eq!(buffer.display_col(0, "你".len()), 2);
+        assert_eq!(buffer.display_col(0, "你好".len()), 4);
+        assert_eq!(buffer.display_col(0, "你好a".len()), 5);
+
+        // Either cell of a wide character maps back to its start
+        assert_eq!(buffer.col_at_display(0, 3), "你".len());
+        assert_eq!(buffer.col_at_display(0, 4), "你好".len());
+        assert_eq!(buffer.col_at_display(0, 9), "你好a".len());
+    }
+
+    #[test]
+    fn test_set_cursor_position_clamps_row() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["first".to_string(), "last".to_string()]);
+        buffer.set_cursor_position(10, 2);
+        assert_eq!(buffer.cursor_position, (1, 2));
+        buffer.set_cursor_position(10, 10);
+        assert_eq!(buffer.cursor_position, (1, 4));
+    }
+
+    #[test]
+    fn test_display_col_expands_tabs() {
+        let mut buffer = Buffer::new();
+        buffer.set_tab_size(4);
+        buffer.set_content(vec!["\tx\tab\ty".to_string()]);
+        assert_eq!(buffer.display_col(0, 1), 4);
+        assert_eq!(buffer.display_col(0, 2), 5);
+        // The second tab only reaches the next stop
+        assert_eq!(buffer.display_col(0, 3), 8);
+        assert_eq!(buffer.display_col(0, 6), 12);
+
+        // Any cell of a tab maps back to the tab
+        assert_eq!(buffer.col_at_display(0, 2), 0);
+        assert_eq!(buffer.col_at_display(0, 6), 2);
+        assert_eq!(buffer.col_at_display(0, 9), 4);
+    }
+
+    #[test]
+    fn test_replace_before_cursor_undoes_in_one_step() {
+        let mut buffer = Buffer::new();
+        buffer.insert_text("say teh");
+        buffer.replace_before_cursor(3, "the");
+        assert_eq!(buffer.content[0], "say the");
+        assert_eq!(buffer.cursor_position, (0, 7));
+
+        buffer.undo();
+        assert_eq!(buffer.content[0], "say teh");
+        buffer.redo();
+        assert_eq!(buffer.content[0], "say the");
+    }
+
+    #[test]
+    fn test_insert_line() {
+        let mut buffer = Buffer::new();
+        buffer.insert_char('a');
+        buffer.insert_line();
+        assert_eq!(buffer.content.len(), 2);
+        assert_eq!(buffer.content[0], "");
+        assert_eq!(buffer.content[1], "a");
+    }
+
+    #[test]
+    fn test_paste_keeps_indentation() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["    fn main() {".to_string(), "    }".to_string()];
+        buffer.set_cursor_position(0, 15);
+        buffer.clipboard.as_mut().unwrap().yank("\n        let x = 1;\n\tlet y = 2;".to_string());
+
+        buffer.paste();
+        assert_eq!(buffer.content, vec![
+            "    fn main() {",
+            "        let x = 1;",
+            "\tlet y = 2;",
+            "    }",
+        ]);
+        assert_eq!(buffer.cursor_position, (2, 11));
+
+        // The whole paste is one change
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["    fn main() {", "    }"]);
+    }
+
+    #[test]
+    fn test_line_range_edits() {
+        let mut buffer = Buffer::new();
+        let original: Vec<String> = ["b foo foo", "c", "a foo"].iter().map(|l| l.to_string()).collect();
+        buffer.content = original.clone();
+
+        assert_eq!(buffer.substitute(0, 2, "foo", "bar", false), 2);
+        assert_eq!(buffer.content, vec!["b bar foo", "c", "a bar"]);
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.undo();
+        assert_eq!(buffer.content, original);
+
+        assert_eq!(buffer.substitute(0, 1, "foo", "", true), 2);
+        assert_eq!(buffer.content, vec!["b  ", "c", "a foo"]);
+        buffer.undo();
+
+        buffer.sort_lines(0, 2);
+        assert_eq!(buffer.content, vec!["a foo", "b foo foo", "c"]);
+        buffer.undo();
+        assert_eq!(buffer.content, original);
+
+        buffer.indent_lines(1, 2, 4, 1);
+        assert_eq!(buffer.content, vec!["b foo foo", "    c", "    a foo"]);
+        buffer.dedent_lines(0, 2, 4, 1);
+        assert_eq!(buffer.content, original);
+
+        buffer.delete_lines(0, 1);
+        assert_eq!(buffer.content, vec!["a foo"]);
+    }
+
+    #[test]
+    fn test_last_visual_rows() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(buffer.last_visual_rows(), None);
+
+        buffer.cursor_position = (2, 0);
+        buffer.toggle_visual_mode(VisualMode::Line);
+        buffer.cursor_position = (1, 0);
+        buffer.clear_visual();
+        assert_eq!(buffer.last_visual_rows(), Some((1, 2)));
+    }
+
+    #[test]
+    fn test_stale_cursor_row() {
+        let edits: Vec<fn(&mut Buffer)> = vec![
+            |b| b.insert_char('x'),
+            |b| b.insert_text("xy"),
+            |b| b.insert_char_replace('x'),
+            |b| b.move_word_forward(),
+            |b| b.move_word_backward(),
+            |b| b.indent_line(4),
+            |b| b.dedent_line(4),
+            |b| b.delete_char(),
+            |b| b.delete_char_forward(),
+            |b| b.delete_word_backward(),
+            |b| b.cut_char(),
+            |b| b.insert_line(),
+            |b| b.insert_line_below(),
+            |b| b.prepare_append(),
+            |b| b.prepare_insert_start_of_line(),
+            |b| b.join_lines(2, true),
+            |b| { b.goto_local_definition(); },
+            |b| b.delete_line(),
+            |b| b.delete_block_selection((4, 0), (6, 2)),
+        ];
+        for edit in edits {
+            let mut buffer = Buffer::new();
+            buffer.content = vec!["one two".to_string(), "three".to_string()];
+            buffer.cursor_position = (5, 3);
+            edit(&mut buffer);
+            assert!(buffer.cursor_position.0 < buffer.content.len());
+        }
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "foo.bar(baz)  qux".to_string(),
+            "".to_string(),
+            "  last".to_string(),
+        ];
+
+        let mut stops = Vec::new();
+        for _ in 0..8 {
+            buffer.move_word_forward();
+            stops.push(buffer.cursor_position);
+        }
+        assert_eq!(stops, vec![(0, 3), (0, 4), (0, 7), (0, 8), (0, 11), (0, 14), (1, 0), (2, 2)]);
+        buffer.move_word_forward();
+        assert_eq!(buffer.cursor_position, (2, 5)); // Last char of the buffer
+
+        let mut stops = Vec::new();
+        for _ in 0..4 {
+            buffer.move_word_backward();
+            stops.push(buffer.cursor_position);
+        }
+        assert_eq!(stops, vec![(2, 2), (1, 0), (0, 14), (0, 11)]);
+
+        buffer.cursor_position = (0, 0);
+        buffer.move_word_end();
+        assert_eq!(buffer.cursor_position, (0, 2));
+        buffer.move_word_end();
+        assert_eq!(buffer.cursor_position, (0, 3));
+        buffer.cursor_position = (0, 16);
+        buffer.move_word_end();
+        assert_eq!(buffer.cursor_position, (2, 5)); // Past the empty line
+    }
+
+    #[test]
+    fn test_word_end_from_inside_a_word() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["naïve café, done".to_string()];
+
+        // From the middle of a word, e goes to that word's last char
+        buffer.cursor_position = (0, 1);
+        buffer.move_word_end();
+        assert_eq!(buffer.cursor_position, (0, 5));
+        buffer.move_word_end();
+        assert_eq!(buffer.cursor_position, (0, 10)); // The é
+        buffer.move_word_end();
+        assert_eq!(buffer.cursor_position, (0, 12)); // The comma
+
+        buffer.cursor_position = (0, 7);
+        buffer.move_big_word_end();
+        assert_eq!(buffer.cursor_position, (0, 12));
+    }
+
+    #[test]
+    fn test_big_word_motions() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["foo.bar(baz)  qux".to_string(), "next".to_string()];
+
+        buffer.move_big_word_forward();
+        assert_eq!(buffer.cursor_position, (0, 14));
+        buffer.move_big_word_forward();
+        assert_eq!(buffer.cursor_position, (1, 0));
+        buffer.move_big_word_backward();
+        assert_eq!(buffer.cursor_position, (0, 14));
+        buffer.move_big_word_backward();
+        assert_eq!(buffer.cursor_position, (0, 0));
+        buffer.move_big_word_end();
+        assert_eq!(buffer.cursor_position, (0, 11));
+    }
+
+    #[test]
+    fn test_delete_word_backward_stops_at_line_start() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["above".to_string(), "   foo".to_string()];
+        buffer.cursor_position = (1, 3);
+        buffer.delete_word_backward();
+        assert_eq!(buffer.content, vec!["above", "foo"]);
+        assert_eq!(buffer.cursor_position, (1, 0));
+    }
+
+    #[test]
+    fn test_select_word_on_space_and_at_eol() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["let  café = x;".to_string()];
+
+        // On whitespace, iw takes the run of spaces and aw the next word too
+        buffer.cursor_position = (0, 4);
+        buffer.select_word(SelectionType::Inner);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 3), (0, 5))));
+        buffer.cursor_position = (0, 4);
+        buffer.select_word(SelectionType::Around);
+        assert_eq!(buffer.get_selected_text().as_deref(), Some("  café"));
+
+        // Byte columns past a multi-byte character
+        buffer.cursor_position = (0, 6);
+        buffer.select_word(SelectionType::Inner);
+        assert_eq!(buffer.get_selected_text().as_deref(), Some("café"));
+        buffer.cursor_position = (0, 6);
+        buffer.select_word(SelectionType::Around);
+        assert_eq!(buffer.get_selected_text().as_deref(), Some("café "));
+
+        // At or past the end of the line the last character is used
+        buffer.cursor_position = (0, 15);
+        buffer.select_word(SelectionType::Inner);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 14), (0, 15))));
+        buffer.cursor_position = (0, 40);
+        buffer.select_word(SelectionType::Around);
+        assert_eq!(buffer.get_selected_text().as_deref(), Some(";"));
+
+        buffer.content = vec![String::new()];
+        buffer.cursor_position = (0, 0);
+        buffer.select_word(SelectionType::Inner);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 0), (0, 0))));
+    }
+
+    #[test]
+    fn test_paragraph_motions() {
+        let mut buffer = Buffer::new();
+        buffer.content = ["one", "two", "", "", "three", "", "four"]
+            .iter().map(|s| s.to_string()).collect();
+
+        buffer.move_paragraph_forward(1);
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.move_paragraph_forward(1);
+        assert_eq!(buffer.cursor_position, (5, 0));
+        buffer.move_paragraph_forward(1);
+        assert_eq!(buffer.cursor_position, (6, 4)); // End of the last line
+
+        buffer.move_paragraph_backward(1);
+        assert_eq!(buffer.cursor_position, (5, 0));
+        buffer.move_paragraph_backward(1);
+        assert_eq!(buffer.cursor_position, (3, 0));
+        buffer.move_paragraph_backward(1);
+        assert_eq!(buffer.cursor_position, (0, 0));
+
+        buffer.move_paragraph_forward(2);
+        assert_eq!(buffer.cursor_position, (5, 0));
+        buffer.move_paragraph_backward(5);
+        assert_eq!(buffer.cursor_position, (0, 0));
+    }
+
+    #[test]
+    fn test_sentence_motions() {
+        let mut buffer = Buffer::new();
+        buffer.content = [
+            "One. Two (really!) three",
+            "goes on. e.g.x stays",
+            "",
+            "Last?",
+        ].iter().map(|s| s.to_string()).collect();
+
+        let mut stops = Vec::new();
+        for _ in 0..6 {
+            buffer.move_sentence_forward(1);
+            stops.push(buffer.cursor_position);
+        }
+        assert_eq!(stops, vec![(0, 5), (0, 19), (1, 9), (2, 0), (3, 0), (3, 4)]);
+
+        buffer.move_sentence_backward(2);
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.move_sentence_backward(1);
+        assert_eq!(buffer.cursor_position, (1, 9));
+        buffer.move_sentence_backward(9);
+        assert_eq!(buffer.cursor_position, (0, 0));
+
+        buffer.move_sentence_forward(3);
+        assert_eq!(buffer.cursor_position, (1, 9));
+    }
+
+    #[test]
+    fn test_select_paragraph_at_file_edges() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["one".to_string(), "two".to_string(), "".to_string(), "three".to_string()];
+
+        buffer.select_paragraph(SelectionType::Inner);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 0), (1, 3))));
+        buffer.select_paragraph(SelectionType::Around);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 0), (2, 0))));
+
+        buffer.cursor_position = (3, 2);
+        buffer.select_paragraph(SelectionType::Inner);
+        assert_eq!(buffer.get_visual_selection(), Some(((3, 0), (3, 5))));
+        buffer.select_paragraph(SelectionType::Around);
+        assert_eq!(buffer.get_visual_selection(), Some(((2, 0), (3, 5))));
+
+        buffer.content = vec!["only".to_string()];
+        buffer.cursor_position = (0, 0);
+        buffer.select_paragraph(SelectionType::Around);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 0), (0, 4))));
+    }
+
+    #[test]
+    fn test_unsaved_changes_follow_undo_and_redo() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["text".to_string()]);
+        assert!(!buffer.has_unsaved_changes());
+
+        buffer.insert_char('a');
+        buffer.mark_saved();
+        assert!(!buffer.has_unsaved_changes());
+
+        buffer.insert_char('b');
+        assert!(buffer.has_unsaved_changes());
+        buffer.undo();
+        assert!(!buffer.has_unsaved_changes());
+        buffer.undo();
+        assert!(buffer.has_unsaved_changes());
+        buffer.redo();
+        assert!(!buffer.has_unsaved_changes());
+        buffer.redo();
+        assert!(buffer.has_unsaved_changes());
+
+        // A new edit after undoing is a different state from the saved one
+        buffer.undo();
+        buffer.undo();
+        buffer.insert_char('c');
+        assert!(buffer.has_unsaved_changes());
+
+        buffer.mark_modified();
+        buffer.undo();
+        assert!(buffer.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_undo_levels() {
+        let mut buffer = Buffer::new();
+        buffer.set_undo_levels(2);
+        for c in ['a', 'b', 'c'] {
+            buffer.insert_char(c);
+        }
+        assert!(buffer.undo());
+        assert!(buffer.undo());
+        assert!(!buffer.undo());
+        assert_eq!(buffer.get_content(), &["a"]);
+        // Forgetting the change that made it doesn't make the text saved
+        assert!(buffer.has_unsaved_changes());
+
+        // A merge holds across records trimmed while it's under way
+        buffer.set_undo_levels(3);
+        buffer.redo();
+        buffer.set_cursor_position(0, 2);
+        let len = buffer.begin_merge();
+        buffer.insert_char('x');
+        buffer.insert_char('y');
+        buffer.insert_char('z');
+        buffer.merge_changes_since(len);
+        buffer.undo();
+        assert_eq!(buffer.get_content(), &["ab"]);
+
+        buffer.set_undo_levels(0);
+        buffer.insert_char('d');
+        assert!(!buffer.undo());
+        assert!(buffer.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_undo_every_edit() {
+        let original = vec!["\tone two".to_string(), "three".to_string()];
+        let edits: Vec<fn(&mut Buffer)> = vec![
+            |b| b.cut_char(),
+            |b| { b.cursor_position.1 = 8; b.delete_char_fn() },
+            |b| b.insert_text("x"),
+            |b| b.insert_at_cursor("y"),
+            |b| { b.cursor_position.1 = 4; b.insert_line() },
+            |b| b.indent_line(4),
+            |b| b.dedent_line(4),
+            |b| { b.cursor_position.1 = 8; b.delete_word_backward() },
+            |b| { b.cursor_position.1 = 4; b.delete_to_line_start() },
+            |b| b.insert_line_below(),
+            |b| b.insert_line_above(),
+            |b| b.delete_line(),
+            |b| b.insert_lines_at(1, "a\nb"),
+            |b| b.insert_block_at((1, 7), "c\nd\ne"),
+        ];
+        for edit in edits {
+            let mut buffer = Buffer::new();
+            buffer.content = original.clone();
+            edit(&mut buffer);
+            assert_ne!(buffer.content, original);
+            let edited = buffer.content.clone();
+
+            assert!(buffer.undo());
+            assert_eq!(buffer.content, original);
+            assert!(buffer.redo());
+            assert_eq!(buffer.content, edited);
+        }
+    }
+
+    #[test]
+    fn test_undo_line_selection_delete() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["a".to_string(), "b".to_string()];
+        buffer.toggle_visual_mode(VisualMode::Line);
+        buffer.set_cursor_position(1, 0);
+        buffer.delete_selection();
+        assert_eq!(buffer.content, vec![""]);
+
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_undo_multi_line_selection_delete() {
+        let original: Vec<String> = ["first line", "  second", "third", "fourth line"]
+            .iter().map(|line| line.to_string()).collect();
+        let selections = [
+            (VisualMode::Char, (0, 6), (3, 7)),
+            (VisualMode::Char, (2, 2), (1, 4)),
+            (VisualMode::Char, (0, 10), (1, 0)),
+            (VisualMode::Line, (3, 0), (1, 0)),
+            (VisualMode::Block, (0, 2), (3, 5)),
+        ];
+        for (mode, start, end) in selections {
+            let mut buffer = Buffer::new();
+            buffer.content = original.clone();
+            buffer.cursor_position = start;
+            buffer.toggle_visual_mode(mode);
+            buffer.cursor_position = end;
+            buffer.delete_selection();
+            let deleted = buffer.content.clone();
+            assert_ne!(deleted, original);
+
+            assert!(buffer.undo());
+            assert_eq!(buffer.content, original, "{:?} {:?}..{:?}", mode, start, end);
+            assert!(buffer.redo());
+            assert_eq!(buffer.content, deleted, "{:?} {:?}..{:?}", mode, start, end);
+        }
+    }
+
+    #[test]
+    fn test_multi_line_char_selection_delete() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["first line".to_string(), "second".to_string(), "fourth line".to_string()];
+        buffer.cursor_position = (0, 6);
+        buffer.toggle_visual_mode(VisualMode::Char);
+        buffer.cursor_position = (2, 7);
+        buffer.delete_selection();
+        assert_eq!(buffer.content, vec!["first line"]);
+        assert_eq!(buffer.cursor_position, (0, 6));
+    }
+
+    #[test]
+    fn test_undo_line_joins() {
+        let original = vec!["ab".to_string(), "cd".to_string()];
+        let joins: Vec<fn(&mut Buffer)> = vec![
+            |b| { b.cursor_position = (1, 0); b.delete_char() },
+            |b| { b.cursor_position = (0, 2); b.delete_char_forward() },
+        ];
+        for join in joins {
+            let mut buffer = Buffer::new();
+            buffer.content = original.clone();
+            join(&mut buffer);
+            assert_eq!(buffer.content, vec!["abcd"]);
+            assert_eq!(buffer.cursor_position, (0, 2));
+
+            buffer.undo();
+            assert_eq!(buffer.content, original);
+            buffer.redo();
+            assert_eq!(buffer.content, vec!["abcd"]);
+        }
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "let x = f(".to_string(),
+            "    a,".to_string(),
+            "    b".to_string(),
+            ")".to_string(),
+        ];
+
+        buffer.join_lines(3, true);
+        assert_eq!(buffer.content[0], "let x = f( a, b");
+        buffer.join_lines(2, true);
+        assert_eq!(buffer.content, vec!["let x = f( a, b)"]);
+        assert_eq!(buffer.cursor_position, (0, 15));
+
+        // Each J undoes as one step, however many lines it joined,
+        // restoring the indent
+        buffer.undo();
+        assert_eq!(buffer.content[..2], ["let x = f( a, b", ")"]);
+        buffer.undo();
+        assert_eq!(buffer.content, ["let x = f(", "    a,", "    b", ")"]);
+        buffer.redo();
+        assert_eq!(buffer.content[0], "let x = f( a, b");
+    }
+
+    #[test]
+    fn test_join_lines_without_space() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["\"abc".to_string(), "  def\"".to_string()];
+
+        buffer.join_lines(1, false);
+        assert_eq!(buffer.content, vec!["\"abc  def\""]);
+        assert_eq!(buffer.cursor_position, (0, 4));
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["\"abc", "  def\""]);
+    }
+
+    #[test]
+    fn test_cursor_movement() {
+        let mut buffer = Buffer::new();
+        buffer.insert_char('a');
+        buffer.insert_line();
+        buffer.insert_char('b');
+        
+        buffer.move_cursor("up");
+        assert_eq!(buffer.cursor_position, (0, 1));
+        
+        buffer.move_cursor("down");
+        assert_eq!(buffer.cursor_position, (1, 1));
+        
+        buffer.move_cursor("left");
+        assert_eq!(buffer.cursor_position, (1, 0));
+        
+        buffer.move_cursor("right");
+        assert_eq!(buffer.cursor_position, (1, 1));
+    }
+
+    #[test]
+    fn test_move_cursor_wrapping() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["ab".to_string(), "c".to_string()];
+        buffer.set_cursor_position(0, 2);
+
+        buffer.move_cursor("right");
+        assert_eq!(buffer.cursor_position, (0, 2));
+        buffer.move_cursor_wrapping("right");
+        assert_eq!(buffer.cursor_position, (1, 0));
+        buffer.move_cursor_wrapping("left");
+        assert_eq!(buffer.cursor_position, (0, 2));
+
+        // Nowhere to go at the very start and end
+        buffer.set_cursor_position(0, 0);
+        buffer.move_cursor_wrapping("left");
+        assert_eq!(buffer.cursor_position, (0, 0));
+        buffer.set_cursor_position(1, 1);
+        buffer.move_cursor_wrapping("right");
+        assert_eq!(buffer.cursor_position, (1, 1));
+    }
+
+    #[test]
+    fn test_virtual_edit() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["ab".to_string(), "".to_string()];
+        buffer.set_virtual_edit(VirtualEdit::All);
+        buffer.set_cursor_position(0, 2);
+
+        buffer.move_cursor("right");
+        buffer.move_cursor("right");
+        assert_eq!(buffer.cursor_position, (0, 4));
+        assert_eq!(buffer.display_col(0, 4), 4);
+        // Moving over a shorter line keeps the column
+        buffer.move_cursor("down");
+        assert_eq!(buffer.cursor_position, (1, 4));
+        assert_eq!(buffer.content[1], "");
+
+        // Spaces appear only once something is typed
+        buffer.insert_char('x');
+        assert_eq!(buffer.content[1], "    x");
+        assert_eq!(buffer.cursor_position, (1, 5));
+    }
+
+    #[test]
+    fn test_virtual_edit_block_only() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["ab".to_string()];
+        buffer.set_virtual_edit(VirtualEdit::Block);
+        buffer.set_cursor_position(0, 2);
+        buffer.move_cursor("right");
+        assert_eq!(buffer.cursor_position, (0, 2));
+
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.move_cursor("right");
+        assert_eq!(buffer.cursor_position, (0, 3));
+        assert_eq!(buffer.get_selected_text().as_deref(), Some(""));
+
+        buffer.clear_visual();
+        assert_eq!(buffer.cursor_position, (0, 2));
+    }
+
+    #[test]
+    fn test_desired_column_restored() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "a long first line".to_string(),
+            "ab".to_string(),
+            "another long line".to_string(),
+        ];
+        buffer.set_cursor_position(0, 10);
+
+        buffer.move_cursor("down");
+        assert_eq!(buffer.cursor_position, (1, 2)); // Clamped to short line
+
+        buffer.move_cursor("down");
+        assert_eq!(buffer.cursor_position, (2, 10)); // Original column restored
+
+        buffer.move_cursor("left");
+        buffer.move_cursor("up");
+        buffer.move_cursor("up");
+        assert_eq!(buffer.cursor_position, (0, 9)); // Horizontal move resets target
+    }
+
+    #[test]
+    fn test_desired_column_line_end() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "short".to_string(),
+            "a much longer line".to_string(),
+        ];
+        buffer.move_cursor("line_end");
+        buffer.move_cursor("down");
+        assert_eq!(buffer.cursor_position, (1, 18));
+    }
+
+    #[test]
+    fn test_scroll_to_cursor() {
+        let mut buffer = Buffer::new();
+        buffer.content = (0..50).map(|i| i.to_string()).collect();
+        buffer.set_viewport_size(10, 80);
+
+        buffer.set_cursor_position(25, 0);
+        buffer.scroll_to_cursor();
+        assert_eq!(buffer.get_viewport().start, 16);
+
+        buffer.set_cursor_position(3, 0);
+        buffer.scroll_to_cursor();
+        assert_eq!(buffer.get_viewport().start, 3);
+    }
+
+    #[test]
+    fn test_gutter_width() {
+        let mut buffer = Buffer::new();
+        assert_eq!(buffer.gutter_width(), 6);
+        assert_eq!(buffer.format_line_number(0), "  1 │ ");
+
+        buffer.content = vec![String::new(); 12345];
+        assert_eq!(buffer.gutter_width(), 8);
+        assert_eq!(buffer.format_line_number(12344), "12345 │ ");
+    }
+
+    #[test]
+    fn test_move_to_percentage() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![String::new(); 200];
+
+        buffer.move_to_percentage(50);
+        assert_eq!(buffer.cursor_position, (99, 0));
+
+        buffer.move_to_percentage(1);
+        assert_eq!(buffer.cursor_position, (1, 0));
+
+        buffer.move_to_percentage(250);
+        assert_eq!(buffer.cursor_position, (199, 0));
+    }
+
+    #[test]
+    fn test_goto_local_definition() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "let total_count = 0;".to_string(),
+            "fn helper() {}".to_string(),
+            "let x = total_count + 1;".to_string(),
+            "helper();".to_string(),
+        ];
+
+        buffer.set_cursor_position(2, 10);
+        assert!(buffer.goto_local_definition());
+        assert_eq!(buffer.cursor_position, (0, 4));
+
+        buffer.set_cursor_position(3, 2);
+        assert!(buffer.goto_local_definition());
+        assert_eq!(buffer.cursor_position, (1, 3));
+
+        // Ctrl-o returns to where gd was used
+        assert!(buffer.jump_back());
+        assert_eq!(buffer.cursor_position, (3, 2));
+        assert!(buffer.jump_forward());
+        assert_eq!(buffer.cursor_position, (1, 3));
+    }
+
+    #[test]
+    fn test_goto_local_definition_falls_back_to_first_use() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "print(value)".to_string(),
+            "print(value)".to_string(),
+        ];
+        buffer.set_cursor_position(1, 7);
+        assert!(buffer.goto_local_definition());
+        assert_eq!(buffer.cursor_position, (0, 6));
+    }
+
+    #[test]
+    fn test_line_selection_takes_whole_lines() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["one".to_string(), "  two".to_string(), "three".to_string()];
+        buffer.set_cursor_position(0, 2);
+        buffer.toggle_visual_mode(VisualMode::Line);
+        buffer.set_cursor_position(1, 1);
+        let text = buffer.get_selected_text().unwrap();
+        assert_eq!(text, "one\n  two\n");
+
+        // Pasted line-wise text goes below the cursor's line
+        let kind = buffer.selection_yank_type();
+        buffer.clear_visual();
+        buffer.clipboard.as_mut().unwrap().yank_as(text, kind);
+        buffer.set_cursor_position(2, 3);
+        buffer.paste();
+        assert_eq!(buffer.content, vec!["one", "  two", "three", "one", "  two"]);
+        assert_eq!(buffer.cursor_position, (3, 0));
     }
 
-    pub fn restore_visual_bounds(&mut self) {
-        if let Some((start, end)) = self.visual_bounds {
-            self.visual_start = Some(start);
-            self.cursor_position = end;
-        }
+    #[test]
+    fn test_search_in_selection() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["foo bar".to_string(), "bar foo".to_string(), "foo".to_string()];
+        buffer.set_cursor_position(0, 2);
+        buffer.toggle_visual_mode(VisualMode::Char);
+        buffer.set_cursor_position(1, 7);
+        buffer.store_visual_bounds();
+
+        // The foo at (0, 0) starts before the selection
+        assert!(buffer.search_in_selection("foo"));
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 2), (1, 4))));
+        assert_eq!(buffer.stored_visual_mode(), None);
+
+        buffer.store_visual_bounds();
+        assert!(!buffer.search_in_selection("baz"));
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 2), (1, 4))));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_cut_chars() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["héllo".to_string(), "two".to_string()];
+        buffer.cut_chars(3);
+        assert_eq!(buffer.content[0], "lo");
+        assert_eq!(buffer.clipboard.as_ref().unwrap().peek().map(String::as_str), Some("hél"));
+        // Stops at the end of the line
+        buffer.cut_chars(5);
+        assert_eq!(buffer.content[0], "");
+        buffer.undo();
+        assert_eq!(buffer.content[0], "lo");
+    }
 
     #[test]
-    fn test_new_buffer() {
-        let buffer = Buffer::new();
-        assert_eq!(buffer.cursor_position, (0, 0));
-        assert_eq!(buffer.content, vec![String::new()]);
-        assert_eq!(buffer.tab_size, 4);
+    fn test_set_clipboard_history() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["a".to_string(), "b".to_string()]);
+        buffer.set_clipboard_history(1);
+        buffer.yank();
+        buffer.set_cursor_position(1, 0);
+        buffer.yank();
+        assert_eq!(buffer.clipboard.as_ref().map(Clipboard::len), Some(1));
     }
 
     #[test]
-    fn test_insert_char() {
+    fn test_yank_line_pastes_below() {
         let mut buffer = Buffer::new();
-        buffer.insert_char('a');
-        assert_eq!(buffer.content[0], "a");
-        assert_eq!(buffer.cursor_position, (0, 1));
+        buffer.content = vec!["one".to_string(), "two".to_string()];
+        buffer.cursor_position = (0, 2);
+        buffer.yank();
+        buffer.paste();
+        assert_eq!(buffer.content, vec!["one", "one", "two"]);
+        assert_eq!(buffer.cursor_position, (1, 0));
     }
 
     #[test]
-    fn test_delete_char() {
+    fn test_clear_line_to_indent() {
         let mut buffer = Buffer::new();
-        buffer.insert_char('a');
-        buffer.delete_char();
-        assert_eq!(buffer.content[0], "");
+        buffer.content = vec!["    let x = 1;".to_string()];
+        buffer.cursor_position = (0, 9);
+        buffer.clear_line_to_indent();
+        assert_eq!(buffer.content[0], "    ");
+        assert_eq!(buffer.cursor_position, (0, 4));
+        buffer.undo();
+        assert_eq!(buffer.content[0], "    let x = 1;");
+    }
+
+    #[test]
+    fn test_delete_to_line_end() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["let héllo = 1;".to_string()];
+        buffer.cursor_position = (0, 4);
+        buffer.delete_to_line_end();
+        assert_eq!(buffer.content[0], "let ");
+        assert_eq!(buffer.cursor_position, (0, 3));
+        assert_eq!(buffer.clipboard.as_ref().unwrap().peek().map(String::as_str), Some("héllo = 1;"));
+        buffer.undo();
+        assert_eq!(buffer.content[0], "let héllo = 1;");
+    }
+
+    #[test]
+    fn test_selection_span() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["abcdef".to_string(), "ab".to_string(), "abcdef".to_string()];
+        buffer.set_cursor_position(0, 1);
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.set_cursor_position(2, 4);
+        assert_eq!(buffer.selection_span(0), Some((1, 4)));
+        assert_eq!(buffer.selection_span(1), Some((1, 2)));
+
+        buffer.toggle_visual_mode(VisualMode::Char);
+        assert_eq!(buffer.selection_span(0), Some((1, 6)));
+        assert_eq!(buffer.selection_span(2), Some((0, 4)));
+        buffer.toggle_visual_mode(VisualMode::Line);
+        assert_eq!(buffer.selection_span(2), Some((0, 6)));
+        buffer.clear_visual();
+        assert_eq!(buffer.selection_span(0), None);
+    }
+
+    #[test]
+    fn test_file_name_at_cursor() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "  --> src/main.rs:42:7".to_string(),
+            "see ./notes.txt.".to_string(),
+        ];
+        buffer.set_cursor_position(0, 10);
+        assert_eq!(buffer.file_name_at_cursor().as_deref(), Some("src/main.rs:42:7"));
+        buffer.set_cursor_position(0, 5);
+        assert_eq!(buffer.file_name_at_cursor(), None);
+        buffer.set_cursor_position(1, 6);
+        assert_eq!(buffer.file_name_at_cursor().as_deref(), Some("./notes.txt"));
+    }
+
+    #[test]
+    fn test_move_to_misspelling() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "teh cat".to_string(),
+            "the dgo".to_string(),
+        ];
+        assert!(!buffer.move_to_misspelling(true));
+
+        let dictionary = Dictionary::from_words(["the", "cat", "dog"]);
+        buffer.set_spell(Some(Arc::new(dictionary)));
+        assert_eq!(buffer.misspellings(1), vec![(4, 7)]);
+
+        assert!(buffer.move_to_misspelling(true));
+        assert_eq!(buffer.cursor_position, (1, 4));
+        // Wraps around to the top
+        assert!(buffer.move_to_misspelling(true));
         assert_eq!(buffer.cursor_position, (0, 0));
+        assert!(buffer.move_to_misspelling(false));
+        assert_eq!(buffer.cursor_position, (1, 4));
     }
 
     #[test]
-    fn test_insert_line() {
+    fn test_replace_spell_word() {
         let mut buffer = Buffer::new();
-        buffer.insert_char('a');
-        buffer.insert_line();
-        assert_eq!(buffer.content.len(), 2);
-        assert_eq!(buffer.content[0], "");
-        assert_eq!(buffer.content[1], "a");
+        buffer.content = vec!["a dgo here".to_string()];
+        buffer.set_spell(Some(Arc::new(Dictionary::from_words(["dog"]))));
+        buffer.set_cursor_position(0, 3);
+
+        let (word, suggestions) = buffer.spell_suggestions(5).unwrap();
+        assert_eq!(word, "dgo");
+        assert_eq!(suggestions, vec!["dog"]);
+
+        assert!(buffer.replace_spell_word("dog"));
+        assert_eq!(buffer.content[0], "a dog here");
+        assert_eq!(buffer.cursor_position, (0, 2));
+        buffer.undo();
+        assert_eq!(buffer.content[0], "a dgo here");
     }
 
     #[test]
-    fn test_cursor_movement() {
+    fn test_modeline_indentation() {
         let mut buffer = Buffer::new();
-        buffer.insert_char('a');
-        buffer.insert_line();
-        buffer.insert_char('b');
-        
-        buffer.move_cursor("up");
-        assert_eq!(buffer.cursor_position, (0, 1));
-        
-        buffer.move_cursor("down");
-        assert_eq!(buffer.cursor_position, (1, 1));
-        
-        buffer.move_cursor("left");
-        assert_eq!(buffer.cursor_position, (1, 0));
-        
-        buffer.move_cursor("right");
-        assert_eq!(buffer.cursor_position, (1, 1));
+        buffer.content = vec!["text".to_string()];
+        buffer.apply_modeline(&ModelineSettings {
+            tab_size: Some(8),
+            shift_width: Some(2),
+            expand_tab: Some(false),
+            text_width: None,
+        });
+        assert_eq!(buffer.tab_size(), 8);
+        assert_eq!(buffer.shift_width(), 2);
+
+        buffer.indent_line(buffer.shift_width());
+        assert_eq!(buffer.content[0], "\ttext");
+        buffer.dedent_line(buffer.shift_width());
+        assert_eq!(buffer.content[0], "text");
     }
 
     #[test]
@@ -1415,6 +4223,364 @@ mod tests {
         assert_eq!(selected_text, "a\nb\nc");
     }
 
+    #[test]
+    fn test_block_delete_ragged_lines() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["ab".to_string(), "abcdef".to_string(), "abc".to_string(), "".to_string()];
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.visual_start = Some((0, 3));
+        buffer.cursor_position = (3, 5);
+
+        assert!(buffer.delete_selection());
+        assert_eq!(buffer.content, vec!["ab", "abcf", "abc", ""]);
+        // The first row is too short to reach the block's column
+        assert_eq!(buffer.cursor_position, (0, 2));
+
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["ab", "abcdef", "abc", ""]);
+    }
+
+    #[test]
+    fn test_block_yank() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["abcdef".to_string(), "ab".to_string(), "abcdef".to_string()];
+        buffer.set_cursor_position(2, 4);
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.set_cursor_position(0, 1);
+
+        // Each row's columns, the short row giving what it has of them
+        assert_eq!(buffer.get_selected_text().as_deref(), Some("bcd\nb\nbcd"));
+        assert_eq!(buffer.selection_yank_type(), YankType::Block);
+
+        // Yanked with the keys and pasted back, it lands as a block again
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["abcdef".to_string(), "ab".to_string(), "abcdef".to_string()]);
+        editor.buffer.set_cursor_position(0, 1);
+        send_keys(&mut editor, "<C-v>jjllylp").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["abbccdef", "abb", "abbccdef"]);
+    }
+
+    #[test]
+    fn test_block_paste_multiline() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["abcd".to_string(), "efgh".to_string(), "i".to_string()];
+        buffer.clipboard.as_mut().unwrap().yank_as("12\n34\n56".to_string(), YankType::Block);
+
+        // Select columns 1..2 of the first two rows, starting at the bottom right
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.visual_start = Some((1, 2));
+        buffer.cursor_position = (0, 1);
+        buffer.paste_over_selection();
+
+        assert_eq!(buffer.content, vec!["a12cd", "e34gh", "i56"]);
+        assert_eq!(buffer.cursor_position, (0, 1));
+    }
+
+    #[test]
+    fn test_block_paste_single_line_fills_block() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["abcd".to_string(), "e".to_string(), "fghi".to_string()];
+        buffer.clipboard.as_mut().unwrap().yank("XY".to_string());
+
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.visual_start = Some((0, 2));
+        buffer.cursor_position = (2, 3);
+        buffer.paste_over_selection();
+
+        // The short middle line is padded out to the block's column
+        assert_eq!(buffer.content, vec!["abXYd", "e XY", "fgXYi"]);
+    }
+
+    #[test]
+    fn test_modify_number_at_cursor() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["x = 19 + 1".to_string()];
+        buffer.set_cursor_position(0, 5);
+        assert!(buffer.modify_number_at_cursor(1));
+        assert_eq!(buffer.content[0], "x = 20 + 1");
+        assert_eq!(buffer.cursor_position, (0, 5));
+
+        buffer.set_cursor_position(0, 0);
+        assert!(buffer.modify_number_at_cursor(-25));
+        assert_eq!(buffer.content[0], "x = -5 + 1");
+        buffer.set_cursor_position(0, 9);
+        assert!(buffer.modify_number_at_cursor(1));
+        assert_eq!(buffer.content[0], "x = -5 + 2");
+        buffer.content = vec!["none".to_string()];
+        buffer.set_cursor_position(0, 0);
+        assert!(!buffer.modify_number_at_cursor(1));
+    }
+
+    #[test]
+    fn test_increment_selection_progressive() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["a[0]".to_string(), "a[0]".to_string(), "--".to_string(), "a[0]".to_string()];
+        buffer.toggle_visual_mode(VisualMode::Line);
+        buffer.visual_start = Some((0, 0));
+        buffer.cursor_position = (3, 0);
+        assert_eq!(buffer.increment_selection(1, true), 3);
+        assert_eq!(buffer.content, vec!["a[1]", "a[2]", "--", "a[3]"]);
+        assert_eq!(buffer.cursor_position, (0, 0));
+
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["a[0]", "a[0]", "--", "a[0]"]);
+        // Ctrl-A on a one-column block adds the same to each
+        buffer.toggle_visual_mode(VisualMode::Block);
+        buffer.visual_start = Some((0, 2));
+        buffer.cursor_position = (1, 2);
+        assert_eq!(buffer.increment_selection(5, false), 2);
+        assert_eq!(buffer.content, vec!["a[5]", "a[5]", "--", "a[0]"]);
+    }
+
+    #[test]
+    fn test_align_lines() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "let a = 1;".to_string(),
+            "let longer   = 2;".to_string(),
+            "// no sign".to_string(),
+            "let b= a == 1;".to_string(),
+        ];
+        assert_eq!(buffer.align_lines(0, 3, "="), 3);
+        assert_eq!(buffer.content, vec![
+            "let a      = 1;",
+            "let longer = 2;",
+            "// no sign",
+            "let b      = a == 1;",
+        ]);
+
+        // One undo step puts every row back
+        buffer.undo();
+        assert_eq!(buffer.content[0], "let a = 1;");
+        assert_eq!(buffer.content[1], "let longer   = 2;");
+        assert_eq!(buffer.align_lines(0, 3, "=>"), 0);
+    }
+
+    #[test]
+    fn test_move_to_column() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["\tab".to_string()];
+        buffer.move_to_column(3);
+        assert_eq!(buffer.cursor_position, (0, 0));
+        buffer.move_to_column(5);
+        assert_eq!(buffer.cursor_position, (0, 1));
+        buffer.move_to_column(99);
+        assert_eq!(buffer.cursor_position, (0, 3));
+        buffer.move_to_column(0);
+        assert_eq!(buffer.cursor_position, (0, 0));
+    }
+
+    #[test]
+    fn test_scroll_half_page() {
+        let mut buffer = Buffer::new();
+        buffer.content = (0..30).map(|i| format!("line {}", i)).collect();
+        buffer.set_viewport_size(10, 80);
+        buffer.set_cursor_position(2, 3);
+
+        buffer.scroll_half_page(true);
+        assert_eq!(buffer.get_viewport().start, 5);
+        assert_eq!(buffer.cursor_position, (7, 3));
+
+        // Near the end the view stops at the last line but the cursor goes on
+        buffer.set_cursor_position(25, 3);
+        buffer.scroll_half_page(true);
+        assert_eq!(buffer.get_viewport().start, 20);
+        assert_eq!(buffer.cursor_position, (29, 3));
+
+        buffer.scroll_half_page(false);
+        assert_eq!(buffer.get_viewport().start, 15);
+        assert_eq!(buffer.cursor_position, (24, 3));
+    }
+
+    #[test]
+    fn test_scroll_lines() {
+        let mut buffer = Buffer::new();
+        buffer.content = (0..30).map(|i| format!("line {}", i)).collect();
+        buffer.set_viewport_size(10, 80);
+        buffer.set_cursor_position(5, 3);
+
+        buffer.scroll_lines(1, true);
+        assert_eq!(buffer.get_viewport().start, 1);
+        assert_eq!(buffer.cursor_position, (5, 3));
+
+        // The cursor is pushed along once its line scrolls off the top
+        buffer.scroll_lines(7, true);
+        assert_eq!(buffer.get_viewport().start, 8);
+        assert_eq!(buffer.cursor_position, (8, 3));
+
+        // ... or off the bottom
+        buffer.scroll_lines(3, false);
+        assert_eq!(buffer.get_viewport().start, 5);
+        assert_eq!(buffer.cursor_position, (8, 3));
+        buffer.scroll_lines(5, false);
+        assert_eq!(buffer.get_viewport().start, 0);
+        assert_eq!(buffer.cursor_position, (8, 3));
+        buffer.set_cursor_position(29, 0);
+        buffer.scroll_lines(100, false);
+        assert_eq!(buffer.get_viewport().start, 0);
+        assert_eq!(buffer.cursor_position.0, 9);
+
+        // The view stops with the last line at the bottom
+        buffer.scroll_lines(100, true);
+        assert_eq!(buffer.get_viewport().start, 20);
+    }
+
+    #[test]
+    fn test_paste_over_multiline_char_selection() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["one two".to_string(), "three".to_string(), "four five".to_string()];
+        buffer.clipboard.as_mut().unwrap().yank("A\nB".to_string());
+
+        // Selected from the bottom up, so the cursor is at the start
+        buffer.toggle_visual_mode(VisualMode::Char);
+        buffer.visual_start = Some((2, 5));
+        buffer.cursor_position = (0, 4);
+        buffer.paste_over_selection();
+        assert_eq!(buffer.content, vec!["one A", "Bfive"]);
+
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["one two", "three", "four five"]);
+    }
+
+    #[test]
+    fn test_paste_at_cursor_verbatim() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["    x();".to_string()];
+        buffer.set_cursor_position(0, 8);
+
+        // Pasted lines keep their own indentation, however deep the cursor's
+        buffer.paste_at_cursor("\n    if y {\r\n        z();\n    }");
+        assert_eq!(buffer.content, vec!["    x();", "    if y {", "        z();", "    }"]);
+        assert_eq!(buffer.get_cursor_position(), (3, 5));
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["    x();"]);
+    }
+
+    #[test]
+    fn test_paste_times() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["ab".to_string(), "--".to_string()];
+
+        buffer.clipboard.as_mut().unwrap().yank_as("--\n".to_string(), YankType::Line);
+        buffer.set_cursor_position(1, 0);
+        buffer.paste_times(3);
+        assert_eq!(buffer.content, vec!["ab", "--", "--", "--", "--"]);
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["ab", "--"]);
+
+        buffer.clipboard.as_mut().unwrap().yank("xy".to_string());
+        buffer.set_cursor_position(0, 1);
+        buffer.paste_times(2);
+        assert_eq!(buffer.content[0], "axyxyb");
+        buffer.undo();
+        assert_eq!(buffer.content[0], "ab");
+
+        // Copies of a block sit side by side, padded to its width
+        buffer.clipboard.as_mut().unwrap().yank_as("1\n23".to_string(), YankType::Block);
+        buffer.set_cursor_position(0, 0);
+        buffer.paste_times(2);
+        assert_eq!(buffer.content, vec!["1 1ab", "2323--"]);
+
+        // A huge count stops at as many copies as fit
+        buffer.content = vec!["ab".to_string()];
+        buffer.clipboard.as_mut().unwrap().yank("xy".to_string());
+        buffer.set_cursor_position(0, 1);
+        buffer.paste_times(usize::MAX);
+        assert_eq!(buffer.content[0].len(), MAX_PASTE_BYTES + 2);
+        buffer.clipboard.as_mut().unwrap().yank_as("-".repeat(MAX_PASTE_BYTES / 4) + "\n", YankType::Line);
+        buffer.paste_times(usize::MAX);
+        assert_eq!(buffer.content.len(), 4);
+    }
+
+    #[test]
+    fn test_paste_by_yank_type() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["abc".to_string(), "de".to_string()];
+
+        // A block goes in at the cursor's column, one line of it per row
+        buffer.clipboard.as_mut().unwrap().yank_as("12\n34".to_string(), YankType::Block);
+        buffer.set_cursor_position(0, 1);
+        buffer.paste();
+        assert_eq!(buffer.content, vec!["a12bc", "d34e"]);
+
+        // Text with a line break but yanked charwise stays inline
+        buffer.clipboard.as_mut().unwrap().yank("x\n".to_string());
+        buffer.set_cursor_position(1, 0);
+        buffer.paste();
+        assert_eq!(buffer.content, vec!["a12bc", "x", "d34e"]);
+
+        // Lines replacing a charwise selection go on lines of their own
+        buffer.clipboard.as_mut().unwrap().yank_as("new\n".to_string(), YankType::Line);
+        buffer.set_cursor_position(0, 1);
+        buffer.toggle_visual_mode(VisualMode::Char);
+        buffer.set_cursor_position(0, 3);
+        buffer.paste_over_selection();
+        assert_eq!(buffer.content, vec!["a", "new", "bc", "x", "d34e"]);
+    }
+
+    #[test]
+    fn test_indent_line_keeps_cursor_on_its_char() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["    let x".to_string()];
+        buffer.cursor_position = (0, 8);
+
+        buffer.indent_line(4);
+        assert_eq!(buffer.content[0], "        let x");
+        assert_eq!(buffer.cursor_position, (0, 12));
+
+        buffer.dedent_line(4);
+        buffer.dedent_line(4);
+        assert_eq!(buffer.content[0], "let x");
+        assert_eq!(buffer.cursor_position, (0, 4));
+
+        // Nothing left to remove
+        buffer.dedent_line(4);
+        assert_eq!(buffer.content[0], "let x");
+        assert_eq!(buffer.cursor_position, (0, 4));
+    }
+
+    #[test]
+    fn test_indent_selection_keeps_selection() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["ab".to_string(), "  cd".to_string()];
+        buffer.visual_start = Some((0, 1));
+        buffer.cursor_position = (1, 3);
+
+        buffer.indent_selection(4, 2);
+        assert_eq!(buffer.content, vec!["        ab", "          cd"]);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 9), (1, 11))));
+        assert_eq!(buffer.get_selected_text().as_deref(), Some("b\n          c"));
+
+        // Dedenting strips what each line has, one level at a time
+        buffer.dedent_selection(4, 3);
+        assert_eq!(buffer.content, vec!["ab", "cd"]);
+        assert_eq!(buffer.get_visual_selection(), Some(((0, 1), (1, 1))));
+    }
+
+    #[test]
+    fn test_indent_selection_undoes_in_one_step() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["a".to_string(), "  b".to_string(), "c".to_string()];
+        buffer.visual_start = Some((0, 0));
+        buffer.cursor_position = (2, 0);
+
+        buffer.indent_selection(2, 1);
+        buffer.dedent_selection(2, 1);
+        buffer.dedent_selection(2, 1);
+        assert_eq!(buffer.content, vec!["a", "b", "c"]);
+
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["a", "  b", "c"]);
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["  a", "    b", "  c"]);
+        buffer.undo();
+        assert_eq!(buffer.content, vec!["a", "  b", "c"]);
+        assert!(!buffer.undo());
+
+        buffer.redo();
+        assert_eq!(buffer.content, vec!["  a", "    b", "  c"]);
+    }
+
     #[test]
     fn test_prepare_append() {
         let mut buffer = Buffer::new();
@@ -1475,15 +4641,97 @@ mod tests {
         assert_eq!(buffer.cursor_position.1, 1);
     }
 
+    #[test]
+    fn test_insert_char_replace_undo() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["añb".to_string()];
+        buffer.cursor_position = (0, 1);
+
+        // Overwriting runs past the end of the line into appending
+        let undo_len = buffer.begin_merge();
+        for c in "xyz".chars() {
+            buffer.insert_char_replace(c);
+        }
+        assert_eq!(buffer.content[0], "axyz");
+        assert_eq!(buffer.cursor_position, (0, 4));
+
+        buffer.undo();
+        assert_eq!(buffer.content[0], "axy");
+        buffer.redo();
+        buffer.merge_changes_since(undo_len);
+        buffer.undo();
+        assert_eq!(buffer.content[0], "añb");
+        assert_eq!(buffer.cursor_position, (0, 1));
+    }
+
+    #[test]
+    fn test_first_non_blank_and_smart_home() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["    let x;".to_string(), "  ".to_string()];
+        buffer.set_cursor_position(0, 8);
+        buffer.move_cursor("first_non_blank");
+        assert_eq!(buffer.cursor_position, (0, 4));
+
+        // Home goes back and forth between the first non-blank and column 0
+        buffer.set_cursor_position(0, 8);
+        buffer.move_cursor("smart_home");
+        assert_eq!(buffer.cursor_position, (0, 4));
+        buffer.move_cursor("smart_home");
+        assert_eq!(buffer.cursor_position, (0, 0));
+        buffer.move_cursor("smart_home");
+        assert_eq!(buffer.cursor_position, (0, 4));
+
+        // A line of blanks has nothing but its end to go to
+        buffer.set_cursor_position(1, 0);
+        buffer.move_cursor("first_non_blank");
+        assert_eq!(buffer.cursor_position, (1, 2));
+    }
+
+    #[test]
+    fn test_insert_newline_filetype_indent() {
+        let mut buffer = Buffer::new();
+        buffer.set_filetype(Some("python".to_string()));
+        buffer.content[0] = "    if x:".to_string();
+        buffer.cursor_position = (0, 9);
+        buffer.insert_newline(true);
+        assert_eq!(buffer.content[1], "        ");
+        buffer.insert_text("return x");
+        buffer.insert_newline(true);
+        assert_eq!(buffer.content[2], "    ");
+        assert_eq!(buffer.cursor_position, (2, 4));
+        buffer.undo();
+        assert_eq!(buffer.content[1], "        return x");
+
+        // o indents the same way
+        buffer.content = vec!["def f():".to_string()];
+        buffer.cursor_position = (0, 0);
+        buffer.insert_line_below();
+        assert_eq!(buffer.content[1], "    ");
+
+        // Makefile recipes are indented with a tab whatever the buffer's setting
+        buffer.set_filetype(Some("make".to_string()));
+        buffer.content = vec!["all: main".to_string()];
+        buffer.cursor_position = (0, 9);
+        buffer.insert_newline(true);
+        assert_eq!(buffer.content[1], "\t");
+    }
+
     #[test]
     fn test_insert_newline_auto_indent() {
         let mut buffer = Buffer::new();
         buffer.content[0] = "    first line".to_string();
         buffer.cursor_position = (0, 8);
-        buffer.insert_newline_auto_indent();
+        buffer.insert_newline(true);
         assert_eq!(buffer.content[0], "    firs");
         assert_eq!(buffer.content[1], "    t line");
         assert_eq!(buffer.cursor_position, (1, 4));
+
+        buffer.insert_newline(false);
+        assert_eq!(buffer.content[1], "    ");
+        assert_eq!(buffer.content[2], "t line");
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.undo();
+        assert_eq!(buffer.content[1], "    t line");
     }
 
     #[test]
@@ -1501,6 +4749,62 @@ mod tests {
         assert_eq!(buffer.current_match, Some(0));
     }
 
+    #[test]
+    fn test_search_after_edit() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec!["a line".to_string(), "another line".to_string()];
+        buffer.search("line", true);
+        buffer.next_match();
+        assert_eq!(buffer.current_match(), Some((1, 8, 12)));
+
+        // Text typed before the matches moves them along
+        buffer.set_cursor_position(1, 0);
+        buffer.insert_text("yet ");
+        buffer.refresh_search();
+        assert_eq!(buffer.current_match(), Some((1, 12, 16)));
+        assert_eq!(buffer.search_matches.len(), 2);
+
+        // Matches edited away are gone, and undo brings them back
+        buffer.set_cursor_position(0, 2);
+        buffer.delete_line();
+        buffer.refresh_search();
+        assert_eq!(buffer.search_matches, vec![(0, 12, 16)]);
+        buffer.undo();
+        buffer.refresh_search();
+        assert_eq!(buffer.search_matches.len(), 2);
+
+        // Once the search is cleared, edits don't bring it back
+        buffer.clear_search();
+        buffer.insert_text("line");
+        buffer.refresh_search();
+        assert!(buffer.search_matches.is_empty());
+    }
+
+    #[test]
+    fn test_search_refresh_only_rescans_edits() {
+        let lines: Vec<String> = (0..8).map(|i| format!("ab {} ab", i)).collect();
+        let edits: Vec<fn(&mut Buffer)> = vec![
+            |b| b.insert_text("ab"),
+            |b| b.insert_line_below(),
+            |b| b.delete_line(),
+            |b| b.join_lines(3, true),
+            |b| b.insert_lines_at(2, "ab\nx\nab"),
+            |b| { b.delete_line(); b.undo(); },
+            |b| { b.join_lines(2, true); b.set_cursor_position(6, 0); b.insert_line(); },
+            |b| { b.insert_line_above(); b.undo(); b.redo(); },
+        ];
+        for (i, edit) in edits.iter().enumerate() {
+            let mut buffer = Buffer::new();
+            buffer.set_content(lines.clone());
+            buffer.search("ab", true);
+            buffer.set_cursor_position(3, 1);
+            edit(&mut buffer);
+            buffer.refresh_search();
+            let all = buffer.find_matches("ab", true, 0..buffer.content.len());
+            assert_eq!(buffer.search_matches, all, "edit {}", i);
+        }
+    }
+
     #[test]
     fn test_search_case_insensitive() {
         let mut buffer = Buffer::new();
@@ -1528,6 +4832,66 @@ mod tests {
         assert_eq!(buffer.cursor_position, (0, 0)); // Back to first match
     }
 
+    #[test]
+    fn test_search_from_cursor() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "line one".to_string(),
+            "two line".to_string(),
+            "line three".to_string(),
+        ];
+
+        buffer.cursor_position = (1, 0);
+        assert_eq!(buffer.search_from_cursor("line", true, true), 3);
+        assert_eq!(buffer.cursor_position, (1, 4));
+
+        buffer.cursor_position = (1, 4);
+        buffer.search_from_cursor("line", true, false);
+        assert_eq!(buffer.cursor_position, (0, 0));
+
+        // Both directions wrap around the ends of the buffer
+        buffer.search_from_cursor("line", true, false);
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.search_from_cursor("line", true, true);
+        assert_eq!(buffer.cursor_position, (0, 0));
+
+        assert_eq!(buffer.search_from_cursor("missing", true, true), 0);
+        assert_eq!(buffer.cursor_position, (0, 0));
+    }
+
+    #[test]
+    fn test_repeat_search_follows_direction() {
+        let mut buffer = Buffer::new();
+        buffer.content = vec![
+            "line one".to_string(),
+            "two line".to_string(),
+            "line three".to_string(),
+        ];
+        assert!(!buffer.repeat_search(false));
+
+        // After ?, n keeps going up and N goes down
+        buffer.cursor_position = (2, 0);
+        buffer.search_from_cursor("line", true, false);
+        assert_eq!(buffer.cursor_position, (1, 4));
+        buffer.repeat_search(false);
+        assert_eq!(buffer.cursor_position, (0, 0));
+        buffer.repeat_search(false);
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.repeat_search(true);
+        assert_eq!(buffer.cursor_position, (0, 0));
+
+        buffer.search_from_cursor("line", true, true);
+        assert_eq!(buffer.cursor_position, (1, 4));
+        buffer.repeat_search(false);
+        assert_eq!(buffer.cursor_position, (2, 0));
+        buffer.repeat_search(true);
+        assert_eq!(buffer.cursor_position, (1, 4));
+        assert_eq!(buffer.search_position(), Some((2, 3)));
+
+        buffer.clear_search();
+        assert_eq!(buffer.search_position(), None);
+    }
+
     #[test]
     fn test_render_with_search_and_visual() {
         let mut buffer = Buffer::new();
@@ -1567,6 +4931,43 @@ mod tests {
         assert!(rendered[1].contains("\x1b[42m")); // Other match highlight
     }
 
+    #[test]
+    fn test_render_lines_recomputes_dirty_lines() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["one".to_string(), "two".to_string()]);
+        assert!(buffer.render_lines()[1].ends_with("two"));
+        assert!(buffer.render_cache.lock()[1].is_some());
+
+        buffer.replace_line(1, "changed".to_string());
+        assert!(buffer.render_cache.lock()[1].is_none());
+        assert!(buffer.render_lines()[1].ends_with("changed"));
+
+        // Inserting a line shifts everything below it
+        buffer.insert_at(0, "new".to_string());
+        let rendered = buffer.render_lines();
+        assert!(rendered[1].ends_with("one"));
+        assert!(rendered[2].ends_with("changed"));
+    }
+
+    #[test]
+    fn test_edits_mark_lines_dirty() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["one".to_string(), "two".to_string(), "three".to_string()]);
+        buffer.clear_dirty_lines();
+
+        buffer.set_cursor_position(1, 0);
+        buffer.insert_char('x');
+        assert!(buffer.dirty_lines().contains(&1));
+        assert_eq!(buffer.dirty_from(), None);
+
+        buffer.delete_line();
+        assert_eq!(buffer.dirty_from(), Some(1));
+
+        buffer.clear_dirty_lines();
+        assert!(buffer.dirty_lines().is_empty());
+        assert_eq!(buffer.dirty_from(), None);
+    }
+
     #[test]
     fn test_render_with_overlapping_highlights() {
         let mut buffer = Buffer::new();
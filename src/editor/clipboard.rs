@@ -1,9 +1,25 @@
 // src/editor/clipboard.rs
 use std::collections::VecDeque;
 
+/// How yanked text goes back in: inline at the cursor, as whole lines of
+/// its own, or as a block at the same column on successive lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YankType {
+    #[default]
+    Char,
+    Line,  // Text ends in a line break, as vim's line-wise registers do
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipboardEntry {
+    pub text: String,
+    pub kind: YankType,
+}
+
 #[derive(Debug)]
 pub struct Clipboard {
-    history: VecDeque<String>,
+    history: VecDeque<ClipboardEntry>,
     max_history: usize,
 }
 
@@ -22,13 +38,18 @@ impl Clipboard {
         }
     }
 
-    // Add content to clipboard
+    // Add content to clipboard, to be pasted inline
     pub fn yank(&mut self, content: String) {
+        self.yank_as(content, YankType::Char);
+    }
+
+    // Add content to clipboard, to be pasted as `kind`
+    pub fn yank_as(&mut self, content: String, kind: YankType) {
         if content.is_empty() {
             return;
         }
 
-        self.history.push_front(content);
+        self.history.push_front(ClipboardEntry { text: content, kind });
 
         // Maintain max history size
         while self.history.len() > self.max_history {
@@ -38,16 +59,21 @@ impl Clipboard {
 
     // Get most recent clipboard content without removing it
     pub fn peek(&self) -> Option<&String> {
-        self.history.front()
+        self.peek_at(0)
     }
 
     // Get content at specific history index
     pub fn peek_at(&self, index: usize) -> Option<&String> {
+        self.peek_entry(index).map(|entry| &entry.text)
+    }
+
+    // Get the entry at a history index along with how it was yanked
+    pub fn peek_entry(&self, index: usize) -> Option<&ClipboardEntry> {
         self.history.get(index)
     }
 
     // Get and remove the most recent content
-    pub fn pop(&mut self) -> Option<String> {
+    pub fn pop(&mut self) -> Option<ClipboardEntry> {
         self.history.pop_front()
     }
 
@@ -67,7 +93,7 @@ impl Clipboard {
     }
 
     // Get clipboard history
-    pub fn get_history(&self) -> &VecDeque<String> {
+    pub fn get_history(&self) -> &VecDeque<ClipboardEntry> {
         &self.history
     }
 
@@ -88,14 +114,14 @@ impl Clipboard {
         }
     }
 
-    // Yank multiple lines at once
+    // Yank multiple lines at once, line-wise
     pub fn yank_lines(&mut self, lines: Vec<String>) {
         if lines.is_empty() {
             return;
         }
 
-        let content = lines.join("\n");
-        self.yank(content);
+        let content = lines.join("\n") + "\n";
+        self.yank_as(content, YankType::Line);
     }
 
     // Get most recent content split into lines
@@ -168,13 +194,27 @@ mod tests {
         ];
 
         clipboard.yank_lines(lines);
-        assert_eq!(clipboard.peek(), Some(&"line1\nline2\nline3".to_string()));
+        assert_eq!(clipboard.peek(), Some(&"line1\nline2\nline3\n".to_string()));
+        assert_eq!(clipboard.peek_entry(0).map(|entry| entry.kind), Some(YankType::Line));
 
         if let Some(peeked_lines) = clipboard.peek_lines() {
             assert_eq!(peeked_lines, vec!["line1", "line2", "line3"]);
         }
     }
 
+    #[test]
+    fn test_yank_type() {
+        let mut clipboard = Clipboard::new();
+        clipboard.yank("word".to_string());
+        clipboard.yank_as("ab\ncd".to_string(), YankType::Block);
+        assert_eq!(clipboard.peek_entry(0), Some(&ClipboardEntry { text: "ab\ncd".to_string(), kind: YankType::Block }));
+        assert_eq!(clipboard.peek_entry(1).map(|entry| entry.kind), Some(YankType::Char));
+
+        // An empty yank leaves the history alone whatever its kind
+        clipboard.yank_as(String::new(), YankType::Line);
+        assert_eq!(clipboard.len(), 2);
+    }
+
     #[test]
     fn test_clear() {
         let mut clipboard = Clipboard::new();
@@ -0,0 +1,118 @@
+// src/editor/completion.rs
+use std::collections::HashSet;
+use super::buffer::{is_word_char, Buffer};
+
+/// Somewhere insert-mode completion finds words. Sources are asked in the
+/// order they were added and their words shown in that order.
+pub trait CompletionSource {
+    /// Words that could finish `prefix`, which ends at `cursor`, best first
+    fn complete(&self, buffer: &Buffer, prefix: &str, cursor: (usize, usize)) -> Vec<String>;
+}
+
+/// Words found in the buffer, nearest to the cursor first
+#[derive(Debug, Default)]
+pub struct BufferWords;
+
+impl CompletionSource for BufferWords {
+    fn complete(&self, buffer: &Buffer, prefix: &str, cursor: (usize, usize)) -> Vec<String> {
+        let lines = buffer.get_content();
+        let row = cursor.0.min(lines.len().saturating_sub(1));
+        // The cursor's line, then outward a line at a time, above first
+        let nearest = (0..lines.len()).flat_map(|distance| {
+            let above = row.checked_sub(distance).filter(|_| distance > 0);
+            let below = Some(row + distance).filter(|&below| below < lines.len());
+            above.into_iter().chain(below)
+        });
+
+        let mut seen = HashSet::new();
+        let mut words = Vec::new();
+        for line in nearest.map(|row| &lines[row]) {
+            for word in line.split(|c: char| !is_word_char(c)) {
+                if word.len() > prefix.len() && word.starts_with(prefix) && seen.insert(word) {
+                    words.push(word.to_string());
+                }
+            }
+        }
+        words
+    }
+}
+
+/// The completion popup: words that could finish the one being typed, one
+/// of them picked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub row: usize,
+    pub start: usize, // Byte column where the word being finished begins
+    pub prefix: String,
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+impl Completion {
+    /// Pick the next word, or with `forward` false the previous one, going
+    /// round at either end
+    pub fn select(&mut self, forward: bool) {
+        let count = self.items.len().max(1);
+        self.selected = match forward {
+            true => (self.selected + 1) % count,
+            false => (self.selected + count - 1) % count,
+        };
+    }
+
+    pub fn selected_item(&self) -> Option<&str> {
+        self.items.get(self.selected).map(String::as_str)
+    }
+}
+
+/// Where the word ending at byte column `col` of `line` begins
+pub fn word_start(line: &str, col: usize) -> usize {
+    let col = col.min(line.len());
+    line[..col].char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_word_char(c))
+        .last()
+        .map_or(col, |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_words() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec![
+            "let width = 1;".to_string(),
+            "let wide = window;".to_string(),
+            "wi".to_string(),
+            "let window_size = width;".to_string(),
+        ]);
+        let words = BufferWords.complete(&buffer, "wi", (2, 2));
+        assert_eq!(words, vec!["wide", "window", "window_size", "width"]);
+        assert!(BufferWords.complete(&buffer, "zz", (2, 2)).is_empty());
+    }
+
+    #[test]
+    fn test_completion_select() {
+        let mut completion = Completion {
+            row: 0,
+            start: 0,
+            prefix: "w".to_string(),
+            items: vec!["wide".to_string(), "width".to_string()],
+            selected: 0,
+        };
+        completion.select(true);
+        assert_eq!(completion.selected_item(), Some("width"));
+        completion.select(true);
+        assert_eq!(completion.selected_item(), Some("wide"));
+        completion.select(false);
+        assert_eq!(completion.selected_item(), Some("width"));
+    }
+
+    #[test]
+    fn test_word_start() {
+        assert_eq!(word_start("let wid", 7), 4);
+        assert_eq!(word_start("let ", 4), 4);
+        assert_eq!(word_start("héllo", 6), 0);
+    }
+}
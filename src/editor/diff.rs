@@ -0,0 +1,175 @@
+// src/editor/diff.rs
+
+/// Past this many cells in the comparison table, the differing middle of the
+/// two files is marked changed as a whole rather than diffed line by line
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// How a line compares with the other file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineDiff {
+    Same,
+    Changed, // Paired with a different line on the other side
+    Only,    // Added on this side, or removed from the other
+}
+
+/// Line-by-line comparison of two files
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    pub left: Vec<LineDiff>,
+    pub right: Vec<LineDiff>,
+    left_to_right: Vec<usize>,
+}
+
+impl Diff {
+    /// Row of the right file that lines up with `row` of the left one, which
+    /// keeps the two sides scrolled together
+    pub fn right_row(&self, row: usize) -> usize {
+        self.left_to_right.get(row).copied().unwrap_or_else(|| self.right.len().saturating_sub(1))
+    }
+}
+
+/// `--diff`: the file shown beside the buffer and how the two differ
+#[derive(Debug)]
+pub struct DiffView {
+    pub lines: Vec<String>,
+    pub diff: Diff,
+    pub compared: (usize, usize), // Buffer change id and line count the diff was made from
+}
+
+enum Edit {
+    Same(usize, usize),
+    Remove(usize),
+    Add(usize),
+}
+
+/// Compare `left` with `right` using their longest common subsequence of
+/// lines. Runs of removed and added lines between two matches are paired up
+/// as changed lines; whatever is left over in the longer run is `Only`.
+pub fn diff_lines(left: &[String], right: &[String]) -> Diff {
+    let mut diff = Diff {
+        left: vec![LineDiff::Same; left.len()],
+        right: vec![LineDiff::Same; right.len()],
+        left_to_right: Vec::with_capacity(left.len()),
+    };
+
+    // Matching lines at either end need no table
+    let prefix = left.iter().zip(right).take_while(|(a, b)| a == b).count();
+    let suffix = left[prefix..].iter().rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Same(i, i)).collect();
+    edits.extend(middle_edits(&left[prefix..left.len() - suffix], &right[prefix..right.len() - suffix], prefix));
+    edits.extend((0..suffix).map(|k| Edit::Same(left.len() - suffix + k, right.len() - suffix + k)));
+
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let mut next_right = 0;
+    for edit in edits.into_iter().chain([Edit::Same(usize::MAX, usize::MAX)]) {
+        match edit {
+            Edit::Remove(i) => removed.push(i),
+            Edit::Add(j) => added.push(j),
+            Edit::Same(i, j) => {
+                // The run of edits before this match is complete
+                for (k, &row) in removed.iter().enumerate() {
+                    diff.left[row] = if k < added.len() { LineDiff::Changed } else { LineDiff::Only };
+                    let lined_up = added.get(k).copied().unwrap_or(next_right + added.len());
+                    diff.left_to_right.push(lined_up.min(right.len().saturating_sub(1)));
+                }
+                for (k, &row) in added.iter().enumerate() {
+                    diff.right[row] = if k < removed.len() { LineDiff::Changed } else { LineDiff::Only };
+                }
+                next_right += added.len();
+                removed.clear();
+                added.clear();
+                if i != usize::MAX {
+                    diff.left_to_right.push(j);
+                    next_right = j + 1;
+                }
+            }
+        }
+    }
+    diff
+}
+
+// Edits turning `left` into `right`, whose first lines are row `offset` of
+// their files
+fn middle_edits(left: &[String], right: &[String], offset: usize) -> Vec<Edit> {
+    let (n, m) = (left.len(), right.len());
+    if (n + 1) * (m + 1) > MAX_DIFF_CELLS {
+        let mut edits: Vec<Edit> = (0..n).map(|i| Edit::Remove(offset + i)).collect();
+        edits.extend((0..m).map(|j| Edit::Add(offset + j)));
+        return edits;
+    }
+
+    // lengths[i][j]: longest common subsequence of left[i..] and right[j..]
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if left[i] == right[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && left[i] == right[j] {
+            edits.push(Edit::Same(offset + i, offset + j));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            edits.push(Edit::Remove(offset + i));
+            i += 1;
+        } else {
+            edits.push(Edit::Add(offset + j));
+            j += 1;
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LineDiff::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(' ').filter(|line| !line.is_empty()).map(String::from).collect()
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let diff = diff_lines(&lines("a b c d e"), &lines("a x c d y z e"));
+        assert_eq!(diff.left, vec![Same, Changed, Same, Same, Same]);
+        assert_eq!(diff.right, vec![Same, Changed, Same, Same, Only, Only, Same]);
+        assert_eq!((0..5).map(|row| diff.right_row(row)).collect::<Vec<_>>(), vec![0, 1, 2, 3, 6]);
+    }
+
+    #[test]
+    fn test_diff_removed_lines() {
+        let diff = diff_lines(&lines("a b c d"), &lines("a d"));
+        assert_eq!(diff.left, vec![Same, Only, Only, Same]);
+        assert_eq!(diff.right, vec![Same, Same]);
+        // Removed lines line up with the next line that's still there
+        assert_eq!((0..4).map(|row| diff.right_row(row)).collect::<Vec<_>>(), vec![0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_diff_empty_sides() {
+        let diff = diff_lines(&[], &lines("a b"));
+        assert_eq!(diff.left, vec![]);
+        assert_eq!(diff.right, vec![Only, Only]);
+
+        let diff = diff_lines(&lines("a"), &[]);
+        assert_eq!(diff.left, vec![Only]);
+        assert_eq!(diff.right_row(0), 0);
+
+        let same = lines("a b");
+        assert_eq!(diff_lines(&same, &same).left, vec![Same, Same]);
+    }
+}
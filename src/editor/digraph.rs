@@ -0,0 +1,64 @@
+// src/editor/digraph.rs
+
+// (first, second, result) - a subset of the RFC 1345 digraphs vim uses
+const DIGRAPHS: &[(char, char, char)] = &[
+    // Accented Latin letters
+    ('a', ':', 'ä'), ('e', ':', 'ë'), ('i', ':', 'ï'), ('o', ':', 'ö'), ('u', ':', 'ü'), ('y', ':', 'ÿ'),
+    ('A', ':', 'Ä'), ('E', ':', 'Ë'), ('I', ':', 'Ï'), ('O', ':', 'Ö'), ('U', ':', 'Ü'),
+    ('a', '\'', 'á'), ('e', '\'', 'é'), ('i', '\'', 'í'), ('o', '\'', 'ó'), ('u', '\'', 'ú'), ('y', '\'', 'ý'),
+    ('A', '\'', 'Á'), ('E', '\'', 'É'), ('I', '\'', 'Í'), ('O', '\'', 'Ó'), ('U', '\'', 'Ú'), ('Y', '\'', 'Ý'),
+    ('a', '!', 'à'), ('e', '!', 'è'), ('i', '!', 'ì'), ('o', '!', 'ò'), ('u', '!', 'ù'),
+    ('A', '!', 'À'), ('E', '!', 'È'), ('I', '!', 'Ì'), ('O', '!', 'Ò'), ('U', '!', 'Ù'),
+    ('a', '>', 'â'), ('e', '>', 'ê'), ('i', '>', 'î'), ('o', '>', 'ô'), ('u', '>', 'û'),
+    ('A', '>', 'Â'), ('E', '>', 'Ê'), ('I', '>', 'Î'), ('O', '>', 'Ô'), ('U', '>', 'Û'),
+    ('a', '?', 'ã'), ('o', '?', 'õ'), ('n', '?', 'ñ'), ('A', '?', 'Ã'), ('O', '?', 'Õ'), ('N', '?', 'Ñ'),
+    ('c', ',', 'ç'), ('C', ',', 'Ç'), ('a', 'a', 'å'), ('A', 'A', 'Å'), ('o', '/', 'ø'), ('O', '/', 'Ø'),
+    ('a', 'e', 'æ'), ('A', 'E', 'Æ'), ('s', 's', 'ß'),
+    // Greek
+    ('a', '*', 'α'), ('b', '*', 'β'), ('g', '*', 'γ'), ('d', '*', 'δ'), ('e', '*', 'ε'),
+    ('l', '*', 'λ'), ('m', '*', 'μ'), ('p', '*', 'π'), ('s', '*', 'σ'), ('w', '*', 'ω'),
+    ('D', '*', 'Δ'), ('S', '*', 'Σ'), ('W', '*', 'Ω'),
+    // Arrows
+    ('-', '>', '→'), ('<', '-', '←'), ('-', '!', '↑'), ('-', 'v', '↓'), ('<', '>', '↔'),
+    ('=', '>', '⇒'), ('<', '=', '⇐'),
+    // Maths
+    ('+', '-', '±'), ('*', 'X', '×'), ('-', ':', '÷'), ('!', '=', '≠'), ('=', '<', '≤'),
+    ('>', '=', '≥'), ('0', '0', '∞'), ('D', 'G', '°'), ('R', 'T', '√'),
+    ('1', '2', '½'), ('1', '4', '¼'), ('3', '4', '¾'), ('1', 'S', '¹'), ('2', 'S', '²'), ('3', 'S', '³'),
+    // Currency and typography
+    ('E', 'u', '€'), ('P', 'd', '£'), ('Y', 'e', '¥'), ('C', 't', '¢'),
+    ('C', 'o', '©'), ('R', 'g', '®'), ('T', 'M', '™'), ('S', 'E', '§'), ('P', 'I', '¶'),
+    ('<', '<', '«'), ('>', '>', '»'), ('!', 'I', '¡'), ('?', 'I', '¿'), ('.', 'M', '·'),
+    ('-', 'N', '–'), ('-', 'M', '—'), ('N', 'S', '\u{a0}'),
+    ('O', 'K', '✓'), ('X', 'X', '✗'),
+];
+
+/// Character for the digraph `first` `second`. Like vim, the two characters
+/// may also be typed the other way round.
+pub fn lookup(first: char, second: char) -> Option<char> {
+    let find = |a: char, b: char| {
+        DIGRAPHS.iter()
+            .find(|&&(x, y, _)| x == a && y == b)
+            .map(|&(_, _, c)| c)
+    };
+    find(first, second).or_else(|| find(second, first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup('a', ':'), Some('ä'));
+        assert_eq!(lookup('-', '>'), Some('→'));
+        assert_eq!(lookup('E', 'u'), Some('€'));
+        assert_eq!(lookup('q', 'q'), None);
+    }
+
+    #[test]
+    fn test_lookup_reversed() {
+        assert_eq!(lookup(':', 'a'), Some('ä'));
+        assert_eq!(lookup('>', '-'), Some('→'));
+    }
+}
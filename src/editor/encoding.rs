@@ -0,0 +1,210 @@
+// src/editor/encoding.rs
+use std::io;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// How a file's text is stored on disk. The buffer always holds it as UTF-8;
+/// it's converted on the way in and back on the way out. Whether the file
+/// starts with a byte order mark is kept beside it, as `bomb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1, // ISO-8859-1: one byte per character, U+0000 to U+00FF
+}
+
+impl Encoding {
+    /// An encoding by the name :set fileencoding takes
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    /// The byte order mark a file in this encoding may start with; Latin-1
+    /// has none
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => UTF8_BOM,
+            Encoding::Utf16Le => UTF16LE_BOM,
+            Encoding::Utf16Be => UTF16BE_BOM,
+            Encoding::Latin1 => &[],
+        }
+    }
+
+    pub fn has_bom(self, bytes: &[u8]) -> bool {
+        !self.bom().is_empty() && bytes.starts_with(self.bom())
+    }
+
+    /// Work out the encoding of `bytes`: from a byte order mark if there is
+    /// one, then UTF-16 if every other byte is mostly zero, as in ASCII text
+    /// stored that way, then UTF-8 if they're valid as it, and Latin-1, which
+    /// any bytes are, failing all that
+    pub fn detect(bytes: &[u8]) -> Self {
+        let marked = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be];
+        if let Some(&encoding) = marked.iter().find(|encoding| encoding.has_bom(bytes)) {
+            return encoding;
+        }
+        // NULs are valid UTF-8, so this has to come first
+        if !bytes.is_empty() && bytes.len().is_multiple_of(2) {
+            let zeros = |parity: usize| bytes.iter().skip(parity).step_by(2).filter(|&&b| b == 0).count();
+            let half = bytes.len() / 2;
+            if zeros(1) * 2 > half && zeros(0) == 0 {
+                return Encoding::Utf16Le;
+            }
+            if zeros(0) * 2 > half && zeros(1) == 0 {
+                return Encoding::Utf16Be;
+            }
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            return Encoding::Utf8;
+        }
+        Encoding::Latin1
+    }
+
+    /// The text in `bytes`, without any byte order mark. What can't be
+    /// decoded becomes U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let bytes = if self.has_bom(bytes) { &bytes[self.bom().len()..] } else { bytes };
+        self.decode_text(bytes)
+    }
+
+    /// The text in `bytes`, taking a byte order mark at the start as text
+    pub fn decode_text(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+            Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+        }
+    }
+
+    /// `text` as this encoding stores it, after a byte order mark if `bom`.
+    /// Latin-1 can't hold everything UTF-8 can; that's an error naming the
+    /// line at fault.
+    pub fn encode(self, text: &str, bom: bool) -> io::Result<Vec<u8>> {
+        let mut bytes = if bom { self.bom().to_vec() } else { Vec::new() };
+        match self {
+            Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Encoding::Latin1 => {
+                bytes.reserve(text.len());
+                for (row, line) in text.split('\n').enumerate() {
+                    if row > 0 {
+                        bytes.push(b'\n');
+                    }
+                    for c in line.chars() {
+                        let byte = u8::try_from(c).map_err(|_| io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Can't convert line {} to latin1", row + 1),
+                        ))?;
+                        bytes.push(byte);
+                    }
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+// An odd byte at the end is dropped as U+FFFD
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks(2).map(|pair| match pair {
+        &[a, b] => unit([a, b]),
+        _ => 0xFFFD,
+    });
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Text read from a file, and how the file stored it
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    pub bom: bool, // The file started with a byte order mark, left out of `text`
+}
+
+/// Decode `bytes` as `encoding` or, without one, as whatever encoding they
+/// seem to be in
+pub fn decode(bytes: &[u8], encoding: Option<Encoding>) -> Decoded {
+    let encoding = encoding.unwrap_or_else(|| Encoding::detect(bytes));
+    Decoded { text: encoding.decode(bytes), encoding, bom: encoding.has_bom(bytes) }
+}
+
+pub fn read_file(path: &std::path::Path, encoding: Option<Encoding>) -> io::Result<Decoded> {
+    Ok(decode(&std::fs::read(path)?, encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect(b"plain"), Encoding::Utf8);
+        assert_eq!(Encoding::detect("caf\u{e9}".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xEF\xBB\xBFhi"), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xFF\xFEh\0i\0"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\xFE\xFF\0h\0i"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"h\0i\0!\0"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\0h\0i\0!"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"caf\xE9"), Encoding::Latin1);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "caf\u{e9}\nna\u{ef}ve";
+        for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be, Encoding::Latin1] {
+            let bytes = encoding.encode(text, true).unwrap();
+            assert_eq!(Encoding::detect(&bytes), encoding);
+            assert_eq!(encoding.decode(&bytes), text);
+        }
+        assert_eq!(Encoding::Latin1.encode("caf\u{e9}", true).unwrap(), b"caf\xE9");
+        assert_eq!(Encoding::Utf16Le.encode("hi", true).unwrap(), b"\xFF\xFEh\0i\0");
+        assert_eq!(Encoding::Utf16Le.encode("hi", false).unwrap(), b"h\0i\0");
+    }
+
+    #[test]
+    fn test_latin1_cannot_hold_everything() {
+        let e = Encoding::Latin1.encode("ok\n\u{2603}", false).unwrap_err();
+        assert_eq!(e.to_string(), "Can't convert line 2 to latin1");
+    }
+
+    #[test]
+    fn test_decode_keeps_bom_apart() {
+        let decoded = decode(b"\xEF\xBB\xBFhi", None);
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.bom), ("hi", Encoding::Utf8, true));
+        let decoded = decode(b"h\0i\0", None);
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.bom), ("hi", Encoding::Utf16Le, false));
+        // A forced encoding still drops its own mark
+        let decoded = decode(b"\xFE\xFF\0h", Some(Encoding::Utf16Be));
+        assert_eq!((decoded.text.as_str(), decoded.bom), ("h", true));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Encoding::parse("UTF-16LE"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::parse("latin1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::parse("ebcdic"), None);
+    }
+}
@@ -0,0 +1,113 @@
+// src/editor/filetype.rs
+use std::path::Path;
+
+// File extension -> filetype name
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("py", "python"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("ts", "typescript"),
+    ("md", "markdown"),
+    ("toml", "toml"),
+    ("json", "json"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cpp", "cpp"),
+    ("hpp", "cpp"),
+    ("go", "go"),
+    ("java", "java"),
+    ("rb", "ruby"),
+    ("sh", "sh"),
+    ("bash", "sh"),
+    ("html", "html"),
+    ("css", "css"),
+    ("txt", "text"),
+];
+
+// Files recognised by their whole name rather than an extension
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("Makefile", "make"),
+    ("makefile", "make"),
+    ("Dockerfile", "dockerfile"),
+];
+
+// Interpreter named in a shebang line -> filetype name
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "python"),
+    ("node", "javascript"),
+    ("bash", "sh"),
+    ("sh", "sh"),
+    ("zsh", "sh"),
+    ("ruby", "ruby"),
+    ("perl", "perl"),
+];
+
+/// Detect a filetype from the path, falling back to a `#!` line
+pub fn detect_filetype(path: &Path, first_line: Option<&str>) -> Option<String> {
+    let by_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| lookup(FILE_NAMES, name));
+    let by_extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| lookup(EXTENSIONS, ext));
+
+    by_name
+        .or(by_extension)
+        .or_else(|| first_line.and_then(detect_from_shebang))
+        .map(String::from)
+}
+
+// "#!/usr/bin/env python3" or "#!/bin/bash -e"
+fn detect_from_shebang(line: &str) -> Option<&'static str> {
+    let command = line.strip_prefix("#!")?;
+    let mut words = command.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    // python3, python3.11 -> python
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    lookup(INTERPRETERS, program)
+}
+
+fn lookup(table: &[(&str, &'static str)], key: &str) -> Option<&'static str> {
+    table.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_by_extension() {
+        assert_eq!(detect_filetype(Path::new("src/main.rs"), None), Some("rust".to_string()));
+        assert_eq!(detect_filetype(Path::new("Cargo.toml"), None), Some("toml".to_string()));
+        assert_eq!(detect_filetype(Path::new("Makefile"), None), Some("make".to_string()));
+        assert_eq!(detect_filetype(Path::new("notes.xyz"), None), None);
+    }
+
+    #[test]
+    fn test_detect_by_shebang() {
+        assert_eq!(
+            detect_filetype(Path::new("script"), Some("#!/usr/bin/env python3")),
+            Some("python".to_string())
+        );
+        assert_eq!(
+            detect_filetype(Path::new("run"), Some("#!/bin/bash -e")),
+            Some("sh".to_string())
+        );
+        assert_eq!(detect_filetype(Path::new("run"), Some("echo hi")), None);
+    }
+
+    #[test]
+    fn test_extension_wins_over_shebang() {
+        assert_eq!(
+            detect_filetype(Path::new("tool.rb"), Some("#!/usr/bin/env python")),
+            Some("ruby".to_string())
+        );
+    }
+}
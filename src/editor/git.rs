@@ -0,0 +1,160 @@
+// src/editor/git.rs
+use std::path::Path;
+use std::process::{Command, Stdio};
+use super::diff::{self, Diff, LineDiff};
+
+/// How a line of the buffer differs from the file as committed, shown in the
+/// gutter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GitMark {
+    Added,
+    Modified,
+    Deleted, // Lines were removed below this one
+}
+
+impl GitMark {
+    pub fn symbol(self) -> char {
+        match self {
+            GitMark::Added => '+',
+            GitMark::Modified => '~',
+            GitMark::Deleted => '_',
+        }
+    }
+}
+
+/// A file's committed lines and the marks they give the buffer's lines
+#[derive(Debug)]
+pub struct GitGutter {
+    head: Vec<String>,
+    marks: Vec<Option<GitMark>>,
+    compared: (usize, usize), // Buffer change id and line count the marks were made from
+}
+
+impl GitGutter {
+    pub fn new(head: Vec<String>) -> Self {
+        Self { head, marks: Vec::new(), compared: (usize::MAX, 0) }
+    }
+
+    /// Compare `lines` with HEAD again unless they are the ones last
+    /// compared. True if any mark changed.
+    pub fn refresh(&mut self, lines: &[String], compared: (usize, usize)) -> bool {
+        if self.compared == compared {
+            return false;
+        }
+        self.compared = compared;
+        let marks = line_marks(&diff::diff_lines(lines, &self.head));
+        let changed = marks != self.marks;
+        self.marks = marks;
+        changed
+    }
+
+    pub fn mark(&self, row: usize) -> Option<GitMark> {
+        self.marks.get(row).copied().flatten()
+    }
+}
+
+/// The lines of `path` as committed at HEAD. None outside a repository, for
+/// a file git doesn't track, or when git can't be run.
+pub fn head_lines(path: &Path) -> Option<Vec<String>> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name()?.to_string_lossy();
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("HEAD:./{}", name))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success()
+        .then(|| String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect())
+}
+
+/// The branch checked out in the repository holding `path`, or the start of
+/// the commit hash when HEAD is detached. None outside a repository.
+pub fn branch_name(path: &Path) -> Option<String> {
+    let path = std::path::absolute(path).ok()?;
+    let dot_git = path.ancestors().skip(1).map(|dir| dir.join(".git")).find(|dot_git| dot_git.exists())?;
+    // In a worktree or submodule `.git` is a file naming the real git directory
+    let git_dir = match std::fs::read_to_string(&dot_git) {
+        Ok(contents) => {
+            let dir = Path::new(contents.trim().strip_prefix("gitdir:")?.trim());
+            dot_git.parent()?.join(dir)
+        }
+        Err(_) => dot_git,
+    };
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string())
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// A mark for each line of the buffer, from `diff` of the buffer (left) with
+/// HEAD (right). Removed lines are marked on the line above them, or on the
+/// first line when they were at the top.
+pub fn line_marks(diff: &Diff) -> Vec<Option<GitMark>> {
+    let mut marks: Vec<Option<GitMark>> = diff.left.iter()
+        .map(|status| match status {
+            LineDiff::Same => None,
+            LineDiff::Changed => Some(GitMark::Modified),
+            LineDiff::Only => Some(GitMark::Added),
+        })
+        .collect();
+    let mut above = 0; // Buffer lines lined up before the removed line
+    for (row, _) in diff.right.iter().enumerate().filter(|(_, &status)| status == LineDiff::Only) {
+        while above < marks.len() && diff.right_row(above) < row {
+            above += 1;
+        }
+        if let Some(mark @ None) = marks.get_mut(above.saturating_sub(1)) {
+            *mark = Some(GitMark::Deleted);
+        }
+    }
+    marks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use GitMark::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_line_marks() {
+        let diff = diff::diff_lines(&lines("a x c new d"), &lines("a b c d e"));
+        assert_eq!(line_marks(&diff), vec![None, Some(Modified), None, Some(Added), Some(Deleted)]);
+
+        let diff = diff::diff_lines(&lines("c d"), &lines("a b c d"));
+        assert_eq!(line_marks(&diff), vec![Some(Deleted), None]);
+
+        let diff = diff::diff_lines(&lines("a d"), &lines("a b c d"));
+        assert_eq!(line_marks(&diff), vec![Some(Deleted), None]);
+    }
+
+    #[test]
+    fn test_branch_name() {
+        let dir = std::env::temp_dir().join(format!("zorforge_git_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let file = dir.join("src/main.rs");
+
+        std::fs::write(dir.join(".git/HEAD"), "ref: refs/heads/feature/gutter\n").unwrap();
+        assert_eq!(branch_name(&file).as_deref(), Some("feature/gutter"));
+        std::fs::write(dir.join(".git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(branch_name(&file).as_deref(), Some("0123456"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_head_lines_outside_repository() {
+        assert_eq!(head_lines(Path::new("/no/such/dir/file.rs")), None);
+    }
+}
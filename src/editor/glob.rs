@@ -0,0 +1,188 @@
+// src/editor/glob.rs
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Whether `arg` has `*`, `?` or `[...]` in it, and so names files by pattern
+pub fn is_pattern(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+/// `~` or `~/...` from the home directory
+pub fn expand_tilde(arg: &str) -> PathBuf {
+    let home = || dirs::home_dir().filter(|_| arg == "~" || arg.starts_with("~/"));
+    match home() {
+        Some(home) => home.join(arg[1..].trim_start_matches('/')),
+        None => PathBuf::from(arg),
+    }
+}
+
+/// The files a command-line file argument names, sorted. A name without
+/// wildcards is taken as it is, whether or not the file exists yet, and so is
+/// a pattern that matches nothing, as for `dir/[id].tsx`. `**` stands for
+/// any number of directories.
+pub fn expand(arg: &str) -> io::Result<Vec<PathBuf>> {
+    let path = expand_tilde(arg);
+    if !is_pattern(arg) {
+        return Ok(vec![path]);
+    }
+
+    let mut found = vec![PathBuf::new()];
+    for component in path.components() {
+        let part = match component {
+            Component::Normal(part) => part.to_string_lossy(),
+            other => {
+                found.iter_mut().for_each(|path| path.push(other));
+                continue;
+            }
+        };
+        found = match part.as_ref() {
+            "**" => found.iter().flat_map(|dir| subdirectories(dir)).collect(),
+            part if is_pattern(part) => found.iter()
+                .flat_map(|dir| matching_entries(dir, part))
+                .collect(),
+            part => found.into_iter().map(|path| path.join(part)).collect(),
+        };
+    }
+
+    found.retain(|path| path.exists());
+    if found.is_empty() {
+        return Ok(vec![path]);
+    }
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+/// A file argument that can only name one file, as for :w
+pub fn expand_one(arg: &str) -> io::Result<PathBuf> {
+    let mut paths = expand(arg)?;
+    if paths.len() > 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Too many file names: {}", arg)));
+    }
+    Ok(paths.remove(0))
+}
+
+// An empty path is the working directory, as relative names are
+fn list_dir(dir: &Path) -> impl Iterator<Item = (String, PathBuf)> + '_ {
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    std::fs::read_dir(listed).into_iter()
+        .flatten()
+        .flatten()
+        .map(move |entry| (entry.file_name().to_string_lossy().into_owned(), dir.join(entry.file_name())))
+}
+
+fn matching_entries(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    list_dir(dir)
+        .filter(|(name, _)| matches(pattern, name))
+        .map(|(_, path)| path)
+        .collect()
+}
+
+// `dir` and every directory below it, leaving out hidden ones
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![dir.to_path_buf()];
+    let mut next = 0;
+    while next < dirs.len() {
+        let below: Vec<PathBuf> = list_dir(&dirs[next])
+            .filter(|(name, path)| !name.starts_with('.') && path.is_dir())
+            .map(|(_, path)| path)
+            .collect();
+        dirs.extend(below);
+        next += 1;
+    }
+    dirs
+}
+
+/// Whether the file name `name` fits `pattern`, as a shell matches: `*` is
+/// any run of characters, `?` any one, and `[abc]`, `[a-z]` or `[!abc]` one
+/// of a set. Wildcards don't match the `.` that starts a hidden file.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_from(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_from(rest, &name[1..]),
+        Some(('[', rest)) => match (name.first(), rest.iter().position(|&c| c == ']')) {
+            (Some(&c), Some(close)) if close > 0 => {
+                in_set(&rest[..close], c) && matches_from(&rest[close + 1..], &name[1..])
+            }
+            // No closing bracket: the `[` is an ordinary character
+            _ => name.first() == Some(&'[') && matches_from(rest, &name[1..]),
+        },
+        Some((&c, rest)) => name.first() == Some(&c) && matches_from(rest, &name[1..]),
+    }
+}
+
+// `abc`, `a-z` or `!abc`, the inside of a `[...]`
+fn in_set(set: &[char], c: char) -> bool {
+    let (negated, set) = match set.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, set),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("ma?n.*", "main.rs"));
+        assert!(matches("[a-m]ain.rs", "main.rs"));
+        assert!(!matches("[!m]ain.rs", "main.rs"));
+        assert!(matches("[ab", "[ab"));
+        assert!(!matches("*", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("zorforge_glob_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        for file in ["src/b.rs", "src/a.rs", "src/notes.txt", "src/nested/c.rs"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let pattern = |rest: &str| format!("{}/{}", dir.display(), rest);
+
+        assert_eq!(expand(&pattern("src/*.rs")).unwrap(), vec![dir.join("src/a.rs"), dir.join("src/b.rs")]);
+        assert_eq!(expand(&pattern("**/c.rs")).unwrap(), vec![dir.join("src/nested/c.rs")]);
+        assert_eq!(expand(&pattern("src/*.md")).unwrap(), vec![dir.join("src/*.md")]);
+        assert!(expand_one(&pattern("src/*.rs")).is_err());
+        assert_eq!(expand(&pattern("new.rs")).unwrap(), vec![dir.join("new.rs")]);
+        // Names with brackets or ? in them are taken as they are
+        std::fs::write(dir.join("src/[id].tsx"), "").unwrap();
+        assert_eq!(expand_one(&pattern("src/[id].tsx")).unwrap(), dir.join("src/[id].tsx"));
+        assert_eq!(expand_one(&pattern("new?.txt")).unwrap(), dir.join("new?.txt"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_tilde("~/notes.txt"), home.join("notes.txt"));
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~user/x"), PathBuf::from("~user/x"));
+    }
+}
@@ -0,0 +1,102 @@
+// src/editor/help.rs
+use super::which_key;
+
+// Normal-mode keys that do something on their own. Keys that wait for a
+// second one come from which_key's table.
+const KEYS: &[(&str, &str)] = &[
+    ("h j k l", "move left, down, up, right"),
+    ("w b e", "next word, previous word, end of word"),
+    ("W B E", "the same by WORDs, which only blanks end"),
+    ("0 ^ $", "start, first non-blank or end of line"),
+    ("Home", "first non-blank, then start of line"),
+    ("{count}|", "screen column {count}"),
+    ("{ }", "previous or next paragraph"),
+    ("( )", "previous or next sentence"),
+    ("{count}%", "{count} percent of the way into the file"),
+    ("G", "last line"),
+    ("^D ^U", "scroll half a page down or up"),
+    ("^E ^Y", "scroll a line down or up"),
+    ("^O Tab", "back or forward in the jump list"),
+    ("i a I A", "insert before, after, at start, at end of line"),
+    ("o O", "open a line below or above"),
+    ("R", "replace mode"),
+    ("v V ^V", "select characters, lines or a block"),
+    (": / ?", "command line, search forward, search backward"),
+    ("n N", "next or previous match"),
+    ("x s", "cut characters, and insert in their place"),
+    ("S C D", "change line, change or delete to end of line"),
+    ("J", "join lines"),
+    ("Y p", "yank line, paste"),
+    ("u ^R", "undo, redo"),
+    (".", "repeat the last insert"),
+    ("^A ^X", "add to or subtract from the number at the cursor"),
+    ("^G", "file name and position"),
+    ("^L", "redraw the screen"),
+];
+
+/// Normal-mode keys as :help lists them, as (keys, what they do)
+pub fn key_entries() -> Vec<(String, String)> {
+    KEYS.iter()
+        .map(|(keys, action)| (keys.to_string(), action.to_string()))
+        .chain(which_key::all_continuations())
+        .collect()
+}
+
+/// A :help listing, shown a page at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct HelpView {
+    lines: Vec<String>,
+    top: usize,
+}
+
+impl HelpView {
+    pub fn new(lines: Vec<String>) -> Self {
+        Self { lines, top: 0 }
+    }
+
+    /// Move `delta` lines down the listing (up if negative), stopping with
+    /// its last line at the bottom of a `height`-line page
+    pub fn scroll(&mut self, delta: isize, height: usize) {
+        let last_top = self.lines.len().saturating_sub(height);
+        self.top = self.top.saturating_add_signed(delta).min(last_top);
+    }
+
+    /// The lines on a page of `height` lines
+    pub fn page(&self, height: usize) -> &[String] {
+        let end = (self.top + height).min(self.lines.len());
+        &self.lines[self.top..end]
+    }
+
+    /// The first line shown, counting from 1, and how many there are
+    pub fn position(&self) -> (usize, usize) {
+        (self.top + 1, self.lines.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_entries() {
+        let keys = key_entries();
+        assert!(keys.iter().any(|(keys, _)| keys == "u ^R"));
+        assert!(keys.iter().any(|(keys, action)| keys == "gg" && action == "go to first line"));
+        assert!(keys.iter().any(|(keys, _)| keys == "^Ws"));
+    }
+
+    #[test]
+    fn test_help_view_scroll() {
+        let mut view = HelpView::new((1..=10).map(|i| i.to_string()).collect());
+        assert_eq!(view.page(4), ["1", "2", "3", "4"]);
+
+        view.scroll(3, 4);
+        assert_eq!(view.page(4), ["4", "5", "6", "7"]);
+        // The last page stays full
+        view.scroll(100, 4);
+        assert_eq!(view.page(4), ["7", "8", "9", "10"]);
+        assert_eq!(view.position(), (7, 10));
+        view.scroll(-100, 4);
+        assert_eq!(view.position(), (1, 10));
+    }
+}
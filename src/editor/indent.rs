@@ -0,0 +1,184 @@
+// src/editor/indent.rs
+use super::modeline::ModelineSettings;
+
+/// Number of indented lines looked at to work out a file's indentation
+const INDENT_SCAN_LINES: usize = 100;
+
+/// How auto-indent indents a new line, by filetype
+pub trait IndentProvider {
+    /// Levels the line after `prev_line` is indented by compared to it: 1
+    /// for one deeper, -1 for one shallower, 0 for the same
+    fn indent_after(&self, prev_line: &str) -> i32;
+
+    /// What one level is, when the filetype insists on it; otherwise the
+    /// buffer's own tabs or spaces
+    fn indent_unit(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Keeps the previous line's indentation, for filetypes without rules of
+/// their own (YAML and plain text among them)
+struct CopyIndent;
+
+impl IndentProvider for CopyIndent {
+    fn indent_after(&self, _prev_line: &str) -> i32 {
+        0
+    }
+}
+
+/// Languages with braces: one deeper after an opening bracket
+struct BracketIndent;
+
+impl IndentProvider for BracketIndent {
+    fn indent_after(&self, prev_line: &str) -> i32 {
+        match prev_line.trim_end().ends_with(['{', '(', '[']) {
+            true => 1,
+            false => 0,
+        }
+    }
+}
+
+/// One deeper after a block's `:`, and back out after a line that ends one
+struct PythonIndent;
+
+impl IndentProvider for PythonIndent {
+    fn indent_after(&self, prev_line: &str) -> i32 {
+        let line = prev_line.trim();
+        let first_word = line.split(|c: char| !c.is_alphanumeric()).next().unwrap_or("");
+        if line.ends_with(':') {
+            1
+        } else if matches!(first_word, "return" | "pass" | "break" | "continue" | "raise") {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+/// A rule's recipe goes under its target line, indented with a tab
+struct MakeIndent;
+
+impl IndentProvider for MakeIndent {
+    fn indent_after(&self, prev_line: &str) -> i32 {
+        let is_target = !prev_line.starts_with(char::is_whitespace)
+            && !prev_line.starts_with('#')
+            && prev_line.split_once(':').is_some_and(|(target, rest)| {
+                !target.contains('=') && !rest.starts_with('=')
+            });
+        match is_target {
+            true => 1,
+            false => 0,
+        }
+    }
+
+    fn indent_unit(&self) -> Option<&'static str> {
+        Some("\t")
+    }
+}
+
+/// The auto-indent rules for `filetype`
+pub fn indent_provider(filetype: Option<&str>) -> &'static dyn IndentProvider {
+    match filetype {
+        Some("rust" | "c" | "cpp" | "go" | "java" | "javascript" | "typescript" | "json" | "css") => &BracketIndent,
+        Some("python") => &PythonIndent,
+        Some("make") => &MakeIndent,
+        _ => &CopyIndent,
+    }
+}
+
+/// Whether `lines` indent with tabs or spaces, and by how many spaces a
+/// level, going by the first indented lines. The answer is put the way a
+/// modeline would say it; None when nothing is indented.
+pub fn detect_indent<S: AsRef<str>>(lines: &[S]) -> Option<ModelineSettings> {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    let mut steps = [0usize; 9]; // How often the indent grows by 1-8 spaces from one line to the next
+    let mut previous = 0;
+    let indented = lines.iter()
+        .map(AsRef::as_ref)
+        .filter(|line| !line.trim().is_empty());
+    for line in indented {
+        let depth = line.len() - line.trim_start_matches(' ').len();
+        if line.starts_with('\t') {
+            tabs += 1;
+        } else if depth > 0 {
+            spaces += 1;
+            if let Some(count) = steps.get_mut(depth.saturating_sub(previous)) {
+                *count += 1;
+            }
+        }
+        if !line.starts_with('\t') {
+            previous = depth;
+        }
+        if tabs + spaces >= INDENT_SCAN_LINES {
+            break;
+        }
+    }
+
+    if tabs == 0 && spaces == 0 {
+        return None;
+    }
+    if tabs > spaces {
+        return Some(ModelineSettings { expand_tab: Some(false), ..Default::default() });
+    }
+    // The most common step is the indent width; ties go to the smaller one
+    let width = (2..steps.len())
+        .max_by_key(|&width| (steps[width], std::cmp::Reverse(width)))
+        .filter(|&width| steps[width] > 0)?;
+    Some(ModelineSettings {
+        tab_size: Some(width),
+        shift_width: Some(width),
+        expand_tab: Some(true),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indent_providers() {
+        let rust = indent_provider(Some("rust"));
+        assert_eq!(rust.indent_after("fn main() {"), 1);
+        assert_eq!(rust.indent_after("    let x = 1;"), 0);
+
+        let python = indent_provider(Some("python"));
+        assert_eq!(python.indent_after("def f(x):  "), 1);
+        assert_eq!(python.indent_after("    return x"), -1);
+        assert_eq!(python.indent_after("    returned = x"), 0);
+
+        let make = indent_provider(Some("make"));
+        assert_eq!(make.indent_after("all: main.o"), 1);
+        assert_eq!(make.indent_after("CC := gcc"), 0);
+        assert_eq!(make.indent_after("\tcc -o main main.o"), 0);
+        assert_eq!(make.indent_unit(), Some("\t"));
+
+        assert_eq!(indent_provider(Some("yaml")).indent_after("key:"), 0);
+        assert_eq!(indent_provider(None).indent_after("{"), 0);
+    }
+
+    #[test]
+    fn test_detect_spaces() {
+        let lines = ["fn main() {", "  if x {", "    y();", "  }", "", "  z();", "}"];
+        let settings = detect_indent(&lines).unwrap();
+        assert_eq!(settings.expand_tab, Some(true));
+        assert_eq!(settings.shift_width, Some(2));
+        assert_eq!(settings.tab_size, Some(2));
+    }
+
+    #[test]
+    fn test_detect_tabs() {
+        let lines = ["int main() {", "\tif (x) {", "\t\ty();", "\t}", "}"];
+        let settings = detect_indent(&lines).unwrap();
+        assert_eq!(settings.expand_tab, Some(false));
+        assert_eq!(settings.shift_width, None);
+    }
+
+    #[test]
+    fn test_detect_nothing_indented() {
+        assert_eq!(detect_indent(&["one", "two"]), None);
+        assert_eq!(detect_indent::<&str>(&[]), None);
+    }
+}
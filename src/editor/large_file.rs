@@ -0,0 +1,117 @@
+// src/editor/large_file.rs
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use parking_lot::Mutex;
+
+/// Files at least this large open read-only, backed by the file on disk
+pub const LARGE_FILE_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// Bytes read at a time while indexing line starts
+const INDEX_CHUNK: usize = 64 * 1024;
+
+/// A read-only view of a file too large to hold as owned lines. Only the
+/// byte offset of each line is kept in memory; line text is read from disk
+/// when it's asked for, so just the visible rows are ever materialized.
+#[derive(Debug)]
+pub struct LargeFile {
+    file: Mutex<File>, // Seek-then-read must not interleave across threads
+    line_starts: Vec<u64>, // Byte offset at which each line begins
+    len: u64,
+}
+
+impl LargeFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut line_starts = vec![0];
+        let mut chunk = vec![0; INDEX_CHUNK];
+        let mut offset = 0;
+        loop {
+            let n = file.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            line_starts.extend(
+                chunk[..n].iter()
+                    .enumerate()
+                    .filter(|(_, &byte)| byte == b'\n')
+                    .map(|(i, _)| offset + i as u64 + 1),
+            );
+            offset += n as u64;
+        }
+
+        // A trailing newline ends the last line rather than starting a new one
+        if line_starts.len() > 1 && line_starts.last() == Some(&len) {
+            line_starts.pop();
+        }
+
+        Ok(Self {
+            file: Mutex::new(file),
+            line_starts,
+            len,
+        })
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Text of `row` without its line ending. Invalid UTF-8 is replaced
+    /// rather than failing, since the file is only being viewed.
+    pub fn line(&self, row: usize) -> Option<String> {
+        let start = *self.line_starts.get(row)?;
+        let end = self.line_starts.get(row + 1).copied().unwrap_or(self.len);
+
+        let mut bytes = vec![0; (end - start) as usize];
+        let mut file = self.file.lock();
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut bytes).ok()?;
+
+        let text = bytes.strip_suffix(b"\n").unwrap_or(&bytes);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        Some(String::from_utf8_lossy(text).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_with(name: &str, contents: &str) -> (std::path::PathBuf, LargeFile) {
+        let path = std::env::temp_dir().join(format!("zorforge_large_{}_{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let file = LargeFile::open(&path).unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn test_line_index() {
+        let (path, file) = open_with("index", "first\r\nsecond\n\nlast\n");
+        assert_eq!(file.line_count(), 4);
+        assert_eq!(file.line(0).as_deref(), Some("first"));
+        assert_eq!(file.line(1).as_deref(), Some("second"));
+        assert_eq!(file.line(2).as_deref(), Some(""));
+        assert_eq!(file.line(3).as_deref(), Some("last"));
+        assert_eq!(file.line(4), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_line_index_without_trailing_newline() {
+        let (path, file) = open_with("no_newline", "one\ntwo");
+        assert_eq!(file.line_count(), 2);
+        assert_eq!(file.line(1).as_deref(), Some("two"));
+        std::fs::remove_file(&path).unwrap();
+
+        let (path, file) = open_with("empty", "");
+        assert_eq!(file.line_count(), 1);
+        assert_eq!(file.line(0).as_deref(), Some(""));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+// src/editor/loader.rs
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use super::encoding::Encoding;
+
+/// Files at least this large are streamed in on a background thread
+pub const BACKGROUND_LOAD_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// Number of lines sent to the editor at a time
+const CHUNK_LINES: usize = 4096;
+
+/// How much of the start of the file its encoding is judged by
+const DETECT_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum LoadEvent {
+    /// How the file is stored. Comes before the first chunk, and again if
+    /// the file turns out not to be UTF-8 and is read over from the top.
+    Encoding { encoding: Encoding, bom: bool },
+    Lines { lines: Vec<String>, first: bool },
+    Done,
+    Failed(io::Error),
+}
+
+// Message from the reader thread; `bytes_read` drives the progress indicator
+struct LoadChunk {
+    event: LoadEvent,
+    bytes_read: u64,
+}
+
+/// Reads a file line by line on its own thread, handing chunks of lines back
+/// to the editor as they become available
+pub struct FileLoader {
+    receiver: Receiver<LoadChunk>,
+    total_bytes: u64,
+    bytes_read: u64,
+}
+
+impl FileLoader {
+    /// Read `path` as `encoding` or, without one, as what it seems to be in,
+    /// the way encoding::decode reads a whole file
+    pub fn spawn(path: &Path, encoding: Option<Encoding>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let total_bytes = file.metadata()?.len();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let send = |event, bytes_read| sender.send(LoadChunk { event, bytes_read }).is_ok();
+            if let Err(e) = read_lines(file, encoding, total_bytes, &send) {
+                send(LoadEvent::Failed(e), 0);
+            }
+        });
+
+        Ok(Self {
+            receiver,
+            total_bytes,
+            bytes_read: 0,
+        })
+    }
+
+    /// Next chunk if one is ready, without blocking
+    pub fn try_recv(&mut self) -> Option<LoadEvent> {
+        match self.receiver.try_recv() {
+            Ok(chunk) => Some(self.accept(chunk)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Self::disconnected()),
+        }
+    }
+
+    /// Wait for the next chunk
+    pub fn recv(&mut self) -> LoadEvent {
+        match self.receiver.recv() {
+            Ok(chunk) => self.accept(chunk),
+            Err(_) => Self::disconnected(),
+        }
+    }
+
+    /// How much of the file has been read, as a whole percentage
+    pub fn progress(&self) -> usize {
+        (self.bytes_read * 100 / self.total_bytes.max(1)) as usize
+    }
+
+    fn accept(&mut self, chunk: LoadChunk) -> LoadEvent {
+        self.bytes_read = chunk.bytes_read;
+        chunk.event
+    }
+
+    fn disconnected() -> LoadEvent {
+        LoadEvent::Failed(io::Error::other("File loader stopped unexpectedly"))
+    }
+}
+
+// The loader thread's work: send `file` in chunks of lines, stopping early
+// if the editor has dropped the loader, e.g. by opening another file
+fn read_lines(
+    file: File,
+    forced: Option<Encoding>,
+    total_bytes: u64,
+    send: impl Fn(LoadEvent, u64) -> bool,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(file);
+    let mut start = Vec::new();
+    (&mut reader).take(DETECT_BYTES).read_to_end(&mut start)?;
+    reader.rewind()?;
+
+    // Bytes that aren't valid UTF-8 make the whole file Latin-1. The start
+    // may cut a character in two, so that's only settled once a line fails.
+    let mut encoding = forced.unwrap_or_else(|| match Encoding::detect(&start) {
+        Encoding::Latin1 => Encoding::Utf8,
+        encoding => encoding,
+    });
+    let bom = encoding.has_bom(&start);
+    if !send(LoadEvent::Encoding { encoding, bom }, 0) {
+        return Ok(());
+    }
+
+    // UTF-16 has no newline byte to split on, so it's decoded all at once
+    if matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let text = encoding.decode(&bytes);
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let mut first = true;
+        for chunk in lines.chunks(CHUNK_LINES) {
+            if !send(LoadEvent::Lines { lines: chunk.to_vec(), first }, total_bytes) {
+                return Ok(());
+            }
+            first = false;
+        }
+        send(LoadEvent::Done, total_bytes);
+        return Ok(());
+    }
+
+    let mut line = Vec::new();
+    let mut lines = Vec::with_capacity(CHUNK_LINES);
+    let mut bytes_read = 0;
+    let mut first = true;
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)?;
+        if n > 0 {
+            // Same line splitting as str::lines
+            let text = line.strip_suffix(b"\n").unwrap_or(&line);
+            let mut text = text.strip_suffix(b"\r").unwrap_or(text);
+            if bytes_read == 0 && bom {
+                text = &text[encoding.bom().len()..];
+            }
+            if forced.is_none() && encoding == Encoding::Utf8 && std::str::from_utf8(text).is_err() {
+                // Not UTF-8 after all: start again as Latin-1
+                encoding = Encoding::Latin1;
+                reader.rewind()?;
+                lines.clear();
+                bytes_read = 0;
+                first = true;
+                if !send(LoadEvent::Encoding { encoding, bom: false }, 0) {
+                    return Ok(());
+                }
+                continue;
+            }
+            bytes_read += n as u64;
+            lines.push(encoding.decode_text(text));
+        }
+
+        // Hand over a full chunk, or whatever is left at end of file
+        if lines.len() == CHUNK_LINES || (n == 0 && !lines.is_empty()) {
+            let event = LoadEvent::Lines { lines: std::mem::take(&mut lines), first };
+            first = false;
+            if !send(event, bytes_read) {
+                return Ok(());
+            }
+        }
+        if n == 0 {
+            send(LoadEvent::Done, bytes_read);
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Everything a load of `bytes` sends: the encodings, the lines in the
+    // end and whether each chunk was a first one
+    fn load(bytes: &[u8], encoding: Option<Encoding>) -> (Vec<(Encoding, bool)>, Vec<String>, Vec<bool>) {
+        let path = std::env::temp_dir().join(format!("zorforge_loader_{}_{}.txt", std::process::id(), bytes.len()));
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut loader = FileLoader::spawn(&path, encoding).unwrap();
+        let (mut encodings, mut loaded, mut firsts) = (Vec::new(), Vec::new(), Vec::new());
+        loop {
+            match loader.recv() {
+                LoadEvent::Encoding { encoding, bom } => encodings.push((encoding, bom)),
+                LoadEvent::Lines { lines, first } => {
+                    if first {
+                        loaded.clear();
+                    }
+                    loaded.extend(lines);
+                    firsts.push(first);
+                }
+                LoadEvent::Done => break,
+                LoadEvent::Failed(e) => panic!("load failed: {}", e),
+            }
+        }
+        assert_eq!(loader.progress(), 100);
+        std::fs::remove_file(&path).unwrap();
+        (encodings, loaded, firsts)
+    }
+
+    #[test]
+    fn test_load_in_chunks() {
+        let contents: Vec<String> = (0..CHUNK_LINES + 10).map(|i| format!("line {}", i)).collect();
+        let (encodings, loaded, firsts) = load(contents.join("\r\n").as_bytes(), None);
+        assert_eq!(encodings, [(Encoding::Utf8, false)]);
+        assert_eq!(loaded, contents);
+        assert_eq!(firsts, [true, false]);
+    }
+
+    #[test]
+    fn test_load_encodings() {
+        // Latin-1 past the first chunk reads the file over as Latin-1
+        let mut bytes = "caf\u{e9}\n".repeat(CHUNK_LINES).into_bytes();
+        bytes.extend(b"caf\xE9");
+        let (encodings, loaded, firsts) = load(&bytes, None);
+        assert_eq!(encodings, [(Encoding::Utf8, false), (Encoding::Latin1, false)]);
+        assert_eq!(loaded.len(), CHUNK_LINES + 1);
+        assert_eq!(loaded[0], "caf\u{c3}\u{a9}");
+        assert_eq!(loaded[CHUNK_LINES], "caf\u{e9}");
+        assert_eq!(firsts, [true, true, false]);
+
+        let bytes = Encoding::Utf16Le.encode("one\r\ntwo\n", true).unwrap();
+        let (encodings, loaded, _) = load(&bytes, None);
+        assert_eq!(encodings, [(Encoding::Utf16Le, true)]);
+        assert_eq!(loaded, ["one", "two"]);
+
+        let (encodings, loaded, _) = load("\u{feff}caf\u{e9}".as_bytes(), None);
+        assert_eq!(encodings, [(Encoding::Utf8, true)]);
+        assert_eq!(loaded, ["caf\u{e9}"]);
+
+        let (_, loaded, _) = load(b"caf\xE9", Some(Encoding::Utf8));
+        assert_eq!(loaded, ["caf\u{fffd}"]);
+    }
+}
@@ -0,0 +1,98 @@
+// src/editor/messages.rs
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    Info,   // Informational notice, e.g. "Wrote file.txt"
+    Error,  // Something went wrong, e.g. a failed write
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub kind: MessageKind,
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct MessageLog {
+    history: VecDeque<Message>,
+    max_history: usize,
+}
+
+impl MessageLog {
+    pub fn new() -> Self {
+        Self {
+            history: VecDeque::new(),
+            max_history: 100, // Default to keeping the last 100 messages
+        }
+    }
+
+    // Record a message, dropping the oldest once the log is full
+    pub fn push(&mut self, kind: MessageKind, text: &str) {
+        self.history.push_back(Message {
+            kind,
+            text: text.to_string(),
+        });
+
+        while self.history.len() > self.max_history {
+            self.history.pop_front();
+        }
+    }
+
+    // Most recent `count` messages, oldest first
+    pub fn last(&self, count: usize) -> impl Iterator<Item = &Message> {
+        self.history.iter().skip(self.history.len().saturating_sub(count))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+}
+
+impl Message {
+    // Line as shown by :messages, with errors tagged
+    pub fn display(&self) -> String {
+        match self.kind {
+            MessageKind::Info => self.text.clone(),
+            MessageKind::Error => format!("E: {}", self.text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_last() {
+        let mut log = MessageLog::new();
+        log.push(MessageKind::Info, "first");
+        log.push(MessageKind::Error, "second");
+        log.push(MessageKind::Info, "third");
+
+        let last: Vec<_> = log.last(2).map(|m| m.text.as_str()).collect();
+        assert_eq!(last, vec!["second", "third"]);
+        assert_eq!(log.last(10).count(), 3);
+    }
+
+    #[test]
+    fn test_bounded_history() {
+        let mut log = MessageLog {
+            history: VecDeque::new(),
+            max_history: 2,
+        };
+        log.push(MessageKind::Info, "first");
+        log.push(MessageKind::Info, "second");
+        log.push(MessageKind::Info, "third");
+
+        let all: Vec<_> = log.last(10).map(|m| m.text.as_str()).collect();
+        assert_eq!(all, vec!["second", "third"]);
+    }
+
+    #[test]
+    fn test_display_tags_errors() {
+        let mut log = MessageLog::new();
+        log.push(MessageKind::Error, "write failed");
+        assert_eq!(log.last(1).next().unwrap().display(), "E: write failed");
+    }
+}
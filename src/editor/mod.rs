@@ -1,60 +1,413 @@
 // src/editor/mod.rs
 pub mod buffer;
 pub mod clipboard;
+pub mod completion;
+pub mod diff;
+pub mod digraph;
+pub mod encoding;
+pub mod filetype;
+pub mod git;
+pub mod glob;
+pub mod help;
+pub mod indent;
+pub mod large_file;
+pub mod loader;
+pub mod messages;
 pub mod mode;
+pub mod modeline;
+pub mod positions;
+pub mod recent;
+pub mod repeat;
+pub mod session;
+pub mod snippet;
+pub mod spell;
+pub mod swap;
+pub mod watch;
+pub mod which_key;
 mod viewport;
 
 // Re-export the types we need publicly
 pub use buffer::{Buffer, SelectionType};
 pub use clipboard::Clipboard;
+pub use messages::{Message, MessageKind, MessageLog};
 pub use mode::{Mode, CommandType, InsertVariant, VisualVariant};
 
-use crossterm::event::MouseButton;
-use crate::config::EditorConfig;
-use std::path::PathBuf;
+use crossterm::event::{KeyEvent, MouseButton};
+use crate::cli;
+use crate::config::{EditorConfig, Theme};
+use crate::ui::{LayoutShape, SplitDirection, Terminal, Window, WindowContent, WindowId, WindowManager};
+use buffer::VisualMode;
+use mode::ModeTrigger;
+use completion::{BufferWords, Completion, CompletionSource};
+use diff::DiffView;
+use encoding::Encoding;
+use git::{GitGutter, GitMark};
+use help::HelpView;
+use loader::{FileLoader, LoadEvent};
+use positions::CursorPositions;
+use recent::RecentFiles;
+use repeat::InsertRecorder;
+use session::Session;
+use snippet::{SnippetStops, Snippets};
+use parking_lot::RwLock;
+use spell::Dictionary;
+use watch::FileStamp;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::io;
 
+// Number of replacements z= offers for a misspelled word
+const SPELL_SUGGESTIONS: usize = 9;
+
+// Characters of each clipboard entry :registers shows
+const CLIPBOARD_PREVIEW_WIDTH: usize = 60;
+
+// How long yanked text stays highlighted
+const YANK_HIGHLIGHT: Duration = Duration::from_millis(300);
+
+// How long a prefix key waits before the popup of what may follow it shows
+const WHICH_KEY_DELAY: Duration = Duration::from_millis(500);
+
+// Bytes as a short human-readable size: 512B, 3.4K, 12.0M
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+// Whether this process may write `path`, by access(2) with W_OK, which
+// takes in ownership and root as the permission bits alone don't. Opening
+// the file for writing to find out would set off file watchers on close,
+// and block on a FIFO.
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a NUL-terminated string that outlives the call
+    unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), libc::W_OK, libc::AT_EACCESS) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+/// Text just yanked: its ends, and its shape as a selection would have it
+pub type YankRegion = ((usize, usize), (usize, usize), VisualMode);
+
 pub struct Editor {
     pub buffer: Buffer,
-    pub clipboard: Clipboard,
     pub mode: Mode,
     pub config: EditorConfig,
     is_readonly: bool,
+    readonly_files: bool, // --readonly: every file opens read-only
     command_buffer: Option<String>,
+    command_cursor: usize, // Byte offset of the cursor in the command line
+    pending_keys: String,
+    leader_keys: Option<String>, // Keys typed since the leader, while they may still pick a mapping
+    prefix_since: Option<Instant>, // When the prefix or leader now waiting for more keys was pressed
+    insert_undo_len: Option<usize>, // Undo stack length when the insert began, to undo it all at once
     file_path: Option<PathBuf>,
-    message: Option<String>,
+    message: Option<Message>,
+    messages: MessageLog,
+    swap_change_id: usize,  // Change ID last written to the swap file
+    recovery_pending: bool, // A swap file from an earlier session awaits :recover
+    loader: Option<FileLoader>, // Background load of a large file, while in progress
+    pending_cursor: Option<(usize, usize)>, // Where the cursor goes once the background load is done
+    abbreviations: BTreeMap<String, String>, // Insert-mode :iabbrev word -> expansion
+    dictionary: Option<Arc<Dictionary>>, // Word list, loaded the first time :set spell is used
+    spell: bool,
+    overtype: bool, // The Insert key switched typing in insert mode to overwrite
+    redraw_requested: bool, // The whole screen must be repainted, e.g. after :colorscheme
+    should_quit: bool, // Set by :q and friends; the event loop exits when it sees it
+    confirming_quit: bool, // :q or Ctrl-Q with unsaved changes, waiting to hear what to do with them
+    confirming_reload: bool, // The file changed on disk under unsaved changes, waiting to hear whether to reload
+    disk_stamp: Option<FileStamp>, // The file as last read or written, to notice other programs writing it
+    pending_substitute: Option<PendingSubstitute>, // A :s///c waiting for an answer
+    clipboard_browser: Option<usize>, // :registers is open, with this entry picked
+    help: Option<HelpView>,           // :help is open at this page
+    arg_list: Vec<PathBuf>, // Files named on the command line, walked by :next and :prev
+    arg_index: usize,       // Which of them is being edited
+    diff: Option<DiffView>, // --diff: the other file, shown beside the buffer
+    windows: WindowManager, // Splits of the text area; sized by update_viewport
+    mouse_dragging: bool,   // A drag is selecting text, to be copied when the button goes up
+    recent: RecentFiles,    // What :oldfiles lists; recorded by open_file
+    positions: CursorPositions, // Where the cursor was left in each file, for open_file to go back to
+    file_jumps: Vec<(PathBuf, (usize, usize))>, // Where gf left other files, for Ctrl-o to go back to
+    inserts: InsertRecorder, // The keys of the last insert, for `.`
+    git: Option<GitGutter>,  // The file as committed, when it's in a git repository
+    git_branch: Option<String>, // Branch checked out where the file is, for %b
+    completion: Option<Completion>, // The insert-mode completion popup, while it's open
+    completion_sources: Vec<Box<dyn CompletionSource>>, // Asked in turn for words to complete with
+    snippets: Snippets,                   // Templates Tab expands in insert mode
+    snippet_stops: Option<SnippetStops>,  // Tab stops of the snippet being filled in
+    yanked: Option<(YankRegion, Instant)>, // Highlighted for a moment after a yank
+}
+
+// A `:s///c` stopped at a match, waiting to hear whether to replace it
+struct PendingSubstitute {
+    end_row: usize,
+    pattern: String,
+    replacement: String,
+    global: bool,
+    matched: (usize, usize), // Start of the match being asked about
+    count: usize,            // Replacements made so far
+    undo_len: usize,         // Undo stack length before the first replacement
+}
+
+// One line of :registers for a clipboard entry: line breaks shown as ^J and
+// anything past the preview width cut off
+fn clipboard_preview(entry: &str) -> String {
+    let mut preview: String = entry.replace('\n', "^J").replace('\t', "^I");
+    if let Some((cut, _)) = preview.char_indices().nth(CLIPBOARD_PREVIEW_WIDTH) {
+        preview.truncate(cut);
+        preview.push_str("...");
+    }
+    preview
+}
+
+impl PendingSubstitute {
+    // Carry on after the `len` bytes at the current match, or from the next
+    // line when only the first match on each line is wanted
+    fn advance(&mut self, len: usize) {
+        let (row, col) = self.matched;
+        self.matched = if self.global { (row, col + len) } else { (row + 1, 0) };
+    }
 }
 
 impl Editor {
     pub fn new(config: EditorConfig) -> Self {
-        Self {
+        let mut editor = Self {
             buffer: Buffer::new(),
-            clipboard: Clipboard::new(),
             mode: Mode::Normal,
             config,
             is_readonly: false,
+            readonly_files: false,
             command_buffer: None,
+            command_cursor: 0,
+            pending_keys: String::new(),
+            leader_keys: None,
+            prefix_since: None,
+            insert_undo_len: None,
             file_path: None,
             message: None,
+            messages: MessageLog::new(),
+            swap_change_id: 0,
+            recovery_pending: false,
+            loader: None,
+            pending_cursor: None,
+            abbreviations: BTreeMap::new(),
+            dictionary: None,
+            spell: false,
+            overtype: false,
+            redraw_requested: false,
+            should_quit: false,
+            confirming_quit: false,
+            confirming_reload: false,
+            disk_stamp: None,
+            pending_substitute: None,
+            clipboard_browser: None,
+            help: None,
+            arg_list: Vec::new(),
+            arg_index: 0,
+            diff: None,
+            windows: WindowManager::new(0, 0),
+            mouse_dragging: false,
+            recent: RecentFiles::default(),
+            positions: CursorPositions::default(),
+            file_jumps: Vec::new(),
+            inserts: InsertRecorder::default(),
+            git: None,
+            git_branch: None,
+            completion: None,
+            completion_sources: vec![Box::new(BufferWords)],
+            snippets: Snippets::default(),
+            snippet_stops: None,
+            yanked: None,
+        };
+        editor.apply_config_to_buffer();
+        editor
+    }
+
+    // Swap in an empty buffer, set up as the config says and sized to the
+    // window the old one was shown in, so it's right before the next frame
+    fn reset_buffer(&mut self) {
+        let viewport = *self.buffer.get_viewport();
+        // Yanks outlive the file they came from
+        let clipboard = std::mem::replace(self.buffer.clipboard_mut(), Clipboard::new());
+        self.buffer = Buffer::new();
+        *self.buffer.clipboard_mut() = clipboard;
+        self.buffer.set_viewport_size(viewport.height, viewport.width);
+        self.apply_config_to_buffer();
+        if self.spell {
+            self.buffer.set_spell(self.dictionary.clone());
         }
     }
 
+    // Seed buffer-local settings from the editor config
+    fn apply_config_to_buffer(&mut self) {
+        self.buffer.set_tab_size(self.config.tab_size);
+        self.buffer.set_shift_width(self.config.tab_size);
+        self.buffer.set_clipboard_history(self.config.clipboard_history.max(1));
+        self.buffer.set_undo_levels(usize::try_from(self.config.undolevels).unwrap_or(0));
+        let virtual_edit = buffer::VirtualEdit::parse(&self.config.virtualedit).unwrap_or_default();
+        self.buffer.set_virtual_edit(virtual_edit);
+    }
+
     pub fn mode(&self) -> &Mode {
         &self.mode
     }
 
+    /// Flip between inserting and overwriting typed characters. It lasts
+    /// across inserts until toggled back; R replaces either way.
+    pub fn toggle_overtype(&mut self) {
+        self.overtype = !self.overtype;
+    }
+
+    /// Whether typing in insert mode overwrites what is under the cursor
+    pub fn is_overtype(&self) -> bool {
+        match self.mode {
+            Mode::Insert(InsertVariant::Replace) => true,
+            Mode::Insert(_) => self.overtype,
+            _ => false,
+        }
+    }
+
+    /// The cursor for the mode, an underline whenever typing overwrites
+    pub fn cursor_style(&self) -> mode::CursorStyle {
+        match self.is_overtype() {
+            true => mode::CursorStyle::Underline,
+            false => self.mode.cursor_style(),
+        }
+    }
+
     pub fn set_mode(&mut self, mode: Mode) {
+        // Each `:`, `/` or `?` starts with an empty command line, and
+        // leaving one doesn't leave its text behind for the next
+        let is_command = |mode: &Mode| matches!(mode, Mode::Command(_));
+        if mode != self.mode && (is_command(&mode) || is_command(&self.mode)) {
+            self.clear_command();
+        }
+        if mode != self.mode {
+            log::debug!("mode: {:?} -> {:?}", self.mode, mode);
+        }
+        // The completion popup only lasts as long as the insert
+        if !matches!(mode, Mode::Insert(_)) {
+            self.completion = None;
+            self.snippet_stops = None;
+        }
+        // The buffer's selection follows the mode, so the two can't disagree
+        // about its kind. The command line keeps it, as `/` searches inside
+        // it, until normal or insert mode ends it.
+        match mode {
+            Mode::Visual(variant) => self.buffer.set_visual_mode(variant.into()),
+            Mode::Normal | Mode::Insert(_) if self.buffer.get_visual_selection().is_some() => {
+                self.buffer.clear_visual();
+            }
+            _ => {}
+        }
+        // An insert undoes as a single change, along with the line o or O
+        // opened for it
+        match (&self.mode, &mode) {
+            (Mode::Insert(_), Mode::Insert(_)) => {}
+            (_, Mode::Insert(_)) => {
+                self.insert_undo_len.get_or_insert_with(|| self.buffer.begin_merge());
+            }
+            (Mode::Insert(_), _) => {
+                if let Some(len) = self.insert_undo_len.take() {
+                    self.buffer.merge_changes_since(len);
+                }
+            }
+            _ => {}
+        }
         self.mode = mode;
     }
 
+    // s, S, cc and C: make `change` and insert in its place, the two undoing
+    // as one
+    pub fn change_and_insert(&mut self, trigger: ModeTrigger, change: impl FnOnce(&mut Buffer)) {
+        self.insert_undo_len = Some(self.buffer.begin_merge());
+        change(&mut self.buffer);
+        self.set_mode(self.mode.transition(trigger));
+        if !matches!(self.mode, Mode::Insert(_)) {
+            if let Some(len) = self.insert_undo_len.take() {
+                self.buffer.merge_changes_since(len);
+            }
+        }
+    }
+
+    // o and O: open a line below or above the cursor's and insert on it
+    pub fn open_line(&mut self, below: bool) {
+        self.insert_undo_len = Some(self.buffer.begin_merge());
+        if below {
+            self.buffer.insert_line_below();
+            self.set_mode(self.mode.transition(ModeTrigger::InsertLineBelow));
+        } else {
+            self.buffer.insert_line_above();
+            self.set_mode(self.mode.transition(ModeTrigger::InsertLineAbove));
+        }
+        if !matches!(self.mode, Mode::Insert(_)) {
+            if let Some(len) = self.insert_undo_len.take() {
+                self.buffer.merge_changes_since(len);
+            }
+        }
+    }
+
     // Update save_buffer to mark changes as saved
     pub fn save_buffer(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.remember_cursor();
+        let mut message = format!("Wrote {}", self.file_info());
+        if self.buffer.encoding() != Encoding::Utf8 {
+            message.push_str(&format!(" [{}]", self.buffer.encoding().name()));
+        }
+        if self.buffer.has_bom() {
+            message.push_str(" [BOM]");
+        }
+        self.show_message(&message);
+        Ok(())
+    }
+
+    // Write the buffer to its file without reporting success
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_large_file() {
+            return Err(io::Error::other("Large files are opened read-only"));
+        }
+        if self.is_readonly {
+            return Err(io::Error::other("File is read-only (:set noreadonly to override)"));
+        }
+        // Writing now would truncate the file to the part loaded so far
+        if self.is_loading() {
+            return Err(io::Error::other("File is still loading"));
+        }
         if let Some(path) = &self.file_path {
             let content = self.buffer.get_content()
                 .join("\n");
-            std::fs::write(path, content)?;
+            let bytes = self.buffer.encoding().encode(&content, self.buffer.has_bom())?;
+            std::fs::write(path, &bytes)?;
+            log::info!("write: {} ({} bytes, {})", path.display(), bytes.len(), self.buffer.encoding().name());
             self.buffer.mark_saved();  // Mark current state as saved
-            self.show_message(&format!("Wrote {}", path.display()));
+            self.disk_stamp = FileStamp::of(path);
+            if !self.recovery_pending {
+                swap::remove_swap(path)?;
+            }
+            // The file may have been committed since it was opened
+            self.git = git::head_lines(path).map(GitGutter::new);
+            self.refresh_git_marks();
+            self.refresh_git_branch();
             Ok(())
         } else {
             Err(io::Error::new(
@@ -64,6 +417,64 @@ impl Editor {
         }
     }
 
+    // Background save after a period of inactivity. Silent on success, and
+    // never touches no-name or read-only buffers.
+    pub fn autosave(&mut self) {
+        if self.file_path.is_none() || self.is_readonly || self.is_loading()
+            || !self.has_unsaved_changes()
+        {
+            return;
+        }
+        if let Err(e) = self.write_buffer() {
+            self.show_error(&format!("Auto-save failed: {}", e));
+        }
+    }
+
+    // Write unsaved edits to the swap file, if anything changed since last time
+    pub fn update_swap(&mut self) {
+        // Don't clobber a swap left behind by an earlier session
+        if self.recovery_pending || self.is_loading() {
+            return;
+        }
+        let change_id = self.buffer.current_change_id();
+        if change_id == self.swap_change_id || !self.has_unsaved_changes() {
+            return;
+        }
+        if let Some(path) = &self.file_path {
+            match swap::write_swap(path, self.buffer.get_content()) {
+                Ok(()) => self.swap_change_id = change_id,
+                Err(e) => self.show_error(&format!("Could not write swap file: {}", e)),
+            }
+        }
+    }
+
+    // Remove this buffer's swap file, unless it is still awaiting recovery
+    pub fn remove_swap(&mut self) -> io::Result<()> {
+        match &self.file_path {
+            Some(path) if !self.recovery_pending => swap::remove_swap(path),
+            _ => Ok(()),
+        }
+    }
+
+    // :recover - replace the buffer with the edits saved in the swap file
+    pub fn recover_from_swap(&mut self) -> io::Result<()> {
+        let path = self.file_path.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No file name")
+        })?;
+        let lines = swap::read_swap(&path)?;
+        self.buffer.set_content(lines);
+        self.buffer.mark_modified();
+        self.recovery_pending = false;
+        self.show_message("Recovered from swap file; write to keep the changes");
+        Ok(())
+    }
+
+    // :discardswap - drop the swap file from an earlier session
+    pub fn discard_swap(&mut self) -> io::Result<()> {
+        self.recovery_pending = false;
+        self.remove_swap()
+    }
+
     // Update has_unsaved_changes to use buffer's tracking
     pub fn has_unsaved_changes(&self) -> bool {
         self.buffer.has_unsaved_changes()
@@ -75,10 +486,38 @@ impl Editor {
         self.save_buffer()
     }
     pub fn show_message(&mut self, msg: &str) {
-        self.message = Some(msg.to_string());
+        self.post_message(MessageKind::Info, msg);
+    }
+
+    pub fn show_error(&mut self, msg: &str) {
+        self.post_message(MessageKind::Error, msg);
+    }
+
+    fn post_message(&mut self, kind: MessageKind, msg: &str) {
+        self.messages.push(kind, msg);
+        self.message = Some(Message {
+            kind,
+            text: msg.to_string(),
+        });
+    }
+
+    // :messages - display the most recent messages together
+    pub fn show_message_history(&mut self, count: usize) {
+        if self.messages.is_empty() {
+            return;
+        }
+        let text = self.messages
+            .last(count)
+            .map(|message| message.display())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.message = Some(Message {
+            kind: MessageKind::Info,
+            text,
+        });
     }
 
-    pub fn get_message(&self) -> Option<&String> {
+    pub fn get_message(&self) -> Option<&Message> {
         self.message.as_ref()
     }
 
@@ -86,102 +525,2652 @@ impl Editor {
         self.message = None;
     }
 
-    pub fn file_info(&self) -> String {
-        match &self.file_path {
-            Some(path) => path.display().to_string(),
-            None => String::from("[No Name]")
+    // :s///c - ask about each match from `start_row` to `end_row` in turn
+    pub fn start_substitute_confirm(
+        &mut self,
+        start_row: usize,
+        end_row: usize,
+        pattern: &str,
+        replacement: &str,
+        global: bool,
+    ) -> io::Result<()> {
+        let matched = self.buffer.find_text((start_row, 0), end_row, pattern).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Pattern not found: {}", pattern))
+        })?;
+        self.pending_substitute = Some(PendingSubstitute {
+            end_row,
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            global,
+            matched,
+            count: 0,
+            undo_len: self.buffer.begin_merge(),
+        });
+        self.ask_substitute();
+        Ok(())
+    }
+
+    pub fn is_confirming_substitute(&self) -> bool {
+        self.pending_substitute.is_some()
+    }
+
+    // The answer to "replace with ...?": y replaces, n skips, a replaces
+    // this and every later match, l replaces this one and stops, and q
+    // stops. Anything else asks again.
+    pub fn answer_substitute(&mut self, answer: char) {
+        let Some(mut pending) = self.pending_substitute.take() else {
+            return;
+        };
+        match answer {
+            'y' | 'l' | 'a' => self.replace_pending_match(&mut pending),
+            'n' => pending.advance(pending.pattern.len()),
+            'q' => return self.finish_substitute(pending),
+            _ => {
+                self.pending_substitute = Some(pending);
+                return self.ask_substitute();
+            }
+        }
+
+        let mut found = self.next_pending_match(&pending);
+        if answer == 'a' {
+            while let Some(matched) = found {
+                pending.matched = matched;
+                self.replace_pending_match(&mut pending);
+                found = self.next_pending_match(&pending);
+            }
+        }
+        match found {
+            Some(matched) if answer != 'l' => {
+                pending.matched = matched;
+                self.pending_substitute = Some(pending);
+                self.ask_substitute();
+            }
+            _ => self.finish_substitute(pending),
         }
     }
 
-    pub fn command_line_content(&self) -> String {
-        match &self.command_buffer {
-            Some(buffer) => buffer.clone(),
-            None => String::new(),
+    fn replace_pending_match(&mut self, pending: &mut PendingSubstitute) {
+        self.buffer.replace_text(pending.matched, pending.pattern.len(), &pending.replacement);
+        pending.count += 1;
+        pending.advance(pending.replacement.len());
+    }
+
+    fn next_pending_match(&self, pending: &PendingSubstitute) -> Option<(usize, usize)> {
+        if pending.matched.0 > pending.end_row {
+            return None;
         }
+        self.buffer.find_text(pending.matched, pending.end_row, &pending.pattern)
     }
 
-    pub fn handle_mouse_click(&mut self, col: usize, row: usize, _button: MouseButton) {
+    // Show the match under the cursor and the question, which isn't kept
+    // in the message history
+    fn ask_substitute(&mut self) {
+        let Some(pending) = &self.pending_substitute else {
+            return;
+        };
+        let (row, col) = pending.matched;
+        let text = format!("replace with {} (y/n/a/q/l)?", pending.replacement);
         self.buffer.set_cursor_position(row, col);
+        self.buffer.set_current_match(Some((row, col, col + pending.pattern.len())));
+        self.buffer.scroll_to_cursor();
+        self.message = Some(Message { kind: MessageKind::Info, text });
     }
 
-    pub fn handle_mouse_drag(&mut self, col: usize, row: usize, _button: MouseButton) {
-        // If we're not already in visual mode, enter it and mark selection start
-        if !self.mode.is_visual() {
-            self.buffer.start_visual();
-            self.mode = Mode::Visual(VisualVariant::Char);
+    fn finish_substitute(&mut self, pending: PendingSubstitute) {
+        // However many were replaced, one u undoes the whole command
+        self.buffer.merge_changes_since(pending.undo_len);
+        self.buffer.set_current_match(None);
+        match pending.count {
+            0 => {}
+            1 => self.show_message("1 substitution"),
+            count => self.show_message(&format!("{} substitutions", count)),
         }
-        
-        // Update cursor position which will update the selection end
-        self.buffer.set_cursor_position(row, col);
     }
 
-    pub fn scroll_up(&mut self) {
-        self.buffer.move_page_up();
+    // :colorscheme {name}
+    pub fn set_colorscheme(&mut self, name: &str) -> io::Result<()> {
+        self.config.theme = Theme::find(name)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.to_string()))?;
+        self.request_redraw();
+        Ok(())
     }
 
-    pub fn scroll_down(&mut self) {
-        self.buffer.move_page_down();
+    // :saveconfig [file] - write the settings as they are now, :set
+    // changes and all, to `path` or else the config file
+    pub fn save_config(&mut self, path: Option<&Path>) -> io::Result<()> {
+        let config_error = |e: crate::config::ConfigError| io::Error::other(e.to_string());
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => EditorConfig::get_config_path().map_err(config_error)?,
+        };
+        self.config.save_to_file(&path).map_err(config_error)?;
+        self.show_message(&format!("Config saved to {}", path.display()));
+        Ok(())
     }
 
-    pub fn set_visual_object_mode(&mut self, selection_type: SelectionType) {
-        // Instead of directly accessing the field, we'll use a method
-        self.buffer.set_selection_type(selection_type);
+    // Clear the screen and paint everything again, as for Ctrl-L
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
     }
 
-    // Buffer access methods
-    pub fn current_buffer(&self) -> &Buffer {
-        &self.buffer
+    // Whether a full redraw was asked for since the last call
+    pub fn take_redraw_request(&mut self) -> bool {
+        std::mem::take(&mut self.redraw_requested)
     }
 
-    pub fn current_buffer_mut(&mut self) -> &mut Buffer {
-        &mut self.buffer
+    // Ask the event loop to exit once the current key is handled, so the
+    // terminal is restored on the way out
+    pub fn quit(&mut self) {
+        self.should_quit = true;
     }
 
-    pub fn cursor_position(&self) -> (usize, usize) {
-        self.buffer.get_cursor_position()
+    pub fn should_quit(&self) -> bool {
+        self.should_quit
     }
 
-    // File operations
-    pub fn open_file(&mut self, path: &PathBuf) -> io::Result<()> {
-        let contents = std::fs::read_to_string(path)?;
-        self.buffer = Buffer::new();
-        for line in contents.lines() {
-            self.buffer.insert_at(self.buffer.line_count(), line.to_string());
+    // :q and Ctrl-Q - quit, first asking whether to save any unsaved changes
+    pub fn confirm_quit(&mut self) -> io::Result<()> {
+        if !self.has_unsaved_changes() {
+            return self.force_quit();
         }
+        self.confirming_quit = true;
+        self.ask_quit();
         Ok(())
     }
 
-    pub fn set_readonly(&mut self, readonly: bool) {
-        self.is_readonly = readonly;
+    pub fn is_confirming_quit(&self) -> bool {
+        self.confirming_quit
     }
 
-    pub fn is_readonly(&self) -> bool {
-        self.is_readonly
+    // The answer to "save changes?": y writes and quits, n quits without
+    // writing, and c cancels. Anything else asks again.
+    pub fn answer_quit(&mut self, answer: char) -> io::Result<()> {
+        match answer {
+            'y' => {
+                self.confirming_quit = false;
+                self.write_and_quit(true)
+            }
+            'n' => {
+                self.confirming_quit = false;
+                self.force_quit()
+            }
+            'c' => {
+                self.confirming_quit = false;
+                self.clear_message();
+                Ok(())
+            }
+            _ => {
+                self.ask_quit();
+                Ok(())
+            }
+        }
     }
 
-    pub fn cursor_position_info(&self) -> String {
-        let (row, col) = self.cursor_position();
-        format!("{}:{}", row + 1, col + 1)
+    // Like ask_substitute's, the question stays out of the message history
+    fn ask_quit(&mut self) {
+        let text = format!("Save changes to {}? (y/n/c)", self.file_info());
+        self.message = Some(Message { kind: MessageKind::Info, text });
     }
 
-    pub fn append_to_command(&mut self, c: char) {
-        if let Some(buffer) = &mut self.command_buffer {
-            buffer.push(c);
-        } else {
-            self.command_buffer = Some(String::from(c));
+    /// Look for another program having written the file since it was read or
+    /// saved. Without unsaved changes it's read again, the cursor staying
+    /// on its line; with them the user is asked first.
+    pub fn check_file_changed(&mut self) -> io::Result<()> {
+        if self.confirming_reload || self.is_loading() || self.buffer.is_large_file() {
+            return Ok(());
         }
+        let Some(path) = self.file_path.clone() else {
+            return Ok(());
+        };
+        // A file that's been deleted is left for :w to write again
+        let Some(stamp) = FileStamp::of(&path) else {
+            return Ok(());
+        };
+        if self.disk_stamp == Some(stamp) {
+            return Ok(());
+        }
+        self.disk_stamp = Some(stamp);
+        if self.has_unsaved_changes() {
+            self.confirming_reload = true;
+            self.ask_reload();
+            return Ok(());
+        }
+        self.reload_file()?;
+        self.show_message(&format!("{} changed on disk; reloaded", self.file_info()));
+        Ok(())
     }
 
-    pub fn delete_from_command(&mut self) {
-        if let Some(buffer) = &mut self.command_buffer {
-            buffer.pop();
-            if buffer.is_empty() {
-                self.command_buffer = None;
+    pub fn is_confirming_reload(&self) -> bool {
+        self.confirming_reload
+    }
+
+    // The answer to "reload?": y reads the file again, dropping unsaved
+    // changes, and n keeps them. Anything else asks again.
+    pub fn answer_reload(&mut self, answer: char) -> io::Result<()> {
+        match answer {
+            'y' => {
+                self.confirming_reload = false;
+                self.reload_file()
+            }
+            'n' => {
+                self.confirming_reload = false;
+                self.clear_message();
+                Ok(())
+            }
+            _ => {
+                self.ask_reload();
+                Ok(())
             }
         }
     }
 
-    pub fn clear_command(&mut self) {
-        self.command_buffer = None;
+    fn ask_reload(&mut self) {
+        let text = format!("{} changed on disk. Reload and lose your changes? (y/n)", self.file_info());
+        self.message = Some(Message { kind: MessageKind::Info, text });
+    }
+
+    // Read the file again, keeping the cursor where it was as far as the
+    // new contents allow
+    fn reload_file(&mut self) -> io::Result<()> {
+        let Some(path) = self.file_path.clone() else {
+            return Ok(());
+        };
+        let (row, col) = self.buffer.get_cursor_position();
+        self.open_file(&path)?;
+        self.place_cursor(row, col);
+        Ok(())
+    }
+
+    /// :registers: list the clipboard's entries, newest first, to pick one
+    /// to paste with j, k and Enter, or by its number
+    pub fn open_clipboard_browser(&mut self) {
+        if self.buffer.clipboard().is_none_or(Clipboard::is_empty) {
+            self.show_message("Clipboard is empty");
+            return;
+        }
+        self.clipboard_browser = Some(0);
+        self.show_clipboard_browser();
+    }
+
+    pub fn is_browsing_clipboard(&self) -> bool {
+        self.clipboard_browser.is_some()
+    }
+
+    pub fn move_clipboard_selection(&mut self, forward: bool) {
+        let count = self.buffer.clipboard().map_or(0, Clipboard::len);
+        if let Some(selected) = &mut self.clipboard_browser {
+            *selected = match forward {
+                true => (*selected + 1).min(count.saturating_sub(1)),
+                false => selected.saturating_sub(1),
+            };
+        }
+        self.show_clipboard_browser();
+    }
+
+    /// Paste the `index`th newest entry (the picked one if None) and close
+    /// the list
+    pub fn paste_clipboard_entry(&mut self, index: Option<usize>) {
+        let Some(index) = index.or(self.clipboard_browser) else {
+            return;
+        };
+        self.close_clipboard_browser();
+        if !self.buffer.paste_entry(index, 1) {
+            self.show_error(&format!("No clipboard entry {}", index + 1));
+        }
+    }
+
+    pub fn close_clipboard_browser(&mut self) {
+        self.clipboard_browser = None;
+        self.clear_message();
+    }
+
+    // Like ask_quit's, the list stays out of the message history
+    fn show_clipboard_browser(&mut self) {
+        let (Some(selected), Some(clipboard)) = (self.clipboard_browser, self.buffer.clipboard()) else {
+            return;
+        };
+        let mut listing = vec!["Clipboard (j/k to pick, Enter or a number to paste, Esc to close):".to_string()];
+        listing.extend(clipboard.get_history().iter().enumerate().map(|(i, entry)| {
+            let marker = if i == selected { '>' } else { ' ' };
+            format!("{}{:>3} {}", marker, i + 1, clipboard_preview(&entry.text))
+        }));
+        let text = listing.join("\n");
+        self.message = Some(Message { kind: MessageKind::Info, text });
+    }
+
+    /// :help: page through `lines` with j, k, Space and b; any other key
+    /// closes them
+    pub fn open_help(&mut self, lines: Vec<String>) {
+        self.help = Some(HelpView::new(lines));
+        self.show_help_page();
+    }
+
+    pub fn is_showing_help(&self) -> bool {
+        self.help.is_some()
+    }
+
+    /// Move the help `delta` lines along, or `delta` pages when `by_page`
+    pub fn scroll_help(&mut self, delta: isize, by_page: bool) {
+        let height = self.help_page_height();
+        if let Some(help) = &mut self.help {
+            let step = if by_page { height as isize } else { 1 };
+            help.scroll(delta * step, height);
+        }
+        self.show_help_page();
+    }
+
+    pub fn close_help(&mut self) {
+        self.help = None;
+        self.clear_message();
+    }
+
+    // Listing lines that fit under the heading, as much as :messages shows
+    fn help_page_height(&self) -> usize {
+        self.buffer.get_viewport().height.saturating_sub(1).max(1)
+    }
+
+    // Like the clipboard browser's, the page stays out of the message history
+    fn show_help_page(&mut self) {
+        let height = self.help_page_height();
+        let Some(help) = &self.help else {
+            return;
+        };
+        let (first, count) = help.position();
+        let mut page = vec![format!(
+            "Help, line {} of {} (j/k to scroll, Space/b for pages, Esc to close):",
+            first, count,
+        )];
+        page.extend(help.page(height).iter().cloned());
+        let text = page.join("\n");
+        self.message = Some(Message { kind: MessageKind::Info, text });
+    }
+
+    /// yy and Y: yank the cursor's line, highlighting it for a moment
+    pub fn yank_line(&mut self) {
+        self.buffer.yank();
+        let row = self.buffer.get_cursor_position().0;
+        self.highlight_yank(((row, 0), (row, 0), VisualMode::Line));
+    }
+
+    /// d, x or c in visual mode: cut the selection to the clipboard
+    pub fn cut_selection(&mut self) {
+        if let Some(text) = self.buffer.get_selected_text() {
+            let kind = self.buffer.selection_yank_type();
+            self.buffer.clipboard_mut().yank_as(text, kind);
+            self.buffer.delete_selection();
+        }
+    }
+
+    /// y in visual mode: yank the selection, highlighting it for a moment,
+    /// and put the cursor where it begins as vim does
+    pub fn yank_selection(&mut self) {
+        let (Some(text), Some((start, end))) = (self.buffer.get_selected_text(), self.buffer.get_visual_selection()) else {
+            return;
+        };
+        let mode = self.buffer.visual_mode().unwrap_or(VisualMode::Char);
+        let kind = self.buffer.selection_yank_type();
+        self.buffer.clipboard_mut().yank_as(text, kind);
+        self.highlight_yank((start, end, mode));
+        if let Some((row, col)) = self.buffer.selection_top_left() {
+            self.buffer.set_cursor_position(row, col);
+        }
+    }
+
+    fn highlight_yank(&mut self, region: YankRegion) {
+        self.yanked = Some((region, Instant::now()));
+    }
+
+    /// What was just yanked, until its highlight has had its moment
+    pub fn yank_highlight(&self) -> Option<YankRegion> {
+        self.yanked.filter(|(_, at)| at.elapsed() < YANK_HIGHLIGHT).map(|(region, _)| region)
+    }
+
+    /// How long until the yank highlight is to be taken down, so the event
+    /// loop can wake up to redraw without it
+    pub fn yank_highlight_remaining(&self) -> Option<Duration> {
+        self.yanked.map(|(_, at)| YANK_HIGHLIGHT.saturating_sub(at.elapsed())).filter(|left| !left.is_zero())
+    }
+
+    // :wq, :x and ZZ - write, then quit only if the write worked. :x and ZZ
+    // (`always` false) skip the write when there's nothing to save, leaving
+    // the file's modification time alone for build tools and file watchers.
+    pub fn write_and_quit(&mut self, always: bool) -> io::Result<()> {
+        if always || self.has_unsaved_changes() {
+            self.save_buffer()?;
+        }
+        self.force_quit()
+    }
+
+    // :wa - write every modified buffer that has a file name, returning
+    // whether all of them could be. The editor holds a single buffer for
+    // now, so that's the only one there is to visit.
+    pub fn write_all(&mut self) -> io::Result<bool> {
+        if !self.has_unsaved_changes() {
+            self.show_message("No modified buffers to write");
+            return Ok(true);
+        }
+        if self.file_path.is_none() {
+            self.show_error("Skipped [No Name]: no file name (use :w <path>)");
+            return Ok(false);
+        }
+        self.write_buffer()?;
+        self.show_message("1 buffer written");
+        Ok(true)
+    }
+
+    // :xa and :wqa - write all modified buffers, then quit if nothing was
+    // left unwritten
+    pub fn write_all_and_quit(&mut self) -> io::Result<()> {
+        if self.write_all()? {
+            self.quit();
+        }
+        Ok(())
+    }
+
+    // :q! and ZQ - quit, throwing away unsaved changes
+    pub fn force_quit(&mut self) -> io::Result<()> {
+        self.remove_swap()?;
+        self.quit();
+        Ok(())
+    }
+
+    pub fn file_path(&self) -> Option<&Path> {
+        self.file_path.as_deref()
+    }
+
+    pub fn file_info(&self) -> String {
+        match &self.file_path {
+            Some(path) => path.display().to_string(),
+            None => String::from("[No Name]")
+        }
+    }
+
+    // A fresh editor opened without a file shows how to get going, until
+    // the first edit or the first key that leaves normal mode
+    pub fn shows_intro(&self) -> bool {
+        self.file_path.is_none()
+            && self.mode == Mode::Normal
+            && self.windows.window_count() <= 1
+            && self.buffer.line_count() == 1
+            && self.buffer.line_text(0).is_some_and(|line| line.is_empty())
+            && !self.has_unsaved_changes()
+    }
+
+    pub fn command_line_content(&self) -> String {
+        match &self.command_buffer {
+            Some(buffer) => buffer.clone(),
+            None => String::new(),
+        }
+    }
+
+    pub fn handle_mouse_click(&mut self, col: usize, row: usize, button: MouseButton) {
+        self.mouse_dragging = false;
+        // A click in another window focuses it
+        if let Some(id) = self.windows.window_at(col as u16, row as u16) {
+            if Some(id) != self.windows.active_id() {
+                self.focus_window(id);
+            }
+        }
+        // A middle click pastes where it lands, as terminals paste their
+        // selection: into the shell of a terminal window
+        let middle = button == MouseButton::Middle;
+        if let Some(terminal) = self.focused_terminal() {
+            if let Some(text) = self.buffer.clipboard().and_then(Clipboard::peek).filter(|_| middle) {
+                if let Err(e) = terminal.write().paste(text) {
+                    self.show_error(&format!("Cannot write to terminal: {}", e));
+                }
+            }
+            return;
+        }
+        if self.windows.active_id() != self.windows.buffer_window().map(Window::id) {
+            return;
+        }
+        if middle && self.mode.is_visual() {
+            self.buffer.clear_visual();
+            self.set_mode(Mode::Normal);
+        }
+        let (row, col) = self.mouse_position(col, row);
+        self.buffer.set_cursor_position(row, col);
+
+        if middle && !self.buffer.is_large_file() {
+            if let Some(text) = self.buffer.clipboard().and_then(Clipboard::peek).cloned() {
+                self.buffer.paste_at_cursor(&text);
+            }
+        }
+    }
+
+    // Buffer position under screen cell (`col`, `row`) of the window
+    // showing the buffer. Below the text counts as the last line.
+    fn mouse_position(&self, col: usize, row: usize) -> (usize, usize) {
+        let (x, y) = self.windows.buffer_window()
+            .map_or((0, 0), |window| (window.dimensions().x as usize, window.dimensions().y as usize));
+        let visible = self.visible_range();
+        let row = (visible.start + row.saturating_sub(y)).min(visible.end.saturating_sub(1));
+        let cells = col.saturating_sub(x + self.buffer.gutter_width());
+        (row, self.buffer.col_at_display(row, cells))
     }
-}
\ No newline at end of file
+
+    pub fn handle_mouse_drag(&mut self, col: usize, row: usize, _button: MouseButton) {
+        // If we're not already in visual mode, enter it and mark selection start
+        // Large files can't be edited, so a drag just moves the cursor
+        if !self.mode.is_visual() && !self.buffer.is_large_file() {
+            self.set_mode(Mode::Visual(VisualVariant::Char));
+        }
+        
+        // Update cursor position which will update the selection end
+        let (row, col) = self.mouse_position(col, row);
+        self.buffer.set_cursor_position(row, col);
+        self.mouse_dragging = self.mode.is_visual();
+    }
+
+    // Letting go at the end of a drag copies what it selected, which stays
+    // selected for a visual-mode command
+    pub fn handle_mouse_release(&mut self) {
+        if !std::mem::take(&mut self.mouse_dragging) || !self.mode.is_visual() {
+            return;
+        }
+        if let Some(text) = self.buffer.get_selected_text() {
+            self.buffer.clipboard_mut().yank(text);
+        }
+    }
+
+    // Keep the windows, and the buffer's viewport in the one with the
+    // cursor, in sync with the screen area available for text
+    pub fn update_viewport(&mut self, height: usize, width: usize) {
+        let total = self.windows.total_dimensions();
+        if (total.width as usize, total.height as usize) != (width, height) {
+            let _ = self.windows.resize(width as u16, height as u16);
+        }
+        let (height, width) = self.windows.buffer_window()
+            .map(|window| (window.dimensions().height as usize, window.dimensions().width as usize))
+            .unwrap_or((height, width));
+        self.buffer.set_viewport_size(height, width);
+        self.buffer.scroll_to_cursor();
+    }
+
+    /// The buffer lines on screen in the window with the cursor, as of the
+    /// last update_viewport, which the event loop calls before each frame.
+    /// Rows past the end of the buffer aren't counted.
+    pub fn visible_range(&self) -> Range<usize> {
+        let lines = self.buffer.get_viewport().visible_lines();
+        lines.start..lines.end.min(self.buffer.line_count())
+    }
+
+    pub fn windows(&self) -> &WindowManager {
+        &self.windows
+    }
+
+    // :split and :vsplit: another window onto the buffer, which takes focus
+    pub fn split_window(&mut self, direction: SplitDirection) -> io::Result<()> {
+        self.park_buffer_view();
+        self.windows.split(direction, self.buffer.get_viewport().start)?;
+        self.request_redraw();
+        Ok(())
+    }
+
+    // :close. The last window stays; :q is for leaving.
+    pub fn close_window(&mut self) -> io::Result<()> {
+        if self.windows.window_count() <= 1 {
+            return Err(io::Error::other("Cannot close last window"));
+        }
+        if let Some(id) = self.windows.active_id() {
+            self.park_buffer_view();
+            self.windows.close_window(id)?;
+            self.restore_buffer_view();
+        }
+        self.request_redraw();
+        Ok(())
+    }
+
+    // Ctrl-W w
+    pub fn focus_next_window(&mut self) {
+        if let Some(id) = self.windows.next_window() {
+            self.focus_window(id);
+        }
+    }
+
+    // Buffer windows all show the one buffer, so moving between them just
+    // trades scroll positions
+    pub fn focus_window(&mut self, id: WindowId) {
+        self.park_buffer_view();
+        self.windows.focus_window(id);
+        self.restore_buffer_view();
+        self.request_redraw();
+    }
+
+    // Ctrl-W t: to a terminal, opening one in a panel along the bottom if
+    // there's none, or from a terminal back to the buffer
+    pub fn toggle_terminal(&mut self) -> io::Result<()> {
+        self.park_buffer_view();
+        let result = self.windows.toggle_terminal();
+        self.restore_buffer_view();
+        self.request_redraw();
+        result
+    }
+
+    // :terminal: another shell, splitting the focused window
+    pub fn open_terminal(&mut self) -> io::Result<()> {
+        self.park_buffer_view();
+        let result = self.windows.open_terminal(SplitDirection::Horizontal);
+        self.restore_buffer_view();
+        self.request_redraw();
+        result
+    }
+
+    // The terminal in the focused window, which then gets the keys
+    pub fn focused_terminal(&self) -> Option<Arc<RwLock<Terminal>>> {
+        match self.windows.active_window()?.content() {
+            WindowContent::Terminal(terminal) => Some(Arc::clone(terminal)),
+            WindowContent::Buffer { .. } => None,
+        }
+    }
+
+    // Take in output from the shells; true if any printed something
+    pub fn poll_terminals(&self) -> bool {
+        let mut changed = false;
+        for terminal in self.windows.terminals() {
+            changed |= terminal.write().poll_output();
+        }
+        changed
+    }
+
+    // Remember where the window losing the buffer's viewport was scrolled
+    fn park_buffer_view(&mut self) {
+        let start = self.buffer.get_viewport().start;
+        if let Some(WindowContent::Buffer { top }) = self.windows.buffer_window_mut().map(Window::content_mut) {
+            *top = start;
+        }
+    }
+
+    fn restore_buffer_view(&mut self) {
+        if let Some(&WindowContent::Buffer { top }) = self.windows.buffer_window().map(Window::content) {
+            self.buffer.set_viewport_start(top);
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.buffer.move_page_up();
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.buffer.move_page_down();
+    }
+
+    pub fn set_visual_object_mode(&mut self, selection_type: SelectionType) {
+        // Instead of directly accessing the field, we'll use a method
+        self.buffer.set_selection_type(selection_type);
+    }
+
+    // Buffer access methods
+    pub fn current_buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn current_buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffer
+    }
+
+    pub fn cursor_position(&self) -> (usize, usize) {
+        self.buffer.get_cursor_position()
+    }
+
+    // File operations
+    pub fn open_file(&mut self, path: &PathBuf) -> io::Result<()> {
+        let metadata = std::fs::metadata(path)?;
+        let size = metadata.len();
+        log::info!("open: {} ({} bytes)", path.display(), size);
+
+        self.remember_cursor();
+        self.reset_buffer();
+        self.git = None;
+        self.file_path = Some(path.clone());
+        // A file that can't be written opens read-only, so edits to it
+        // aren't a surprise at save time; :set noreadonly overrides
+        self.is_readonly = self.readonly_files || !is_writable(path);
+        self.disk_stamp = FileStamp::of(path);
+        self.confirming_reload = false;
+        self.pending_cursor = None;
+
+        if size >= large_file::LARGE_FILE_THRESHOLD {
+            self.loader = None;
+            self.buffer.open_large_file(path)?;
+            let first_line = self.buffer.line_text(0);
+            let filetype = filetype::detect_filetype(path, first_line.as_deref());
+            self.buffer.set_filetype(filetype);
+            self.is_readonly = true;
+            self.show_message("File is too large to edit; opened read-only");
+        } else if size >= loader::BACKGROUND_LOAD_THRESHOLD {
+            self.load_in_background(path)?;
+        } else {
+            self.loader = None;
+            let decoded = encoding::read_file(path, self.forced_encoding()?)?;
+            self.buffer.set_content(decoded.text.lines().map(String::from).collect());
+            self.buffer.set_encoding(decoded.encoding);
+            self.buffer.set_bom(decoded.bom);
+            self.apply_file_settings(path);
+            self.git = git::head_lines(path).map(GitGutter::new);
+            self.refresh_git_marks();
+        }
+        // Back where the cursor was left, unless the file has shrunk
+        // since. A file still loading can't tell yet, and gets its last
+        // line if it has.
+        if self.config.restore_cursor {
+            if let Some((row, col)) = self.positions.get(path) {
+                if self.loader.is_some() || row < self.buffer.line_count() {
+                    self.place_cursor(row, col);
+                }
+            }
+        }
+
+        self.swap_change_id = self.buffer.current_change_id();
+        self.recovery_pending = swap::swap_exists(path);
+        if self.recovery_pending {
+            self.show_message(&format!(
+                "Found swap file {}: :recover to restore it, :discardswap to delete it",
+                swap::swap_path(path).display()
+            ));
+        }
+
+        self.refresh_git_branch();
+        self.recent.add(path);
+        if let Err(e) = self.recent.save() {
+            log::warn!("Failed to save recent files: {}", e);
+        }
+        Ok(())
+    }
+
+    /// gf: open the file named under the cursor, at the line and column after
+    /// its name in a `src/main.rs:42:7` as compilers print them. Names are
+    /// taken relative to the working directory.
+    pub fn goto_file(&mut self) -> io::Result<()> {
+        let Some(mut name) = self.buffer.file_name_at_cursor() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No file name under cursor"));
+        };
+        // Drop whatever follows the position, as the text of a grep match
+        let target = loop {
+            let target = cli::parse_file_args(&[name.clone()], |path| path.exists()).remove(0);
+            if target.path.exists() {
+                break target;
+            }
+            match name.rsplit_once(':') {
+                Some((rest, _)) => name = rest.to_string(),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Can't find file \"{}\"", target.path.display()),
+                    ));
+                }
+            }
+        };
+
+        let same_file = |open: &Path| {
+            std::fs::canonicalize(open).ok() == std::fs::canonicalize(&target.path).ok()
+        };
+        if self.file_path().is_some_and(same_file) {
+            self.buffer.push_jump();
+        } else {
+            if self.has_unsaved_changes() {
+                return Err(io::Error::other("No write since last change"));
+            }
+            // The file's own jump list goes with it, so the way back is
+            // taken before opening and kept here
+            let from = self.file_path.clone().map(|path| (path, self.buffer.get_cursor_position()));
+            self.open_file(&target.path)?;
+            self.file_jumps.extend(from);
+        }
+        if let Some(line) = target.line {
+            let col = target.col.unwrap_or(1);
+            self.buffer.set_cursor_position(line.saturating_sub(1), col.saturating_sub(1));
+        }
+        Ok(())
+    }
+
+    /// Ctrl-o: back along the buffer's jump list, and once that's done,
+    /// back to the file gf came from
+    pub fn jump_back(&mut self) -> io::Result<()> {
+        if self.buffer.jump_back() {
+            return Ok(());
+        }
+        let Some((path, (row, col))) = self.file_jumps.last().cloned() else {
+            return Ok(());
+        };
+        if self.has_unsaved_changes() {
+            return Err(io::Error::other("No write since last change"));
+        }
+        self.open_file(&path)?;
+        self.file_jumps.pop();
+        self.place_cursor(row, col);
+        Ok(())
+    }
+
+    /// Where to keep the :oldfiles list between sessions
+    pub fn set_recent_files(&mut self, recent: RecentFiles) {
+        self.recent = recent;
+    }
+
+    /// Where to keep the cursor positions open_file restores
+    pub fn set_cursor_positions(&mut self, positions: CursorPositions) {
+        self.positions = positions;
+    }
+
+    /// Note where the cursor is in the file, for opening it there next time.
+    /// Done on save, on leaving the file for another, and on quitting.
+    pub fn remember_cursor(&mut self) {
+        if !self.config.restore_cursor || self.is_loading() {
+            return;
+        }
+        if let Some(path) = &self.file_path {
+            self.positions.set(path, self.buffer.get_cursor_position());
+            if let Err(e) = self.positions.save() {
+                log::warn!("Failed to save cursor positions: {}", e);
+            }
+        }
+    }
+
+    /// :oldfiles: list the files opened lately, or open the `choice`th one
+    pub fn recent_files(&mut self, choice: Option<usize>) -> io::Result<()> {
+        if self.recent.paths().is_empty() {
+            self.show_message("No recent files");
+            return Ok(());
+        }
+        match choice {
+            Some(n) => match n.checked_sub(1).and_then(|i| self.recent.paths().get(i)) {
+                Some(path) => {
+                    let path = path.clone();
+                    self.open_file(&path)?;
+                }
+                None => self.show_error(&format!("No recent file {}", n)),
+            },
+            None => {
+                let mut listing = vec!["Recent files (type :oldfiles {number} to open):".to_string()];
+                listing.extend(self.recent.paths()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, path)| format!("{:>3} {}", i + 1, path.display())));
+                self.show_message(&listing.join("\n"));
+            }
+        }
+        Ok(())
+    }
+
+    // The files to step through with :next and :prev; the first is assumed
+    // to be open already
+    pub fn set_arg_list(&mut self, files: Vec<PathBuf>) {
+        self.arg_list = files;
+        self.arg_index = 0;
+    }
+
+    // :next and :prev - open the file `offset` places along the argument list
+    pub fn open_next_arg(&mut self, offset: isize) -> io::Result<()> {
+        let index = self.arg_index.checked_add_signed(offset);
+        let path = match index.and_then(|index| self.arg_list.get(index)) {
+            Some(path) => path.clone(),
+            None if offset < 0 => return Err(io::Error::other("Cannot go before first file")),
+            None => return Err(io::Error::other("Cannot go beyond last file")),
+        };
+        self.open_file(&path)?;
+        self.arg_index = index.unwrap_or_default();
+        Ok(())
+    }
+
+    // :args - the argument list, with the current file in brackets
+    pub fn arg_list_display(&self) -> String {
+        self.arg_list.iter()
+            .enumerate()
+            .map(|(i, path)| match i == self.arg_index {
+                true => format!("[{}]", path.display()),
+                false => path.display().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // :mksession - save the windows, files and cursor to pick up later.
+    // An existing file is only replaced with :mksession!.
+    pub fn make_session(&mut self, path: &Path, force: bool) -> io::Result<()> {
+        if !force && path.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!(
+                "{} exists (add ! to override)", path.display()
+            )));
+        }
+        self.park_buffer_view();
+        let windows = self.windows.layout_windows();
+        let focus = windows.iter()
+            .position(|window| Some(window.id()) == self.windows.active_id())
+            .unwrap_or(0);
+        let session = Session {
+            cwd: std::env::current_dir()?,
+            file: self.file_path.clone(),
+            cursor: self.buffer.get_cursor_position(),
+            args: self.arg_list.clone(),
+            arg_index: self.arg_index,
+            focus,
+            layout: self.windows.shape().unwrap_or(LayoutShape::Buffer { top: 0 }),
+        };
+        session.save(path)?;
+        self.show_message(&format!("Session saved to {}", path.display()));
+        Ok(())
+    }
+
+    // --session and :source - go back to a session saved by :mksession
+    pub fn load_session(&mut self, path: &Path) -> io::Result<()> {
+        let session = Session::load(path)?;
+        std::env::set_current_dir(&session.cwd)?;
+        log::info!("session: {} in {}", path.display(), session.cwd.display());
+        if let Some(file) = &session.file {
+            self.open_file(file)?;
+        }
+        self.arg_list = session.args;
+        self.arg_index = session.arg_index.min(self.arg_list.len().saturating_sub(1));
+        let (row, col) = session.cursor;
+        self.place_cursor(row, col);
+
+        let restored = self.windows.restore(&session.layout, session.focus);
+        self.restore_buffer_view();
+        self.request_redraw();
+        restored
+    }
+
+    /// :cd - make `dir` the working directory, which relative names in :e and
+    /// :w and new terminals start from. Without one, go home.
+    pub fn change_directory(&mut self, dir: &str) -> io::Result<()> {
+        let dir = match dir {
+            "" => dirs::home_dir().ok_or_else(|| io::Error::other("No home directory"))?,
+            dir => glob::expand_tilde(dir),
+        };
+        // The files already open stay the same files
+        if let Some(path) = &self.file_path {
+            self.file_path = Some(std::path::absolute(path)?);
+        }
+        for path in &mut self.arg_list {
+            *path = std::path::absolute(&*path)?;
+        }
+        std::env::set_current_dir(&dir)?;
+        log::info!("cd: {}", dir.display());
+        self.show_working_directory()
+    }
+
+    /// :pwd
+    pub fn show_working_directory(&mut self) -> io::Result<()> {
+        let cwd = std::env::current_dir()?;
+        self.show_message(&cwd.display().to_string());
+        Ok(())
+    }
+
+    // Fill a new, unnamed buffer with everything `reader` has, as for
+    // `cmd | zorforge -`. :w needs a file name to save it.
+    pub fn load_from_reader(&mut self, mut reader: impl io::Read) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let decoded = encoding::decode(&bytes, self.forced_encoding()?);
+
+        self.reset_buffer();
+        self.loader = None;
+        self.file_path = None;
+        self.disk_stamp = None;
+        self.git = None;
+        self.git_branch = None;
+        self.buffer.set_content(decoded.text.lines().map(String::from).collect());
+        self.buffer.set_encoding(decoded.encoding);
+        self.buffer.set_bom(decoded.bom);
+        self.swap_change_id = self.buffer.current_change_id();
+        Ok(())
+    }
+
+    // The `fileencoding` config option, which files are read as instead of
+    // detecting their encoding
+    fn forced_encoding(&self) -> io::Result<Option<Encoding>> {
+        let Some(name) = &self.config.fileencoding else {
+            return Ok(None);
+        };
+        Encoding::parse(name).map(Some).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown encoding: {}", name))
+        })
+    }
+
+    /// :set fileencoding: write the buffer as `encoding` from now on
+    pub fn set_file_encoding(&mut self, encoding: Encoding) {
+        if encoding != self.buffer.encoding() {
+            self.buffer.set_encoding(encoding);
+            self.buffer.mark_modified();
+        }
+    }
+
+    /// :set bomb / :set nobomb: whether the file is written with a byte
+    /// order mark
+    pub fn set_bom(&mut self, bom: bool) {
+        if bom != self.buffer.has_bom() {
+            self.buffer.set_bom(bom);
+            self.buffer.mark_modified();
+        }
+    }
+
+    // Start streaming the file in; lines arrive through poll_loading
+    fn load_in_background(&mut self, path: &Path) -> io::Result<()> {
+        self.loader = Some(FileLoader::spawn(path, self.forced_encoding()?)?);
+        // Show the first screenful as soon as it has been read
+        while self.loader.is_some() && !self.wait_for_load_event() {}
+        Ok(())
+    }
+
+    // Indent detection, modeline and filetype detection, once the whole file
+    // is in the buffer. A modeline has the last word over what was detected.
+    fn apply_file_settings(&mut self, path: &Path) {
+        if self.config.detect_indent {
+            if let Some(settings) = indent::detect_indent(self.buffer.get_content()) {
+                self.buffer.apply_modeline(&settings);
+            }
+        }
+        if let Some(settings) = modeline::find_modeline(self.buffer.get_content()) {
+            self.buffer.apply_modeline(&settings);
+        }
+        let first_line = self.buffer.get_line(0).map(String::as_str);
+        let filetype = filetype::detect_filetype(path, first_line);
+        self.buffer.set_filetype(filetype);
+    }
+
+    // :iabbrev {word} {expansion}
+    pub fn add_abbreviation(&mut self, word: &str, expansion: &str) {
+        self.abbreviations.insert(word.to_string(), expansion.to_string());
+    }
+
+    pub fn remove_abbreviation(&mut self, word: &str) -> bool {
+        self.abbreviations.remove(word).is_some()
+    }
+
+    pub fn abbreviations(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.abbreviations.iter().map(|(word, expansion)| (word.as_str(), expansion.as_str()))
+    }
+
+    // Called in insert mode just before a non-word character goes in: if the
+    // word ending at the cursor is an abbreviation, swap in its expansion
+    pub fn expand_abbreviation(&mut self) {
+        if self.abbreviations.is_empty() {
+            return;
+        }
+        let (_, col) = self.buffer.get_cursor_position();
+        let Some(before) = self.buffer.get_current_line().and_then(|line| line.get(..col)) else {
+            return;
+        };
+        let word_start = before.char_indices()
+            .rev()
+            .take_while(|&(_, c)| buffer::is_word_char(c))
+            .last()
+            .map_or(col, |(i, _)| i);
+
+        if let Some(expansion) = self.abbreviations.get(&before[word_start..]).cloned() {
+            self.buffer.replace_before_cursor(col - word_start, &expansion);
+        }
+    }
+
+    // :set spell / :set nospell
+    pub fn set_spell(&mut self, enabled: bool) -> io::Result<()> {
+        if enabled && self.dictionary.is_none() {
+            let path = self.config.spell_file.clone()
+                .unwrap_or_else(|| PathBuf::from(spell::DEFAULT_SPELL_FILE));
+            self.dictionary = Some(Arc::new(Dictionary::load(&path)?));
+        }
+        self.spell = enabled;
+        self.buffer.set_spell(if enabled { self.dictionary.clone() } else { None });
+        Ok(())
+    }
+
+    // ]s / [s
+    pub fn move_to_misspelling(&mut self, forward: bool) {
+        if !self.spell {
+            self.show_error("Spell checking is not enabled (:set spell)");
+        } else if !self.buffer.move_to_misspelling(forward) {
+            self.show_message("No misspelled words");
+        }
+    }
+
+    // z= lists suggestions for the word under the cursor; {count}z= uses
+    // the count-th one
+    pub fn spell_suggest(&mut self, choice: Option<usize>) {
+        if !self.spell {
+            self.show_error("Spell checking is not enabled (:set spell)");
+            return;
+        }
+        let Some((word, suggestions)) = self.buffer.spell_suggestions(SPELL_SUGGESTIONS) else {
+            return;
+        };
+        if suggestions.is_empty() {
+            self.show_message(&format!("Sorry, no suggestions for \"{}\"", word));
+            return;
+        }
+
+        match choice {
+            Some(n) => match n.checked_sub(1).and_then(|i| suggestions.get(i)) {
+                Some(suggestion) => {
+                    self.buffer.replace_spell_word(suggestion);
+                }
+                None => self.show_error(&format!("No suggestion {} for \"{}\"", n, word)),
+            },
+            None => {
+                let mut listing = vec![format!("Change \"{}\" to (type {{number}}z= to pick):", word)];
+                listing.extend(suggestions.iter()
+                    .enumerate()
+                    .map(|(i, suggestion)| format!("{:>2} \"{}\"", i + 1, suggestion)));
+                self.show_message(&listing.join("\n"));
+            }
+        }
+    }
+
+    // Horizontal move for `key`, wrapping to the adjacent line if the key is
+    // listed in the `whichwrap` setting
+    pub fn move_horizontally(&mut self, direction: &str, key: char) {
+        if self.config.whichwrap.contains(key) {
+            self.buffer.move_cursor_wrapping(direction);
+        } else {
+            self.buffer.move_cursor(direction);
+        }
+    }
+
+    pub fn is_large_file(&self) -> bool {
+        self.buffer.is_large_file()
+    }
+
+    // --diff: edit `left` with `right` shown beside it, differing lines
+    // highlighted on both sides
+    pub fn open_diff(&mut self, left: &PathBuf, right: &Path) -> io::Result<()> {
+        let right = encoding::read_file(right, self.forced_encoding()?)?;
+        self.open_file(left)?;
+        self.diff = Some(DiffView {
+            lines: right.text.lines().map(String::from).collect(),
+            diff: diff::Diff::default(),
+            compared: (usize::MAX, 0),
+        });
+        self.refresh_diff();
+        Ok(())
+    }
+
+    pub fn diff_view(&self) -> Option<&DiffView> {
+        self.diff.as_ref()
+    }
+
+    // Compare again once the buffer has changed. Any line's highlight may
+    // move, so the whole screen is repainted.
+    pub fn refresh_diff(&mut self) {
+        let Some(view) = &mut self.diff else {
+            return;
+        };
+        let compared = (self.buffer.current_change_id(), self.buffer.line_count());
+        if view.compared != compared {
+            view.diff = diff::diff_lines(self.buffer.get_content(), &view.lines);
+            view.compared = compared;
+            self.redraw_requested = true;
+        }
+    }
+
+    // Mark the lines that differ from HEAD again once the buffer has
+    // changed, repainting if any mark moved
+    pub fn refresh_git_marks(&mut self) {
+        let Some(git) = &mut self.git else {
+            return;
+        };
+        let compared = (self.buffer.current_change_id(), self.buffer.line_count());
+        if git.refresh(self.buffer.get_content(), compared) {
+            self.redraw_requested = true;
+        }
+    }
+
+    pub fn git_mark(&self, row: usize) -> Option<GitMark> {
+        self.git.as_ref().and_then(|git| git.mark(row))
+    }
+
+    // Read the checked out branch again, as after a save or when the
+    // terminal regains focus
+    pub fn refresh_git_branch(&mut self) {
+        self.git_branch = self.file_path.as_deref().and_then(git::branch_name);
+    }
+
+    pub fn git_branch(&self) -> Option<&str> {
+        self.git_branch.as_deref()
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loader.is_some()
+    }
+
+    // Percentage of the file read so far, while a background load is running
+    pub fn load_progress(&self) -> Option<usize> {
+        self.loader.as_ref().map(FileLoader::progress)
+    }
+
+    // Move any lines the background loader has read into the buffer
+    pub fn poll_loading(&mut self) {
+        while let Some(event) = self.loader.as_mut().and_then(FileLoader::try_recv) {
+            self.handle_load_event(event);
+        }
+    }
+
+    /// Put the cursor at (row, col) and bring it into view. While a
+    /// background load is running that waits until it's done, so a row
+    /// past what's been read so far isn't cut short.
+    pub fn place_cursor(&mut self, row: usize, col: usize) {
+        self.pending_cursor = Some((row, col));
+        if self.loader.is_none() {
+            self.place_pending_cursor();
+        }
+    }
+
+    fn place_pending_cursor(&mut self) {
+        if let Some((row, col)) = self.pending_cursor.take() {
+            self.buffer.set_cursor_position(row, col);
+            self.buffer.scroll_to_cursor();
+        }
+    }
+
+    // Block until a background load has read the whole file, for what
+    // needs all of it, like --cmd
+    pub fn finish_loading(&mut self) {
+        while self.is_loading() {
+            self.wait_for_load_event();
+        }
+    }
+
+    // Whether the event waited for brought lines
+    fn wait_for_load_event(&mut self) -> bool {
+        let Some(event) = self.loader.as_mut().map(FileLoader::recv) else {
+            return false;
+        };
+        let lines = matches!(event, LoadEvent::Lines { .. });
+        self.handle_load_event(event);
+        lines
+    }
+
+    fn handle_load_event(&mut self, event: LoadEvent) {
+        match event {
+            LoadEvent::Encoding { encoding, bom } => {
+                self.buffer.set_encoding(encoding);
+                self.buffer.set_bom(bom);
+            }
+            // The first chunk replaces the empty placeholder line, or what
+            // was read before the file turned out not to be UTF-8
+            LoadEvent::Lines { lines, first: true } => self.buffer.set_content(lines),
+            LoadEvent::Lines { lines, first: false } => self.buffer.append_lines(lines),
+            LoadEvent::Done => {
+                self.loader = None;
+                self.place_pending_cursor();
+                if let Some(path) = self.file_path.clone() {
+                    self.apply_file_settings(&path);
+                    self.git = git::head_lines(&path).map(GitGutter::new);
+                    self.refresh_git_marks();
+                }
+            }
+            // What's in the buffer isn't the whole file, so writing it
+            // back would lose the rest
+            LoadEvent::Failed(e) => {
+                self.loader = None;
+                self.place_pending_cursor();
+                self.is_readonly = true;
+                self.show_error(&format!("Error loading file, opened read-only: {}", e));
+            }
+        }
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.is_readonly = readonly;
+    }
+
+    /// --readonly: the buffer, and every file opened after it, is read-only
+    pub fn set_readonly_files(&mut self, readonly: bool) {
+        self.readonly_files = readonly;
+        self.is_readonly = readonly;
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.is_readonly
+    }
+
+    pub fn filetype(&self) -> Option<&str> {
+        self.buffer.filetype()
+    }
+
+    // How far through the file the cursor is, as a whole percentage
+    pub fn cursor_percentage(&self) -> usize {
+        let (row, _) = self.cursor_position();
+        (row + 1) * 100 / self.buffer.line_count().max(1)
+    }
+
+    // Current search match and the number of matches, as `[3/12]` shows them
+    pub fn search_position(&self) -> Option<(usize, usize)> {
+        self.buffer.search_position()
+    }
+
+    // Length of the buffer, as `120 lines, 3.4K`
+    pub fn buffer_size_info(&self) -> String {
+        let lines = self.buffer.line_count();
+        let plural = if lines == 1 { "" } else { "s" };
+        format!("{} line{}, {}", lines, plural, format_size(self.buffer.byte_len()))
+    }
+
+    // Ctrl-G: the file's name and state, its length and how far through
+    // it the cursor is
+    pub fn show_file_info(&mut self) {
+        let modified = if self.has_unsaved_changes() { " [Modified]" } else { "" };
+        let readonly = if self.is_readonly() { " [readonly]" } else { "" };
+        let message = format!(
+            "\"{}\"{}{} {} --{}%--",
+            self.file_info(),
+            modified,
+            readonly,
+            self.buffer_size_info(),
+            self.cursor_percentage(),
+        );
+        self.show_message(&message);
+    }
+
+    pub fn cursor_position_info(&self) -> String {
+        let (row, col) = self.cursor_position();
+        format!("{}:{}", row + 1, col + 1)
+    }
+
+    // Typed characters go in at the command-line cursor
+    pub fn append_to_command(&mut self, c: char) {
+        let buffer = self.command_buffer.get_or_insert_with(String::new);
+        let at = self.command_cursor.min(buffer.len());
+        buffer.insert(at, c);
+        self.command_cursor = at + c.len_utf8();
+    }
+
+    // Pasted text goes in at the command-line cursor. The command line is a
+    // single line, so line breaks in it become spaces.
+    pub fn paste_into_command(&mut self, text: &str) {
+        let text = text.trim_end_matches(['\r', '\n']).replace("\r\n", "\n");
+        for c in text.chars() {
+            self.append_to_command(if c == '\n' || c == '\r' { ' ' } else { c });
+        }
+    }
+
+    // Backspace: the character before the command-line cursor
+    pub fn delete_from_command(&mut self) {
+        if let Some(buffer) = &mut self.command_buffer {
+            let at = self.command_cursor.min(buffer.len());
+            if let Some(c) = buffer[..at].chars().next_back() {
+                buffer.remove(at - c.len_utf8());
+                self.command_cursor = at - c.len_utf8();
+            }
+            if buffer.is_empty() {
+                self.clear_command();
+            }
+        }
+    }
+
+    // Start the command line off with `text`, e.g. the '<,'> range
+    pub fn set_command_line(&mut self, text: &str) {
+        self.command_buffer = (!text.is_empty()).then(|| text.to_string());
+        self.command_cursor = text.len();
+    }
+
+    pub fn clear_command(&mut self) {
+        self.command_buffer = None;
+        self.command_cursor = 0;
+    }
+
+    // Byte offset of the cursor in the command line
+    pub fn command_cursor(&self) -> usize {
+        self.command_cursor
+    }
+
+    // Left/Right: one character along the command line
+    pub fn move_command_cursor(&mut self, forward: bool) {
+        let buffer = self.command_buffer.as_deref().unwrap_or("");
+        let at = self.command_cursor.min(buffer.len());
+        let step = match forward {
+            true => buffer[at..].chars().next(),
+            false => buffer[..at].chars().next_back(),
+        }.map_or(0, char::len_utf8);
+        self.command_cursor = match forward {
+            true => at + step,
+            false => at - step,
+        };
+    }
+
+    // Home/End: the start or end of the command line
+    pub fn command_cursor_to_end(&mut self, end: bool) {
+        self.command_cursor = match end {
+            true => self.command_buffer.as_ref().map_or(0, String::len),
+            false => 0,
+        };
+    }
+
+    // Partially typed normal-mode command (count prefix, operator), for showcmd
+    pub fn pending_keys(&self) -> &str {
+        &self.pending_keys
+    }
+
+    pub fn push_pending_key(&mut self, c: char) {
+        if !c.is_ascii_digit() {
+            self.prefix_since = Some(Instant::now());
+        }
+        self.pending_keys.push(c);
+    }
+
+    pub fn clear_pending_keys(&mut self) {
+        self.pending_keys.clear();
+    }
+
+    // The leader was pressed: the keys typed next pick one of the config's
+    // leader mappings
+    pub fn start_leader(&mut self) {
+        self.leader_keys = Some(String::new());
+        self.prefix_since = Some(Instant::now());
+    }
+
+    pub fn leader_keys(&self) -> Option<&str> {
+        self.leader_keys.as_deref()
+    }
+
+    pub fn cancel_leader(&mut self) {
+        self.leader_keys = None;
+    }
+
+    // Add a key to the leader sequence, returning the command of the mapping
+    // it completes. While the keys could still become a mapping the sequence
+    // waits for more; once they can't it's dropped. A mapping that begins a
+    // longer one is taken as soon as it's typed.
+    pub fn push_leader_key(&mut self, c: char) -> Option<String> {
+        let keys = self.leader_keys.as_mut()?;
+        keys.push(c);
+        let mappings = &self.config.leader_mappings;
+        if let Some(command) = mappings.get(keys.as_str()) {
+            let command = command.clone();
+            self.leader_keys = None;
+            return Some(command);
+        }
+        if !mappings.keys().any(|mapped| mapped.starts_with(keys.as_str())) {
+            self.leader_keys = None;
+        }
+        None
+    }
+
+    // Count typed before the command, if any (the `3` in `3dd`)
+    pub fn pending_count(&self) -> Option<usize> {
+        let digits: String = self.pending_keys
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        digits.parse().ok()
+    }
+
+    // Operator or prefix key waiting for its second key (the `d` in `3d`)
+    pub fn pending_operator(&self) -> Option<char> {
+        self.pending_keys.chars().rev().find(|c| !c.is_ascii_digit())
+    }
+
+    /// The keys that may follow the pending prefix or leader keys, with what
+    /// each does, once they've waited long enough to want a reminder
+    pub fn which_key(&self) -> Option<Vec<(String, String)>> {
+        if self.which_key_remaining().is_some() {
+            return None;
+        }
+        let keys = match (&self.leader_keys, self.pending_operator()) {
+            (Some(typed), _) => which_key::leader_continuations(&self.config.leader_mappings, typed),
+            (None, Some(prefix)) => which_key::continuations(prefix),
+            (None, None) => return None,
+        };
+        (!keys.is_empty()).then_some(keys)
+    }
+
+    /// How long until the which-key popup is due, so the event loop can wake
+    /// up to show it
+    pub fn which_key_remaining(&self) -> Option<Duration> {
+        if self.leader_keys.is_none() && self.pending_operator().is_none() {
+            return None;
+        }
+        self.prefix_since
+            .map(|at| WHICH_KEY_DELAY.saturating_sub(at.elapsed()))
+            .filter(|left| !left.is_zero())
+    }
+
+    // Words from every source that could finish `prefix`, without repeats
+    fn completions(&self, prefix: &str) -> Vec<String> {
+        let cursor = self.buffer.get_cursor_position();
+        let mut items: Vec<String> = Vec::new();
+        for source in &self.completion_sources {
+            for item in source.complete(&self.buffer, prefix, cursor) {
+                if !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+        }
+        items
+    }
+
+    /// Ctrl-N or Ctrl-P in insert mode: open the completion popup for the
+    /// word before the cursor, on its first or last word
+    pub fn start_completion(&mut self, forward: bool) {
+        let (row, col) = self.buffer.get_cursor_position();
+        let Some(line) = self.buffer.get_current_line() else {
+            return;
+        };
+        let start = completion::word_start(line, col);
+        let prefix = line[start..col.min(line.len())].to_string();
+        let items = self.completions(&prefix);
+        if items.is_empty() {
+            self.show_error("Pattern not found");
+            return;
+        }
+        let selected = if forward { 0 } else { items.len() - 1 };
+        self.completion = Some(Completion { row, start, prefix, items, selected });
+    }
+
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref()
+    }
+
+    pub fn select_completion(&mut self, forward: bool) {
+        if let Some(completion) = &mut self.completion {
+            completion.select(forward);
+        }
+    }
+
+    /// Finish the word being typed with the picked completion
+    pub fn accept_completion(&mut self) {
+        let Some(completion) = self.completion.take() else {
+            return;
+        };
+        if let Some(item) = completion.selected_item() {
+            self.buffer.insert_text(&item[completion.prefix.len()..]);
+        }
+    }
+
+    pub fn cancel_completion(&mut self) {
+        self.completion = None;
+    }
+
+    // Narrow the popup to the word as typed so far, closing it once the
+    // cursor has left the word or nothing fits any more
+    pub fn update_completion(&mut self) {
+        let Some(completion) = &self.completion else {
+            return;
+        };
+        let (row, col) = self.buffer.get_cursor_position();
+        let start = completion.start;
+        let prefix = match self.buffer.get_current_line() {
+            Some(line) if row == completion.row && completion::word_start(line, col) == start => {
+                line[start..col].to_string()
+            }
+            _ => {
+                self.completion = None;
+                return;
+            }
+        };
+        let items = self.completions(&prefix);
+        self.completion = (!items.is_empty())
+            .then_some(Completion { row, start, prefix, items, selected: 0 });
+    }
+
+    pub fn set_snippets(&mut self, snippets: Snippets) {
+        self.snippets = snippets;
+    }
+
+    /// Tab in insert mode: if the word before the cursor triggers a snippet
+    /// for the filetype, put the snippet in its place as one change and go to
+    /// its first tab stop. False if there's no such snippet.
+    pub fn expand_snippet(&mut self) -> bool {
+        let (row, col) = self.buffer.get_cursor_position();
+        let Some(line) = self.buffer.get_current_line().filter(|line| col <= line.len()) else {
+            return false;
+        };
+        let start = completion::word_start(line, col);
+        if start == col {
+            return false;
+        }
+        let trigger = line[start..col].to_string();
+        let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+        let template = match self.snippets.find(self.buffer.filetype(), &trigger) {
+            Ok(Some(template)) => template,
+            Ok(None) => return false,
+            Err(e) => {
+                self.show_error(&e.to_string());
+                return false;
+            }
+        };
+
+        // Lines after the first line up with the trigger's line
+        let snippet = snippet::parse(&template);
+        let text = snippet.lines.join(&format!("\n{}", indent));
+        let stops: Vec<(usize, usize)> = snippet.stops.iter()
+            .map(|&(line, stop_col)| match line {
+                0 => (row, start + stop_col),
+                line => (row + line, indent.len() + stop_col),
+            })
+            .collect();
+        let undo_len = self.buffer.begin_merge();
+        self.buffer.replace_before_cursor(col - start, "");
+        self.buffer.paste_at_cursor(&text);
+        self.buffer.merge_changes_since(undo_len);
+
+        let stops = SnippetStops::new(stops);
+        let (row, col) = stops.current();
+        self.buffer.set_cursor_position(row, col);
+        self.snippet_stops = (!stops.is_last()).then_some(stops);
+        true
+    }
+
+    /// Tab or Shift-Tab while filling in a snippet: go to its next or
+    /// previous tab stop; reaching the last one finishes the snippet. False
+    /// if no snippet is being filled in.
+    pub fn next_snippet_stop(&mut self, forward: bool) -> bool {
+        let Some(stops) = &mut self.snippet_stops else {
+            return false;
+        };
+        let (row, col) = stops.advance(forward, self.buffer.get_cursor_position());
+        if stops.is_last() {
+            self.snippet_stops = None;
+        }
+        self.buffer.set_cursor_position(row, col);
+        true
+    }
+
+    /// Note a key that was handled in mode `before`, so that `.` can play
+    /// back the insert it belongs to
+    pub fn record_key(&mut self, key: KeyEvent, before: &Mode) {
+        let pending = !self.pending_keys.is_empty();
+        self.inserts.record(key, before, &self.mode, pending);
+    }
+
+    /// The keys of the last insert, from the command that began it to `Esc`
+    pub fn last_insert(&self) -> Option<Vec<KeyEvent>> {
+        self.inserts.last().map(<[KeyEvent]>::to_vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_keys() {
+        let mut editor = Editor::new(EditorConfig::default());
+        assert_eq!(editor.pending_count(), None);
+        assert_eq!(editor.pending_operator(), None);
+
+        editor.push_pending_key('1');
+        editor.push_pending_key('2');
+        editor.push_pending_key('d');
+        assert_eq!(editor.pending_keys(), "12d");
+        assert_eq!(editor.pending_count(), Some(12));
+        assert_eq!(editor.pending_operator(), Some('d'));
+
+        editor.clear_pending_keys();
+        assert_eq!(editor.pending_keys(), "");
+    }
+
+    #[test]
+    fn test_substitute_confirm() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["a a".to_string(), "b".to_string(), "a".to_string()]);
+        editor.start_substitute_confirm(0, 2, "a", "xy", true).unwrap();
+        assert!(editor.is_confirming_substitute());
+        assert_eq!(editor.buffer.current_match(), Some((0, 0, 1)));
+
+        editor.answer_substitute('y');
+        assert_eq!(editor.buffer.current_match(), Some((0, 3, 4)));
+        editor.answer_substitute('?'); // Asks again
+        editor.answer_substitute('n');
+        assert_eq!(editor.buffer.get_cursor_position(), (2, 0));
+        editor.answer_substitute('y');
+        assert!(!editor.is_confirming_substitute());
+        assert_eq!(*editor.buffer.get_content(), ["xy a", "b", "xy"]);
+        assert_eq!(editor.buffer.current_match(), None);
+        assert_eq!(editor.get_message().unwrap().text, "2 substitutions");
+
+        // One undo takes back the whole command
+        editor.buffer.undo();
+        assert_eq!(*editor.buffer.get_content(), ["a a", "b", "a"]);
+    }
+
+    #[test]
+    fn test_substitute_confirm_all_and_quit() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["a a".to_string(), "a a".to_string()]);
+        editor.start_substitute_confirm(0, 1, "a", "b", false).unwrap();
+        editor.answer_substitute('q');
+        assert_eq!(*editor.buffer.get_content(), ["a a", "a a"]);
+
+        // Without g only the first match on each line is offered
+        editor.start_substitute_confirm(0, 1, "a", "b", false).unwrap();
+        editor.answer_substitute('a');
+        assert!(!editor.is_confirming_substitute());
+        assert_eq!(*editor.buffer.get_content(), ["b a", "b a"]);
+
+        assert!(editor.start_substitute_confirm(0, 1, "zz", "b", true).is_err());
+    }
+
+    #[test]
+    fn test_command_line_starts_empty() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.set_mode(Mode::Command(CommandType::Regular));
+        editor.append_to_command('w');
+        assert_eq!(editor.command_line_content(), "w");
+
+        editor.set_mode(Mode::Normal);
+        assert_eq!(editor.command_line_content(), "");
+
+        // Text typed outside command mode doesn't carry into the next one
+        editor.append_to_command('x');
+        editor.set_mode(Mode::Command(CommandType::Search));
+        editor.append_to_command('f');
+        assert_eq!(editor.command_line_content(), "f");
+    }
+
+    #[test]
+    fn test_shows_intro() {
+        let mut editor = Editor::new(EditorConfig::default());
+        assert!(editor.shows_intro());
+
+        editor.set_mode(Mode::Insert(InsertVariant::Insert));
+        assert!(!editor.shows_intro());
+        editor.buffer.insert_char('a');
+        editor.set_mode(Mode::Normal);
+        assert!(!editor.shows_intro());
+    }
+
+    #[test]
+    fn test_overtype_toggle() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.set_mode(Mode::Insert(InsertVariant::Insert));
+        assert!(!editor.is_overtype());
+
+        editor.toggle_overtype();
+        assert!(editor.is_overtype());
+        assert_eq!(editor.cursor_style(), mode::CursorStyle::Underline);
+
+        // The toggle outlives the insert, but only shows inside one
+        editor.set_mode(Mode::Normal);
+        assert!(!editor.is_overtype());
+        assert_eq!(editor.cursor_style(), mode::CursorStyle::Block);
+        editor.set_mode(Mode::Insert(InsertVariant::Append));
+        assert!(editor.is_overtype());
+
+        editor.toggle_overtype();
+        assert_eq!(editor.cursor_style(), mode::CursorStyle::Line);
+    }
+
+    #[test]
+    fn test_command_line_cursor() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.set_mode(Mode::Command(CommandType::Regular));
+        for c in "s/fo/bar/".chars() {
+            editor.append_to_command(c);
+        }
+
+        // Fix the typo in the middle: s/fo/ -> s/foo/
+        for _ in 0..5 {
+            editor.move_command_cursor(false);
+        }
+        editor.append_to_command('o');
+        assert_eq!(editor.command_line_content(), "s/foo/bar/");
+        assert_eq!(editor.command_cursor(), 5);
+
+        // Backspace takes the character before the cursor
+        editor.move_command_cursor(true);
+        editor.delete_from_command();
+        assert_eq!(editor.command_line_content(), "s/foobar/");
+
+        editor.command_cursor_to_end(false);
+        editor.move_command_cursor(false);
+        editor.delete_from_command();
+        editor.append_to_command('%');
+        assert_eq!(editor.command_line_content(), "%s/foobar/");
+        editor.command_cursor_to_end(true);
+        assert_eq!(editor.command_cursor(), "%s/foobar/".len());
+        editor.move_command_cursor(true);
+        assert_eq!(editor.command_cursor(), "%s/foobar/".len());
+
+        // Starting text leaves the cursor after it
+        editor.set_command_line("'<,'>");
+        editor.append_to_command('d');
+        assert_eq!(editor.command_line_content(), "'<,'>d");
+
+        // A paste goes in at the cursor, on the one line
+        editor.move_command_cursor(false);
+        editor.paste_into_command("s/a\r\nb/c/\n");
+        assert_eq!(editor.command_line_content(), "'<,'>s/a b/c/d");
+    }
+
+    #[test]
+    fn test_write_and_quit() {
+        let path = std::env::temp_dir().join(format!("zorforge_wq_{}.txt", std::process::id()));
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.insert_char('a');
+
+        // Nowhere to write, so the editor stays open
+        assert!(editor.write_and_quit(true).is_err());
+        assert!(!editor.should_quit());
+
+        editor.file_path = Some(path.clone());
+        editor.write_and_quit(false).unwrap();
+        assert!(editor.should_quit());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_all_skips_unnamed_buffers() {
+        let mut editor = Editor::new(EditorConfig::default());
+        assert!(editor.write_all().unwrap());
+
+        editor.buffer.insert_char('a');
+        editor.write_all_and_quit().unwrap();
+        assert!(!editor.should_quit());
+        assert_eq!(editor.get_message().unwrap().kind, MessageKind::Error);
+
+        let path = std::env::temp_dir().join(format!("zorforge_wa_{}.txt", std::process::id()));
+        editor.file_path = Some(path.clone());
+        editor.write_all_and_quit().unwrap();
+        assert!(editor.should_quit());
+        assert!(!editor.has_unsaved_changes());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_from_reader() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.load_from_reader("one\ntwo\n".as_bytes()).unwrap();
+        assert_eq!(*editor.buffer.get_content(), ["one", "two"]);
+        assert_eq!(editor.file_path(), None);
+        assert!(editor.save_buffer().is_err());
+    }
+
+    #[test]
+    fn test_windows_keep_their_scroll_position() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content((0..100).map(|i| i.to_string()).collect());
+        editor.update_viewport(20, 80);
+        assert_eq!(editor.buffer.get_viewport().height, 20);
+
+        editor.split_window(SplitDirection::Horizontal).unwrap();
+        editor.update_viewport(20, 80);
+        assert_eq!(editor.buffer.get_viewport().height, 9);
+        editor.buffer.set_cursor_position(50, 0);
+        editor.update_viewport(20, 80);
+        assert_eq!(editor.buffer.get_viewport().start, 42);
+
+        // The other window is still at the top, and the cursor is shared
+        editor.focus_next_window();
+        assert_eq!(editor.buffer.get_viewport().start, 0);
+        editor.buffer.set_cursor_position(3, 0);
+        editor.focus_next_window();
+        assert_eq!(editor.buffer.get_viewport().start, 42);
+
+        editor.close_window().unwrap();
+        assert!(editor.close_window().is_err());
+        editor.update_viewport(20, 80);
+        assert_eq!(editor.buffer.get_viewport().height, 20);
+    }
+
+    #[test]
+    fn test_clipboard_history() {
+        let config = EditorConfig { clipboard_history: 2, ..EditorConfig::default() };
+        let mut editor = Editor::new(config);
+        for row in 0..3 {
+            editor.buffer.clipboard_mut().yank(row.to_string());
+        }
+        assert_eq!(editor.buffer.clipboard_mut().len(), 2);
+    }
+
+    #[test]
+    fn test_help_pages() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.update_viewport(5, 80);
+        editor.open_help((1..=10).map(|i| format!("line {}", i)).collect());
+        assert!(editor.is_showing_help());
+        let page = |editor: &Editor| editor.get_message().unwrap().text.lines().skip(1).collect::<Vec<_>>().join(" ");
+        assert_eq!(page(&editor), "line 1 line 2 line 3 line 4");
+
+        editor.scroll_help(1, true);
+        assert_eq!(page(&editor), "line 5 line 6 line 7 line 8");
+        editor.scroll_help(-1, false);
+        assert!(editor.get_message().unwrap().text.starts_with("Help, line 4 of 10"));
+
+        editor.close_help();
+        assert!(!editor.is_showing_help());
+        assert!(editor.get_message().is_none());
+    }
+
+    #[test]
+    fn test_clipboard_browser() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_clipboard_browser();
+        assert!(!editor.is_browsing_clipboard());
+
+        editor.buffer.set_content(vec!["one".to_string(), "two".to_string()]);
+        editor.buffer.yank();
+        editor.buffer.set_cursor_position(1, 0);
+        editor.buffer.yank();
+        editor.open_clipboard_browser();
+        assert!(editor.is_browsing_clipboard());
+        assert!(editor.get_message().unwrap().text.contains(">  1 two^J"));
+        editor.move_clipboard_selection(true);
+        editor.move_clipboard_selection(true);
+        assert!(editor.get_message().unwrap().text.contains(">  2 one^J"));
+
+        editor.paste_clipboard_entry(None);
+        assert!(!editor.is_browsing_clipboard());
+        assert_eq!(*editor.buffer.get_content(), ["one", "two", "one"]);
+        editor.paste_clipboard_entry(Some(5));
+        assert_eq!(editor.get_message().unwrap().kind, MessageKind::Error);
+    }
+
+    #[test]
+    fn test_save_config() {
+        let path = std::env::temp_dir().join(format!("zorforge_saveconfig_{}.toml", std::process::id()));
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.config.colorcolumn = "80".to_string();
+        editor.save_config(Some(&path)).unwrap();
+        assert!(editor.get_message().unwrap().text.starts_with("Config saved to"));
+
+        let saved = EditorConfig::load_from_file(&path).unwrap();
+        assert_eq!(saved.colorcolumn, "80");
+        assert_eq!(saved.tab_size, editor.config.tab_size);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_leader_mappings() {
+        let mut config = EditorConfig::default();
+        config.leader_mappings.insert("w".to_string(), ":w".to_string());
+        config.leader_mappings.insert("ff".to_string(), ":files".to_string());
+        let mut editor = Editor::new(config);
+
+        editor.start_leader();
+        assert_eq!(editor.push_leader_key('w').as_deref(), Some(":w"));
+        assert_eq!(editor.leader_keys(), None);
+
+        editor.start_leader();
+        assert_eq!(editor.push_leader_key('f'), None);
+        assert_eq!(editor.leader_keys(), Some("f"));
+        assert_eq!(editor.push_leader_key('f').as_deref(), Some(":files"));
+
+        // Keys no mapping starts with end the sequence
+        editor.start_leader();
+        assert_eq!(editor.push_leader_key('x'), None);
+        assert_eq!(editor.leader_keys(), None);
+    }
+
+    #[test]
+    fn test_open_line_undoes_with_insert() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["    one".to_string(), "two".to_string()]);
+        editor.open_line(true);
+        assert!(matches!(editor.mode, Mode::Insert(_)));
+        for c in "new".chars() {
+            editor.buffer.insert_char(c);
+        }
+        editor.set_mode(Mode::Normal);
+        assert_eq!(*editor.buffer.get_content(), ["    one", "    new", "two"]);
+
+        editor.buffer.undo();
+        assert_eq!(*editor.buffer.get_content(), ["    one", "two"]);
+        editor.buffer.redo();
+        assert_eq!(*editor.buffer.get_content(), ["    one", "    new", "two"]);
+    }
+
+    #[test]
+    fn test_buffer_size_info() {
+        let mut editor = Editor::new(EditorConfig::default());
+        assert_eq!(editor.buffer_size_info(), "1 line, 0B");
+        editor.buffer.set_content(vec!["x".repeat(2047), "y".to_string()]);
+        assert_eq!(editor.buffer_size_info(), "2 lines, 2.0K");
+        assert_eq!(format_size(3 * 1024 * 1024 + 512 * 1024), "3.5M");
+
+        editor.show_file_info();
+        assert_eq!(editor.get_message().unwrap().text, "\"[No Name]\" 2 lines, 2.0K --50%--");
+    }
+
+    #[test]
+    fn test_which_key() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.push_pending_key('2');
+        assert_eq!(editor.which_key_remaining(), None);
+
+        // Nothing shows until the prefix has waited a moment
+        editor.push_pending_key('Z');
+        assert!(editor.which_key_remaining().is_some());
+        assert_eq!(editor.which_key(), None);
+        editor.prefix_since = Some(Instant::now() - WHICH_KEY_DELAY);
+        let keys: Vec<String> = editor.which_key().unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["Z", "Q"]);
+        editor.clear_pending_keys();
+        assert_eq!(editor.which_key(), None);
+
+        editor.config.leader_mappings.insert("w".to_string(), ":w".to_string());
+        editor.start_leader();
+        editor.prefix_since = Some(Instant::now() - WHICH_KEY_DELAY);
+        assert_eq!(editor.which_key(), Some(vec![("w".to_string(), ":w".to_string())]));
+    }
+
+    #[test]
+    fn test_yank_highlight() {
+        let mut editor = Editor::new(EditorConfig::default());
+        assert_eq!(editor.yank_highlight(), None);
+        editor.buffer.set_content(vec!["one".to_string(), "two words".to_string()]);
+        editor.buffer.set_cursor_position(1, 2);
+        editor.yank_line();
+        assert_eq!(editor.yank_highlight(), Some(((1, 0), (1, 0), VisualMode::Line)));
+        assert!(editor.yank_highlight_remaining().is_some_and(|left| left <= YANK_HIGHLIGHT));
+
+        editor.buffer.set_cursor_position(1, 4);
+        editor.buffer.toggle_visual_mode(VisualMode::Char);
+        editor.buffer.set_cursor_position(1, 9);
+        editor.yank_selection();
+        assert_eq!(editor.yank_highlight(), Some(((1, 4), (1, 9), VisualMode::Char)));
+        assert_eq!(editor.buffer.clipboard_mut().peek().map(String::as_str), Some("words"));
+        assert_eq!(editor.buffer.get_cursor_position(), (1, 4));
+
+        // A block made from its top-right corner: the cursor goes to the top-left
+        editor.buffer.clear_visual();
+        editor.buffer.set_cursor_position(0, 2);
+        editor.buffer.toggle_visual_mode(VisualMode::Block);
+        editor.buffer.set_cursor_position(1, 1);
+        editor.yank_selection();
+        assert_eq!(editor.buffer.get_cursor_position(), (0, 1));
+    }
+
+    #[test]
+    fn test_clipboard_preview() {
+        assert_eq!(clipboard_preview("fn main() {\n\tx\n"), "fn main() {^J^Ix^J");
+        assert_eq!(clipboard_preview(&"é".repeat(70)), format!("{}...", "é".repeat(60)));
+    }
+
+    #[test]
+    fn test_visible_range() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content((0..30).map(|i| i.to_string()).collect());
+        editor.buffer.set_cursor_position(25, 0);
+        editor.update_viewport(10, 80);
+        assert_eq!(editor.visible_range(), 16..26);
+
+        editor.buffer.set_content(vec!["a".to_string(), "b".to_string()]);
+        editor.buffer.set_viewport_start(0);
+        assert_eq!(editor.visible_range(), 0..2);
+    }
+
+    #[test]
+    fn test_diff_follows_edits() {
+        use diff::LineDiff::*;
+        let paths: Vec<PathBuf> = ["left", "right"].iter()
+            .map(|name| std::env::temp_dir().join(format!("zorforge_diff_{}_{}.txt", name, std::process::id())))
+            .collect();
+        std::fs::write(&paths[0], "a\nb\nc\n").unwrap();
+        std::fs::write(&paths[1], "a\nc\n").unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_diff(&paths[0], &paths[1]).unwrap();
+        assert_eq!(editor.diff_view().unwrap().diff.left, vec![Same, Only, Same]);
+
+        editor.buffer.set_cursor_position(1, 0);
+        editor.buffer.insert_char('x');
+        editor.refresh_diff();
+        assert_eq!(editor.diff_view().unwrap().diff.left, vec![Same, Only, Same]);
+        editor.buffer.set_cursor_position(2, 0);
+        editor.buffer.delete_line();
+        editor.refresh_diff();
+        assert_eq!(editor.diff_view().unwrap().diff.left, vec![Same, Changed]);
+        for path in &paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_arg_list() {
+        let paths: Vec<PathBuf> = ["a", "b"].iter()
+            .map(|name| std::env::temp_dir().join(format!("zorforge_args_{}_{}.txt", name, std::process::id())))
+            .collect();
+        for (path, text) in paths.iter().zip(["first", "second"]) {
+            std::fs::write(path, text).unwrap();
+        }
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&paths[0]).unwrap();
+        editor.set_arg_list(paths.clone());
+        assert_eq!(editor.arg_list_display(), format!("[{}] {}", paths[0].display(), paths[1].display()));
+
+        editor.open_next_arg(1).unwrap();
+        assert_eq!(editor.buffer.get_content()[0], "second");
+        assert!(editor.open_next_arg(1).is_err());
+        assert_eq!(editor.file_path(), Some(paths[1].as_path()));
+
+        editor.open_next_arg(-1).unwrap();
+        assert_eq!(editor.buffer.get_content()[0], "first");
+        assert!(editor.open_next_arg(-1).is_err());
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_message_history() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.show_message("Wrote a.txt");
+        editor.show_error("Permission denied");
+        assert_eq!(editor.get_message().unwrap().kind, MessageKind::Error);
+        assert_eq!(editor.messages.last(10).count(), 2);
+
+        editor.clear_message();
+        editor.show_message_history(10);
+        assert_eq!(
+            editor.get_message().unwrap().text,
+            "Wrote a.txt\nE: Permission denied"
+        );
+        // Reviewing history doesn't add to it
+        assert_eq!(editor.messages.last(10).count(), 2);
+    }
+
+    #[test]
+    fn test_autosave_skips_unsafe_buffers() {
+        let path = std::env::temp_dir().join(format!("zorforge_autosave_{}.txt", std::process::id()));
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.insert_char('a');
+
+        // No file name: nothing to write and no message
+        editor.autosave();
+        assert!(editor.get_message().is_none());
+
+        editor.file_path = Some(path.clone());
+        editor.set_readonly(true);
+        editor.autosave();
+        assert!(!path.exists());
+
+        editor.set_readonly(false);
+        editor.autosave();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a");
+        assert!(editor.get_message().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_background_load() {
+        let path = std::env::temp_dir().join(format!("zorforge_bgload_{}.rs", std::process::id()));
+        std::fs::write(&path, "fn main() {}\n// vim: sw=2\n").unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.file_path = Some(path.clone());
+        editor.load_in_background(&path).unwrap();
+        editor.finish_loading();
+
+        assert!(!editor.is_loading());
+        assert_eq!(editor.buffer.line_count(), 2);
+        assert_eq!(editor.buffer.shift_width(), 2);
+        assert_eq!(editor.filetype(), Some("rust"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cursor_placed_after_background_load() {
+        let path = std::env::temp_dir().join(format!("zorforge_bgcursor_{}.txt", std::process::id()));
+        let line = "x".repeat(99) + "\n";
+        let lines = loader::BACKGROUND_LOAD_THRESHOLD as usize / line.len() + 1;
+        std::fs::write(&path, line.repeat(lines)).unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        assert!(editor.is_loading());
+        editor.place_cursor(lines - 2, 3);
+        editor.finish_loading();
+        assert_eq!(editor.cursor_position(), (lines - 2, 3));
+
+        // Reloading keeps the cursor's row, however far down
+        editor.reload_file().unwrap();
+        editor.finish_loading();
+        assert_eq!(editor.cursor_position(), (lines - 2, 3));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_background_load_git_marks() {
+        let dir = std::env::temp_dir().join(format!("zorforge_bggit_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        std::fs::write(&path, "one\n").unwrap();
+        let git = |args: &[&str]| std::process::Command::new("git")
+            .args(["-C", dir.to_str().unwrap(), "-c", "user.name=t", "-c", "user.email=t@t"])
+            .args(args)
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if !(git(&["init", "-q"]) && git(&["add", "file.txt"]) && git(&["commit", "-qm", "one"])) {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return; // No git to compare with
+        }
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.file_path = Some(path.clone());
+        editor.load_in_background(&path).unwrap();
+        editor.finish_loading();
+        assert_eq!(editor.git_mark(1), Some(GitMark::Added));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detect_indent_on_open() {
+        let path = std::env::temp_dir().join(format!("zorforge_indent_{}.txt", std::process::id()));
+        std::fs::write(&path, "if x:\n\tpass\n").unwrap();
+
+        let mut config = EditorConfig::default();
+        config.detect_indent = true;
+        let mut editor = Editor::new(config);
+        editor.open_file(&path).unwrap();
+        assert!(!editor.buffer.expand_tab());
+
+        // A modeline wins over what the indentation suggests
+        std::fs::write(&path, "if x:\n\tpass\n# vim: et sw=3\n").unwrap();
+        editor.open_file(&path).unwrap();
+        assert!(editor.buffer.expand_tab());
+        assert_eq!(editor.buffer.shift_width(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_goto_file_is_a_jump() {
+        let path = std::env::temp_dir().join(format!("zorforge_goto_{}.txt", std::process::id()));
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let from = std::env::temp_dir().join(format!("zorforge_goto_from_{}.txt", std::process::id()));
+        std::fs::write(&from, format!("see\n{}:3\n", path.display())).unwrap();
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&from).unwrap();
+        editor.buffer.set_cursor_position(1, 2);
+        editor.goto_file().unwrap();
+        assert_eq!(editor.file_path(), Some(path.as_path()));
+        assert_eq!(editor.cursor_position(), (2, 0));
+
+        // Ctrl-o goes back to where the name was
+        editor.jump_back().unwrap();
+        assert_eq!(editor.file_path(), Some(from.as_path()));
+        assert_eq!(editor.cursor_position(), (1, 2));
+        editor.jump_back().unwrap();
+        assert_eq!(editor.file_path(), Some(from.as_path()));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&from).unwrap();
+    }
+
+    #[test]
+    fn test_open_unwritable_file() {
+        let path = std::env::temp_dir().join(format!("zorforge_readonly_{}.txt", std::process::id()));
+        std::fs::write(&path, "text\n").unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions.clone()).unwrap();
+
+        // Root may write it all the same, and then it isn't read-only
+        #[cfg(unix)]
+        let writable = unsafe { libc::geteuid() } == 0;
+        #[cfg(not(unix))]
+        let writable = false;
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        assert_eq!(editor.is_readonly(), !writable);
+        if !writable {
+            editor.buffer.insert_char('a');
+            assert!(editor.save_buffer().is_err());
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "text\n");
+        }
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        editor.open_file(&path).unwrap();
+        assert!(!editor.is_readonly());
+
+        // --readonly holds for every file opened
+        editor.set_readonly_files(true);
+        editor.open_file(&path).unwrap();
+        assert!(editor.is_readonly());
+        editor.open_file(&path).unwrap();
+        assert!(editor.is_readonly());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_encoding() {
+        let path = std::env::temp_dir().join(format!("zorforge_encoding_{}.txt", std::process::id()));
+        std::fs::write(&path, b"caf\xE9\n").unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        assert_eq!(editor.buffer.encoding(), Encoding::Latin1);
+        assert_eq!(editor.buffer.get_content()[0], "caf\u{e9}");
+        editor.write_buffer().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xE9");
+
+        editor.set_file_encoding(Encoding::Utf16Le);
+        assert!(editor.has_unsaved_changes());
+        editor.write_buffer().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"c\0a\0f\0\xE9\0");
+        editor.open_file(&path).unwrap();
+        assert_eq!(editor.buffer.encoding(), Encoding::Utf16Le);
+        assert_eq!(editor.buffer.get_content()[0], "caf\u{e9}");
+
+        // A byte order mark is kept out of the text and written back
+        std::fs::write(&path, b"\xEF\xBB\xBFhi\n").unwrap();
+        editor.open_file(&path).unwrap();
+        assert_eq!(editor.buffer.get_content()[0], "hi");
+        assert!(editor.buffer.has_bom());
+        editor.write_buffer().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xEF\xBB\xBFhi");
+        editor.set_bom(false);
+        editor.write_buffer().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hi");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_background_load_encoding() {
+        let path = std::env::temp_dir().join(format!("zorforge_bgencoding_{}.log", std::process::id()));
+        let line = b"caf\xE9 au lait\n";
+        let lines = loader::BACKGROUND_LOAD_THRESHOLD as usize / line.len() + 1;
+        std::fs::write(&path, line.repeat(lines)).unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        editor.finish_loading();
+        assert_eq!(editor.buffer.encoding(), Encoding::Latin1);
+        assert_eq!(editor.buffer.line_count(), lines);
+        assert_eq!(editor.buffer.get_content()[lines - 1], "caf\u{e9} au lait");
+        assert!(!editor.is_readonly());
+        assert!(editor.get_message().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_expand_abbreviation() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.add_abbreviation("teh", "the");
+        editor.buffer.insert_text("fix teh");
+        editor.expand_abbreviation();
+        assert_eq!(editor.buffer.get_content()[0], "fix the");
+        assert_eq!(editor.cursor_position(), (0, 7));
+
+        // Only whole words expand
+        editor.buffer.insert_text(" steh");
+        editor.expand_abbreviation();
+        assert_eq!(editor.buffer.get_content()[0], "fix the steh");
+
+        assert!(editor.remove_abbreviation("teh"));
+        assert!(!editor.remove_abbreviation("teh"));
+    }
+
+    #[test]
+    fn test_swap_recovery() {
+        let path = std::env::temp_dir().join(format!("zorforge_recover_{}.txt", std::process::id()));
+        std::fs::write(&path, "original").unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        editor.buffer.insert_char('x');
+        editor.update_swap();
+        assert!(swap::swap_exists(&path));
+
+        // A later session finds the swap and restores from it
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        assert!(editor.recovery_pending);
+        editor.recover_from_swap().unwrap();
+        assert_eq!(editor.buffer.get_content(), &vec!["xoriginal".to_string()]);
+
+        // Saving cleans the swap up
+        editor.save_buffer().unwrap();
+        assert!(!swap::swap_exists(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restore_cursor() {
+        let paths: Vec<PathBuf> = ["a", "b"].iter()
+            .map(|name| std::env::temp_dir().join(format!("zorforge_cursor_{}_{}.txt", name, std::process::id())))
+            .collect();
+        std::fs::write(&paths[0], "one\ntwo\nthree").unwrap();
+        std::fs::write(&paths[1], "other").unwrap();
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&paths[0]).unwrap();
+        editor.buffer.set_cursor_position(2, 3);
+        editor.open_file(&paths[1]).unwrap();
+        assert_eq!(editor.cursor_position(), (0, 0));
+        editor.open_file(&paths[0]).unwrap();
+        assert_eq!(editor.cursor_position(), (2, 3));
+
+        // A line that's gone leaves the cursor at the top
+        editor.open_file(&paths[1]).unwrap();
+        std::fs::write(&paths[0], "one").unwrap();
+        editor.open_file(&paths[0]).unwrap();
+        assert_eq!(editor.cursor_position(), (0, 0));
+
+        let mut config = EditorConfig::default();
+        config.restore_cursor = false;
+        let mut editor = Editor::new(config);
+        editor.open_file(&paths[1]).unwrap();
+        editor.buffer.set_cursor_position(0, 2);
+        editor.open_file(&paths[0]).unwrap();
+        editor.open_file(&paths[1]).unwrap();
+        assert_eq!(editor.cursor_position(), (0, 0));
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recent_files() {
+        let paths: Vec<PathBuf> = ["a", "b"].iter()
+            .map(|name| std::env::temp_dir().join(format!("zorforge_recent_{}_{}.txt", name, std::process::id())))
+            .collect();
+        for (path, text) in paths.iter().zip(["first", "second"]) {
+            std::fs::write(path, text).unwrap();
+        }
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&paths[0]).unwrap();
+        editor.open_file(&paths[1]).unwrap();
+        editor.recent_files(None).unwrap();
+        assert!(editor.get_message().unwrap().text.contains("  2 "));
+
+        // Entries count from the newest file
+        editor.recent_files(Some(2)).unwrap();
+        assert_eq!(editor.buffer.get_content()[0], "first");
+        editor.recent_files(Some(3)).unwrap();
+        assert_eq!(editor.get_message().unwrap().kind, MessageKind::Error);
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["window width".to_string(), "w".to_string()]);
+        editor.buffer.set_cursor_position(1, 1);
+        editor.set_mode(Mode::Insert(InsertVariant::Insert));
+
+        editor.start_completion(true);
+        assert_eq!(editor.completion().map(|c| c.items.clone()), Some(vec!["window".to_string(), "width".to_string()]));
+        editor.buffer.insert_char('i');
+        editor.update_completion();
+        editor.buffer.insert_char('d');
+        editor.update_completion();
+        assert_eq!(editor.completion().map(|c| c.items.len()), Some(1));
+        editor.accept_completion();
+        assert_eq!(editor.buffer.get_content()[1], "width");
+        assert!(editor.completion().is_none());
+
+        editor.start_completion(false);
+        assert!(editor.completion().is_none());
+        editor.buffer.insert_char(' ');
+        editor.start_completion(false);
+        // The cursor's own line comes first, and Ctrl-P starts from the end
+        assert_eq!(editor.completion().and_then(|c| c.selected_item()), Some("window"));
+        editor.set_mode(Mode::Normal);
+        assert!(editor.completion().is_none());
+    }
+
+    #[test]
+    fn test_expand_snippet() {
+        let dir = std::env::temp_dir().join(format!("zorforge_expand_snippet_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rust.toml"), "fn = \"fn $1($2) {\\n    $0\\n}\"\n").unwrap();
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.set_snippets(Snippets::new(dir.clone()));
+        editor.buffer.set_filetype(Some("rust".to_string()));
+        editor.buffer.set_content(vec!["    fn".to_string()]);
+        editor.buffer.set_cursor_position(0, 6);
+        editor.set_mode(Mode::Insert(InsertVariant::Insert));
+
+        assert!(editor.expand_snippet());
+        assert_eq!(*editor.buffer.get_content(), ["    fn () {", "        ", "    }"]);
+        assert_eq!(editor.buffer.get_cursor_position(), (0, 7));
+        editor.buffer.insert_text("add");
+        assert!(editor.next_snippet_stop(true));
+        assert_eq!(editor.buffer.get_cursor_position(), (0, 11));
+        assert!(editor.next_snippet_stop(true));
+        assert_eq!(editor.buffer.get_cursor_position(), (1, 8));
+        // The last stop ends the snippet, and Tab indents again
+        assert!(!editor.next_snippet_stop(true));
+
+        editor.buffer.undo();
+        assert_eq!(*editor.buffer.get_content(), ["    fn () {", "        ", "    }"]);
+        editor.buffer.undo();
+        assert_eq!(*editor.buffer.get_content(), ["    fn"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_file_changed() {
+        let path = std::env::temp_dir().join(format!("zorforge_changed_{}.txt", std::process::id()));
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        editor.buffer.set_cursor_position(1, 0);
+        editor.check_file_changed().unwrap();
+        assert_eq!(editor.buffer.line_count(), 3);
+
+        std::fs::write(&path, "a\nB\nc\nd\n").unwrap();
+        editor.check_file_changed().unwrap();
+        assert_eq!(*editor.buffer.get_content(), ["a", "B", "c", "d"]);
+        assert_eq!(editor.cursor_position(), (1, 0));
+
+        // Unsaved changes aren't thrown away without asking
+        editor.buffer.insert_char('x');
+        std::fs::write(&path, "fresh\n").unwrap();
+        editor.check_file_changed().unwrap();
+        assert!(editor.is_confirming_reload());
+        editor.answer_reload('n').unwrap();
+        assert_eq!(editor.buffer.get_content()[1], "xB");
+        editor.check_file_changed().unwrap();
+        assert!(!editor.is_confirming_reload());
+
+        std::fs::write(&path, "newer\n").unwrap();
+        editor.check_file_changed().unwrap();
+        editor.answer_reload('y').unwrap();
+        assert_eq!(*editor.buffer.get_content(), ["newer"]);
+        assert_eq!(editor.cursor_position(), (0, 1));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_and_quit_skips_unchanged_file() {
+        let path = std::env::temp_dir().join(format!("zorforge_xit_{}.txt", std::process::id()));
+        std::fs::write(&path, "a\n").unwrap();
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        // Written behind the editor's back: only a write from it would undo this
+        std::fs::write(&path, "b\n").unwrap();
+        editor.write_and_quit(false).unwrap();
+        assert!(editor.should_quit());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        editor.buffer.insert_char('x');
+        editor.write_and_quit(false).unwrap();
+        assert!(editor.should_quit());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xb");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_opened_buffer_keeps_config_and_view() {
+        let path = std::env::temp_dir().join(format!("zorforge_reset_{}.txt", std::process::id()));
+        std::fs::write(&path, "a\n").unwrap();
+        let config = EditorConfig { tab_size: 2, ..EditorConfig::default() };
+        let mut editor = Editor::new(config);
+        assert_eq!(editor.buffer.tab_size(), 2);
+        editor.update_viewport(12, 60);
+
+        editor.open_file(&path).unwrap();
+        assert_eq!(editor.buffer.tab_size(), 2);
+        assert_eq!(editor.buffer.shift_width(), 2);
+        let viewport = editor.buffer.get_viewport();
+        assert_eq!((viewport.height, viewport.width), (12, 60));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_confirm_quit() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.confirm_quit().unwrap();
+        assert!(editor.should_quit());
+
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.insert_char('x');
+        editor.confirm_quit().unwrap();
+        assert!(editor.is_confirming_quit() && !editor.should_quit());
+        editor.answer_quit('?').unwrap();
+        assert!(editor.is_confirming_quit());
+        editor.answer_quit('c').unwrap();
+        assert!(!editor.is_confirming_quit() && !editor.should_quit());
+
+        // Saving needs a file name, so the editor stays open
+        editor.confirm_quit().unwrap();
+        assert!(editor.answer_quit('y').is_err());
+        assert!(!editor.is_confirming_quit() && !editor.should_quit());
+
+        editor.confirm_quit().unwrap();
+        editor.answer_quit('n').unwrap();
+        assert!(editor.should_quit());
+    }
+
+    #[test]
+    fn test_mouse_drag_copies_selection() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["hello world".to_string(), "second".to_string()]);
+        editor.update_viewport(20, 80);
+        let gutter = editor.buffer.gutter_width();
+
+        editor.handle_mouse_click(gutter, 0, MouseButton::Left);
+        editor.handle_mouse_release();
+        assert!(editor.buffer.clipboard_mut().peek().is_none());
+
+        editor.handle_mouse_drag(gutter + 5, 0, MouseButton::Left);
+        editor.handle_mouse_release();
+        assert_eq!(editor.buffer.clipboard_mut().peek().map(String::as_str), Some("hello"));
+        assert!(editor.mode.is_visual());
+
+        // Middle click ends the selection and pastes where it lands
+        editor.handle_mouse_click(gutter, 1, MouseButton::Middle);
+        assert!(!editor.mode.is_visual());
+        assert_eq!(editor.buffer.get_content()[1], "hellosecond");
+    }
+
+    #[test]
+    fn test_visual_mode_follows_editor_mode() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.set_content(vec!["one".to_string(), "two".to_string()]);
+        editor.set_mode(Mode::Visual(VisualVariant::Line));
+        assert_eq!(editor.buffer.visual_mode(), Some(VisualMode::Line));
+
+        editor.buffer.set_cursor_position(1, 1);
+        editor.set_mode(Mode::Visual(VisualVariant::Block));
+        assert_eq!(editor.buffer.visual_mode(), Some(VisualMode::Block));
+        assert_eq!(editor.buffer.get_visual_selection(), Some(((0, 0), (1, 1))));
+
+        editor.set_mode(Mode::Command(CommandType::Search));
+        assert!(editor.buffer.get_visual_selection().is_some());
+        editor.set_mode(Mode::Normal);
+        assert_eq!(editor.buffer.visual_mode(), None);
+        assert_eq!(editor.buffer.get_visual_selection(), None);
+    }
+}
@@ -0,0 +1,115 @@
+// src/editor/modeline.rs
+
+/// Number of lines at the start and end of a file searched for a modeline
+const MODELINE_SCAN_LINES: usize = 5;
+
+/// Per-file settings read from a vim-style modeline. Only a small, safe
+/// subset of options is understood; everything else is skipped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelineSettings {
+    pub tab_size: Option<usize>,
+    pub shift_width: Option<usize>,
+    pub expand_tab: Option<bool>,
+    pub text_width: Option<usize>,
+}
+
+/// Find and parse the first valid modeline in the first or last few lines
+pub fn find_modeline<S: AsRef<str>>(lines: &[S]) -> Option<ModelineSettings> {
+    let head = lines.iter().take(MODELINE_SCAN_LINES);
+    let tail_start = lines.len().saturating_sub(MODELINE_SCAN_LINES).max(MODELINE_SCAN_LINES);
+    let tail = lines.iter().skip(tail_start);
+
+    head.chain(tail).find_map(|line| parse_modeline(line.as_ref()))
+}
+
+/// Parse a single line such as `# vim: ts=2 sw=2 et` or
+/// `/* vim: set ts=8 noet : */`. Returns `None` if the line holds no
+/// modeline or any understood option has a malformed value.
+pub fn parse_modeline(line: &str) -> Option<ModelineSettings> {
+    let options = ["vim:", "vi:", "ex:"]
+        .iter()
+        .filter_map(|marker| {
+            line.find(marker)
+                // The marker must start a word: "avim:" is not a modeline
+                .filter(|&idx| idx == 0 || line[..idx].ends_with(char::is_whitespace))
+                .map(|idx| &line[idx + marker.len()..])
+        })
+        .next()?;
+
+    // "set " form: options end at the next ':'
+    let options = match options.trim_start().strip_prefix("set ") {
+        Some(rest) => rest.split(':').next().unwrap_or(""),
+        None => options,
+    };
+
+    let mut settings = ModelineSettings::default();
+    for option in options.split(|c: char| c.is_whitespace() || c == ':') {
+        match option.split_once('=') {
+            Some(("ts" | "tabstop", value)) => settings.tab_size = Some(parse_width(value)?),
+            Some(("sw" | "shiftwidth", value)) => settings.shift_width = Some(parse_width(value)?),
+            Some(("tw" | "textwidth", value)) => settings.text_width = Some(value.parse().ok()?),
+            Some(_) => {}
+            None => match option {
+                "et" | "expandtab" => settings.expand_tab = Some(true),
+                "noet" | "noexpandtab" => settings.expand_tab = Some(false),
+                _ => {}
+            },
+        }
+    }
+
+    if settings == ModelineSettings::default() {
+        None
+    } else {
+        Some(settings)
+    }
+}
+
+// Tab and indent widths must be positive and sane
+fn parse_width(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|&width| (1..=32).contains(&width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_modeline() {
+        let settings = parse_modeline("# vim: ts=2 sw=2 et").unwrap();
+        assert_eq!(settings.tab_size, Some(2));
+        assert_eq!(settings.shift_width, Some(2));
+        assert_eq!(settings.expand_tab, Some(true));
+        assert_eq!(settings.text_width, None);
+    }
+
+    #[test]
+    fn test_parse_set_form() {
+        let settings = parse_modeline("/* vim: set tabstop=8 noexpandtab tw=100 : */").unwrap();
+        assert_eq!(settings.tab_size, Some(8));
+        assert_eq!(settings.expand_tab, Some(false));
+        assert_eq!(settings.text_width, Some(100));
+    }
+
+    #[test]
+    fn test_malformed_modelines_ignored() {
+        assert_eq!(parse_modeline("# vim: ts=abc"), None);
+        assert_eq!(parse_modeline("# vim: ts=0"), None);
+        assert_eq!(parse_modeline("# vim: foldmethod=marker"), None);
+        assert_eq!(parse_modeline("let avim: ts=2"), None);
+        assert_eq!(parse_modeline("no modeline here"), None);
+    }
+
+    #[test]
+    fn test_find_modeline_in_head_or_tail() {
+        let mut lines = vec!["code"; 20];
+        assert_eq!(find_modeline(&lines), None);
+
+        lines[19] = "// vim: sw=3";
+        assert_eq!(find_modeline(&lines).unwrap().shift_width, Some(3));
+
+        // Lines in the middle of the file are not scanned
+        lines[19] = "code";
+        lines[10] = "// vim: sw=3";
+        assert_eq!(find_modeline(&lines), None);
+    }
+}
@@ -0,0 +1,100 @@
+// src/editor/positions.rs
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many files' cursor positions are remembered
+pub const MAX_POSITIONS: usize = 100;
+
+/// Where the cursor was in each file when it was last left, newest first, to
+/// put it back on the next open. Kept in `file` one `row col path` line per
+/// file; without one the positions only last the session.
+#[derive(Debug, Default)]
+pub struct CursorPositions {
+    positions: Vec<(PathBuf, (usize, usize))>,
+    file: Option<PathBuf>,
+}
+
+impl CursorPositions {
+    /// `cursor_positions` in the config directory
+    pub fn default_file() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("zorforge").join("cursor_positions"))
+    }
+
+    /// Read the positions kept in `file`, skipping lines that don't parse.
+    /// There are none yet if the file doesn't exist.
+    pub fn load(file: PathBuf) -> io::Result<Self> {
+        let positions = match std::fs::read_to_string(&file) {
+            Ok(contents) => contents.lines().filter_map(parse_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { positions, file: Some(file) })
+    }
+
+    /// The (row, col) the cursor was last left at in `path`
+    pub fn get(&self, path: &Path) -> Option<(usize, usize)> {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.positions.iter()
+            .find(|(known, _)| *known == path)
+            .map(|&(_, position)| position)
+    }
+
+    /// Remember `position` for `path`, dropping the oldest files past the cap
+    pub fn set(&mut self, path: &Path, position: (usize, usize)) {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.positions.retain(|(known, _)| *known != path);
+        self.positions.insert(0, (path, position));
+        self.positions.truncate(MAX_POSITIONS);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for (path, (row, col)) in &self.positions {
+            contents.push_str(&format!("{} {} {}\n", row, col, path.to_string_lossy()));
+        }
+        std::fs::write(file, contents)
+    }
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, (usize, usize))> {
+    let mut fields = line.splitn(3, ' ');
+    let row = fields.next()?.parse().ok()?;
+    let col = fields.next()?.parse().ok()?;
+    let path = fields.next().filter(|path| !path.is_empty())?;
+    Some((PathBuf::from(path), (row, col)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_positions() {
+        let file = std::env::temp_dir().join(format!("zorforge_positions_{}", std::process::id()));
+        let mut positions = CursorPositions::load(file.clone()).unwrap();
+        assert_eq!(positions.get(Path::new("/no/such/a.rs")), None);
+
+        positions.set(Path::new("/no/such/a.rs"), (3, 4));
+        positions.set(Path::new("/no/such/with space.rs"), (10, 0));
+        positions.set(Path::new("/no/such/a.rs"), (5, 1));
+        assert_eq!(positions.get(Path::new("/no/such/a.rs")), Some((5, 1)));
+
+        positions.save().unwrap();
+        let loaded = CursorPositions::load(file.clone()).unwrap();
+        assert_eq!(loaded.get(Path::new("/no/such/a.rs")), Some((5, 1)));
+        assert_eq!(loaded.get(Path::new("/no/such/with space.rs")), Some((10, 0)));
+        std::fs::remove_file(&file).unwrap();
+
+        for i in 0..MAX_POSITIONS {
+            positions.set(&PathBuf::from(format!("/no/such/{}.rs", i)), (i, 0));
+        }
+        assert_eq!(positions.get(Path::new("/no/such/a.rs")), None);
+        assert_eq!(positions.get(Path::new("/no/such/0.rs")), Some((0, 0)));
+    }
+}
@@ -0,0 +1,86 @@
+// src/editor/recent.rs
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many files :oldfiles remembers
+pub const MAX_RECENT_FILES: usize = 100;
+
+/// Files opened lately, newest first, as :oldfiles lists them. Kept one path
+/// per line in `file`; without one the list only lasts the session.
+#[derive(Debug, Default)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+    file: Option<PathBuf>,
+}
+
+impl RecentFiles {
+    /// `recent_files` in the config directory
+    pub fn default_file() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("zorforge").join("recent_files"))
+    }
+
+    /// Read the list kept in `file`, which starts out empty if there's none yet
+    pub fn load(file: PathBuf) -> io::Result<Self> {
+        let paths = match std::fs::read_to_string(&file) {
+            Ok(contents) => contents.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { paths, file: Some(file) })
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Move `path` to the front, dropping the oldest files past the cap
+    pub fn add(&mut self, path: &Path) {
+        // The same file under another name shouldn't show up twice
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.paths.retain(|known| *known != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for path in &self.paths {
+            contents.push_str(&path.to_string_lossy());
+            contents.push('\n');
+        }
+        std::fs::write(file, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_files() {
+        let file = std::env::temp_dir().join(format!("zorforge_recent_{}", std::process::id()));
+        let mut recent = RecentFiles::load(file.clone()).unwrap();
+        assert!(recent.paths().is_empty());
+
+        recent.add(Path::new("/no/such/a.rs"));
+        recent.add(Path::new("/no/such/b.rs"));
+        recent.add(Path::new("/no/such/a.rs"));
+        assert_eq!(recent.paths(), [PathBuf::from("/no/such/a.rs"), PathBuf::from("/no/such/b.rs")]);
+
+        recent.save().unwrap();
+        assert_eq!(RecentFiles::load(file.clone()).unwrap().paths(), recent.paths());
+        std::fs::remove_file(&file).unwrap();
+
+        for i in 0..MAX_RECENT_FILES + 5 {
+            recent.add(&PathBuf::from(format!("/no/such/{}.rs", i)));
+        }
+        assert_eq!(recent.paths().len(), MAX_RECENT_FILES);
+        assert_eq!(recent.paths()[0], PathBuf::from(format!("/no/such/{}.rs", MAX_RECENT_FILES + 4)));
+    }
+}
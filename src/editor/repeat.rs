@@ -0,0 +1,73 @@
+// src/editor/repeat.rs
+use crossterm::event::KeyEvent;
+use super::Mode;
+
+/// The keys of the last insert, from the normal-mode command that started it
+/// (`i`, `3s`, `cc`, ...) through the `Esc` that ended it, for `.` to play
+/// back. Typing is kept as keys rather than text so that Enter, Backspace and
+/// the like happen again as they did the first time.
+#[derive(Debug, Default)]
+pub struct InsertRecorder {
+    command: Vec<KeyEvent>,           // Keys of the normal-mode command being typed
+    recording: Option<Vec<KeyEvent>>, // The insert under way
+    last: Option<Vec<KeyEvent>>,      // The last finished insert
+}
+
+impl InsertRecorder {
+    /// Note `key`, which was handled in mode `before` and left the editor in
+    /// `after`. `pending` is whether a normal-mode command is still waiting
+    /// for more keys.
+    pub fn record(&mut self, key: KeyEvent, before: &Mode, after: &Mode, pending: bool) {
+        match (before, after) {
+            (Mode::Normal, Mode::Insert(_)) => {
+                self.command.push(key);
+                self.recording = Some(std::mem::take(&mut self.command));
+            }
+            (Mode::Normal, Mode::Normal) if pending => self.command.push(key),
+            (Mode::Insert(_), after) => {
+                if let Some(keys) = &mut self.recording {
+                    keys.push(key);
+                    if !matches!(after, Mode::Insert(_)) {
+                        self.last = self.recording.take();
+                    }
+                }
+            }
+            _ => self.command.clear(),
+        }
+    }
+
+    pub fn last(&self) -> Option<&[KeyEvent]> {
+        self.last.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use crate::editor::InsertVariant;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_insert_recorder() {
+        let insert = Mode::Insert(InsertVariant::Insert);
+        let mut recorder = InsertRecorder::default();
+        recorder.record(key(KeyCode::Char('j')), &Mode::Normal, &Mode::Normal, false);
+        recorder.record(key(KeyCode::Char('3')), &Mode::Normal, &Mode::Normal, true);
+        recorder.record(key(KeyCode::Char('s')), &Mode::Normal, &insert, false);
+        recorder.record(key(KeyCode::Char('x')), &insert, &insert, false);
+        recorder.record(key(KeyCode::Backspace), &insert, &insert, false);
+        assert_eq!(recorder.last(), None);
+
+        recorder.record(key(KeyCode::Esc), &insert, &Mode::Normal, false);
+        let keys = [KeyCode::Char('3'), KeyCode::Char('s'), KeyCode::Char('x'), KeyCode::Backspace, KeyCode::Esc];
+        assert_eq!(recorder.last(), Some(&keys.map(key)[..]));
+
+        // Commands that don't insert leave the last insert alone
+        recorder.record(key(KeyCode::Char('x')), &Mode::Normal, &Mode::Normal, false);
+        assert_eq!(recorder.last().map(<[KeyEvent]>::len), Some(5));
+    }
+}
@@ -0,0 +1,66 @@
+// src/editor/session.rs
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::ui::LayoutShape;
+
+/// Where :mksession writes without a file name, as vim's Session.vim
+pub const DEFAULT_SESSION_FILE: &str = "Session.toml";
+
+/// What :mksession saves to pick up where a session left off. Paths are as
+/// they were given, so relative ones rely on `cwd`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub cwd: PathBuf,
+    pub file: Option<PathBuf>,
+    pub cursor: (usize, usize),
+    pub args: Vec<PathBuf>,
+    pub arg_index: usize,
+    pub focus: usize, // The focused window, counting in screen order
+    pub layout: LayoutShape,
+}
+
+impl Session {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        std::fs::write(path, contents)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Bad session file {}: {}", path.display(), e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::SplitDirection;
+
+    #[test]
+    fn test_session_round_trip() {
+        let session = Session {
+            cwd: PathBuf::from("/src/project"),
+            file: Some(PathBuf::from("src/main.rs")),
+            cursor: (41, 6),
+            args: vec![PathBuf::from("src/main.rs"), PathBuf::from("README.md")],
+            arg_index: 0,
+            focus: 1,
+            layout: LayoutShape::Split {
+                direction: SplitDirection::Horizontal,
+                ratio: 0.5,
+                first: Box::new(LayoutShape::Buffer { top: 30 }),
+                second: Box::new(LayoutShape::Terminal),
+            },
+        };
+        let path = std::env::temp_dir().join(format!("zorforge_session_{}.toml", std::process::id()));
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), session);
+
+        std::fs::write(&path, "cwd = 3").unwrap();
+        assert!(Session::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+// src/editor/snippet.rs
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Snippets in `all.toml` work whatever the filetype
+const ANY_FILETYPE: &str = "all";
+
+/// Snippet templates by filetype, each file read the first time a snippet
+/// for it is wanted. `{filetype}.toml` in the snippet directory maps
+/// triggers to templates:
+///
+/// ```toml
+/// fn = "fn $1($2) {\n    $0\n}"
+/// ```
+#[derive(Debug, Default)]
+pub struct Snippets {
+    dir: Option<PathBuf>,
+    loaded: HashMap<String, BTreeMap<String, String>>,
+}
+
+impl Snippets {
+    /// `snippets` in the config directory
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("zorforge").join("snippets"))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir: Some(dir), loaded: HashMap::new() }
+    }
+
+    /// The template `trigger` expands to in a file of `filetype`, looking in
+    /// the snippets for any filetype after that filetype's own
+    pub fn find(&mut self, filetype: Option<&str>, trigger: &str) -> io::Result<Option<String>> {
+        for filetype in filetype.into_iter().chain([ANY_FILETYPE]) {
+            if let Some(template) = self.load(filetype)?.get(trigger) {
+                return Ok(Some(template.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn load(&mut self, filetype: &str) -> io::Result<&BTreeMap<String, String>> {
+        if !self.loaded.contains_key(filetype) {
+            let snippets = match &self.dir {
+                Some(dir) => read_snippets(&dir.join(format!("{}.toml", filetype))),
+                None => Ok(BTreeMap::new()),
+            };
+            // A file that can't be read counts as empty from then on, so its
+            // error is only shown once
+            self.loaded.insert(filetype.to_string(), snippets.as_ref().cloned().unwrap_or_default());
+            snippets?;
+        }
+        Ok(&self.loaded[filetype])
+    }
+}
+
+// No file is no snippets rather than an error
+fn read_snippets(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(io::Error::new(e.kind(), format!("Cannot read snippets {}: {}", path.display(), e))),
+    };
+    toml::from_str(&contents).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Cannot read snippets {}: {}", path.display(), e))
+    })
+}
+
+/// A template with its tab stops taken out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub lines: Vec<String>,
+    pub stops: Vec<(usize, usize)>, // (line, byte column) of $1, $2, ... and last $0
+}
+
+/// Read a template: `$1`, `$2`, ... are tab stops, visited in number order,
+/// `$0` is where the cursor is left, and `$$` is a dollar sign. Without a
+/// `$0` the cursor is left after the text.
+pub fn parse(template: &str) -> Snippet {
+    let mut lines = vec![String::new()];
+    let mut numbered: Vec<(u32, (usize, usize))> = Vec::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let row = lines.len() - 1;
+        match c {
+            '\n' => lines.push(String::new()),
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                lines[row].push('$');
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let mut number: u32 = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    number = number.saturating_mul(10).saturating_add(digit);
+                    chars.next();
+                }
+                numbered.push((number, (row, lines[row].len())));
+            }
+            c => lines[row].push(c),
+        }
+    }
+
+    // $0 goes last; a number used twice is a stop where it first appears
+    numbered.sort_by_key(|&(number, _)| (number == 0, number));
+    numbered.dedup_by_key(|&mut (number, _)| number);
+    let mut stops: Vec<(usize, usize)> = numbered.iter().map(|&(_, position)| position).collect();
+    if numbered.last().is_none_or(|&(number, _)| number != 0) {
+        let row = lines.len() - 1;
+        stops.push((row, lines[row].len()));
+    }
+    Snippet { lines, stops }
+}
+
+/// Where the tab stops of an expanded snippet are in the buffer, and which
+/// one the cursor was last sent to
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnippetStops {
+    stops: Vec<(usize, usize)>,
+    current: usize,
+}
+
+impl SnippetStops {
+    pub fn new(stops: Vec<(usize, usize)>) -> Self {
+        Self { stops, current: 0 }
+    }
+
+    pub fn current(&self) -> (usize, usize) {
+        self.stops[self.current]
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.current + 1 >= self.stops.len()
+    }
+
+    /// Move on to the next stop, or with `forward` false back to the one
+    /// before, first making room for what was typed at this one: everything
+    /// from it up to `cursor`
+    pub fn advance(&mut self, forward: bool, cursor: (usize, usize)) -> (usize, usize) {
+        self.typed(cursor);
+        self.current = match forward {
+            true => (self.current + 1).min(self.stops.len() - 1),
+            false => self.current.saturating_sub(1),
+        };
+        self.current()
+    }
+
+    // Stops after the current one move along by the text inserted there
+    fn typed(&mut self, cursor: (usize, usize)) {
+        let (row, col) = self.current();
+        if cursor < (row, col) {
+            return;
+        }
+        let current = self.current;
+        for (i, stop) in self.stops.iter_mut().enumerate() {
+            if *stop < (row, col) || (*stop == (row, col) && i <= current) {
+                continue;
+            }
+            if stop.0 == row {
+                stop.1 = stop.1 - col + cursor.1;
+            }
+            stop.0 += cursor.0 - row;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let snippet = parse("fn $1($2) {\n    $0\n}");
+        assert_eq!(snippet.lines, vec!["fn () {", "    ", "}"]);
+        assert_eq!(snippet.stops, vec![(0, 3), (0, 4), (1, 4)]);
+
+        let snippet = parse("$2 costs $$$1");
+        assert_eq!(snippet.lines, vec![" costs $"]);
+        assert_eq!(snippet.stops, vec![(0, 8), (0, 0), (0, 8)]);
+    }
+
+    #[test]
+    fn test_snippet_stops() {
+        // fn |(|) {  with $0 on the next line
+        let mut stops = SnippetStops::new(vec![(4, 3), (4, 4), (5, 4)]);
+        assert_eq!(stops.advance(true, (4, 7)), (4, 8)); // Typed "add"
+        assert_eq!(stops.advance(true, (4, 14)), (5, 4)); // Typed "x: u8"
+        assert!(stops.is_last());
+        assert_eq!(stops.advance(false, (5, 4)), (4, 8));
+        assert_eq!(stops.advance(false, (4, 8)), (4, 3));
+
+        // A newline typed at a stop moves the stops below it down
+        let mut stops = SnippetStops::new(vec![(0, 2), (0, 5), (1, 0)]);
+        assert_eq!(stops.advance(true, (1, 1)), (1, 4));
+        assert_eq!(stops.advance(true, (1, 4)), (2, 0));
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = std::env::temp_dir().join(format!("zorforge_snippets_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rust.toml"), "fn = \"fn $1() {}\"\n").unwrap();
+        std::fs::write(dir.join("all.toml"), "date = \"2024\"\nfn = \"function\"\n").unwrap();
+        std::fs::write(dir.join("broken.toml"), "fn = \n").unwrap();
+
+        let mut snippets = Snippets::new(dir.clone());
+        assert_eq!(snippets.find(Some("rust"), "fn").unwrap().as_deref(), Some("fn $1() {}"));
+        assert_eq!(snippets.find(Some("rust"), "date").unwrap().as_deref(), Some("2024"));
+        assert_eq!(snippets.find(None, "fn").unwrap().as_deref(), Some("function"));
+        assert_eq!(snippets.find(Some("python"), "nope").unwrap(), None);
+        assert!(snippets.find(Some("broken"), "fn").is_err());
+        assert_eq!(snippets.find(Some("broken"), "fn").unwrap().as_deref(), Some("function"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+// src/editor/spell.rs
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+/// Word list used when the config doesn't name one
+pub const DEFAULT_SPELL_FILE: &str = "/usr/share/dict/words";
+
+/// Suggestions further than this many edits from the word aren't offered
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// A set of known words, stored lowercased so "The" matches "the"
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    /// Load a word list with one word per line
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("Cannot read spell file {}: {}", path.display(), e))
+        })?;
+        Ok(Self::from_words(contents.lines()))
+    }
+
+    pub fn from_words<'a>(words: impl IntoIterator<Item = &'a str>) -> Self {
+        let words = words.into_iter()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        Self { words }
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(&word.to_lowercase())
+    }
+
+    /// Byte ranges of the words in `line` that aren't in the dictionary
+    pub fn misspellings(&self, line: &str) -> Vec<(usize, usize)> {
+        words(line)
+            .filter(|&(start, end)| !self.contains(&line[start..end]))
+            .collect()
+    }
+
+    /// Known words within a couple of edits of `word`, closest first
+    pub fn suggest(&self, word: &str, limit: usize) -> Vec<String> {
+        let target: Vec<char> = word.to_lowercase().chars().collect();
+        let mut candidates: Vec<(usize, &String)> = self.words.iter()
+            .filter(|known| known.chars().count().abs_diff(target.len()) <= MAX_SUGGESTION_DISTANCE)
+            .filter_map(|known| {
+                let distance = edit_distance(&target, &known.chars().collect::<Vec<_>>());
+                (distance > 0 && distance <= MAX_SUGGESTION_DISTANCE).then_some((distance, known))
+            })
+            .collect();
+        candidates.sort();
+
+        // Keep the capital on a word that starts a sentence
+        let capitalize = word.chars().next().is_some_and(char::is_uppercase);
+        candidates.into_iter()
+            .take(limit)
+            .map(|(_, known)| if capitalize { capitalized(known) } else { known.clone() })
+            .collect()
+    }
+}
+
+/// Byte ranges of the words to check in `line`: runs of letters, possibly
+/// joined by apostrophes as in "don't". Tokens with digits or underscores
+/// look like identifiers and are skipped.
+pub fn words(line: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut chars = line.char_indices().peekable();
+    std::iter::from_fn(move || loop {
+        let (start, c) = chars.next()?;
+        if !super::buffer::is_word_char(c) {
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        let mut checkable = c.is_alphabetic();
+        while let Some(&(i, c)) = chars.peek() {
+            if super::buffer::is_word_char(c) {
+                checkable &= c.is_alphabetic();
+            } else if c != '\'' || !line[i + 1..].starts_with(char::is_alphabetic) {
+                break;
+            }
+            chars.next();
+            end = i + c.len_utf8();
+        }
+        if checkable {
+            return Some((start, end));
+        }
+    })
+}
+
+// Levenshtein distance, counting a swap of neighbouring letters as one edit
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+fn capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words() {
+        let line = "Don't flag x2 or snake_case, café";
+        let found: Vec<&str> = words(line).map(|(start, end)| &line[start..end]).collect();
+        assert_eq!(found, vec!["Don't", "flag", "or", "café"]);
+    }
+
+    #[test]
+    fn test_misspellings() {
+        let dictionary = Dictionary::from_words(["the", "quick", "fox"]);
+        assert_eq!(dictionary.misspellings("The quikc fox"), vec![(4, 9)]);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let dictionary = Dictionary::from_words(["quick", "quack", "quiet", "brown"]);
+        assert_eq!(dictionary.suggest("quikc", 5), vec!["quick", "quack", "quiet"]);
+        assert_eq!(dictionary.suggest("Quikc", 1), vec!["Quick"]);
+        assert!(dictionary.suggest("zzzzzz", 5).is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+// src/editor/swap.rs
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Sidecar file holding unsaved edits: `dir/file.txt` -> `dir/.file.txt.swp`
+pub fn swap_path(path: &Path) -> PathBuf {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+pub fn swap_exists(path: &Path) -> bool {
+    swap_path(path).exists()
+}
+
+pub fn write_swap(path: &Path, lines: &[String]) -> io::Result<()> {
+    std::fs::write(swap_path(path), lines.join("\n"))
+}
+
+pub fn read_swap(path: &Path) -> io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(swap_path(path))?;
+    Ok(contents.lines().map(String::from).collect())
+}
+
+// Removing a swap that was never written is not an error
+pub fn remove_swap(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(swap_path(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_path() {
+        assert_eq!(swap_path(Path::new("src/main.rs")), PathBuf::from("src/.main.rs.swp"));
+        assert_eq!(swap_path(Path::new("notes")), PathBuf::from(".notes.swp"));
+    }
+
+    #[test]
+    fn test_swap_round_trip() {
+        let path = std::env::temp_dir().join(format!("zorforge_swap_{}.txt", std::process::id()));
+        let lines = vec!["first".to_string(), "second".to_string()];
+
+        assert!(!swap_exists(&path));
+        write_swap(&path, &lines).unwrap();
+        assert!(swap_exists(&path));
+        assert_eq!(read_swap(&path).unwrap(), lines);
+
+        remove_swap(&path).unwrap();
+        assert!(!swap_exists(&path));
+        assert!(remove_swap(&path).is_ok());
+    }
+}
@@ -18,6 +18,9 @@ impl Viewport {
     }
 
     pub fn set_start(&mut self, start: usize) {
+        if start != self.start {
+            log::debug!("scroll: top {} -> {}", self.start, start);
+        }
         self.start = start;
     }
 
@@ -0,0 +1,38 @@
+// src/editor/watch.rs
+use std::path::Path;
+use std::time::SystemTime;
+
+/// What the file on disk looked like when it was last read or written:
+/// enough to tell that something else has written it since
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    /// None if the file can't be looked at, e.g. because it's gone
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self { modified: metadata.modified().ok()?, len: metadata.len() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_stamp() {
+        let path = std::env::temp_dir().join(format!("zorforge_watch_{}", std::process::id()));
+        std::fs::write(&path, "one").unwrap();
+        let stamp = FileStamp::of(&path);
+        assert!(stamp.is_some());
+        assert_eq!(FileStamp::of(&path), stamp);
+
+        std::fs::write(&path, "three").unwrap();
+        assert_ne!(FileStamp::of(&path), stamp);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(FileStamp::of(&path), None);
+    }
+}
@@ -0,0 +1,184 @@
+// src/editor/which_key.rs
+use std::collections::BTreeMap;
+use std::io;
+use crate::ui::SplitDirection;
+use super::Editor;
+use super::buffer::Buffer;
+use super::mode::ModeTrigger;
+
+type KeyHandler = fn(&mut Editor, Option<usize>) -> io::Result<()>;
+
+// A normal-mode command of two keys, which the normal-mode handler runs
+// and the which-key popup and :help list
+pub struct KeyBinding {
+    pub prefix: char, // The first key; Ctrl-W is kept as 'W', as in the pending keys
+    pub key: char,
+    pub edits: bool,  // Changes the text, so only where it may be changed
+    pub description: &'static str,
+    pub run: KeyHandler, // Given the count typed before the prefix
+}
+
+const fn binding(prefix: char, key: char, description: &'static str, run: KeyHandler) -> KeyBinding {
+    KeyBinding { prefix, key, edits: false, description, run }
+}
+
+const fn editing_binding(prefix: char, key: char, description: &'static str, run: KeyHandler) -> KeyBinding {
+    KeyBinding { edits: true, ..binding(prefix, key, description, run) }
+}
+
+// Show what went wrong without ending the key's handling, as for a
+// command typed on the command line
+fn showing_error(editor: &mut Editor, result: io::Result<()>) -> io::Result<()> {
+    if let Err(e) = result {
+        editor.show_error(&e.to_string());
+    }
+    Ok(())
+}
+
+// Every two-key command, kept together by prefix
+const BINDINGS: &[KeyBinding] = &[
+    editing_binding('d', 'd', "delete line", |editor, count| {
+        match count {
+            // `{count}dd` goes as one change, down to the last line at most
+            Some(count @ 2..) => {
+                let row = editor.buffer.get_cursor_position().0;
+                let last = (row + count - 1).min(editor.buffer.line_count() - 1);
+                editor.buffer.delete_lines(row, last);
+            }
+            _ => editor.buffer.delete_line(),
+        }
+        Ok(())
+    }),
+    editing_binding('c', 'c', "change line", |editor, _| {
+        editor.change_and_insert(ModeTrigger::InsertNormal, Buffer::clear_line_to_indent);
+        Ok(())
+    }),
+    binding('y', 'y', "yank line", |editor, _| {
+        editor.yank_line();
+        Ok(())
+    }),
+    binding('g', 'g', "go to first line", |editor, _| {
+        editor.buffer.move_cursor("top");
+        Ok(())
+    }),
+    binding('g', 'd', "go to local definition", |editor, _| {
+        editor.buffer.goto_local_definition();
+        Ok(())
+    }),
+    binding('g', 'f', "open file under cursor", |editor, _| {
+        let result = editor.goto_file();
+        showing_error(editor, result)
+    }),
+    editing_binding('g', 'J', "join lines without spaces", |editor, count| {
+        editor.buffer.join_lines(count.unwrap_or(2), false);
+        Ok(())
+    }),
+    binding(']', 's', "next misspelled word", |editor, _| {
+        editor.move_to_misspelling(true);
+        Ok(())
+    }),
+    binding('[', 's', "previous misspelled word", |editor, _| {
+        editor.move_to_misspelling(false);
+        Ok(())
+    }),
+    binding('z', '=', "spelling suggestions", |editor, count| {
+        editor.spell_suggest(count);
+        Ok(())
+    }),
+    binding('Z', 'Z', "write and quit", |editor, _| {
+        let result = editor.write_and_quit(false);
+        showing_error(editor, result)
+    }),
+    binding('Z', 'Q', "quit without writing", |editor, _| editor.force_quit()),
+    // The second key of Ctrl-W may have Ctrl held too
+    binding('W', 'w', "next window", |editor, _| {
+        editor.focus_next_window();
+        Ok(())
+    }),
+    binding('W', 's', "split horizontally", |editor, _| editor.split_window(SplitDirection::Horizontal)),
+    binding('W', 'v', "split vertically", |editor, _| editor.split_window(SplitDirection::Vertical)),
+    binding('W', 't', "toggle terminal", |editor, _| {
+        let result = editor.toggle_terminal();
+        showing_error(editor, result)
+    }),
+    binding('W', 'c', "close window", |editor, _| {
+        let result = editor.close_window();
+        showing_error(editor, result)
+    }),
+];
+
+/// The command `key` runs after the prefix key `prefix`, if there is one
+pub fn find(prefix: char, key: char) -> Option<&'static KeyBinding> {
+    BINDINGS.iter().find(|binding| binding.prefix == prefix && binding.key == key)
+}
+
+/// Keys that may follow the prefix key `prefix`, with what each does
+pub fn continuations(prefix: char) -> Vec<(String, String)> {
+    BINDINGS.iter()
+        .filter(|binding| binding.prefix == prefix)
+        .map(|binding| (binding.key.to_string(), binding.description.to_string()))
+        .collect()
+}
+
+/// Every two-key command, as (both keys, what it does), with Ctrl-W
+/// written ^W
+pub fn all_continuations() -> Vec<(String, String)> {
+    BINDINGS.iter()
+        .map(|binding| {
+            let prefix = match binding.prefix {
+                'W' => String::from("^W"),
+                c => c.to_string(),
+            };
+            (format!("{}{}", prefix, binding.key), binding.description.to_string())
+        })
+        .collect()
+}
+
+/// Leader mappings that begin with the keys typed so far, as the keys left
+/// to type and the command each runs
+pub fn leader_continuations(mappings: &BTreeMap<String, String>, typed: &str) -> Vec<(String, String)> {
+    mappings.iter()
+        .filter_map(|(keys, command)| {
+            keys.strip_prefix(typed)
+                .filter(|rest| !rest.is_empty())
+                .map(|rest| (rest.to_string(), command.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_continuations() {
+        let keys: Vec<String> = continuations('Z').into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["Z", "Q"]);
+        assert!(continuations('q').is_empty());
+    }
+
+    #[test]
+    fn test_find() {
+        assert!(find('d', 'd').is_some_and(|binding| binding.edits));
+        assert!(find('g', 'f').is_some_and(|binding| !binding.edits));
+        assert!(find('d', 'x').is_none());
+        // Each pair of keys runs one thing
+        for binding in BINDINGS {
+            assert_eq!(find(binding.prefix, binding.key).unwrap().description, binding.description);
+        }
+    }
+
+    #[test]
+    fn test_leader_continuations() {
+        let mappings = BTreeMap::from([
+            ("w".to_string(), ":w".to_string()),
+            ("fo".to_string(), ":oldfiles".to_string()),
+            ("fs".to_string(), ":w".to_string()),
+        ]);
+        assert_eq!(leader_continuations(&mappings, "").len(), 3);
+        assert_eq!(
+            leader_continuations(&mappings, "f"),
+            [("o".to_string(), ":oldfiles".to_string()), ("s".to_string(), ":w".to_string())],
+        );
+    }
+}
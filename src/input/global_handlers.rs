@@ -11,15 +11,18 @@ impl GlobalKeyHandler {
     /// Attempt to handle global key bindings
     /// Returns true if the key was handled, false otherwise
     pub fn handle(editor: &mut Editor, key: KeyEvent) -> io::Result<bool> {
+        // Both held; `CONTROL | SHIFT` as a pattern would take either alone,
+        // which swallowed plain Ctrl-V
+        let ctrl_shift = key.modifiers == KeyModifiers::CONTROL | KeyModifiers::SHIFT;
         match (key.code, key.modifiers) {
             // Clipboard operations
-            (KeyCode::Char('c'), KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
+            (KeyCode::Char('c'), _) if ctrl_shift => {
                 Self::handle_global_copy(editor)
             },
-            (KeyCode::Char('x'), KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
+            (KeyCode::Char('x'), _) if ctrl_shift => {
                 Self::handle_global_cut(editor)
             },
-            (KeyCode::Char('v'), KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
+            (KeyCode::Char('v'), _) if ctrl_shift => {
                 Self::handle_global_paste(editor)
             },
 
@@ -27,7 +30,7 @@ impl GlobalKeyHandler {
             (KeyCode::Char('z'), KeyModifiers::CONTROL) => {
                 Self::handle_undo(editor)
             },
-            (KeyCode::Char('z'), KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
+            (KeyCode::Char('z'), _) if ctrl_shift => {
                 Self::handle_redo(editor)
             },
 
@@ -43,7 +46,7 @@ impl GlobalKeyHandler {
             .or_else(|| editor.buffer.get_current_line().cloned());
         
         if let Some(content) = text {
-            editor.clipboard.yank(content);
+            editor.buffer.clipboard_mut().yank(content);
         }
         
         Ok(true)
@@ -54,14 +57,14 @@ impl GlobalKeyHandler {
         // Try to get and delete selected text first, fallback to current line
         if editor.buffer.get_selected_text().is_some() {
             if let Some(text) = editor.buffer.get_selected_text() {
-                editor.clipboard.yank(text);
+                editor.buffer.clipboard_mut().yank(text);
                 editor.buffer.delete_selection();
             }
             editor.buffer.clear_visual();
         } else {
             // Cut current line
             if let Some(line) = editor.buffer.get_current_line().cloned() {
-                editor.clipboard.yank(line);
+                editor.buffer.clipboard_mut().yank(line);
                 editor.buffer.delete_line();
             }
         }
@@ -1,21 +1,42 @@
 // src/input/handlers/command.rs
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use crate::editor::Editor;
+use crate::editor::{glob, help, session, Editor};
+use crate::editor::encoding::Encoding;
+use crate::editor::buffer::{is_word_char, VirtualEdit};
 use crate::editor::mode::{Mode, ModeTrigger, CommandType};
+use crate::config::parse_color_columns;
+use crate::ui::SplitDirection;
 
 pub fn handle_command_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
     match key.code {
-        // Exit command mode
-        KeyCode::Esc => {
-            editor.set_mode(editor.mode.transition(ModeTrigger::Escape));
-        }
+        // Exit command mode; a search begun in visual mode goes back to it
+        KeyCode::Esc => match editor.buffer.stored_visual_mode() {
+            Some(mode) => {
+                editor.buffer.restore_visual_bounds();
+                editor.set_mode(Mode::Visual(mode.into()));
+            }
+            None => editor.set_mode(editor.mode.transition(ModeTrigger::Escape)),
+        },
 
-        // Execute command
+        // Execute command, or search for the typed pattern
         KeyCode::Enter => {
             let cmd = editor.command_line_content();
-            execute_command(editor, &cmd)?;
+            if let Some(mode) = editor.buffer.stored_visual_mode() {
+                execute_selection_search(editor, &cmd);
+                editor.set_mode(Mode::Visual(mode.into()));
+                return Ok(());
+            }
+            match *editor.mode() {
+                Mode::Command(CommandType::Search) => execute_search(editor, &cmd, true),
+                Mode::Command(CommandType::Backward) => execute_search(editor, &cmd, false),
+                _ => {
+                    if let Err(e) = execute_command(editor, &cmd) {
+                        editor.show_error(&e.to_string());
+                    }
+                }
+            }
             editor.set_mode(editor.mode.transition(ModeTrigger::Enter));
         }
 
@@ -31,61 +52,681 @@ pub fn handle_command_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
             editor.delete_from_command();
         }
 
+        // Move along the command line
+        KeyCode::Left => editor.move_command_cursor(false),
+        KeyCode::Right => editor.move_command_cursor(true),
+        KeyCode::Home => editor.command_cursor_to_end(false),
+        KeyCode::End => editor.command_cursor_to_end(true),
+
         _ => (),
     }
     Ok(())
 }
 
-fn execute_command(editor: &mut Editor, cmd: &str) -> io::Result<()> {
-    // Basic command implementation
-    match cmd {
-        "q" | "quit" => {
-            if editor.has_unsaved_changes() {
-                editor.show_message("No write since last change (add ! to override)");
-            } else {
-                // TODO: Implement proper exit
-                std::process::exit(0);
-            }
-        }
+// The y/n/a/q/l answer while `:s///c` asks about a match
+pub fn handle_substitute_confirm(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    match key.code {
+        KeyCode::Char(c) => editor.answer_substitute(c),
+        KeyCode::Esc => editor.answer_substitute('q'),
+        _ => editor.answer_substitute('\0'),
+    }
+    Ok(())
+}
+
+// The y/n/c answer to saving changes before quitting
+pub fn handle_quit_confirm(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    let answered = match key.code {
+        KeyCode::Char(c) => editor.answer_quit(c),
+        KeyCode::Esc => editor.answer_quit('c'),
+        _ => editor.answer_quit('\0'),
+    };
+    // A failed write leaves the editor open to say why
+    if let Err(e) = answered {
+        editor.show_error(&e.to_string());
+    }
+    Ok(())
+}
 
-        "q!" | "quit!" => {
-            std::process::exit(0);
+// Keys while :registers lists the clipboard
+pub fn handle_clipboard_browser(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down => editor.move_clipboard_selection(true),
+        KeyCode::Char('k') | KeyCode::Up => editor.move_clipboard_selection(false),
+        KeyCode::Enter => editor.paste_clipboard_entry(None),
+        KeyCode::Char(c @ '1'..='9') => {
+            let n = c.to_digit(10).unwrap_or(1) as usize;
+            editor.paste_clipboard_entry(Some(n - 1));
         }
+        _ => editor.close_clipboard_browser(),
+    }
+    Ok(())
+}
+
+// Keys while :help is open
+pub fn handle_help_view(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => editor.scroll_help(1, false),
+        KeyCode::Char('k') | KeyCode::Up => editor.scroll_help(-1, false),
+        KeyCode::Char(' ') | KeyCode::PageDown => editor.scroll_help(1, true),
+        KeyCode::Char('b') | KeyCode::PageUp => editor.scroll_help(-1, true),
+        _ => editor.close_help(),
+    }
+    Ok(())
+}
+
+pub fn handle_reload_confirm(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    let answered = match key.code {
+        KeyCode::Char(c) => editor.answer_reload(c),
+        KeyCode::Esc => editor.answer_reload('n'),
+        _ => editor.answer_reload('\0'),
+    };
+    if let Err(e) = answered {
+        editor.show_error(&e.to_string());
+    }
+    Ok(())
+}
+
+// `/pattern` or `?pattern`
+fn execute_search(editor: &mut Editor, pattern: &str, forward: bool) {
+    if pattern.is_empty() {
+        return;
+    }
+    if editor.buffer.search_from_cursor(pattern, true, forward) == 0 {
+        editor.show_error(&format!("Pattern not found: {}", pattern));
+    } else {
+        editor.buffer.scroll_to_cursor();
+    }
+}
+
+// `/` from visual mode looks only inside the selection, which stays
+// selected whether or not there's a match
+fn execute_selection_search(editor: &mut Editor, pattern: &str) {
+    if pattern.is_empty() {
+        editor.buffer.restore_visual_bounds();
+    } else if !editor.buffer.search_in_selection(pattern) {
+        editor.show_error(&format!("Pattern not found in selection: {}", pattern));
+    }
+    editor.buffer.scroll_to_cursor();
+}
+
+// An ex command split into its name, whether `!` followed the name, and
+// whatever comes after: ":w! out.txt" is ("w", true, "out.txt")
+struct ParsedCommand<'a> {
+    name: &'a str,
+    force: bool,
+    args: &'a str,
+}
+
+fn parse_command(cmd: &str) -> ParsedCommand<'_> {
+    let cmd = cmd.trim();
+    // `:>>` shifts twice, so the name of a shift is the whole run
+    let name_len = match cmd.chars().next() {
+        Some(shift @ ('<' | '>')) => cmd.find(|c| c != shift).unwrap_or(cmd.len()),
+        _ => cmd.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(cmd.len()),
+    };
+    let (name, rest) = cmd.split_at(name_len);
+    let (force, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    ParsedCommand { name, force, args: rest.trim() }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// The line range in front of a command - `%`, `'<,'>`, `3,$`, `.,+2` and
+// so on - as zero-based first and last rows, with the text after it
+fn parse_range<'a>(editor: &Editor, cmd: &'a str) -> io::Result<(Option<(usize, usize)>, &'a str)> {
+    let cmd = cmd.trim_start();
+    let last_row = editor.buffer.line_count() - 1;
+    if let Some(rest) = cmd.strip_prefix('%') {
+        return Ok((Some((0, last_row)), rest));
+    }
+
+    let Some((first, rest)) = parse_address(editor, cmd)? else {
+        return Ok((None, cmd));
+    };
+    let (last, rest) = match rest.strip_prefix(',') {
+        Some(rest) => parse_address(editor, rest)?.ok_or_else(|| invalid_input("Invalid range"))?,
+        None => (first, rest),
+    };
+    if first > last_row || last > last_row {
+        return Err(invalid_input("Invalid range"));
+    }
+    Ok((Some((first.min(last), first.max(last))), rest))
+}
+
+// One line address: a line number, `.`, `$` or a '< '> mark, followed by
+// any number of +N / -N offsets. An offset alone is relative to the cursor.
+fn parse_address<'a>(editor: &Editor, text: &'a str) -> io::Result<Option<(usize, &'a str)>> {
+    let cursor_row = editor.buffer.get_cursor_position().0;
+    let visual_rows = || editor.buffer.last_visual_rows().ok_or_else(|| invalid_input("Mark not set"));
+    let digits = |text: &'a str| {
+        let len = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        // A number too big for usize is past any line, not no number at all
+        ((len > 0).then(|| text[..len].parse::<usize>().unwrap_or(usize::MAX)), &text[len..])
+    };
 
-        "w" | "write" => {
-            editor.save_buffer()?;
+    let (mut row, mut rest) = if let Some(rest) = text.strip_prefix('.') {
+        (Some(cursor_row), rest)
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (Some(editor.buffer.line_count() - 1), rest)
+    } else if let Some(rest) = text.strip_prefix("'<") {
+        (Some(visual_rows()?.0), rest)
+    } else if let Some(rest) = text.strip_prefix("'>") {
+        (Some(visual_rows()?.1), rest)
+    } else {
+        match digits(text) {
+            (Some(line), rest) => (Some(line.saturating_sub(1)), rest),
+            (None, rest) => (None, rest),
         }
+    };
+
+    while let Some(sign) = rest.chars().next().filter(|&c| c == '+' || c == '-') {
+        let (amount, after) = digits(&rest[1..]);
+        let amount = amount.unwrap_or(1);
+        let base = row.unwrap_or(cursor_row);
+        row = Some(if sign == '+' { base.saturating_add(amount) } else { base.saturating_sub(amount) });
+        rest = after;
+    }
+    Ok(row.map(|row| (row, rest)))
+}
+
+// What a command's handler gets: the parsed command, and the lines it
+// works on for one that takes a range
+struct Invocation<'a> {
+    name: &'a str,
+    force: bool,
+    args: &'a str,
+    range: (usize, usize),
+}
+
+type CommandHandler = fn(&mut Editor, &Invocation) -> io::Result<()>;
+
+// An ex command: the names it goes by, what it takes and what runs it
+struct CommandSpec {
+    names: &'static [&'static str], // The full name first, then the others it goes by
+    args: &'static str,             // What goes after the name, for :help
+    range: bool,                    // Works on a range of lines, the cursor's line by default
+    whole_file: bool,               // With no range, works on every line, as :sort does
+    bang: bool,                     // Takes a `!`, as :q! does
+    description: &'static str,
+    run: CommandHandler,
+}
+
+const fn command(names: &'static [&'static str], args: &'static str, description: &'static str, run: CommandHandler) -> CommandSpec {
+    CommandSpec { names, args, range: false, whole_file: false, bang: false, description, run }
+}
+
+const fn bang_command(names: &'static [&'static str], args: &'static str, description: &'static str, run: CommandHandler) -> CommandSpec {
+    CommandSpec { bang: true, ..command(names, args, description, run) }
+}
+
+const fn range_command(names: &'static [&'static str], args: &'static str, description: &'static str, run: CommandHandler) -> CommandSpec {
+    CommandSpec { range: true, ..command(names, args, description, run) }
+}
+
+const fn file_range_command(names: &'static [&'static str], args: &'static str, description: &'static str, run: CommandHandler) -> CommandSpec {
+    CommandSpec { whole_file: true, ..range_command(names, args, description, run) }
+}
+
+// Every ex command there is. `:>` and `:<` shift by as many levels as
+// there are of them, so they go by any run of one or the other.
+const COMMANDS: &[CommandSpec] = &[
+    bang_command(&["quit", "q"], "", "close the window, or quit", quit),
+    bang_command(&["write", "w"], "[file]", "write the buffer, to [file] if given", write),
+    bang_command(&["wq"], "[file]", "write and quit", write_quit),
+    bang_command(&["xit", "x", "exi", "exit"], "[file]", "write if there are changes, and quit", exit),
+    bang_command(&["wall", "wa"], "", "write every modified buffer", |editor, cmd| {
+        forcing_write(editor, cmd, |editor| editor.write_all().map(drop))
+    }),
+    bang_command(&["xall", "xa", "wqa", "wqall"], "", "write every modified buffer and quit", |editor, cmd| {
+        forcing_write(editor, cmd, |editor| editor.write_all_and_quit())
+    }),
+    bang_command(&["edit", "e"], "[file]", "edit [file], or reload this one", edit),
+    bang_command(&["oldfiles", "ol", "recent"], "[n]", "list recent files, or edit the [n]th", oldfiles),
+    bang_command(&["next", "n", "bn", "bnext"], "", "edit the next file in the argument list", next_arg),
+    bang_command(&["Next", "N", "prev", "previous", "bp", "bprevious"], "", "edit the previous file in the argument list", next_arg),
+    bang_command(&["args", "ar"], "[files]", "show the argument list, or replace it", args),
+    command(&["split", "sp"], "", "split the window horizontally", |editor, _| editor.split_window(SplitDirection::Horizontal)),
+    command(&["vsplit", "vs"], "", "split the window vertically", |editor, _| editor.split_window(SplitDirection::Vertical)),
+    command(&["close", "clo"], "", "close the window", |editor, _| editor.close_window()),
+    command(&["terminal", "term"], "", "open a terminal window", |editor, _| editor.open_terminal()),
+    command(&["cd", "chd", "chdir"], "[dir]", "change the working directory", |editor, cmd| editor.change_directory(cmd.args)),
+    command(&["pwd", "pw"], "", "show the working directory", |editor, _| editor.show_working_directory()),
+    bang_command(&["mksession", "mks"], "[file]", "save the session", mksession),
+    bang_command(&["source", "so"], "{file}", "load a saved session", source),
+    command(&["recover", "rec"], "", "bring back changes from the swap file", |editor, _| editor.recover_from_swap()),
+    command(&["discardswap"], "", "delete the swap file", |editor, _| editor.discard_swap()),
+    command(&["registers", "reg", "clipboard"], "[n]", "browse the clipboard, or paste entry [n]", registers),
+    command(&["messages", "mes"], "", "show recent messages", messages),
+    command(&["iabbrev", "iab"], "[word expansion]", "list or add insert-mode abbreviations", |editor, cmd| execute_iabbrev(editor, cmd.args)),
+    command(&["iunabbrev", "iuna"], "{word}", "remove an abbreviation", iunabbrev),
+    command(&["nohlsearch", "noh"], "", "stop highlighting the last search", |editor, _| {
+        editor.buffer.clear_search();
+        Ok(())
+    }),
+    command(&["colorscheme", "colo"], "[name]", "show or switch the theme", colorscheme),
+    command(&["set", "se"], "{option}[=value] ...", "change options", |editor, cmd| execute_set(editor, cmd.args)),
+    command(&["normal", "norm"], "{keys}", "type {keys} in normal mode, <Esc> and the like included", normal),
+    command(&["saveconfig"], "[file]", "write the current settings to the config file", saveconfig),
+    command(&["help", "h"], "[topic]", "this help: commands, keys, or a command or key", |editor, cmd| {
+        editor.open_help(help_lines(cmd.args)?);
+        Ok(())
+    }),
+    range_command(&["delete", "d"], "", "delete lines", |editor, cmd| {
+        editor.buffer.delete_lines(cmd.range.0, cmd.range.1);
+        Ok(())
+    }),
+    file_range_command(&["sort", "sor"], "", "sort lines, all of them by default", |editor, cmd| {
+        editor.buffer.sort_lines(cmd.range.0, cmd.range.1);
+        Ok(())
+    }),
+    range_command(&["substitute", "s"], "/{pattern}/{replacement}/[gc]", "replace text, [g] all on a line, [c] asking", substitute),
+    range_command(&["align"], "{delimiter}", "line up {delimiter} across lines", align),
+    range_command(&[">", "<"], "", "shift lines right or left, once per > or <", shift),
+];
 
-        "wq" => {
-            editor.save_buffer()?;
-            std::process::exit(0);
+fn find_command(name: &str) -> Option<&'static CommandSpec> {
+    let name = match name.starts_with(['<', '>']) {
+        true => &name[..1],
+        false => name,
+    };
+    COMMANDS.iter().find(|spec| spec.names.contains(&name))
+}
+
+// :help {topic} - every command and key, just the `commands` or `keys`,
+// or those going by the name `topic`
+fn help_lines(topic: &str) -> io::Result<Vec<String>> {
+    let name = topic.strip_prefix(':').unwrap_or(topic);
+    let commands: Vec<String> = COMMANDS.iter()
+        .filter(|spec| matches!(topic, "" | "commands") || spec.names.contains(&name))
+        .map(|spec| {
+            let range = if spec.range { "[range]" } else { "" };
+            let bang = if spec.bang { "[!]" } else { "" };
+            let usage = format!(":{}{}{} {}", range, spec.names[0], bang, spec.args);
+            let others = match spec.names.len() {
+                1 => String::new(),
+                _ => format!(" (also :{})", spec.names[1..].join(", :")),
+            };
+            format!("  {:<40} {}{}", usage, spec.description, others)
+        })
+        .collect();
+    let keys: Vec<String> = help::key_entries().into_iter()
+        .filter(|(keys, _)| matches!(topic, "" | "keys") || keys.split(' ').any(|key| key == topic))
+        .map(|(keys, action)| format!("  {:<12} {}", keys, action))
+        .collect();
+    if commands.is_empty() && keys.is_empty() {
+        return Err(invalid_input(&format!("Sorry, no help for {}", topic)));
+    }
+
+    let mut lines = Vec::new();
+    if !commands.is_empty() {
+        lines.push(String::from("Commands:"));
+        lines.extend(commands);
+    }
+    if !keys.is_empty() {
+        if !lines.is_empty() {
+            lines.push(String::new());
         }
+        lines.push(String::from("Normal-mode keys:"));
+        lines.extend(keys);
+    }
+    Ok(lines)
+}
 
-        // Add more commands here as needed
+// Run an ex command from somewhere other than the command line, like a
+// leader mapping; the leading `:` is optional
+pub fn run_command(editor: &mut Editor, cmd: &str) {
+    if let Err(e) = execute_command(editor, cmd.strip_prefix(':').unwrap_or(cmd)) {
+        editor.show_error(&e.to_string());
+    }
+}
 
-        _ => {
-            // Handle save-as command
-            if cmd.starts_with("w ") || cmd.starts_with("write ") {
-                let file_path = cmd.split_whitespace().nth(1)
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file specified"))?;
-                editor.save_buffer_as(PathBuf::from(file_path))?;
-                return Ok(());
+pub fn execute_command(editor: &mut Editor, cmd: &str) -> io::Result<()> {
+    let (range, rest) = parse_range(editor, cmd)?;
+    let ParsedCommand { name, force, args } = parse_command(rest);
+    let Some(spec) = find_command(name) else {
+        match range {
+            // A range alone, as in `:42`, goes to its last line
+            Some((_, last)) if name.is_empty() && args.is_empty() => {
+                editor.buffer.push_jump();
+                editor.buffer.set_cursor_position(last, 0);
             }
+            Some(_) => return Err(invalid_input("No range allowed")),
+            None => editor.show_message(&format!("Unknown command: {}", cmd)),
+        }
+        return Ok(());
+    };
+    if range.is_some() && !spec.range {
+        return Err(invalid_input("No range allowed"));
+    }
+    if force && !spec.bang {
+        return Err(invalid_input("No ! allowed"));
+    }
+    let row = editor.buffer.get_cursor_position().0;
+    let default_range = match spec.whole_file {
+        true => (0, editor.buffer.line_count() - 1),
+        false => (row, row),
+    };
+    (spec.run)(editor, &Invocation { name, force, args, range: range.unwrap_or(default_range) })
+}
+
+// Commands that would throw away unsaved changes need a `!` to do it
+fn refuse_unsaved(editor: &mut Editor, cmd: &Invocation) -> bool {
+    let refuse = !cmd.force && editor.has_unsaved_changes();
+    if refuse {
+        editor.show_message("No write since last change (add ! to override)");
+    }
+    refuse
+}
+
+// With splits open, :q just closes one; the buffer stays in the others.
+// Terminals don't count: closing the last window onto the buffer quits.
+fn quit(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if editor.windows().buffer_window_count() > 1 {
+        editor.close_window()
+    } else if cmd.force {
+        editor.force_quit()
+    } else {
+        editor.confirm_quit()
+    }
+}
+
+// A file name writes to it and makes it the buffer's file
+fn write(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    forcing_write(editor, cmd, |editor| match cmd.args {
+        "" => editor.save_buffer(),
+        args => editor.save_buffer_as(glob::expand_one(args)?),
+    })
+}
 
-            // Handle edit command
-            if cmd.starts_with("e ") || cmd.starts_with("edit ") {
-                if editor.has_unsaved_changes() {
-                    editor.show_message("No write since last change (add ! to override)");
-                    return Ok(());
+fn write_quit(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        return forcing_write(editor, cmd, |editor| editor.write_and_quit(true));
+    }
+    forcing_write(editor, cmd, |editor| editor.save_buffer_as(glob::expand_one(cmd.args)?))?;
+    editor.quit();
+    Ok(())
+}
+
+// With !, a read-only buffer is written all the same and stays writable
+// from then on. If the write fails it's read-only again.
+fn forcing_write(
+    editor: &mut Editor,
+    cmd: &Invocation,
+    write: impl FnOnce(&mut Editor) -> io::Result<()>,
+) -> io::Result<()> {
+    let readonly = editor.is_readonly();
+    if cmd.force {
+        editor.set_readonly(false);
+    }
+    let result = write(editor);
+    if result.is_err() {
+        editor.set_readonly(readonly);
+    }
+    result
+}
+
+// :x writes only when there's something to write
+fn exit(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        return forcing_write(editor, cmd, |editor| editor.write_and_quit(false));
+    }
+    if editor.has_unsaved_changes() {
+        forcing_write(editor, cmd, |editor| editor.save_buffer_as(glob::expand_one(cmd.args)?))?;
+    }
+    editor.force_quit()
+}
+
+// :e! throws away unsaved changes; without a name it reloads the current
+// file. A pattern matching several files opens the first and makes them
+// the argument list.
+fn edit(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if refuse_unsaved(editor, cmd) {
+        return Ok(());
+    }
+    let paths = match cmd.args {
+        "" => vec![editor.file_path().map(PathBuf::from).ok_or_else(|| invalid_input("No file name"))?],
+        pattern => glob::expand(pattern)?,
+    };
+    editor.open_file(&paths[0])?;
+    if paths.len() > 1 {
+        editor.set_arg_list(paths);
+    }
+    Ok(())
+}
+
+fn oldfiles(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    let choice = match cmd.args {
+        "" => None,
+        n => Some(n.parse().map_err(|_| invalid_input(&format!("Not a number: {}", n)))?),
+    };
+    if choice.is_some() && refuse_unsaved(editor, cmd) {
+        return Ok(());
+    }
+    editor.recent_files(choice)
+}
+
+// Walking the files named on the command line
+fn next_arg(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if refuse_unsaved(editor, cmd) {
+        return Ok(());
+    }
+    let forward = matches!(cmd.name, "n" | "next" | "bn" | "bnext");
+    editor.open_next_arg(if forward { 1 } else { -1 })
+}
+
+fn args(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        let list = editor.arg_list_display();
+        editor.show_message(&list);
+        return Ok(());
+    }
+    if refuse_unsaved(editor, cmd) {
+        return Ok(());
+    }
+    let mut files: Vec<PathBuf> = Vec::new();
+    for word in cmd.args.split_whitespace() {
+        files.extend(glob::expand(word)?);
+    }
+    editor.open_file(&files[0])?;
+    editor.set_arg_list(files);
+    Ok(())
+}
+
+fn mksession(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    let path = match cmd.args {
+        "" => PathBuf::from(session::DEFAULT_SESSION_FILE),
+        path => PathBuf::from(path),
+    };
+    editor.make_session(&path, cmd.force)
+}
+
+fn source(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        return Err(invalid_input("Argument required"));
+    }
+    if refuse_unsaved(editor, cmd) {
+        return Ok(());
+    }
+    editor.load_session(Path::new(cmd.args))
+}
+
+// A number pastes that entry straight away
+fn registers(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    match cmd.args {
+        "" => editor.open_clipboard_browser(),
+        n => {
+            let n: usize = n.parse().map_err(|_| invalid_input(&format!("Not a number: {}", n)))?;
+            editor.paste_clipboard_entry(Some(n.saturating_sub(1)));
+        }
+    }
+    Ok(())
+}
+
+// The keys go in as typed from normal mode; whatever they leave unfinished,
+// an insert or a half-typed command, is ended as Esc would
+fn normal(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        return Err(invalid_input("Argument required"));
+    }
+    editor.set_mode(Mode::Normal);
+    let result = crate::input::send_keys(editor, cmd.args);
+    if *editor.mode() != Mode::Normal {
+        super::handle_input(editor, KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))?;
+    }
+    editor.clear_pending_keys();
+    result
+}
+
+fn messages(editor: &mut Editor, _: &Invocation) -> io::Result<()> {
+    let count = editor.buffer.get_viewport().height;
+    editor.show_message_history(count);
+    Ok(())
+}
+
+fn iunabbrev(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if !editor.remove_abbreviation(cmd.args) {
+        editor.show_error(&format!("No such abbreviation: {}", cmd.args));
+    }
+    Ok(())
+}
+
+// Theme switching; with no name, show the current one
+fn colorscheme(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        let current = editor.config.theme.name.clone();
+        editor.show_message(&current);
+        return Ok(());
+    }
+    editor.set_colorscheme(cmd.args)
+}
+
+fn saveconfig(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    editor.save_config((!cmd.args.is_empty()).then(|| Path::new(cmd.args)))
+}
+
+// s/pattern/replacement/flags, with any delimiter after the s
+fn substitute(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    let (first, last) = cmd.range;
+    let mut delimiter = [0; 4];
+    let delimiter = cmd.args.chars().next()
+        .ok_or_else(|| invalid_input("No previous substitute"))?
+        .encode_utf8(&mut delimiter);
+    let mut parts = cmd.args[delimiter.len()..].splitn(3, &*delimiter);
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    let global = flags.contains('g');
+    if flags.contains('c') {
+        return editor.start_substitute_confirm(first, last, pattern, replacement, global);
+    }
+    if editor.buffer.substitute(first, last, pattern, replacement, global) == 0 {
+        return Err(invalid_input(&format!("Pattern not found: {}", pattern)));
+    }
+    Ok(())
+}
+
+// :align {delimiter} lines the delimiters up, e.g. :'<,'>align =
+fn align(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    if cmd.args.is_empty() {
+        return Err(invalid_input("Argument required"));
+    }
+    if editor.buffer.align_lines(cmd.range.0, cmd.range.1, cmd.args) == 0 {
+        return Err(invalid_input(&format!("Pattern not found: {}", cmd.args)));
+    }
+    Ok(())
+}
+
+fn shift(editor: &mut Editor, cmd: &Invocation) -> io::Result<()> {
+    let (first, last) = cmd.range;
+    let size = editor.buffer.shift_width();
+    if cmd.name.starts_with('>') {
+        editor.buffer.indent_lines(first, last, size, cmd.name.len());
+    } else {
+        editor.buffer.dedent_lines(first, last, size, cmd.name.len());
+    }
+    editor.buffer.set_cursor_position(last, 0);
+    Ok(())
+}
+
+// :iabbrev {word} {expansion}, or :iabbrev alone to list them
+fn execute_iabbrev(editor: &mut Editor, args: &str) -> io::Result<()> {
+    let args = args.trim();
+    if args.is_empty() {
+        let listing: Vec<String> = editor.abbreviations()
+            .map(|(word, expansion)| format!("i  {}  {}", word, expansion))
+            .collect();
+        if listing.is_empty() {
+            editor.show_message("No abbreviation found");
+        } else {
+            editor.show_message(&listing.join("\n"));
+        }
+        return Ok(());
+    }
+
+    match args.split_once(char::is_whitespace) {
+        Some((word, expansion)) if word.chars().all(is_word_char) => {
+            editor.add_abbreviation(word, expansion.trim());
+            Ok(())
+        }
+        _ => Err(invalid_input(&format!("Invalid abbreviation: {}", args))),
+    }
+}
+
+// :set {option}={value} ...
+fn execute_set(editor: &mut Editor, args: &str) -> io::Result<()> {
+    for arg in args.split_whitespace() {
+        match arg.split_once('=') {
+            Some(("filetype" | "ft", value)) => {
+                let filetype = (!value.is_empty()).then(|| value.to_string());
+                editor.buffer.set_filetype(filetype);
+            }
+            Some(("fileencoding" | "fenc", value)) => {
+                let encoding = Encoding::parse(value)
+                    .ok_or_else(|| invalid_input(&format!("Invalid argument: {}", arg)))?;
+                editor.set_file_encoding(encoding);
+            }
+            Some(("whichwrap" | "ww", value)) => {
+                editor.config.whichwrap = value.to_string();
+            }
+            Some(("colorcolumn" | "cc", value)) => {
+                if parse_color_columns(value).is_none() {
+                    return Err(invalid_input(&format!("Invalid argument: {}", arg)));
                 }
-                let file_path = cmd.split_whitespace().nth(1)
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file specified"))?;
-                editor.open_file(&PathBuf::from(file_path))?;
-                return Ok(());
+                editor.config.colorcolumn = value.to_string();
+                editor.request_redraw();
+            }
+            Some(("virtualedit" | "ve", value)) => {
+                let virtual_edit = VirtualEdit::parse(value)
+                    .ok_or_else(|| invalid_input(&format!("Invalid argument: {}", arg)))?;
+                editor.config.virtualedit = value.to_string();
+                editor.buffer.set_virtual_edit(virtual_edit);
+            }
+            Some(("undolevels" | "ul", value)) => {
+                let levels = value.parse::<i64>()
+                    .map_err(|_| invalid_input(&format!("Invalid argument: {}", arg)))?;
+                editor.config.undolevels = levels;
+                editor.buffer.set_undo_levels(usize::try_from(levels).unwrap_or(0));
+            }
+            Some(("loglevel", value)) => {
+                let level = value.parse::<log::LevelFilter>()
+                    .map_err(|_| invalid_input(&format!("Invalid argument: {}", arg)))?;
+                log::set_max_level(level);
+            }
+            None if arg == "bomb" => editor.set_bom(true),
+            None if arg == "nobomb" => editor.set_bom(false),
+            None if arg == "readonly" || arg == "ro" => editor.set_readonly(true),
+            None if arg == "noreadonly" || arg == "noro" => editor.set_readonly(false),
+            None if arg == "spell" => editor.set_spell(true)?,
+            None if arg == "nospell" => editor.set_spell(false)?,
+            _ => {
+                return Err(invalid_input(&format!("Unknown option: {}", arg)));
             }
-
-            editor.show_message(&format!("Unknown command: {}", cmd));
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,14 +1,62 @@
 // src/input/handlers/insert.rs
 use std::io;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use crate::editor::Editor;
-use crate::editor::mode::{Mode, ModeTrigger, InsertVariant};
+use crate::editor::{digraph, Editor};
+use crate::editor::buffer::is_word_char;
+use crate::editor::mode::ModeTrigger;
 
 /// Handler keypress event in insert mode
 pub fn handle_insert_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    // Ctrl-K {char1} {char2} enters a digraph
+    if let Some(typed) = editor.pending_keys().strip_prefix("^K") {
+        let first = typed.chars().next();
+        if let KeyCode::Char(c) = key.code {
+            match first {
+                None => editor.push_pending_key(c),
+                Some(first) => {
+                    editor.clear_pending_keys();
+                    // An unknown pair inserts the second character, as vim does
+                    insert_typed_char(editor, digraph::lookup(first, c).unwrap_or(c));
+                }
+            }
+            return Ok(());
+        }
+        // Any other key abandons the digraph and is handled as usual
+        editor.clear_pending_keys();
+    }
+
+    // The completion popup takes the keys that work it; any other key closes
+    // it, carrying on as usual, except that typing more of the word narrows it
+    if editor.completion().is_some() {
+        let ctrl = key.modifiers == KeyModifiers::CONTROL;
+        match key.code {
+            KeyCode::Char('n') if ctrl => editor.select_completion(true),
+            KeyCode::Char('p') if ctrl => editor.select_completion(false),
+            KeyCode::Down => editor.select_completion(true),
+            KeyCode::Up => editor.select_completion(false),
+            KeyCode::Enter | KeyCode::Tab => editor.accept_completion(),
+            KeyCode::Char(c) if is_word_char(c) && !ctrl => {
+                insert_typed_char(editor, c);
+                editor.update_completion();
+            }
+            KeyCode::Backspace => {
+                editor.buffer.delete_char();
+                editor.update_completion();
+            }
+            _ => {
+                editor.cancel_completion();
+                return handle_insert_mode(editor, key);
+            }
+        }
+        return Ok(());
+    }
+
     match key.code {
         // Mode Transitions
         KeyCode::Esc => {
+            // An abbreviation just typed ends at the cursor, so it's
+            // expanded before the cursor steps back
+            editor.expand_abbreviation();
             // Move cursor back one space when exiting insert mode
             // (vim behavior: cursor should end up on last insert character)
             if let Some(line) = editor.buffer.get_current_line() {
@@ -25,13 +73,13 @@ pub fn handle_insert_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
                 match c {
                     // Modern clipboard operations
                     'C' | 'c' => {
-                        if let Some(line) = editor.buffer.get_current_line() {
-                            editor.clipboard.yank(line.to_string());
+                        if let Some(line) = editor.buffer.get_current_line().cloned() {
+                            editor.buffer.clipboard_mut().yank(line);
                         }
                     }
                     'V' | 'v' => {
-                        if let Some(line) = editor.clipboard.peek() {
-                            editor.buffer.paste_at_cursor(line);
+                        if let Some(line) = editor.buffer.clipboard_mut().peek().cloned() {
+                            editor.buffer.paste_at_cursor(&line);
                         }
                     }
                     _ => (),
@@ -49,38 +97,51 @@ pub fn handle_insert_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
                         editor.buffer.delete_char();
                     }
                     'j' | 'm' => { // New line (same as Enter)
-                        editor.buffer.insert_newline_auto_indent();
+                        editor.buffer.insert_newline(editor.config.auto_indent);
                     }
                     't' => { // Indent one shiftwidth
-                        editor.buffer.indent_line(editor.config.tab_size);
+                        editor.buffer.indent_line(editor.buffer.shift_width());
                     }
                     'd' => { // De-indent one shiftwidth
-                        editor.buffer.dedent_line(editor.config.tab_size);    
+                        editor.buffer.dedent_line(editor.buffer.shift_width());
+                    }
+                    'k' => { // Digraph: the next two characters name the one to insert
+                        editor.push_pending_key('^');
+                        editor.push_pending_key('K');
+                    }
+                    'n' | 'p' => { // Complete the word before the cursor
+                        editor.start_completion(c == 'n');
                     }
                     _ => (), 
                 }
             } else {
-                // Normal character insertion
-                match editor.mode {
-                    Mode::Insert(InsertVariant::Replace) => {
-                        editor.buffer.insert_char_replace(c);
-                    }
-                    _ => {
-                        editor.buffer.insert_char(c);
-                    }
+                // A non-word character ends the word typed so far
+                if !is_word_char(c) {
+                    editor.expand_abbreviation();
                 }
+
+                insert_typed_char(editor, c);
             }
         }
 
         // Special Keys
         KeyCode::Enter => {
-            editor.buffer.insert_newline_auto_indent();
-        }
-        KeyCode::Tab => {
-            if key.modifiers == KeyModifiers::SHIFT {
-                editor.buffer.dedent_line(editor.config.tab_size);
+            editor.expand_abbreviation();
+            editor.buffer.insert_newline(editor.config.auto_indent);
+        }
+        // Most terminals send Shift-Tab as its own key rather than Tab with Shift
+        KeyCode::Tab | KeyCode::BackTab => {
+            // Inside a snippet Tab moves between its stops; otherwise it
+            // expands one, and failing that indents
+            let forward = key.code == KeyCode::Tab && key.modifiers != KeyModifiers::SHIFT;
+            if editor.next_snippet_stop(forward) || (forward && editor.expand_snippet()) {
+                return Ok(());
+            }
+            editor.expand_abbreviation();
+            if forward {
+                editor.buffer.indent_line(editor.buffer.shift_width());
             } else {
-                editor.buffer.indent_line(editor.config.tab_size);
+                editor.buffer.dedent_line(editor.buffer.shift_width());
             }
         }
         KeyCode::Backspace => {
@@ -95,14 +156,14 @@ pub fn handle_insert_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
             if key.modifiers == KeyModifiers::CONTROL {
                 editor.buffer.move_word_backward();
             } else {
-                editor.buffer.move_cursor("left");
+                editor.move_horizontally("left", '[');
             }
         }
         KeyCode::Right => {
             if key.modifiers == KeyModifiers::CONTROL {
                 editor.buffer.move_word_forward();
             } else {
-                editor.buffer.move_cursor("right");
+                editor.move_horizontally("right", ']');
             }
         }
         KeyCode::Up => {
@@ -115,7 +176,7 @@ pub fn handle_insert_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
             if key.modifiers == KeyModifiers::CONTROL {
                 editor.buffer.move_cursor("file_start");
             } else {
-                editor.buffer.move_cursor("line_start");
+                editor.buffer.move_cursor("smart_home");
             }
         }
         KeyCode::End => {
@@ -131,38 +192,25 @@ pub fn handle_insert_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
         KeyCode::PageDown => {
             editor.buffer.move_page_down();
         }
+        KeyCode::Insert => {
+            editor.toggle_overtype();
+        }
         
         _ => (),
     }
     Ok(())
 }
 
-impl Editor {
-    /// Extend Editor with helper methods needed by insert mode
-    fn indent_line(&mut self, size: usize) {
-        let spaces = " ".repeat(size);
-        self.buffer.insert_at_cursor(&spaces);
-    }
-
-    fn dedent_line(&mut self, size: usize) {
-        let current_pos = self.buffer.get_cursor_position();
-        if let Some(line) = self.buffer.get_current_line() {
-            let whitespace_count = line.chars()
-                .take_while(|c| c.is_whitespace())
-                .count();
-            let remove_count = whitespace_count.min(size);
-            if remove_count > 0 {
-                for _ in 0..remove_count {
-                    self.buffer.delete_char();
-                }
-                // Restore cursor position if it wasn't at the start
-                if current_pos.1 > remove_count {
-                    self.buffer.set_cursor_position(current_pos.0, current_pos.1 - remove_count);
-                }
-            }
-        }
+// Normal character insertion, overwriting in replace mode or overtype
+fn insert_typed_char(editor: &mut Editor, c: char) {
+    match editor.is_overtype() {
+        true => editor.buffer.insert_char_replace(c),
+        false => editor.buffer.insert_char(c),
     }
+}
 
+impl Editor {
+    /// Extend Editor with helper methods needed by insert mode
     fn delete_word_backward(&mut self) {
         while let Some(c) = self.buffer.get_char_before_cursor() {
             if !c.is_alphanumeric() && c != '_' {
@@ -2,6 +2,7 @@
 mod command;
 mod insert;
 mod normal;
+mod terminal;
 mod visual;
 
 use std::io;
@@ -10,7 +11,80 @@ use crate::editor::Editor;
 use crate::editor::Mode;
 
 pub fn handle_input(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    let before = *editor.mode();
+    let result = dispatch(editor, key);
+    editor.record_key(key, &before);
+    result
+}
+
+/// Run the ex command `cmd` as if entered on the command line, the leading
+/// `:` optional, returning what went wrong rather than showing it
+pub fn execute_command(editor: &mut Editor, cmd: &str) -> io::Result<()> {
+    command::execute_command(editor, cmd.strip_prefix(':').unwrap_or(cmd))
+}
+
+/// `.` after an insert: run its keys again, from the command that began it
+fn repeat_last_insert(editor: &mut Editor) -> io::Result<()> {
+    let Some(keys) = editor.last_insert() else {
+        return Ok(());
+    };
+    for key in keys {
+        dispatch(editor, key)?;
+    }
+    Ok(())
+}
+
+/// Text the terminal pasted in one piece (bracketed paste). It goes in as
+/// it is, without auto-indent or abbreviations, and undoes in one step.
+/// Only insert and command mode take text; elsewhere a paste is ignored
+/// rather than run as keys.
+pub fn handle_paste(editor: &mut Editor, text: &str) -> io::Result<()> {
+    if let Mode::Command(_) = editor.mode() {
+        editor.paste_into_command(text);
+        return Ok(());
+    }
+    if let Some(terminal) = editor.focused_terminal() {
+        if let Err(e) = terminal.write().paste(text) {
+            editor.show_error(&format!("Cannot write to terminal: {}", e));
+        }
+        return Ok(());
+    }
+    if let Mode::Insert(_) = editor.mode() {
+        editor.buffer.paste_at_cursor(text);
+    }
+    Ok(())
+}
+
+fn dispatch(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    // A `:s///c` takes every key until it has its answers
+    if editor.is_confirming_substitute() {
+        return command::handle_substitute_confirm(editor, key);
+    }
+    if editor.is_confirming_quit() {
+        return command::handle_quit_confirm(editor, key);
+    }
+    if editor.is_confirming_reload() {
+        return command::handle_reload_confirm(editor, key);
+    }
+    if editor.is_browsing_clipboard() {
+        return command::handle_clipboard_browser(editor, key);
+    }
+    if editor.is_showing_help() {
+        return command::handle_help_view(editor, key);
+    }
+    // A focused terminal window takes keys, except on the command line
+    if let Some(terminal) = editor.focused_terminal() {
+        if !matches!(editor.mode(), Mode::Command(_)) {
+            return terminal::handle_terminal_window(editor, &mut terminal.write(), key);
+        }
+    }
     match editor.mode() {
+        // Large files are view-only: anything but moving around is refused
+        Mode::Normal if editor.is_large_file() && !normal::is_view_key(editor, key) => {
+            editor.clear_pending_keys();
+            editor.show_error("Large files are opened read-only");
+            Ok(())
+        }
         Mode::Normal => normal::handle_normal_mode(editor, key),
         Mode::Insert(_) => insert::handle_insert_mode(editor, key),
         Mode::Visual(_) => visual::handle_visual_mode(editor, key),
@@ -4,6 +4,7 @@ use crossterm::event::{KeyEvent, KeyCode, KeyModifiers};
 use crate::editor::Editor;
 use crate::editor::mode::{Mode, ModeTrigger, InsertVariant, CommandType};
 use crate::editor::buffer::{Buffer, VisualMode};
+use crate::editor::which_key;
 use crate::input::global_handlers::GlobalKeyHandler;
 
 pub fn handle_normal_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
@@ -12,8 +13,62 @@ pub fn handle_normal_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
         return Ok(());
     }
 
+    // Keys after the leader pick one of the config's mappings; anything
+    // else gives up on it
+    if editor.leader_keys().is_some() {
+        match key.code {
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                if let Some(command) = editor.push_leader_key(c) {
+                    super::command::run_command(editor, &command);
+                }
+            }
+            _ => editor.cancel_leader(),
+        }
+        return Ok(());
+    }
+    if let KeyCode::Char(c) = key.code {
+        if Some(c) == editor.config.leader_key()
+            && editor.pending_keys().is_empty()
+            && !editor.config.leader_mappings.is_empty()
+        {
+            editor.start_leader();
+            return Ok(());
+        }
+    }
+
+    // Count prefix: a leading 0 is the line-start motion, not a count
+    if let KeyCode::Char(c @ '0'..='9') = key.code {
+        if key.modifiers == KeyModifiers::NONE
+            && editor.pending_operator().is_none()
+            && (c != '0' || editor.pending_count().is_some())
+        {
+            editor.push_pending_key(c);
+            return Ok(());
+        }
+    }
+
+    // Second key of a multi-key command
+    if let Some(prefix) = editor.pending_operator() {
+        let count = editor.pending_count();
+        editor.clear_pending_keys();
+        if let KeyCode::Char(c) = key.code {
+            if let Some(binding) = which_key::find(prefix, c) {
+                if !binding.edits || editor.mode.allows_deletion() {
+                    (binding.run)(editor, count)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
     // handle normal-mode specific calls
     match key.code {
+        // Add to or subtract from the number under the cursor
+        KeyCode::Char(c @ ('a' | 'x')) if key.modifiers == KeyModifiers::CONTROL && editor.mode.allows_deletion() => {
+            let count = editor.pending_count().unwrap_or(1) as i64;
+            editor.buffer.modify_number_at_cursor(if c == 'a' { count } else { -count });
+        }
+
         // Mode transitions
         KeyCode::Char('i') => {
             editor.set_mode(editor.mode.transition(ModeTrigger::InsertNormal));
@@ -30,23 +85,31 @@ pub fn handle_normal_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
             editor.buffer.prepare_insert_start_of_line();
             editor.set_mode(editor.mode.transition(ModeTrigger::InsertLineStart));
         }
-        KeyCode::Char('o') => {
-            editor.buffer.insert_line_below();
-            editor.set_mode(editor.mode.transition(ModeTrigger::InsertLineBelow));
+        // Jump list
+        KeyCode::Char('o') if key.modifiers == KeyModifiers::CONTROL => {
+            if let Err(e) = editor.jump_back() {
+                editor.show_error(&e.to_string());
+            }
+        }
+        KeyCode::Tab => {
+            // Ctrl-i arrives as Tab in most terminals
+            editor.buffer.jump_forward();
         }
-        KeyCode::Char('O') => {
-            editor.buffer.insert_line_above();
-            editor.set_mode(editor.mode.transition(ModeTrigger::InsertLineAbove));
+        KeyCode::BackTab if editor.mode.allows_deletion() => {
+            editor.buffer.dedent_line(editor.buffer.shift_width());
         }
+        KeyCode::Char('o') => editor.open_line(true),
+        KeyCode::Char('O') => editor.open_line(false),
         KeyCode::Char('R') => {
             editor.set_mode(editor.mode.transition(ModeTrigger::InsertReplace));
         }
+        KeyCode::Char('v') if key.modifiers == KeyModifiers::CONTROL => {
+            editor.set_mode(editor.mode.transition(ModeTrigger::VisualBlock));
+        }
         KeyCode::Char('v') => {
-            editor.buffer.start_visual();
             editor.set_mode(editor.mode.transition(ModeTrigger::VisualChar));
         }
         KeyCode::Char('V') => {
-            editor.buffer.start_visual();
             editor.set_mode(editor.mode.transition(ModeTrigger::VisualLine));
         }
         KeyCode::Char(':') => {
@@ -58,6 +121,25 @@ pub fn handle_normal_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
         KeyCode::Char('?') => {
             editor.set_mode(editor.mode.transition(ModeTrigger::SearchBackward));
         }
+        KeyCode::Char(c @ ('n' | 'N')) => {
+            if editor.buffer.repeat_search(c == 'N') {
+                editor.buffer.scroll_to_cursor();
+            } else {
+                editor.show_error("No previous search pattern");
+            }
+        }
+
+        // Half-page scrolling
+        KeyCode::Char('d') if key.modifiers == KeyModifiers::CONTROL => editor.buffer.scroll_half_page(true),
+        KeyCode::Char('u') if key.modifiers == KeyModifiers::CONTROL => editor.buffer.scroll_half_page(false),
+
+        // Scroll by lines, the cursor staying on its line where it can
+        KeyCode::Char('e') if key.modifiers == KeyModifiers::CONTROL => {
+            editor.buffer.scroll_lines(editor.pending_count().unwrap_or(1), true);
+        }
+        KeyCode::Char('y') if key.modifiers == KeyModifiers::CONTROL => {
+            editor.buffer.scroll_lines(editor.pending_count().unwrap_or(1), false);
+        }
 
         // Undo/Redo
         KeyCode::Char('u') if editor.mode.allows_undo() => {
@@ -67,43 +149,138 @@ pub fn handle_normal_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
             editor.buffer.redo();
         }
 
+        // Window commands
+        KeyCode::Char('w') if key.modifiers == KeyModifiers::CONTROL => {
+            editor.push_pending_key('^');
+            editor.push_pending_key('W');
+            return Ok(());
+        }
+
+        // Name, length and position of the file
+        KeyCode::Char('g') if key.modifiers == KeyModifiers::CONTROL => editor.show_file_info(),
+
+        // Repaint a garbled screen
+        KeyCode::Char('l') if key.modifiers == KeyModifiers::CONTROL => editor.request_redraw(),
+
         // Movement keys (Vim style)
-        KeyCode::Char('h') => editor.buffer.move_cursor("left"),
+        KeyCode::Char('h') => editor.move_horizontally("left", 'h'),
         KeyCode::Char('j') => editor.buffer.move_cursor("down"),
         KeyCode::Char('k') => editor.buffer.move_cursor("up"),
-        KeyCode::Char('l') => editor.buffer.move_cursor("right"),
-        KeyCode::Char('0') | KeyCode::Char('^') => editor.buffer.move_cursor("line_start"),
+        KeyCode::Char('l') => editor.move_horizontally("right", 'l'),
+        KeyCode::Char('w') => editor.buffer.move_word_forward(),
+        KeyCode::Char('b') => editor.buffer.move_word_backward(),
+        KeyCode::Char('e') => editor.buffer.move_word_end(),
+        KeyCode::Char('W') => editor.buffer.move_big_word_forward(),
+        KeyCode::Char('B') => editor.buffer.move_big_word_backward(),
+        KeyCode::Char('E') => editor.buffer.move_big_word_end(),
+        KeyCode::Char('0') => editor.buffer.move_cursor("line_start"),
+        KeyCode::Char('^') => editor.buffer.move_cursor("first_non_blank"),
         KeyCode::Char('$') => editor.buffer.move_cursor("line_end"),
-        KeyCode::Char('g') if key.modifiers == KeyModifiers::NONE => editor.buffer.move_cursor("top"),
+        KeyCode::Char('|') => editor.buffer.move_to_column(editor.pending_count().unwrap_or(1)),
+        KeyCode::Char('g') if key.modifiers == KeyModifiers::NONE => {
+            editor.push_pending_key('g');
+            return Ok(());
+        }
+        KeyCode::Char(c @ (']' | '[' | 'z' | 'Z')) => {
+            editor.push_pending_key(c);
+            return Ok(());
+        }
         KeyCode::Char('G') => editor.buffer.move_cursor("bottom"),
+        KeyCode::Char('}') => editor.buffer.move_paragraph_forward(editor.pending_count().unwrap_or(1)),
+        KeyCode::Char('{') => editor.buffer.move_paragraph_backward(editor.pending_count().unwrap_or(1)),
+        KeyCode::Char(')') => editor.buffer.move_sentence_forward(editor.pending_count().unwrap_or(1)),
+        KeyCode::Char('(') => editor.buffer.move_sentence_backward(editor.pending_count().unwrap_or(1)),
+        KeyCode::Char('%') => {
+            if let Some(count) = editor.pending_count() {
+                editor.buffer.move_to_percentage(count);
+            }
+        }
 
         // Movement keys (Modern)
-        KeyCode::Left => editor.buffer.move_cursor("left"),
-        KeyCode::Right => editor.buffer.move_cursor("right"),
+        KeyCode::Left => editor.move_horizontally("left", '<'),
+        KeyCode::Right => editor.move_horizontally("right", '>'),
         KeyCode::Up => editor.buffer.move_cursor("up"),
         KeyCode::Down => editor.buffer.move_cursor("down"),
-        KeyCode::Home => editor.buffer.move_cursor("line_start"),
+        KeyCode::Home => editor.buffer.move_cursor("smart_home"),
         KeyCode::End => editor.buffer.move_cursor("line_end"),
         KeyCode::PageUp => editor.buffer.move_page_up(),
         KeyCode::PageDown => editor.buffer.move_page_down(),
 
         // Clipboard operations
         KeyCode::Char('y') if key.modifiers == (KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
-            editor.buffer.yank()
+            editor.yank_line()
         },
         KeyCode::Char('p') if key.modifiers == (KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
             editor.buffer.paste()
         },
+        KeyCode::Char('x') if editor.mode.allows_deletion() => {
+            editor.buffer.cut_chars(editor.pending_count().unwrap_or(1));
+        }
+        KeyCode::Char('s') if editor.mode.allows_deletion() => {
+            let count = editor.pending_count().unwrap_or(1);
+            editor.change_and_insert(ModeTrigger::InsertNormal, |buffer| buffer.cut_chars(count));
+        }
+        KeyCode::Char('S') if editor.mode.allows_deletion() => {
+            editor.change_and_insert(ModeTrigger::InsertNormal, Buffer::clear_line_to_indent);
+        }
+        KeyCode::Char('D') if editor.mode.allows_deletion() => {
+            editor.buffer.delete_to_line_end();
+        }
+        KeyCode::Char('C') if editor.mode.allows_deletion() => {
+            editor.change_and_insert(ModeTrigger::InsertAppendEnd, |buffer| {
+                buffer.delete_to_line_end();
+                buffer.prepare_append_end_of_line();
+            });
+        }
         KeyCode::Delete if editor.mode.allows_deletion() => {
             editor.buffer.delete_char_forward();
         },
         KeyCode::Char('d') if editor.mode.allows_deletion() => {
-            editor.buffer.delete_line();
+            editor.push_pending_key('d');
+            return Ok(());
         },
-        KeyCode::Char('y') => editor.buffer.yank(),
-        KeyCode::Char('p') => editor.buffer.paste(),
+        KeyCode::Char('c') if editor.mode.allows_deletion() => {
+            editor.push_pending_key('c');
+            return Ok(());
+        }
+        KeyCode::Char('y') => {
+            editor.push_pending_key('y');
+            return Ok(());
+        }
+        KeyCode::Char('Y') => editor.yank_line(),
+        KeyCode::Char('p') => editor.buffer.paste_times(editor.pending_count().unwrap_or(1)),
+        KeyCode::Char('.') => {
+            editor.clear_pending_keys();
+            super::repeat_last_insert(editor)?;
+        }
+        KeyCode::Char('J') if editor.mode.allows_deletion() => {
+            editor.buffer.join_lines(editor.pending_count().unwrap_or(2), true);
+        }
 
         _ => {}
     }
+
+    // Any completed command consumes the count typed before it
+    editor.clear_pending_keys();
     Ok(())
-}
\ No newline at end of file
+}
+
+// Keys that only move the cursor or open the command line, and so are safe
+// on a buffer that can't be edited
+pub fn is_view_key(editor: &Editor, key: KeyEvent) -> bool {
+    match editor.pending_operator() {
+        Some('g') => return matches!(key.code, KeyCode::Char('g' | 'f')),
+        Some('W') => return true,
+        _ => {}
+    }
+    match key.code {
+        KeyCode::Char('o' | 'l' | 'w' | 'd' | 'u' | 'g' | 'e' | 'y') if key.modifiers == KeyModifiers::CONTROL => true,
+        KeyCode::Char('o') => false,
+        KeyCode::Char(c) => (key.modifiers - KeyModifiers::SHIFT).is_empty()
+            && matches!(c, 'h' | 'j' | 'k' | 'l' | 'w' | 'b' | 'e' | 'W' | 'B' | 'E' | '{' | '}' | '(' | ')' | '0'..='9' | '^' | '$' | '|' | 'g' | 'G' | '%' | ':'),
+        KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down
+        | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown
+        | KeyCode::Tab => true,
+        _ => false,
+    }
+}
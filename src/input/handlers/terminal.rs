@@ -0,0 +1,94 @@
+// src/input/handlers/terminal.rs
+use std::io;
+use crossterm::event::{KeyEvent, KeyCode, KeyModifiers};
+use crate::editor::{Clipboard, Editor};
+use crate::editor::mode::ModeTrigger;
+use crate::ui::{SplitDirection, Terminal};
+
+// Lines PageUp and PageDown move by in copy mode
+const COPY_PAGE: isize = 10;
+
+pub fn handle_terminal_window(editor: &mut Editor, terminal: &mut Terminal, key: KeyEvent) -> io::Result<()> {
+    if terminal.copy_mode().is_some() {
+        return handle_copy_mode(editor, terminal, key);
+    }
+
+    // Ctrl-W commands work here as in a buffer window, plus N for copy mode.
+    // Every other key goes to the shell.
+    if editor.pending_operator() == Some('W') {
+        editor.clear_pending_keys();
+        match key.code {
+            KeyCode::Char('N') => terminal.enter_copy_mode(),
+            // Ctrl-W . sends the shell a Ctrl-W of its own
+            KeyCode::Char('.') => {
+                let sent = terminal.send_key(KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL));
+                report_write_error(editor, sent);
+            }
+            KeyCode::Char(':') => {
+                editor.set_mode(editor.mode.transition(ModeTrigger::CommandMode));
+            }
+            KeyCode::Char('w') => editor.focus_next_window(),
+            KeyCode::Char('s') => editor.split_window(SplitDirection::Horizontal)?,
+            KeyCode::Char('v') => editor.split_window(SplitDirection::Vertical)?,
+            KeyCode::Char('t') => {
+                if let Err(e) = editor.toggle_terminal() {
+                    editor.show_error(&e.to_string());
+                }
+            }
+            KeyCode::Char('c') => {
+                if let Err(e) = editor.close_window() {
+                    editor.show_error(&e.to_string());
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+    let sent = match key.code {
+        KeyCode::Char('w') if key.modifiers == KeyModifiers::CONTROL => {
+            editor.push_pending_key('^');
+            editor.push_pending_key('W');
+            Ok(())
+        }
+        KeyCode::Char('V' | 'v') if key.modifiers == (KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
+            match editor.buffer.clipboard().and_then(Clipboard::peek) {
+                Some(text) => terminal.paste(text),
+                None => Ok(()),
+            }
+        }
+        _ => terminal.send_key(key),
+    };
+    report_write_error(editor, sent);
+    Ok(())
+}
+
+// The shell may have exited
+fn report_write_error(editor: &mut Editor, sent: io::Result<()>) {
+    if let Err(e) = sent {
+        editor.show_error(&format!("Cannot write to terminal: {}", e));
+    }
+}
+
+// Moving over the scrollback and yanking lines of it
+fn handle_copy_mode(editor: &mut Editor, terminal: &mut Terminal, key: KeyEvent) -> io::Result<()> {
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down => terminal.copy_move(1),
+        KeyCode::Char('k') | KeyCode::Up => terminal.copy_move(-1),
+        KeyCode::PageDown => terminal.copy_move(COPY_PAGE),
+        KeyCode::PageUp => terminal.copy_move(-COPY_PAGE),
+        KeyCode::Char('g') => terminal.copy_move(isize::MIN),
+        KeyCode::Char('G') => terminal.copy_move(isize::MAX),
+        KeyCode::Char('v') | KeyCode::Char('V') => terminal.toggle_selection(),
+        KeyCode::Char('y') => {
+            if let Some(text) = terminal.yank_selection() {
+                editor.buffer.clipboard_mut().yank(text);
+            }
+        }
+        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('i') => terminal.exit_copy_mode(),
+        KeyCode::Char(':') => {
+            editor.set_mode(editor.mode.transition(ModeTrigger::CommandMode));
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -6,6 +6,31 @@ use crate::editor::mode::{Mode, ModeTrigger, InsertVariant, CommandType};
 use crate::editor::buffer::{SelectionType, VisualMode};
 
 pub fn handle_visual_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()> {
+    // Count prefix: a leading 0 is the line-start motion, not a count
+    if let KeyCode::Char(c @ '0'..='9') = key.code {
+        if key.modifiers == KeyModifiers::NONE && (c != '0' || editor.pending_count().is_some()) {
+            editor.push_pending_key(c);
+            return Ok(());
+        }
+    }
+    let count = editor.pending_count().unwrap_or(1);
+    let prefix = editor.pending_operator();
+    let count_keys: String = editor.pending_keys().chars().take_while(char::is_ascii_digit).collect();
+    editor.clear_pending_keys();
+
+    // Second key after g: g again goes to the top, Ctrl-A and Ctrl-X turn
+    // the numbers selected into a sequence
+    if prefix == Some('g') {
+        match key.code {
+            KeyCode::Char('g') => editor.buffer.move_cursor("top"),
+            KeyCode::Char(c @ ('a' | 'x')) if key.modifiers == KeyModifiers::CONTROL => {
+                increment_selection(editor, if c == 'a' { count as i64 } else { -(count as i64) }, true);
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
     match key.code {
         // Mode transitions
         KeyCode::Esc => {
@@ -14,54 +39,44 @@ pub fn handle_visual_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
         }
 
         // Visual mode operations
+        KeyCode::Char(c @ ('a' | 'x')) if key.modifiers == KeyModifiers::CONTROL => {
+            increment_selection(editor, if c == 'a' { count as i64 } else { -(count as i64) }, false);
+        }
         KeyCode::Char('y') => {
             // Yank selection and return to normal mode
-            if let Some(text) = editor.buffer.get_selected_text() {
-                editor.clipboard.yank(text);
-            }
+            editor.yank_selection();
             editor.buffer.clear_visual();
             editor.set_mode(Mode::Normal);
         }
         KeyCode::Char('d') | KeyCode::Char('x') => {
             // Delete/cut selection and return to normal mode
-            if let Some(text) = editor.buffer.get_selected_text() {
-                editor.clipboard.yank(text); // Save to clipboard before deleting
-                editor.buffer.delete_selection();
-            }
+            editor.cut_selection();
             editor.buffer.clear_visual();
             editor.set_mode(Mode::Normal);
         }
         KeyCode::Char('c') => {
             // Change selection (delete and enter insert mode)
-            if let Some(text) = editor.buffer.get_selected_text() {
-                editor.clipboard.yank(text);
-                editor.buffer.delete_selection();
-            }
+            editor.cut_selection();
             editor.buffer.clear_visual();
             editor.set_mode(Mode::Insert(InsertVariant::Insert));
         }
         KeyCode::Char('>') => {
             // Indent selection
-            editor.buffer.indent_selection(editor.config.tab_size);
+            editor.buffer.indent_selection(editor.buffer.shift_width(), count);
         }
         KeyCode::Char('<') => {
             // De-indent selection
-            editor.buffer.dedent_selection(editor.config.tab_size);
+            editor.buffer.dedent_selection(editor.buffer.shift_width(), count);
         }
 
         // Modern clipboard operations
         KeyCode::Char('c') if key.modifiers == (KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
-            if let Some(text) = editor.buffer.get_selected_text() {
-                editor.clipboard.yank(text);
-            }
+            editor.yank_selection();
             editor.buffer.clear_visual();
             editor.set_mode(Mode::Normal);
         }
         KeyCode::Char('x') if key.modifiers == (KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
-            if let Some(text) = editor.buffer.get_selected_text() {
-                editor.clipboard.yank(text);
-                editor.buffer.delete_selection();
-            }
+            editor.cut_selection();
             editor.buffer.clear_visual();
             editor.set_mode(Mode::Normal);
         }
@@ -72,27 +87,48 @@ pub fn handle_visual_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
         }
 
         // Movement keys (Vim style)
-        KeyCode::Char('h') => editor.buffer.move_cursor("left"),
+        KeyCode::Char('h') => editor.move_horizontally("left", 'h'),
         KeyCode::Char('j') => editor.buffer.move_cursor("down"),
         KeyCode::Char('k') => editor.buffer.move_cursor("up"),
-        KeyCode::Char('l') => editor.buffer.move_cursor("right"),
+        KeyCode::Char('l') => editor.move_horizontally("right", 'l'),
         KeyCode::Char('w') => editor.buffer.move_word_forward(),
         KeyCode::Char('b') => editor.buffer.move_word_backward(),
-        KeyCode::Char('0') | KeyCode::Char('^') => editor.buffer.move_cursor("line_start"),
+        KeyCode::Char('e') => editor.buffer.move_word_end(),
+        KeyCode::Char('W') => editor.buffer.move_big_word_forward(),
+        KeyCode::Char('B') => editor.buffer.move_big_word_backward(),
+        KeyCode::Char('E') => editor.buffer.move_big_word_end(),
+        KeyCode::Char('}') => editor.buffer.move_paragraph_forward(1),
+        KeyCode::Char('{') => editor.buffer.move_paragraph_backward(1),
+        KeyCode::Char(')') => editor.buffer.move_sentence_forward(1),
+        KeyCode::Char('(') => editor.buffer.move_sentence_backward(1),
+        KeyCode::Char('0') => editor.buffer.move_cursor("line_start"),
+        KeyCode::Char('^') => editor.buffer.move_cursor("first_non_blank"),
         KeyCode::Char('$') => editor.buffer.move_cursor("line_end"),
-        KeyCode::Char('g') if key.modifiers == KeyModifiers::NONE => editor.buffer.move_cursor("top"),
+        KeyCode::Char('|') => editor.buffer.move_to_column(count),
+        KeyCode::Char('g') if key.modifiers == KeyModifiers::NONE => {
+            for c in count_keys.chars().chain(['g']) {
+                editor.push_pending_key(c);
+            }
+        }
         KeyCode::Char('G') => editor.buffer.move_cursor("bottom"),
         
         // Movement keys (Modern)
-        KeyCode::Left => editor.buffer.move_cursor("left"),
-        KeyCode::Right => editor.buffer.move_cursor("right"),
+        KeyCode::Left => editor.move_horizontally("left", '<'),
+        KeyCode::Right => editor.move_horizontally("right", '>'),
         KeyCode::Up => editor.buffer.move_cursor("up"),
         KeyCode::Down => editor.buffer.move_cursor("down"),
-        KeyCode::Home => editor.buffer.move_cursor("line_start"),
+        KeyCode::Home => editor.buffer.move_cursor("smart_home"),
         KeyCode::End => editor.buffer.move_cursor("line_end"),
         KeyCode::PageUp => editor.buffer.move_page_up(),
         KeyCode::PageDown => editor.buffer.move_page_down(),
 
+        // Ex command over the selected lines
+        KeyCode::Char(':') => {
+            editor.buffer.clear_visual();
+            editor.set_mode(Mode::Command(CommandType::Regular));
+            editor.set_command_line("'<,'>");
+        }
+
         // Search within selection
         KeyCode::Char('/') => {
             // Store the current selection bounds before entering search mode
@@ -100,15 +136,16 @@ pub fn handle_visual_mode(editor: &mut Editor, key: KeyEvent) -> io::Result<()>
             editor.set_mode(Mode::Command(CommandType::Search));
         }
 
-        // Switch visual mode type (char, line, block)
+        // Switch visual mode type (char, line, block); the current one's key
+        // goes back to normal mode
         KeyCode::Char('v') if key.modifiers == KeyModifiers::NONE => {
-            editor.buffer.toggle_visual_mode(VisualMode::Char);
+            switch_visual_mode(editor, VisualMode::Char, ModeTrigger::VisualChar);
         }
         KeyCode::Char('V') => {
-            editor.buffer.toggle_visual_mode(VisualMode::Line);
+            switch_visual_mode(editor, VisualMode::Line, ModeTrigger::VisualLine);
         }
         KeyCode::Char('v') if key.modifiers == KeyModifiers::CONTROL => {
-            editor.buffer.toggle_visual_mode(VisualMode::Block);
+            switch_visual_mode(editor, VisualMode::Block, ModeTrigger::VisualBlock);
         }
 
         // Text object selection
@@ -140,4 +177,18 @@ fn handle_text_object(editor: &mut Editor, c: char, selection_type: SelectionTyp
         '`' => editor.buffer.select_paired_chars('`', '`', selection_type),
         _ => {}
     }
-}
\ No newline at end of file
+}
+
+// Ctrl-A and Ctrl-X over the selection, which ends it
+fn increment_selection(editor: &mut Editor, delta: i64, progressive: bool) {
+    editor.buffer.increment_selection(delta, progressive);
+    editor.buffer.clear_visual();
+    editor.set_mode(Mode::Normal);
+}
+
+fn switch_visual_mode(editor: &mut Editor, mode: VisualMode, trigger: ModeTrigger) {
+    match editor.buffer.visual_mode() == Some(mode) {
+        true => editor.set_mode(Mode::Normal),
+        false => editor.set_mode(editor.mode.transition(trigger)),
+    }
+}
@@ -1,5 +1,7 @@
 // src/input/mod.rs
 mod handlers;
 mod global_handlers;
+mod script;
 
-pub use handlers::handle_input;
\ No newline at end of file
+pub use handlers::{execute_command, handle_input, handle_paste};
+pub use script::send_keys;
\ No newline at end of file
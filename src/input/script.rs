@@ -0,0 +1,264 @@
+// src/input/script.rs
+use std::io;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::editor::Editor;
+use super::handlers::handle_input;
+
+/// Keys written the way vim writes them: characters stand for themselves
+/// and `<...>` names the rest, as in `ihello<Esc>`, `<C-r>` or `<S-Tab>`.
+/// `<lt>` is a literal `<`, as is one that doesn't start a key's name.
+pub fn parse_keys(keys: &str) -> Vec<KeyEvent> {
+    let mut events = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        let named = match c {
+            '<' => rest[1..].split_once('>').and_then(|(name, _)| Some((name, parse_key_name(name)?))),
+            _ => None,
+        };
+        match named {
+            Some((name, key)) => {
+                events.push(key);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                events.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    events
+}
+
+// The key `<name>` stands for, if it's one
+fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut key = name;
+    // `C-`, `S-` and `A-` (or `M-`) in front, in any order; the last part
+    // is the key itself, which may be `-`
+    while let Some((prefix, rest)) = key.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+        modifiers |= match prefix.to_ascii_lowercase().as_str() {
+            "c" => KeyModifiers::CONTROL,
+            "s" => KeyModifiers::SHIFT,
+            "a" | "m" => KeyModifiers::ALT,
+            _ => return None,
+        };
+        key = rest;
+    }
+
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if !modifiers.is_empty() => KeyCode::Char(c),
+        (Some(_), None) | (None, _) => return None,
+        _ => match key.to_ascii_lowercase().as_str() {
+            "esc" => KeyCode::Esc,
+            "cr" | "enter" | "return" => KeyCode::Enter,
+            "bs" | "backspace" => KeyCode::Backspace,
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "del" | "delete" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            function => KeyCode::F(function.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+/// Type `keys`, given as parse_keys reads them, as if at the keyboard.
+/// Lets tests and scripts drive the editor without a terminal.
+pub fn send_keys(editor: &mut Editor, keys: &str) -> io::Result<()> {
+    for key in parse_keys(keys) {
+        handle_input(editor, key)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EditorConfig;
+    use crate::editor::Mode;
+    use crate::input::{execute_command, handle_paste};
+
+    #[test]
+    fn test_parse_keys() {
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        assert_eq!(parse_keys("a<Esc>"), [
+            key(KeyCode::Char('a'), KeyModifiers::NONE),
+            key(KeyCode::Esc, KeyModifiers::NONE),
+        ]);
+        assert_eq!(parse_keys("<C-r><S-Tab><F5>"), [
+            key(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            key(KeyCode::BackTab, KeyModifiers::SHIFT),
+            key(KeyCode::F(5), KeyModifiers::NONE),
+        ]);
+        assert_eq!(parse_keys("<C-->"), [key(KeyCode::Char('-'), KeyModifiers::CONTROL)]);
+        // A `<` that doesn't begin a key's name is just typed
+        let typed = |text: &str| text.chars()
+            .map(|c| key(KeyCode::Char(c), KeyModifiers::NONE))
+            .collect::<Vec<_>>();
+        assert_eq!(parse_keys("<lt>b"), typed("<b"));
+        assert_eq!(parse_keys("a<b>c<"), typed("a<b>c<"));
+        assert_eq!(parse_keys("<Nope>"), typed("<Nope>"));
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let mut editor = Editor::new(EditorConfig::default());
+        send_keys(&mut editor, "ione two<CR>three<Esc>").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["one two", "three"]);
+        assert_eq!(*editor.mode(), Mode::Normal);
+        assert_eq!(editor.cursor_position(), (1, 4));
+
+        send_keys(&mut editor, "ggdd").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["three"]);
+        send_keys(&mut editor, "u").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["one two", "three"]);
+
+        execute_command(&mut editor, ":%s/o/0/g").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["0ne tw0", "three"]);
+        assert!(execute_command(&mut editor, "1,2help").is_err());
+
+        // :normal ends an insert its keys leave open
+        execute_command(&mut editor, "normal Ax<Esc>jAy").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["0ne tw0x", "threey"]);
+        assert_eq!(*editor.mode(), Mode::Normal);
+    }
+
+    #[test]
+    fn test_visual_yank_and_paste() {
+        let mut editor = Editor::new(EditorConfig::default());
+        send_keys(&mut editor, "ione<CR>two<Esc>ggVyjp").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["one", "two", "one"]);
+
+        // A cut goes to the same clipboard p reads
+        send_keys(&mut editor, "ggVdp").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["two", "one", "one"]);
+    }
+
+    #[test]
+    fn test_sort_and_addresses() {
+        let mut editor = Editor::new(EditorConfig::default());
+        send_keys(&mut editor, "ic<CR>a<CR>d<CR>b<Esc>").unwrap();
+        // With no range, :sort takes every line rather than the cursor's
+        execute_command(&mut editor, "sort").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["a", "b", "c", "d"]);
+        execute_command(&mut editor, "2,3d").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["a", "d"]);
+
+        // Offsets past the end of usize are out of range, not an overflow
+        assert!(execute_command(&mut editor, &format!("{}+5", usize::MAX)).is_err());
+        assert!(execute_command(&mut editor, &format!("1+{}", usize::MAX)).is_err());
+        assert!(execute_command(&mut editor, "99999999999999999999+1").is_err());
+        assert_eq!(editor.buffer.get_content(), &["a", "d"]);
+    }
+
+    #[test]
+    fn test_undo_paste_at_end() {
+        let mut editor = Editor::new(EditorConfig::default());
+        send_keys(&mut editor, "ione<CR>two<CR>three<Esc>yyGpu").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["one", "two", "three"]);
+        assert_eq!(editor.cursor_position().0, 2);
+        send_keys(&mut editor, "dd").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["one", "two"]);
+
+        send_keys(&mut editor, "yyGpu<C-v>d").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["one", "two"]);
+    }
+
+    #[test]
+    fn test_abbreviation_on_escape() {
+        let mut editor = Editor::new(EditorConfig::default());
+        execute_command(&mut editor, "iabbrev teh the").unwrap();
+        execute_command(&mut editor, "iabbrev te XX").unwrap();
+        send_keys(&mut editor, "ifix teh<Esc>").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["fix the"]);
+        assert_eq!(editor.cursor_position(), (0, 6));
+
+        send_keys(&mut editor, "oteh<Esc>ote<Esc>").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["fix the", "the", "XX"]);
+    }
+
+    #[test]
+    fn test_visual_increment() {
+        let mut editor = Editor::new(EditorConfig::default());
+        send_keys(&mut editor, "i1<CR>1<CR>1<Esc>ggVG<C-a>").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["2", "2", "2"]);
+        send_keys(&mut editor, "ggVGg<C-a>").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["3", "4", "5"]);
+        send_keys(&mut editor, "gg<C-v>jj$g<C-x>").unwrap();
+        assert_eq!(editor.buffer.get_content(), &["2", "2", "2"]);
+    }
+
+    #[test]
+    fn test_change_undoes_as_one() {
+        for keys in ["Sfoo<Esc>u", "3sX<Esc>u", "ccbar<Esc>u", "wCfoo<Esc>u"] {
+            let mut editor = Editor::new(EditorConfig::default());
+            editor.buffer.set_content(vec!["hello world".to_string()]);
+            send_keys(&mut editor, keys).unwrap();
+            assert_eq!(editor.buffer.get_content(), &["hello world"], "{}", keys);
+        }
+    }
+
+    #[test]
+    fn test_long_changes_undo_as_one() {
+        // Each is more than the default undolevels of separate records
+        let mut editor = Editor::new(EditorConfig::default());
+        send_keys(&mut editor, &format!("i{}<Esc>u", "x".repeat(1500))).unwrap();
+        assert_eq!(editor.buffer.get_content(), &[""]);
+
+        let lines = vec!["line"; 1200].join("\n");
+        send_keys(&mut editor, "i").unwrap();
+        handle_paste(&mut editor, &lines).unwrap();
+        send_keys(&mut editor, "<Esc>u").unwrap();
+        assert_eq!(editor.buffer.get_content(), &[""]);
+
+        let lines = vec!["a".to_string(); 3000];
+        editor.buffer.set_content(lines.clone());
+        send_keys(&mut editor, "2000Ju").unwrap();
+        assert_eq!(editor.buffer.get_content(), &lines);
+    }
+
+    #[test]
+    fn test_forced_write() {
+        let path = std::env::temp_dir().join(format!("zorforge_forced_write_{}.txt", std::process::id()));
+        std::fs::write(&path, "text").unwrap();
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.open_file(&path).unwrap();
+        editor.set_readonly(true);
+        send_keys(&mut editor, "x").unwrap();
+
+        assert!(execute_command(&mut editor, "w").is_err());
+        execute_command(&mut editor, "w!").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ext");
+        assert!(!editor.is_readonly());
+
+        editor.set_readonly(true);
+        send_keys(&mut editor, "x").unwrap();
+        execute_command(&mut editor, "wq!").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xt");
+        assert!(editor.should_quit());
+
+        // :x!, :wa! and :xa! write it all the same too
+        for (keys, cmd, text) in [("x", "x", "t"), ("Ax<Esc>", "wa", "tx"), ("Ay<Esc>", "xa", "txy")] {
+            let mut editor = Editor::new(EditorConfig::default());
+            editor.open_file(&path).unwrap();
+            editor.set_readonly(true);
+            send_keys(&mut editor, keys).unwrap();
+            assert!(execute_command(&mut editor, cmd).is_err());
+            execute_command(&mut editor, &format!("{}!", cmd)).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+            assert_eq!(editor.should_quit(), cmd != "wa");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,16 @@
 // src/main.rs
 use std::{
-    io::{self, stdout},
-    path::PathBuf,
-    time::Duration,
+    io::{self, stdout, IsTerminal},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use crossterm::{
-    event::{self, Event, KeyEvent},
+    event::{self, DisableBracketedPaste, DisableMouseCapture, Event, KeyEvent},
     terminal::{enable_raw_mode, disable_raw_mode},
     ExecutableCommand,
 };
 use clap::Parser;
+use signal_hook::{consts::{SIGHUP, SIGINT, SIGTERM}, iterator::Signals};
 
 mod editor;
 mod ui;
@@ -19,17 +20,19 @@ mod utils;
 mod splash;
 mod cli;
 
-use editor::{Editor, Mode};
+use editor::{positions::CursorPositions, recent::RecentFiles, snippet::Snippets, Editor, Mode};
 use ui::Renderer;
-use input::handle_input;
-use config::EditorConfig;
+use input::{execute_command, handle_input, handle_paste};
+use config::{ColorSupport, EditorConfig};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// File to open
+    /// Files to open, as FILE, FILE:LINE or FILE:LINE:COL. The first one
+    /// is shown and :next moves on to the others. A +LINE argument also
+    /// says which line to start on, and - reads the text from stdin.
     #[arg(name = "FILE")]
-    file: Option<PathBuf>,
+    files: Vec<String>,
 
     /// Config file path
     #[arg(short, long)]
@@ -38,17 +41,42 @@ struct Args {
     /// Start in read-only mode
     #[arg(short, long)]
     readonly: bool,
+
+    /// Compare two files side by side, editing the first
+    #[arg(long)]
+    diff: bool,
+
+    /// Draw without colors, as when NO_COLOR is set
+    #[arg(long)]
+    no_color: bool,
+
+    /// Pick up a session saved with :mksession
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["FILE", "diff"])]
+    session: Option<PathBuf>,
+
+    /// Run an ex command once the files are open, as in --cmd 'set ts=2'.
+    /// Given more than once, the commands run in order.
+    #[arg(long, value_name = "COMMAND")]
+    cmd: Vec<String>,
 }
 
 fn main() -> io::Result<()> {
     // Parse command line arguments
     let args = Args::parse();
 
-    // Initialize logging
+    // Initialize logging, at ZORFORGE_LOG_LEVEL or else info. :set loglevel
+    // changes the level later.
     if let Ok(log_path) = std::env::var("ZORFORGE_LOG") {
+        let level = match std::env::var("ZORFORGE_LOG_LEVEL") {
+            Ok(level) => level.parse().unwrap_or_else(|_| {
+                eprintln!("Warning: Unknown log level {}, using info", level);
+                log::LevelFilter::Info
+            }),
+            Err(_) => log::LevelFilter::Info,
+        };
         simple_logging::log_to_file(
             log_path,
-            log::LevelFilter::Info,
+            level,
         ).expect("Failed to initialize logging");
     }
 
@@ -63,62 +91,230 @@ fn main() -> io::Result<()> {
 
     // Initialize editor
     let mut editor = Editor::new(config);
+    if let Some(dir) = Snippets::default_dir() {
+        editor.set_snippets(Snippets::new(dir));
+    }
+    if let Some(file) = RecentFiles::default_file() {
+        match RecentFiles::load(file) {
+            Ok(recent) => editor.set_recent_files(recent),
+            Err(e) => log::warn!("Failed to load recent files: {}", e),
+        }
+    }
+    if let Some(file) = CursorPositions::default_file() {
+        match CursorPositions::load(file) {
+            Ok(positions) => editor.set_cursor_positions(positions),
+            Err(e) => log::warn!("Failed to load cursor positions: {}", e),
+        }
+    }
     
+    if args.readonly {
+        editor.set_readonly_files(true);
+    }
+
+    // `cmd | zorforge -`, or just `cmd | zorforge`, edits cmd's output
+    let stdin_arg = args.files.iter().any(|file| file == "-");
+    let read_stdin = stdin_arg
+        || (args.files.is_empty() && args.session.is_none() && !io::stdin().is_terminal());
+    if read_stdin {
+        if args.files.len() > usize::from(stdin_arg) {
+            eprintln!("Error: Can't read stdin and open files at the same time");
+            return Ok(());
+        }
+        if let Err(e) = editor.load_from_reader(io::stdin().lock()) {
+            eprintln!("Error reading stdin: {}", e);
+            return Ok(());
+        }
+    }
+
     // Load initial file if specified
-    if let Some(path) = args.file {
-        if let Err(e) = editor.open_file(&path) {
+    let targets = match read_stdin {
+        true => Vec::new(),
+        false => cli::parse_file_args(&args.files, Path::exists),
+    };
+    if let Some(path) = &args.session {
+        if let Err(e) = editor.load_session(path) {
+            eprintln!("Error loading session: {}", e);
+            return Ok(());
+        }
+    } else if args.diff {
+        if read_stdin || targets.len() != 2 {
+            eprintln!("Error: --diff needs exactly two files");
+            return Ok(());
+        }
+        if let Err(e) = editor.open_diff(&targets[0].path, &targets[1].path) {
             eprintln!("Error opening file: {}", e);
             return Ok(());
         }
-        if args.readonly {
-            editor.set_readonly(true);
+    } else {
+        editor.set_arg_list(targets.iter().map(|target| target.path.clone()).collect());
+        if let Some(target) = targets.first() {
+            if let Err(e) = editor.open_file(&target.path) {
+                eprintln!("Error opening file: {}", e);
+                return Ok(());
+            }
+        }
+    }
+    if let Some(target) = targets.first() {
+        if let Some(line) = target.line {
+            let col = target.col.unwrap_or(1);
+            editor.place_cursor(line.saturating_sub(1), col.saturating_sub(1));
+        }
+    }
+    // --cmd works on the whole file, not what a background load has read
+    // of it so far
+    if !args.cmd.is_empty() {
+        editor.finish_loading();
+    }
+    let mut cmd_errors = Vec::new();
+    for cmd in &args.cmd {
+        if let Err(e) = execute_command(&mut editor, cmd) {
+            editor.show_error(&e.to_string());
+            cmd_errors.push(e);
+        }
+    }
+    // `--cmd q` and the like finish before the screen is ever set up, so
+    // what went wrong goes to stderr
+    if editor.should_quit() {
+        for e in cmd_errors {
+            eprintln!("Error: {}", e);
         }
+        editor.remember_cursor();
+        return Ok(());
     }
 
     // Initialize renderer
     let mut renderer = Renderer::new()?;
+    renderer.set_color_support(match args.no_color {
+        true => ColorSupport::Monochrome,
+        false => editor.config.color_support(),
+    });
+    // Buffers start out with a 20x80 view; size it to the terminal before
+    // the first key is handled, bringing a +line jump into view
+    let (height, width) = renderer.viewport_size();
+    editor.update_viewport(height, width);
 
     // Setup terminal
     enable_raw_mode()?;
     stdout().execute(crossterm::terminal::EnterAlternateScreen)?;
+    exit_on_signals()?;
 
-    // Main event loop
-    run_event_loop(&mut editor, &mut renderer)?;
+    // Main event loop. The terminal is restored even if it fails.
+    let result = run_event_loop(&mut editor, &mut renderer);
+    editor.remember_cursor();
+    let _ = editor.remove_swap();
 
     // Cleanup
     cleanup()?;
 
-    Ok(())
+    result
 }
 
 fn run_event_loop(editor: &mut Editor, renderer: &mut Renderer) -> io::Result<()> {
-    let mut last_render = std::time::Instant::now();
     let frame_duration = Duration::from_millis(16); // ~60 FPS
+    let mut last_render: Option<Instant> = None;
+    let mut last_input = Instant::now();
+    let mut autosave_pending = false;
+    let mut last_swap = Instant::now();
+    let swap_interval = Duration::from_secs(4);
+    let mut pending_size = None; // Latest size from a burst of resize events
+    let terminal_poll = Duration::from_millis(50);
 
     loop {
+        // Draw pending changes, at most once per frame. Dragging a window
+        // edge sends many resizes; only the last one per frame is laid out.
+        let frame_due = last_render.is_none_or(|t| t.elapsed() >= frame_duration);
+        if let Some((width, height)) = pending_size.filter(|_| frame_due) {
+            renderer.resize(width, height);
+            pending_size = None;
+        }
+        let (height, width) = renderer.viewport_size();
+        editor.update_viewport(height, width);
+        if editor.take_redraw_request() {
+            renderer.force_redraw();
+        }
+        let mut needs_render = renderer.needs_render(editor) || pending_size.is_some();
+        if needs_render && frame_due {
+            renderer.render(&mut stdout(), editor)?;
+            editor.buffer.clear_dirty_lines();
+            last_render = Some(Instant::now());
+            needs_render = false;
+        }
+
+        // Sleep until input arrives or the next piece of work is due, so an
+        // idle editor doesn't spin
+        let timeout = if needs_render || editor.is_loading() {
+            last_render.map_or(Duration::ZERO, |t| frame_duration.saturating_sub(t.elapsed()))
+        } else if editor.windows().terminals().next().is_some() {
+            // Shells print whenever they like, so check on them regularly
+            terminal_poll
+        } else {
+            let mut wait = swap_interval.saturating_sub(last_swap.elapsed());
+            let autosave_delay = editor.config.autosave_delay;
+            if autosave_pending && autosave_delay > 0 {
+                let until_autosave = Duration::from_secs(autosave_delay)
+                    .saturating_sub(last_input.elapsed());
+                wait = wait.min(until_autosave);
+            }
+            wait
+        };
+        // Wake up to take a yank highlight down when its time is up
+        let timeout = editor.yank_highlight_remaining().map_or(timeout, |left| timeout.min(left));
+        // ... and to show the which-key popup once a prefix key has waited
+        let timeout = editor.which_key_remaining().map_or(timeout, |left| timeout.min(left));
+
         // Handle input events
-        if event::poll(Duration::from_millis(1))? {
+        if event::poll(timeout)? {
             match event::read()? {
                 Event::Key(key) => {
+                    last_input = Instant::now();
+                    autosave_pending = true;
                     if !handle_key_event(editor, key)? {
                         break;
                     }
                 }
+                Event::Paste(text) => {
+                    last_input = Instant::now();
+                    autosave_pending = true;
+                    handle_paste(editor, &text)?;
+                }
                 Event::Resize(width, height) => {
-                    renderer.resize(width, height);
+                    pending_size = Some((width, height));
                 }
                 Event::Mouse(event) => {
                     handle_mouse_event(editor, event);
                 }
+                // The branch may have been switched from another window
+                Event::FocusGained => editor.refresh_git_branch(),
                 _ => {}
             }
         }
 
-        // Throttle rendering to target frame rate
-        let now = std::time::Instant::now();
-        if now.duration_since(last_render) >= frame_duration {
-            renderer.render(&mut stdout(), editor)?;
-            last_render = now;
+        // Pick up lines read by a background file load
+        editor.poll_loading();
+        editor.buffer.refresh_search();
+        if let Err(e) = editor.check_file_changed() {
+            editor.show_error(&format!("Error reloading file: {}", e));
+        }
+        editor.refresh_diff();
+        editor.refresh_git_marks();
+        if editor.poll_terminals() {
+            renderer.mark_windows_dirty();
+        }
+
+        // Auto-save once per idle period
+        let autosave_delay = editor.config.autosave_delay;
+        if autosave_pending
+            && autosave_delay > 0
+            && last_input.elapsed() >= Duration::from_secs(autosave_delay)
+        {
+            editor.autosave();
+            autosave_pending = false;
+        }
+
+        // Periodically back up unsaved edits for crash recovery
+        if last_swap.elapsed() >= swap_interval {
+            editor.update_swap();
+            last_swap = Instant::now();
         }
     }
 
@@ -126,29 +322,18 @@ fn run_event_loop(editor: &mut Editor, renderer: &mut Renderer) -> io::Result<()
 }
 
 fn handle_key_event(editor: &mut Editor, key: KeyEvent) -> io::Result<bool> {
-    match editor.mode() {
-        Mode::Normal => {
-            // Check for quit command
-            if key.matches_ctrl_key('q') {
-                if editor.has_unsaved_changes() {
-                    editor.show_message("Warning: Unsaved changes. Use :q! to force quit.");
-                    return Ok(true);
-                }
-                return Ok(false);
-            }
-        }
-        Mode::Command(_) => {
-            // Handle force quit in command mode
-            if editor.command_line_content() == "q!" {
-                return Ok(false);
-            }
-        }
-        _ => {}
+    // Messages stay up until the next keypress
+    editor.clear_message();
+
+    // Ctrl-Q quits, asking first about unsaved changes
+    if *editor.mode() == Mode::Normal && !editor.is_confirming_quit() && key.matches_ctrl_key('q') {
+        editor.confirm_quit()?;
+        return Ok(!editor.should_quit());
     }
 
-    // Handle all other input
+    // Handle all other input; :q and friends ask to stop from in here
     handle_input(editor, key)?;
-    Ok(true)
+    Ok(!editor.should_quit())
 }
 
 fn handle_mouse_event(editor: &mut Editor, event: event::MouseEvent) {
@@ -169,6 +354,9 @@ fn handle_mouse_event(editor: &mut Editor, event: event::MouseEvent) {
                 button
             );
         }
+        Up(_) => {
+            editor.handle_mouse_release();
+        }
         ScrollDown => {
             editor.scroll_down();
         }
@@ -181,7 +369,27 @@ fn handle_mouse_event(editor: &mut Editor, event: event::MouseEvent) {
 
 fn cleanup() -> io::Result<()> {
     disable_raw_mode()?;
-    stdout().execute(crossterm::terminal::LeaveAlternateScreen)?;
+    stdout()
+        .execute(DisableMouseCapture)?
+        .execute(DisableBracketedPaste)?
+        .execute(crossterm::cursor::SetCursorStyle::DefaultUserShape)?
+        .execute(crossterm::event::DisableFocusChange)?
+        .execute(crossterm::terminal::LeaveAlternateScreen)?;
+    Ok(())
+}
+
+// Restore the terminal and exit when killed or hung up on. The swap file is
+// left behind, so :recover can bring back unsaved edits. Resizes need
+// nothing here: crossterm reports SIGWINCH as Event::Resize.
+fn exit_on_signals() -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGINT])?;
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log::info!("signal: {}, exiting", signal);
+            let _ = cleanup();
+            std::process::exit(128 + signal);
+        }
+    });
     Ok(())
 }
 
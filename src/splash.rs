@@ -0,0 +1,13 @@
+// src/splash.rs
+
+/// What an empty, unnamed buffer shows until something is typed: the
+/// version and how to get started
+pub fn intro_lines() -> Vec<String> {
+    vec![
+        format!("zorforge v{}", env!("CARGO_PKG_VERSION")),
+        String::new(),
+        String::from("i            start typing"),
+        String::from(":e {file}    open a file"),
+        String::from(":q           quit"),
+    ]
+}
@@ -11,4 +11,4 @@ pub use command_line::CommandLine;
 pub use render::Render;
 pub use renderer::Renderer;
 // pub use status_bar::StatusBar;
-pub use windows::WindowManager;
\ No newline at end of file
+pub use windows::{LayoutShape, SplitDirection, Terminal, Window, WindowContent, WindowId, WindowManager};
\ No newline at end of file
@@ -1,16 +1,28 @@
 // src/ui/renderer.rs
-use std::{collections::HashSet, io::{self, Write}, time::Instant};
+use std::{collections::HashSet, io::{self, Write}, ops::Range, time::Instant};
 use crossterm::{
     cursor,
-    event::{DisableMouseCapture, EnableMouseCapture},
+    event::{
+        DisableBracketedPaste, DisableFocusChange, DisableMouseCapture,
+        EnableBracketedPaste, EnableFocusChange, EnableMouseCapture,
+    },
     execute,
     queue,
-    style::{self, Attribute, Color, Colors, Print, SetColors, Stylize},
+    style::{self, Color, Colors, Print, SetBackgroundColor, SetColors, SetForegroundColor, Stylize},
     terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+    Command,
 };
 use parking_lot::RwLock;
+use rayon::prelude::*;
 use std::sync::Arc;
-use crate::editor::{Buffer, Editor, Mode};
+use crate::config::ColorSupport;
+use crate::editor::{Buffer, Editor, Message, MessageKind, Mode, YankRegion};
+use crate::editor::completion::Completion;
+use crate::editor::mode::CursorStyle;
+use crate::editor::buffer::{cell_width, VisualMode};
+use crate::editor::diff::{DiffView, LineDiff};
+use crate::editor::git::GitMark;
+use crate::ui::windows::{Cell, CellStyle, Terminal, Window, WindowContent, WindowDimensions};
 
 #[derive(Debug)]
 pub struct Renderer {
@@ -21,37 +33,44 @@ pub struct Renderer {
     force_redraw: bool,
     status_line_height: u16,
     command_line_height: u16,
+    color_support: ColorSupport, // Theme RGB colors are downgraded to fit this
 }
 
 #[derive(Debug)]
 struct ScreenCache {
-    buffer_lines: Vec<CachedLine>,
+    line_count: usize,
     status_line: String,
     command_line: String,
+    pending_keys: String,
+    message: Option<Message>,
+    viewport_start: usize,
+    cursor_row: usize,
+    selection: Option<Selection>,
+    yanked: Option<YankRegion>,
+    completion: Option<Completion>,
+    which_key: Option<Vec<(String, String)>>,
+    intro: bool,
     last_update: Instant,
 }
 
-#[derive(Debug)]
-struct CachedLine {
-    content: String,
-    styles: Vec<Style>,
-    last_modified: Instant,
-}
+// The visual selection's ends and kind as last drawn
+type Selection = ((usize, usize), (usize, usize), Option<VisualMode>);
+
+// Most words the completion popup shows at once; it scrolls through the rest
+const MAX_COMPLETION_ROWS: usize = 10;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+// Windows with at least this many rows to draw are highlighted in parallel;
+// below it the thread pool overhead outweighs the work
+const PARALLEL_HIGHLIGHT_ROWS: usize = 48;
+
+// Ordered so that regions drawn later (command line messages) overlay earlier ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 enum Region {
     Buffer { start: usize, end: usize },
     StatusLine,
     CommandLine,
 }
 
-#[derive(Debug, Clone)]
-struct Style {
-    foreground: Option<Color>,
-    background: Option<Color>,
-    attributes: Vec<Attribute>,
-}
-
 impl Renderer {
     pub fn new() -> io::Result<Self> {
         // Setup terminal
@@ -59,6 +78,8 @@ impl Renderer {
             io::stdout(),
             EnterAlternateScreen,
             EnableMouseCapture,
+            EnableFocusChange,
+            EnableBracketedPaste,
             terminal::Clear(ClearType::All)
         )?;
         
@@ -68,9 +89,18 @@ impl Renderer {
         
         Ok(Self {
             screen_cache: Arc::new(RwLock::new(ScreenCache {
-                buffer_lines: Vec::new(),
+                line_count: 0,
                 status_line: String::new(),
                 command_line: String::new(),
+                pending_keys: String::new(),
+                message: None,
+                viewport_start: 0,
+                cursor_row: 0,
+                selection: None,
+                yanked: None,
+                completion: None,
+                which_key: None,
+                intro: false,
                 last_update: Instant::now(),
             })),
             dimensions: (width, height),
@@ -79,16 +109,25 @@ impl Renderer {
             force_redraw: true,
             status_line_height: 1,
             command_line_height: 1,
+            color_support: ColorSupport::detect(),
         })
     }
 
+    pub fn set_color_support(&mut self, support: ColorSupport) {
+        self.color_support = support;
+        self.force_redraw = true;
+    }
+
     pub fn cleanup(&mut self) -> io::Result<()> {
         // Restore terminal
         terminal::disable_raw_mode()?;
         execute!(
             io::stdout(),
             LeaveAlternateScreen,
-            DisableMouseCapture
+            DisableMouseCapture,
+            DisableFocusChange,
+            DisableBracketedPaste,
+            cursor::SetCursorStyle::DefaultUserShape
         )?;
         Ok(())
     }
@@ -111,18 +150,32 @@ impl Renderer {
         } else {
             self.collect_dirty_regions(editor)
         };
+        let mut dirty_regions: Vec<Region> = dirty_regions.into_iter().collect();
+        dirty_regions.sort();
 
         if dirty_regions.is_empty() && !self.force_redraw {
             return Ok(());
         }
 
-        // Create off-screen buffer
+        // Create off-screen buffer. A full redraw starts from a blank
+        // screen, wiping anything else that was written to the terminal.
         let mut buffer = Vec::new();
+        if self.force_redraw {
+            queue!(buffer, Clear(ClearType::All))?;
+        }
         
         // Render each region
+        let region_count = dirty_regions.len();
         for region in dirty_regions {
             self.render_region(&mut buffer, editor, region)?;
         }
+        // The completion popup floats over whatever was drawn beneath it
+        if let Some(completion) = editor.completion() {
+            self.render_completion(&mut buffer, editor, completion)?;
+        }
+        if let Some(keys) = editor.which_key() {
+            self.render_which_key(&mut buffer, editor, &keys)?;
+        }
 
         // Hide cursor during updates
         queue!(writer, cursor::Hide)?;
@@ -131,13 +184,22 @@ impl Renderer {
         writer.write_all(&buffer)?;
         writer.flush()?;
 
-        // Show cursor at final position
-        let (cursor_row, cursor_col) = self.get_cursor_screen_position(editor);
-        queue!(
-            writer,
-            cursor::MoveTo(cursor_col, cursor_row),
-            cursor::Show
-        )?;
+        // Show cursor at final position, unless a program in a focused
+        // terminal hid it. Overwriting gets an underline; otherwise the
+        // terminal's own shape is kept.
+        let shape = match editor.cursor_style() {
+            CursorStyle::Underline => cursor::SetCursorStyle::SteadyUnderScore,
+            _ => cursor::SetCursorStyle::DefaultUserShape,
+        };
+        match self.get_cursor_screen_position(editor) {
+            Some((cursor_row, cursor_col)) => queue!(
+                writer,
+                cursor::MoveTo(cursor_col, cursor_row),
+                shape,
+                cursor::Show
+            )?,
+            None => queue!(writer, cursor::Hide)?,
+        }
         
         writer.flush()?;
 
@@ -150,12 +212,20 @@ impl Renderer {
         // Performance logging
         let elapsed = start.elapsed();
         if elapsed.as_millis() > 16 { // Target 60 FPS
-            eprintln!("Slow render: {:?}", elapsed);
+            log::warn!("render: slow, {:?} for {} regions", elapsed, region_count);
+        } else {
+            log::debug!("render: {:?} for {} regions", elapsed, region_count);
         }
         
         Ok(())
     }
 
+    // Whether anything on screen is out of date, so the caller can sleep
+    // instead of rendering when nothing changed
+    pub fn needs_render(&self, editor: &Editor) -> bool {
+        self.force_redraw || !self.collect_dirty_regions(editor).is_empty()
+    }
+
     fn render_region<W: Write>(
         &self,
         writer: &mut W,
@@ -183,62 +253,546 @@ impl Renderer {
         start: usize,
         end: usize,
     ) -> io::Result<()> {
+        let windows = editor.windows();
+        let viewport_start = editor.current_buffer().get_viewport().start;
+        if windows.window_count() <= 1 {
+            let area = self.text_area();
+            let rows = start..end.min(area.height as usize);
+            self.render_buffer_window(writer, editor, &area, viewport_start, rows)?;
+            return self.set_colors(writer, Colors::new(Color::Reset, Color::Reset));
+        }
+
+        // With splits an edit can show in any window, so `start` and `end`
+        // aren't used and every window is drawn whole
+        let buffer_window = windows.buffer_window().map(Window::id);
+        for window in windows.layout_windows() {
+            let area = window.dimensions();
+            match window.content() {
+                WindowContent::Buffer { top } => {
+                    let top = if Some(window.id()) == buffer_window { viewport_start } else { *top };
+                    self.render_buffer_window(writer, editor, area, top, 0..area.height as usize)?;
+                }
+                WindowContent::Terminal(terminal) => {
+                    self.render_terminal_window(writer, editor, area, &terminal.read())?;
+                }
+            }
+        }
+        self.render_separators(writer, editor)?;
+        self.set_colors(writer, Colors::new(Color::Reset, Color::Reset))
+    }
+
+    // The completion popup, just below the word it finishes or above it at
+    // the bottom of the screen, with the picked word highlighted
+    fn render_completion<W: Write>(&self, writer: &mut W, editor: &Editor, completion: &Completion) -> io::Result<()> {
+        let Some((cursor_y, cursor_x)) = self.get_cursor_screen_position(editor) else {
+            return Ok(());
+        };
         let buffer = editor.current_buffer();
-        let viewport_height = self.get_viewport_height();
-        
-        for row in start..end.min(viewport_height) {
+        let (row, col) = buffer.get_cursor_position();
+        let typed = buffer.display_col(row, col).saturating_sub(buffer.display_col(row, completion.start));
+        let longest = completion.items.iter().map(|item| line_cells(item, usize::MAX, 1)).max().unwrap_or(0);
+        let area = editor.windows().popup_area(
+            cursor_x.saturating_sub(typed as u16 + 1),
+            cursor_y,
+            (longest + 2) as u16,
+            completion.items.len().min(MAX_COMPLETION_ROWS) as u16,
+        );
+        let width = area.width as usize;
+
+        // Scrolled just far enough to show the picked word
+        let first = (completion.selected + 1).saturating_sub(area.height as usize);
+        let ui = &editor.config.theme.ui;
+        let colors = Colors::new(ui.foreground.to_color(self.color_support), ui.background.to_color(self.color_support));
+        let selected = Colors::new(ui.foreground.to_color(self.color_support), ui.selected.to_color(self.color_support));
+        for (i, item) in completion.items.iter().enumerate().skip(first).take(area.height as usize) {
+            let item = fit_to_width(item, width.saturating_sub(2), 1);
+            let text = format!(" {}{}", item, " ".repeat(width.saturating_sub(line_cells(item, width, 1) + 1)));
+            queue!(writer, cursor::MoveTo(area.x, area.y + (i - first) as u16))?;
+            match (i == completion.selected, self.colors_enabled()) {
+                (true, true) => self.set_colors(writer, selected)?,
+                (true, false) => queue!(writer, Print(REVERSE.0))?,
+                (false, _) => self.set_colors(writer, colors)?,
+            }
+            queue!(writer, Print(text))?;
+            if i == completion.selected && !self.colors_enabled() {
+                queue!(writer, Print(REVERSE.1))?;
+            }
+        }
+        self.set_colors(writer, Colors::new(Color::Reset, Color::Reset))
+    }
+
+    // The keys that may follow a pending prefix, in a panel at the bottom
+    // right of the text area
+    fn render_which_key<W: Write>(&self, writer: &mut W, editor: &Editor, keys: &[(String, String)]) -> io::Result<()> {
+        let key_width = keys.iter().map(|(key, _)| line_cells(key, usize::MAX, 1)).max().unwrap_or(0);
+        let longest = keys.iter().map(|(_, action)| line_cells(action, usize::MAX, 1)).max().unwrap_or(0);
+        let area = editor.windows().popup_area(
+            u16::MAX,
+            self.status_line_row(),
+            (key_width + longest + 5) as u16,
+            keys.len() as u16,
+        );
+        let width = area.width as usize;
+        let ui = &editor.config.theme.ui;
+        self.set_colors(writer, Colors::new(ui.foreground.to_color(self.color_support), ui.background.to_color(self.color_support)))?;
+        for (i, (key, action)) in keys.iter().enumerate().take(area.height as usize) {
+            let entry = format!(" {:<key_width$} → {}", key, action);
+            let entry = fit_to_width(&entry, width, 1);
+            let text = format!("{}{}", entry, " ".repeat(width.saturating_sub(line_cells(entry, width, 1))));
+            queue!(writer, cursor::MoveTo(area.x, area.y + i as u16), Print(text))?;
+        }
+        self.set_colors(writer, Colors::new(Color::Reset, Color::Reset))
+    }
+
+    // The lines between windows, brighter around the focused one
+    fn render_separators<W: Write>(&self, writer: &mut W, editor: &Editor) -> io::Result<()> {
+        let windows = editor.windows();
+        let ui = &editor.config.theme.ui;
+        let background = editor.config.theme.background.to_color(self.color_support);
+        let active = Colors::new(ui.active.to_color(self.color_support), background);
+        let inactive = Colors::new(ui.inactive.to_color(self.color_support), background);
+        let focused = windows.active_id()
+            .and_then(|id| windows.layout_windows().into_iter().find(|window| window.id() == id))
+            .map(|window| window.dimensions().clone());
+
+        for (x, y, glyph) in windows.separators() {
+            let is_active = focused.as_ref().is_some_and(|area| area.borders_on(x, y));
+            self.set_colors(writer, if is_active { active } else { inactive })?;
+            queue!(writer, cursor::MoveTo(x, y), Print(glyph))?;
+        }
+        Ok(())
+    }
+
+    // Rows `rows` of a window onto the buffer that starts at line `top`
+    fn render_buffer_window<W: Write>(
+        &self,
+        writer: &mut W,
+        editor: &Editor,
+        area: &WindowDimensions,
+        top: usize,
+        rows: Range<usize>,
+    ) -> io::Result<()> {
+        let buffer = editor.current_buffer();
+
+        // Highlight the visible lines up front, spread over threads when
+        // the window is large
+        let mode = *editor.mode();
+        let diff = editor.diff_view();
+        let pane_width = self.left_pane_width(editor, area);
+        let text_width = pane_width.saturating_sub(buffer.gutter_width());
+        let tab_size = buffer.tab_size();
+        let underline = match self.colors_enabled() {
+            true => UNDERLINE,
+            false => PLAIN_UNDERLINE,
+        };
+        let show_guides = editor.config.indent_guides;
+        let guide_style = self.indent_guide_style(editor);
+        let rule_columns: Vec<usize> = editor.config.color_columns()
+            .into_iter()
+            .map(|column| column - 1)
+            .filter(|&cell| cell < text_width)
+            .collect();
+        let rule_style = self.color_column_style(editor);
+        let show_trailing = editor.config.highlight_trailing_whitespace;
+        let trailing_style = self.trailing_whitespace_style(editor);
+        let yanked = editor.yank_highlight();
+        let highlight = |row: usize| {
+            buffer.line_text(top + row).map(|line| {
+                let guides = show_guides.then(|| IndentGuides {
+                    width: guide_width(buffer, top + row, tab_size).min(text_width),
+                    step: tab_size.max(1),
+                    style: (&guide_style.0, &guide_style.1),
+                });
+                let used = line_cells(&line, text_width, tab_size)
+                    .max(guides.map_or(0, |guides| guides.width));
+                let trailing = show_trailing.then(|| trailing_whitespace(&line)).flatten();
+                let line = fit_to_width(&line, text_width, tab_size);
+                let mut spans: Vec<Span> = buffer.misspellings(top + row)
+                    .into_iter()
+                    .map(|(start, end)| (start, end, underline))
+                    .collect();
+                if let Some(start) = trailing {
+                    spans.push((start, line.len(), (&trailing_style.0, &trailing_style.1)));
+                }
+                if let Some((_, start, end)) = buffer.current_match().filter(|m| m.0 == top + row) {
+                    spans.push((start, end, REVERSE));
+                }
+                let rule_style = (rule_style.0.as_str(), rule_style.1.as_str());
+                spans.extend(rule_columns.iter()
+                    .filter_map(|&cell| char_at_cell(line, cell, tab_size))
+                    .map(|(start, end)| (start, end, rule_style)));
+                spans.sort_by_key(|&(start, _, _)| start);
+                // The selection, or what was just yanked, is drawn over
+                // anything else
+                let span = buffer.selection_span(top + row).or_else(|| {
+                    yanked.and_then(|(start, end, kind)| buffer.region_span(start, end, kind, top + row))
+                });
+                if let Some((start, end)) = span {
+                    spans = vec![(start, end, REVERSE)];
+                }
+                let mut line = style_spans(line, &spans, tab_size, guides);
+                // Rules past the end of the line are drawn on blanks
+                let mut used = used;
+                for &cell in rule_columns.iter() {
+                    if cell < used {
+                        continue;
+                    }
+                    line.extend(std::iter::repeat_n(' ', cell - used));
+                    line.push_str(rule_style.0);
+                    line.push(' ');
+                    line.push_str(rule_style.1);
+                    used = cell + 1;
+                }
+                (self.highlight_line(&line, mode), used)
+            })
+        };
+        let lines: Vec<Option<(String, usize)>> = if rows.len() >= PARALLEL_HIGHLIGHT_ROWS {
+            rows.clone().into_par_iter().map(highlight).collect()
+        } else {
+            rows.clone().map(highlight).collect()
+        };
+
+        // An empty, unnamed buffer has the intro centered below its line
+        let intro = match editor.shows_intro() {
+            true => crate::splash::intro_lines(),
+            false => Vec::new(),
+        };
+        let intro_top = (area.height as usize).saturating_sub(intro.len()) / 2;
+        let intro_width = intro.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        let intro_pad = (pane_width.saturating_sub(intro_width) / 2).max(1);
+
+        let text_colors = self.text_colors(editor);
+        let gutter_colors = Colors::new(
+            editor.config.theme.line_numbers.to_color(self.color_support),
+            editor.config.theme.background.to_color(self.color_support),
+        );
+        // The cursor's line number stands out from the rest
+        let cursor_row = buffer.get_cursor_position().0;
+        let cursor_gutter_colors = Colors::new(
+            editor.config.theme.line_numbers_highlight.to_color(self.color_support),
+            editor.config.theme.background.to_color(self.color_support),
+        );
+
+        for (row, line) in rows.zip(lines) {
             // Position cursor
-            queue!(writer, cursor::MoveTo(0, row as u16))?;
+            queue!(writer, cursor::MoveTo(area.x, area.y + row as u16))?;
 
             // Render line with number
-            if let Some(line) = buffer.get_line(row) {
-                let line_num = format!("{:4} │ ", row + 1);
+            if let Some((rendered, used)) = line {
+                let line_num = buffer.format_line_number(top + row);
+                let gutter_colors = match top + row == cursor_row {
+                    true => cursor_gutter_colors,
+                    false => gutter_colors,
+                };
+                self.set_colors(writer, gutter_colors)?;
+                match editor.git_mark(top + row) {
+                    // The mark takes the blank before the separator
+                    Some(mark) => {
+                        let (number, separator) = line_num.split_at(line_num.len() - " │ ".len());
+                        queue!(writer, Print(number))?;
+                        self.set_colors(writer, Colors::new(git_color(mark), gutter_colors.background.unwrap_or(Color::Reset)))?;
+                        queue!(writer, Print(mark.symbol()))?;
+                        self.set_colors(writer, gutter_colors)?;
+                        queue!(writer, Print(&separator[1..]))?;
+                    }
+                    None => queue!(writer, Print(&line_num))?,
+                }
+                self.set_colors(writer, text_colors)?;
+
+                // Render highlighted line content
+                let Some(diff) = diff else {
+                    queue!(writer, Print(rendered))?;
+                    self.clear_rest(writer, area, buffer.gutter_width() + used)?;
+                    continue;
+                };
+                let status = diff.diff.left.get(top + row).copied().unwrap_or(LineDiff::Same);
+                self.start_diff_style(writer, status, Color::DarkRed)?;
+                queue!(writer, Print(rendered), Print(" ".repeat(text_width - used)))?;
+                self.end_diff_style(writer, status, text_colors)?;
+            } else {
+                // Past the end of the buffer: a lone marker in the gutter color
+                self.set_colors(writer, gutter_colors)?;
+                queue!(writer, Print("~"))?;
+                self.set_colors(writer, text_colors)?;
+                if let Some(text) = row.checked_sub(intro_top).and_then(|i| intro.get(i)) {
+                    let text = fit_to_width(text, pane_width.saturating_sub(intro_pad), tab_size);
+                    queue!(writer, Print(" ".repeat(intro_pad - 1)), Print(text))?;
+                    self.clear_rest(writer, area, intro_pad + text.chars().count())?;
+                    continue;
+                }
+                if diff.is_none() {
+                    self.clear_rest(writer, area, 1)?;
+                    continue;
+                }
+                queue!(writer, Print(" ".repeat(pane_width.saturating_sub(1))))?;
+            }
+            if let Some(diff) = diff {
+                let row = diff.diff.right_row(top) + row;
+                self.render_diff_line(writer, area, diff, row, text_colors, tab_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    // A terminal's screen, or in copy mode wherever it has scrolled to
+    fn render_terminal_window<W: Write>(
+        &self,
+        writer: &mut W,
+        editor: &Editor,
+        area: &WindowDimensions,
+        terminal: &Terminal,
+    ) -> io::Result<()> {
+        let screen = terminal.screen();
+        let height = area.height as usize;
+        let width = area.width as usize;
+        let first = terminal.view_top();
+        let selected = terminal.selected_lines();
+        let text_colors = self.text_colors(editor);
+        for row in 0..height {
+            let cells = screen.line(first + row);
+            let cells = &cells[..cells.len().min(width)];
+            queue!(writer, cursor::MoveTo(area.x, area.y + row as u16))?;
+            // Copy mode shows its cursor line, or the selection, in reverse
+            // video across the window
+            if selected.as_ref().is_some_and(|lines| lines.contains(&(first + row))) {
+                let text: String = cells.iter().map(|cell| cell.c).filter(|&c| c != '\0').collect();
+                self.set_colors(writer, text_colors)?;
                 queue!(
                     writer,
-                    SetColors(Colors::new(Color::DarkGrey, Color::Reset)),
-                    Print(&line_num),
-                    SetColors(Colors::new(Color::Reset, Color::Reset)),
+                    Print(REVERSE.0),
+                    Print(text),
+                    Print(" ".repeat(width - cells.len())),
+                    Print(REVERSE.1),
                 )?;
+                continue;
+            }
+            self.render_cells(writer, cells, text_colors)?;
+            self.clear_rest(writer, area, cells.len())?;
+        }
 
-                // Apply syntax highlighting and render line content
-                let rendered = self.highlight_line(line, *editor.mode());
-                queue!(writer, Print(rendered))?;
-
-                // Clear to end of line
-                queue!(writer, Clear(ClearType::UntilNewLine))?;
-            } else {
-                // Empty line marker
+        // Where copy mode is in the scrollback, in the top right corner
+        if let Some(copy) = terminal.copy_mode() {
+            let label = format!("[{}/{}]", copy.line + 1, screen.line_count());
+            if label.len() <= width {
                 queue!(
                     writer,
-                    SetColors(Colors::new(Color::DarkGrey, Color::Reset)),
-                    Print("   ~ │"),
-                    Clear(ClearType::UntilNewLine)
+                    cursor::MoveTo(area.x + (width - label.len()) as u16, area.y),
+                    Print(REVERSE.0),
+                    Print(label),
+                    Print(REVERSE.1),
                 )?;
             }
         }
         Ok(())
     }
 
-    fn render_status_line<W: Write>(&self, writer: &mut W, editor: &Editor) -> io::Result<()> {
-        let row = self.dimensions.1 - 2;
-        let mode_text = editor.mode().display_name();
-        let file_info = editor.file_info();  // Get file info from editor instead of buffer
-        let position_info = editor.cursor_position_info();
+    // Terminal cells in the colors and attributes the program gave them,
+    // with the theme's colors where it gave none. Ends in `text_colors`
+    // with attributes off.
+    fn render_cells<W: Write>(&self, writer: &mut W, cells: &[Cell], text_colors: Colors) -> io::Result<()> {
+        let mut style = None;
+        let mut run = String::new();
+        for cell in cells {
+            if style != Some(cell.style) {
+                queue!(writer, Print(&run))?;
+                run.clear();
+                self.set_cell_style(writer, &cell.style, text_colors)?;
+                style = Some(cell.style);
+            }
+            // The right half of a double-width character takes no output
+            if cell.c != '\0' {
+                run.push(cell.c);
+            }
+        }
+        queue!(writer, Print(run))?;
+        self.set_cell_style(writer, &CellStyle::default(), text_colors)
+    }
 
+    fn set_cell_style<W: Write>(&self, writer: &mut W, style: &CellStyle, text_colors: Colors) -> io::Result<()> {
+        let color = |color: Option<Color>, default: Option<Color>| {
+            color.map(|color| self.color_support.fit(color)).or(default).unwrap_or(Color::Reset)
+        };
+        self.set_colors(writer, Colors::new(
+            color(style.fg, text_colors.foreground),
+            color(style.bg, text_colors.background),
+        ))?;
         queue!(
             writer,
-            cursor::MoveTo(0, row),
-            SetColors(Colors::new(Color::Black, Color::Grey)),
-            Print(format!(" {} | {} | {} ", mode_text, file_info, position_info)),
-            SetColors(Colors::new(Color::Reset, Color::Reset)),
-            Clear(ClearType::UntilNewLine)
+            Print(if style.bold { "\x1b[1m" } else { "\x1b[22m" }),
+            Print(if style.underline { PLAIN_UNDERLINE.0 } else { PLAIN_UNDERLINE.1 }),
+            Print(if style.reverse { REVERSE.0 } else { REVERSE.1 }),
+        )
+    }
+
+    // Blank the rest of a row of `area` after its first `used` cells: up to
+    // the end of the line when the area reaches the right edge, otherwise
+    // just to the area's edge, sparing the window beside it
+    fn clear_rest<W: Write>(&self, writer: &mut W, area: &WindowDimensions, used: usize) -> io::Result<()> {
+        if area.x + area.width >= self.dimensions.0 {
+            queue!(writer, Clear(ClearType::UntilNewLine))
+        } else {
+            queue!(writer, Print(" ".repeat((area.width as usize).saturating_sub(used))))
+        }
+    }
+
+    // Everything above the status line
+    fn text_area(&self) -> WindowDimensions {
+        WindowDimensions {
+            x: 0,
+            y: 0,
+            width: self.dimensions.0,
+            height: self.get_viewport_height() as u16,
+        }
+    }
+
+    // Columns of `area` taken by the buffer and its gutter: all of them, or
+    // the left half in --diff mode
+    fn left_pane_width(&self, editor: &Editor, area: &WindowDimensions) -> usize {
+        match editor.diff_view() {
+            Some(_) => area.width as usize / 2,
+            None => area.width as usize,
+        }
+    }
+
+    // A separator and then `row` of the file compared against, right of the
+    // buffer, on the background its difference calls for
+    fn render_diff_line<W: Write>(
+        &self,
+        writer: &mut W,
+        area: &WindowDimensions,
+        diff: &DiffView,
+        row: usize,
+        text_colors: Colors,
+        tab_size: usize,
+    ) -> io::Result<()> {
+        queue!(writer, Print("│"))?;
+        let left = area.width as usize / 2 + 1;
+        let width = (area.width as usize).saturating_sub(left);
+        let Some(line) = diff.lines.get(row) else {
+            return self.clear_rest(writer, area, left);
+        };
+        let status = diff.diff.right.get(row).copied().unwrap_or(LineDiff::Same);
+        self.start_diff_style(writer, status, Color::DarkGreen)?;
+        let text = style_spans(fit_to_width(line, width, tab_size), &[], tab_size, None);
+        queue!(writer, Print(text), Print(" ".repeat(width - line_cells(line, width, tab_size))))?;
+        self.end_diff_style(writer, status, text_colors)
+    }
+
+    // Highlight a --diff line that differs: its background color, or
+    // reverse video when colors are off
+    fn start_diff_style<W: Write>(&self, writer: &mut W, status: LineDiff, only: Color) -> io::Result<()> {
+        match diff_color(status, only) {
+            None => Ok(()),
+            Some(_) if !self.colors_enabled() => queue!(writer, Print(REVERSE.0)),
+            Some(color) => queue!(writer, SetBackgroundColor(color)),
+        }
+    }
+
+    fn end_diff_style<W: Write>(&self, writer: &mut W, status: LineDiff, text_colors: Colors) -> io::Result<()> {
+        match self.colors_enabled() {
+            true => queue!(writer, SetColors(text_colors)),
+            false if status != LineDiff::Same => queue!(writer, Print(REVERSE.1)),
+            false => Ok(()),
+        }
+    }
+
+    // False under NO_COLOR or --no-color, when nothing but plain text and
+    // reverse video is drawn
+    fn colors_enabled(&self) -> bool {
+        self.color_support != ColorSupport::Monochrome
+    }
+
+    // Switch to `colors`, unless colors are off
+    fn set_colors<W: Write>(&self, writer: &mut W, colors: Colors) -> io::Result<()> {
+        match self.colors_enabled() {
+            true => queue!(writer, SetColors(colors)),
+            false => Ok(()),
+        }
+    }
+
+    // Escapes that switch to the theme's indent guide color and back to
+    // the text's; none when colors are off
+    fn indent_guide_style(&self, editor: &Editor) -> (String, String) {
+        if !self.colors_enabled() {
+            return (String::new(), String::new());
+        }
+        let theme = &editor.config.theme;
+        escapes(
+            SetForegroundColor(theme.indent_guide.to_color(self.color_support)),
+            SetForegroundColor(theme.foreground.to_color(self.color_support)),
+        )
+    }
+
+    // Escapes that put the theme's UI color behind a `colorcolumn` cell and
+    // take it away again; none when colors are off
+    fn color_column_style(&self, editor: &Editor) -> (String, String) {
+        if !self.colors_enabled() {
+            return (String::new(), String::new());
+        }
+        let theme = &editor.config.theme;
+        escapes(
+            SetBackgroundColor(theme.ui.inactive.to_color(self.color_support)),
+            SetBackgroundColor(theme.background.to_color(self.color_support)),
         )
     }
 
+    // Escapes that put the theme's trailing whitespace background behind
+    // text and take it away again, or reverse video when colors are off
+    fn trailing_whitespace_style(&self, editor: &Editor) -> (String, String) {
+        if !self.colors_enabled() {
+            return (REVERSE.0.to_string(), REVERSE.1.to_string());
+        }
+        let theme = &editor.config.theme;
+        escapes(
+            SetBackgroundColor(theme.trailing_whitespace.to_color(self.color_support)),
+            SetBackgroundColor(theme.background.to_color(self.color_support)),
+        )
+    }
+
+    // Theme foreground and background for buffer text and the command line
+    fn text_colors(&self, editor: &Editor) -> Colors {
+        let theme = &editor.config.theme;
+        Colors::new(
+            theme.foreground.to_color(self.color_support),
+            theme.background.to_color(self.color_support),
+        )
+    }
+
+    fn render_status_line<W: Write>(&self, writer: &mut W, editor: &Editor) -> io::Result<()> {
+        let row = self.status_line_row();
+        let status = expand_statusline(&editor.config.statusline, editor);
+        let theme = &editor.config.theme;
+        let background = match editor.mode() {
+            Mode::Normal => &theme.status_line.normal,
+            Mode::Insert(_) => &theme.status_line.insert,
+            Mode::Visual(_) => &theme.status_line.visual,
+            Mode::Command(_) => &theme.status_line.command,
+        };
+        let colors = Colors::new(
+            theme.background.to_color(self.color_support),
+            background.to_color(self.color_support),
+        );
+
+        // Without colors the status line stands out in reverse video
+        queue!(writer, cursor::MoveTo(0, row))?;
+        match self.colors_enabled() {
+            true => queue!(
+                writer,
+                SetColors(colors),
+                Print(status),
+                SetColors(Colors::new(Color::Reset, Color::Reset))
+            )?,
+            false => queue!(writer, Print(REVERSE.0), Print(status), Print(REVERSE.1))?,
+        }
+        queue!(writer, Clear(ClearType::UntilNewLine))
+    }
+
     // Update command line rendering to use mode().command_prefix()
     fn render_command_line<W: Write>(&self, writer: &mut W, editor: &Editor) -> io::Result<()> {
-        let row = self.dimensions.1 - 1;
+        let row = self.command_line_row();
         let mode = editor.mode();
+        let colors = self.text_colors(editor);
+        self.set_colors(writer, colors)?;
         
         if let Mode::Command(_) = mode {
             let prefix = mode.command_prefix();
@@ -249,15 +803,55 @@ impl Renderer {
                 cursor::MoveTo(0, row),
                 Print(format!("{}{}", prefix, command)),
                 Clear(ClearType::UntilNewLine)
-            )
+            )?;
+        } else if let Some(message) = editor.get_message() {
+            self.render_message(writer, message, colors)?;
+            self.render_pending_keys(writer, editor)?;
         } else {
             // Clear command line when not in command mode
             queue!(
                 writer,
                 cursor::MoveTo(0, row),
                 Clear(ClearType::UntilNewLine)
-            )
+            )?;
+            self.render_pending_keys(writer, editor)?;
+        }
+        self.set_colors(writer, Colors::new(Color::Reset, Color::Reset))
+    }
+
+    // Messages end on the command line; longer ones (:messages) grow upward
+    fn render_message<W: Write>(&self, writer: &mut W, message: &Message, colors: Colors) -> io::Result<()> {
+        let lines: Vec<&str> = message.text.lines().collect();
+        let bottom = self.command_line_row() + self.command_line_height;
+        let first_row = bottom.saturating_sub(lines.len() as u16);
+        let message_colors = match message.kind {
+            MessageKind::Info => colors,
+            MessageKind::Error => Colors { foreground: Some(Color::Red), ..colors },
+        };
+
+        for (i, line) in lines.iter().enumerate() {
+            queue!(writer, cursor::MoveTo(0, first_row + i as u16))?;
+            self.set_colors(writer, message_colors)?;
+            queue!(writer, Print(line))?;
+            self.set_colors(writer, colors)?;
+            queue!(writer, Clear(ClearType::UntilNewLine))?;
         }
+        Ok(())
+    }
+
+    // Partially typed command, or the size of the selection, in the
+    // bottom-right corner like vim's showcmd
+    fn render_pending_keys<W: Write>(&self, writer: &mut W, editor: &Editor) -> io::Result<()> {
+        let pending = show_command(editor);
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let col = self.dimensions.0.saturating_sub(pending.chars().count() as u16 + 1);
+        queue!(
+            writer,
+            cursor::MoveTo(col, self.command_line_row()),
+            Print(pending)
+        )
     }
 
     fn highlight_line(&self, line: &str, mode: Mode) -> String {
@@ -266,17 +860,60 @@ impl Renderer {
         line.to_string()
     }
 
-    fn get_cursor_screen_position(&self, editor: &Editor) -> (u16, u16) {
+    fn get_cursor_screen_position(&self, editor: &Editor) -> Option<(u16, u16)> {
+        // Typing a command, the cursor is on the command line
+        if let Mode::Command(_) = editor.mode() {
+            let command = editor.command_line_content();
+            let before = &command[..editor.command_cursor().min(command.len())];
+            let col = editor.mode().command_prefix().len() + line_cells(before, usize::MAX, 1);
+            return Some((self.command_line_row(), col.min(self.dimensions.0.saturating_sub(1) as usize) as u16));
+        }
+        if let Some(window) = editor.windows().active_window() {
+            if let WindowContent::Terminal(terminal) = window.content() {
+                let terminal = terminal.read();
+                let area = window.dimensions();
+                // Copy mode's cursor is its highlighted line
+                let (row, col) = match terminal.copy_mode() {
+                    Some(copy) => (copy.line.saturating_sub(terminal.view_top()), 0),
+                    None => terminal.cursor()?,
+                };
+                return Some((area.y + row as u16, area.x + col as u16));
+            }
+        }
+
         let (row, col) = editor.cursor_position();
-        let line_number_width = 6; // "123 │ "
-        (
-            row as u16,
-            (col + line_number_width) as u16
-        )
+        let top = editor.current_buffer().get_viewport().start;
+        let buffer = editor.current_buffer();
+        let line_number_width = buffer.gutter_width();
+        let area = match editor.windows().window_count() {
+            1 => None,
+            _ => editor.windows().buffer_window().map(|window| window.dimensions().clone()),
+        }.unwrap_or_else(|| self.text_area());
+        let last_col = self.left_pane_width(editor, &area).saturating_sub(1);
+        Some((
+            area.y + row.saturating_sub(top) as u16,
+            area.x + (buffer.display_col(row, col) + line_number_width).min(last_col) as u16
+        ))
     }
 
+    // Screen layout, top to bottom: the text area, then `status_line_height`
+    // rows of status line, then `command_line_height` rows of command line
     fn get_viewport_height(&self) -> usize {
-        (self.dimensions.1 - self.status_line_height - self.command_line_height) as usize
+        self.status_line_row() as usize
+    }
+
+    fn status_line_row(&self) -> u16 {
+        self.dimensions.1
+            .saturating_sub(self.status_line_height + self.command_line_height)
+    }
+
+    fn command_line_row(&self) -> u16 {
+        self.dimensions.1.saturating_sub(self.command_line_height)
+    }
+
+    /// Size of the text area as (height, width), excluding status and command lines
+    pub fn viewport_size(&self) -> (usize, usize) {
+        (self.get_viewport_height(), self.dimensions.0 as usize)
     }
 
     fn get_all_regions(&self) -> HashSet<Region> {
@@ -293,11 +930,43 @@ impl Renderer {
     fn collect_dirty_regions(&self, editor: &Editor) -> HashSet<Region> {
         let mut regions = self.dirty_regions.clone();
         
-        // Check if buffer content changed
+        // Redraw just the lines edited since the last frame, unless the
+        // whole view moved
         let cache = self.screen_cache.read();
         let buffer = editor.current_buffer();
-        
-        if cache.buffer_lines.len() != buffer.line_count() {
+        regions.extend(self.dirty_line_regions(buffer));
+
+        // The line number of the row the cursor left, and of the one it's on
+        let cursor_row = buffer.get_cursor_position().0;
+        if cache.cursor_row != cursor_row {
+            let top = buffer.get_viewport().start;
+            for row in [cache.cursor_row, cursor_row] {
+                if row >= top && row < top + self.get_viewport_height() {
+                    regions.insert(Region::Buffer { start: row - top, end: row - top + 1 });
+                }
+            }
+        }
+
+        // A selection or yank highlight can change the look of every row it
+        // spans, and the completion and which-key popups leave rows to repaint when they move or close
+        if cache.line_count != buffer.line_count()
+            || cache.viewport_start != buffer.get_viewport().start
+            || cache.selection != current_selection(buffer)
+            || cache.yanked != editor.yank_highlight()
+            || cache.completion.as_ref() != editor.completion()
+            || cache.which_key != editor.which_key()
+            || cache.intro != editor.shows_intro()
+        {
+            regions.insert(Region::Buffer {
+                start: 0,
+                end: self.get_viewport_height(),
+            });
+        }
+
+        // Splits are drawn whole (see render_buffer_region), so once is enough
+        let is_buffer = |region: &Region| matches!(region, Region::Buffer { .. });
+        if editor.windows().window_count() > 1 && regions.iter().any(is_buffer) {
+            regions.retain(|region| !is_buffer(region));
             regions.insert(Region::Buffer {
                 start: 0,
                 end: self.get_viewport_height(),
@@ -305,7 +974,7 @@ impl Renderer {
         }
 
         // Check if status line needs update
-        if editor.mode().display_name() != cache.status_line {
+        if expand_statusline(&editor.config.statusline, editor) != cache.status_line {
             regions.insert(Region::StatusLine);
         }
 
@@ -313,28 +982,76 @@ impl Renderer {
         if let Mode::Command(_) = editor.mode() {
             regions.insert(Region::CommandLine);
         }
+        if show_command(editor) != cache.pending_keys {
+            regions.insert(Region::CommandLine);
+        }
+
+        // A multi-line message covers the buffer, so everything under it
+        // needs repainting when it appears or goes away
+        if editor.get_message() != cache.message.as_ref() {
+            let is_multiline = |message: Option<&Message>| {
+                message.is_some_and(|m| m.text.contains('\n'))
+            };
+            if is_multiline(editor.get_message()) || is_multiline(cache.message.as_ref()) {
+                regions.extend(self.get_all_regions());
+            }
+            regions.insert(Region::CommandLine);
+        }
+
+        regions
+    }
+
+    // Screen rows of the lines marked dirty in the buffer, merged into
+    // contiguous ranges
+    fn dirty_line_regions(&self, buffer: &Buffer) -> Vec<Region> {
+        let top = buffer.get_viewport().start;
+        let height = self.get_viewport_height();
+
+        let mut rows: Vec<usize> = buffer.dirty_lines()
+            .iter()
+            .filter(|&&line| line >= top && line < top + height)
+            .map(|&line| line - top)
+            .collect();
+        rows.sort_unstable();
+
+        let mut regions = Vec::new();
+        for row in rows {
+            match regions.last_mut() {
+                Some(Region::Buffer { end, .. }) if *end == row => *end += 1,
+                _ => regions.push(Region::Buffer { start: row, end: row + 1 }),
+            }
+        }
 
+        // Lines were inserted or removed: everything below moved
+        if let Some(from) = buffer.dirty_from() {
+            if from < top + height {
+                regions.push(Region::Buffer {
+                    start: from.saturating_sub(top),
+                    end: height,
+                });
+            }
+        }
         regions
     }
 
     fn update_screen_cache(&self, editor: &Editor) {
         let mut cache = self.screen_cache.write();
         let buffer = editor.current_buffer();
-        
-        // Update buffer lines
-        cache.buffer_lines = buffer
-            .get_content()
-            .iter()
-            .map(|line| CachedLine {
-                content: line.clone(),
-                styles: Vec::new(), // Add styles when implementing syntax highlighting
-                last_modified: Instant::now(),
-            })
-            .collect();
+
+        cache.line_count = buffer.line_count();
 
         // Update status and command lines
-        cache.status_line = editor.mode().display_name().to_string();
+        cache.status_line = expand_statusline(&editor.config.statusline, editor);
         cache.command_line = editor.command_line_content().to_string();
+        cache.pending_keys = show_command(editor);
+        cache.message = editor.get_message().cloned();
+        cache.viewport_start = buffer.get_viewport().start;
+        cache.cursor_row = buffer.get_cursor_position().0;
+        cache.selection = current_selection(buffer);
+        cache.yanked = editor.yank_highlight();
+        cache.completion = editor.completion().cloned();
+        cache.which_key = editor.which_key();
+        cache.intro = editor.shows_intro();
         cache.last_update = Instant::now();
     }
 
@@ -342,6 +1059,14 @@ impl Renderer {
         self.dirty_regions.insert(region);
     }
 
+    // Terminal windows have new output to show
+    pub fn mark_windows_dirty(&mut self) {
+        self.mark_dirty(Region::Buffer {
+            start: 0,
+            end: self.get_viewport_height(),
+        });
+    }
+
     pub fn force_redraw(&mut self) {
         self.force_redraw = true;
     }
@@ -352,8 +1077,379 @@ impl Renderer {
     }
 }
 
+/// Longest prefix of `line` that fits in `width` terminal cells, so long
+/// lines don't wrap onto the next row
+fn fit_to_width(line: &str, width: usize, tab_size: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in line.char_indices() {
+        used += cell_width(c, used, tab_size);
+        if used > width {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+/// Cells `line` takes on screen once clipped to `width`
+fn line_cells(line: &str, width: usize, tab_size: usize) -> usize {
+    fit_to_width(line, width, tab_size)
+        .chars()
+        .fold(0, |used, c| used + cell_width(c, used, tab_size))
+}
+
+/// Background for a line of a --diff pane: `only` for lines just one side
+/// has, blue for changed lines and none for lines both share
+fn diff_color(status: LineDiff, only: Color) -> Option<Color> {
+    match status {
+        LineDiff::Same => None,
+        LineDiff::Changed => Some(Color::DarkBlue),
+        LineDiff::Only => Some(only),
+    }
+}
+
+/// Gutter color of a line that differs from HEAD
+fn git_color(mark: GitMark) -> Color {
+    match mark {
+        GitMark::Added => Color::Green,
+        GitMark::Modified => Color::Yellow,
+        GitMark::Deleted => Color::Red,
+    }
+}
+
+/// Escape sequences that turn a style on and back off
+type Style<'a> = (&'a str, &'a str);
+
+/// The escape sequences of `on` and `off` as a style
+fn escapes(on: impl Command, off: impl Command) -> (String, String) {
+    let mut on_escape = String::new();
+    let mut off_escape = String::new();
+    // Writing to a String can't fail
+    let _ = on.write_ansi(&mut on_escape);
+    let _ = off.write_ansi(&mut off_escape);
+    (on_escape, off_escape)
+}
+
+/// Red underline for misspelled words. Only the underline color is set, so
+/// the text keeps the theme's.
+const UNDERLINE: Style<'static> = ("\x1b[4;58;5;1m", "\x1b[24;59m");
+
+/// Misspelled words when colors are off
+const PLAIN_UNDERLINE: Style<'static> = ("\x1b[4m", "\x1b[24m");
+
+/// Reverse video for the current search match
+const REVERSE: Style<'static> = ("\x1b[7m", "\x1b[27m");
+
+/// A byte range of a line and the style to draw it in
+type Span<'a> = (usize, usize, Style<'a>);
+
+/// What goes in the showcmd corner: the keys of a command being typed, or
+/// else in visual mode how much is selected
+fn show_command(editor: &Editor) -> String {
+    match editor.mode() {
+        Mode::Visual(_) if editor.pending_keys().is_empty() => {
+            selection_size(editor.current_buffer()).unwrap_or_default()
+        }
+        _ => match editor.leader_keys() {
+            Some(keys) => format!("<leader>{}", keys),
+            None => editor.pending_keys().to_string(),
+        },
+    }
+}
+
+/// The size of the visual selection as vim shows it: characters when it's
+/// within one line, rows by columns for a block, and otherwise lines
+fn selection_size(buffer: &Buffer) -> Option<String> {
+    let (start, end) = buffer.get_visual_selection()?;
+    let (first, last) = (start.min(end), start.max(end));
+    let lines = last.0 - first.0 + 1;
+    match buffer.visual_mode().unwrap_or(VisualMode::Char) {
+        VisualMode::Char if lines == 1 => {
+            let (from, to) = buffer.selection_span(first.0)?;
+            let line = buffer.line_text(first.0)?;
+            Some(line[from..to].chars().count().to_string())
+        }
+        VisualMode::Block => {
+            let width = buffer.display_col(start.0, start.1).abs_diff(buffer.display_col(end.0, end.1));
+            Some(format!("{}x{}", lines, width))
+        }
+        _ => Some(lines.to_string()),
+    }
+}
+
+fn current_selection(buffer: &Buffer) -> Option<Selection> {
+    buffer.get_visual_selection().map(|(start, end)| (start, end, buffer.visual_mode()))
+}
+
+/// Byte range of the character of `line` drawn over screen cell `cell`, if
+/// the line reaches that far
+fn char_at_cell(line: &str, cell: usize, tab_size: usize) -> Option<(usize, usize)> {
+    let mut cells = 0;
+    for (i, c) in line.char_indices() {
+        cells += cell_width(c, cells, tab_size);
+        if cells > cell {
+            return Some((i, i + c.len_utf8()));
+        }
+    }
+    None
+}
+
+/// Where the whitespace at the end of `line` starts, if it has any
+fn trailing_whitespace(line: &str) -> Option<usize> {
+    let end = line.trim_end().len();
+    (end < line.len()).then_some(end)
+}
+
+/// Blank lines with no text of their own take their guides from the next
+/// line with text, looking this far ahead at most
+const GUIDE_LOOKAHEAD: usize = 100;
+
+/// Indent guides to draw on a line: a `│` every `step` cells across the
+/// first `width`, between the escapes of `style`
+#[derive(Debug, Clone, Copy)]
+struct IndentGuides<'a> {
+    width: usize,
+    step: usize,
+    style: Style<'a>,
+}
+
+/// Cells of indentation the guides on `row` run across: the line's own, or
+/// for a blank line that of the next line with text
+fn guide_width(buffer: &Buffer, row: usize, tab_size: usize) -> usize {
+    (row..buffer.line_count().min(row + GUIDE_LOOKAHEAD))
+        .filter_map(|row| buffer.line_text(row))
+        .find(|line| !line.trim().is_empty())
+        .map_or(0, |line| {
+            line.chars()
+                .take_while(|c| c.is_whitespace())
+                .fold(0, |cells, c| cells + cell_width(c, cells, tab_size))
+        })
+}
+
+/// Draw the byte ranges `spans` of `line`, sorted by start, in their styles.
+/// Spans running past a clipped line are cut short and ones overlapping an
+/// earlier span are left out. Tabs are drawn as spaces out to the next
+/// `tab_size` stop, and `guides` over the indentation, padding out a line
+/// shorter than they are.
+fn style_spans(line: &str, spans: &[Span], tab_size: usize, guides: Option<IndentGuides>) -> String {
+    let mut decorated = String::with_capacity(line.len());
+    let mut cells = 0;
+    let push_blank = |decorated: &mut String, cell: usize| match guides {
+        Some(guides) if cell < guides.width && cell.is_multiple_of(guides.step) => {
+            decorated.push_str(guides.style.0);
+            decorated.push('│');
+            decorated.push_str(guides.style.1);
+        }
+        _ => decorated.push(' '),
+    };
+    let mut push_text = |decorated: &mut String, text: &str| {
+        for c in text.chars() {
+            let width = cell_width(c, cells, tab_size);
+            if c == '\t' || c == ' ' {
+                (cells..cells + width).for_each(|cell| push_blank(decorated, cell));
+            } else {
+                decorated.push(c);
+            }
+            cells += width;
+        }
+    };
+    let mut last = 0;
+    for &(start, end, (on, off)) in spans {
+        let end = end.min(line.len());
+        if start < last || start >= end {
+            continue;
+        }
+        push_text(&mut decorated, &line[last..start]);
+        decorated.push_str(on);
+        push_text(&mut decorated, &line[start..end]);
+        decorated.push_str(off);
+        last = end;
+    }
+    push_text(&mut decorated, &line[last..]);
+    if let Some(guides) = guides {
+        (cells..guides.width).for_each(|cell| push_blank(&mut decorated, cell));
+    }
+    decorated
+}
+
+/// Expand a status line format string against the editor state.
+///
+/// Supported placeholders: `%m` mode, `%f` file name, `%l` line, `%c` column,
+/// `%p` percentage through the file, `%L` line count and size such as
+/// `120 lines, 3.4K`, `%y` file type, `%r` read-only flag, `%M` modified
+/// flag, `%S` search match count such as `[3/12]`, `%b` git branch and
+/// `%%` for a literal percent sign. Anything else is
+/// rendered as written. While a large file is still loading, its progress is
+/// appended.
+pub fn expand_statusline(format: &str, editor: &Editor) -> String {
+    let (row, col) = editor.cursor_position();
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('m') => out.push_str(editor.mode().display_name()),
+            Some('f') => out.push_str(&editor.file_info()),
+            Some('l') => out.push_str(&(row + 1).to_string()),
+            Some('c') => out.push_str(&(col + 1).to_string()),
+            Some('p') => out.push_str(&format!("{}%", editor.cursor_percentage())),
+            Some('L') => out.push_str(&editor.buffer_size_info()),
+            Some('y') => {
+                if let Some(filetype) = editor.filetype() {
+                    out.push_str(&format!("[{}]", filetype));
+                }
+            }
+            Some('r') => {
+                if editor.is_readonly() {
+                    out.push_str("[RO]");
+                }
+            }
+            Some('M') => {
+                if editor.has_unsaved_changes() {
+                    out.push_str("[+]");
+                }
+            }
+            Some('b') => {
+                if let Some(branch) = editor.git_branch() {
+                    out.push_str(branch);
+                }
+            }
+            Some('S') => {
+                if let Some((current, total)) = editor.search_position() {
+                    out.push_str(&format!("[{}/{}]", current, total));
+                }
+            }
+            Some('%') => out.push('%'),
+            // Unknown or trailing placeholder: keep it literally
+            _ => {
+                out.push('%');
+                continue;
+            }
+        }
+        chars.next();
+    }
+
+    if let Some(percent) = editor.load_progress() {
+        out.push_str(&format!(" [loading {}%]", percent));
+    }
+    out
+}
+
 impl Drop for Renderer {
     fn drop(&mut self) {
         let _ = self.cleanup();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EditorConfig;
+
+    #[test]
+    fn test_expand_statusline() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.buffer.insert_char('a');
+
+        let status = expand_statusline("%m %f%M %l:%c %p", &editor);
+        assert_eq!(status, "NORMAL [No Name][+] 1:2 100%");
+        assert_eq!(expand_statusline("%L", &editor), "1 line, 1B");
+
+        assert_eq!(expand_statusline("%S", &editor), "");
+        editor.buffer.search_from_cursor("a", true, true);
+        assert_eq!(expand_statusline("%S", &editor), "[1/1]");
+    }
+
+    #[test]
+    fn test_expand_statusline_literals() {
+        let mut editor = Editor::new(EditorConfig::default());
+        editor.set_readonly(true);
+
+        assert_eq!(expand_statusline("%r %% %q %", &editor), "[RO] % %q %");
+        assert_eq!(expand_statusline("%y%M%b", &editor), "");
+    }
+
+    #[test]
+    fn test_fit_to_width() {
+        assert_eq!(fit_to_width("hello", 10, 4), "hello");
+        assert_eq!(fit_to_width("hello", 3, 4), "hel");
+        // A wide character that would straddle the edge is dropped whole
+        assert_eq!(fit_to_width("a你好", 4, 4), "a你");
+        assert_eq!(fit_to_width("你好", 3, 4), "你");
+    }
+
+    #[test]
+    fn test_style_spans() {
+        assert_eq!(style_spans("teh cat", &[], 4, None), "teh cat");
+        assert_eq!(style_spans("teh cta", &[(0, 3, UNDERLINE), (4, 7, UNDERLINE)], 4, None), "\x1b[4;58;5;1mteh\x1b[24;59m \x1b[4;58;5;1mcta\x1b[24;59m");
+        // Cut off by the window edge
+        assert_eq!(style_spans("a tehh", &[(2, 8, UNDERLINE)], 4, None), "a \x1b[4;58;5;1mtehh\x1b[24;59m");
+        // A match inside a misspelling is covered by it
+        assert_eq!(style_spans("tehh x", &[(0, 4, UNDERLINE), (1, 3, REVERSE), (5, 6, REVERSE)], 4, None), "\x1b[4;58;5;1mtehh\x1b[24;59m \x1b[7mx\x1b[27m");
+    }
+
+    #[test]
+    fn test_tabs_expand_to_stops() {
+        assert_eq!(fit_to_width("\tab\tc", 8, 4), "\tab\t");
+        assert_eq!(fit_to_width("\tab\tc", 7, 4), "\tab");
+        assert_eq!(style_spans("\tab\tc", &[], 4, None), "    ab  c");
+        assert_eq!(style_spans("ab\tteh", &[(3, 6, UNDERLINE)], 4, None), "ab  \x1b[4;58;5;1mteh\x1b[24;59m");
+    }
+
+    #[test]
+    fn test_selection_size() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec!["héllo world".to_string(), "two".to_string(), "three".to_string()]);
+        assert_eq!(selection_size(&buffer), None);
+
+        buffer.set_visual_mode(VisualMode::Char);
+        buffer.set_cursor_position(0, 6);
+        assert_eq!(selection_size(&buffer).as_deref(), Some("5"));
+        buffer.set_cursor_position(2, 1);
+        assert_eq!(selection_size(&buffer).as_deref(), Some("3"));
+        buffer.set_visual_mode(VisualMode::Block);
+        assert_eq!(selection_size(&buffer).as_deref(), Some("3x1"));
+        buffer.set_visual_mode(VisualMode::Line);
+        assert_eq!(selection_size(&buffer).as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn test_char_at_cell() {
+        assert_eq!(char_at_cell("abc", 1, 4), Some((1, 2)));
+        assert_eq!(char_at_cell("abc", 3, 4), None);
+        assert_eq!(char_at_cell("\tb", 2, 4), Some((0, 1)));
+        assert_eq!(char_at_cell("你好", 3, 4), Some((3, 6)));
+    }
+
+    #[test]
+    fn test_trailing_whitespace() {
+        assert_eq!(trailing_whitespace("let x = 1;"), None);
+        assert_eq!(trailing_whitespace("let x = 1; \t"), Some(10));
+        assert_eq!(trailing_whitespace("    "), Some(0));
+        assert_eq!(trailing_whitespace(""), None);
+        assert_eq!(style_spans("x  ", &[(1, 3, REVERSE)], 4, None), "x\x1b[7m  \x1b[27m");
+    }
+
+    #[test]
+    fn test_indent_guides() {
+        let mut buffer = Buffer::new();
+        buffer.set_content(vec![
+            "fn main() {".to_string(),
+            "        if x {".to_string(),
+            "".to_string(),
+            "\t\ty();".to_string(),
+        ]);
+        assert_eq!(guide_width(&buffer, 0, 4), 0);
+        assert_eq!(guide_width(&buffer, 1, 4), 8);
+        // A blank line takes the guides of the line below
+        assert_eq!(guide_width(&buffer, 2, 4), 8);
+
+        let guides = |width| Some(IndentGuides { width, step: 4, style: ("<", ">") });
+        assert_eq!(style_spans("        if x {", &[], 4, guides(8)), "<│>   <│>   if x {");
+        assert_eq!(style_spans("\t\ty();", &[], 4, guides(8)), "<│>   <│>   y();");
+        assert_eq!(style_spans("", &[], 4, guides(8)), "<│>   <│>   ");
+    }
+}
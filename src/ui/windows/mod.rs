@@ -1,12 +1,21 @@
 // src/ui/windows/mod.rs
-use std::collections::HashMap;
+mod screen;
+
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Receiver};
 use std::sync::Arc;
+use std::thread;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use parking_lot::RwLock;
 use portable_pty::{native_pty_system, CommandBuilder, Child as PtyChild, MasterPty, PtySize};
-use crate::editor::Buffer;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+pub use screen::{Cell, CellStyle, Screen};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SplitDirection {
     Vertical,
     Horizontal,
@@ -14,7 +23,9 @@ pub enum SplitDirection {
 
 #[derive(Debug)]
 pub enum WindowContent {
-    Buffer(Arc<RwLock<Buffer>>),
+    // A view of the editor's buffer. `top` is the first row it shows while
+    // another window has the buffer's viewport.
+    Buffer { top: usize },
     Terminal(Arc<RwLock<Terminal>>),
 }
 
@@ -26,6 +37,24 @@ pub struct WindowDimensions {
     pub height: u16,
 }
 
+impl WindowDimensions {
+    // Whether cell (`x`, `y`) is just outside this area, corners included
+    pub fn borders_on(&self, x: u16, y: u16) -> bool {
+        let (x, y) = (u32::from(x), u32::from(y));
+        let (left, top) = (u32::from(self.x), u32::from(self.y));
+        let (right, bottom) = (left + u32::from(self.width), top + u32::from(self.height));
+        let beside = (x + 1 == left || x == right) && (top..=bottom).contains(&(y + 1));
+        let above_or_below = (y + 1 == top || y == bottom) && (left..=right).contains(&(x + 1));
+        beside || above_or_below
+    }
+}
+
+// Which neighbours a separator cell joins up with
+const UP: u8 = 1;
+const DOWN: u8 = 2;
+const LEFT: u8 = 4;
+const RIGHT: u8 = 8;
+
 #[derive(Debug)]
 pub struct Window {
     id: WindowId,
@@ -36,21 +65,34 @@ pub struct Window {
 
 pub struct Terminal {
     pty: TerminalPty,
-    scrollback: Vec<String>,
-    cursor: (u16, u16),
+    screen: Screen,
+    size: (u16, u16),                 // Columns and rows of its window
+    output: Option<Receiver<Vec<u8>>>, // Bytes read from the shell by a background thread
+    partial: Vec<u8>,                 // The start of a UTF-8 sequence cut off by a read
+    copy: Option<CopyMode>,
+}
+
+/// Copy mode in a terminal window: a line cursor over the scrollback, with
+/// the output frozen in place while it's moved around
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CopyMode {
+    pub line: usize,
+    pub anchor: Option<usize>, // Where a selection started, once `v` starts one
+    top: usize,
 }
 
 struct TerminalPty {
     master: Option<Box<dyn MasterPty>>,
-    child: Option<Box<dyn PtyChild>>,
+    writer: Option<Box<dyn Write + Send>>, // The master only hands this out once
+    child: Option<Box<dyn PtyChild + Send + Sync>>,
 }
 
 // Manual Debug implementation for Terminal
 impl std::fmt::Debug for Terminal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Terminal")
-            .field("scrollback", &self.scrollback)
-            .field("cursor", &self.cursor)
+            .field("screen", &self.screen)
+            .field("copy", &self.copy)
             .field("has_pty", &self.pty.master.is_some())
             .finish()
     }
@@ -59,6 +101,21 @@ impl std::fmt::Debug for Terminal {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct WindowId(usize);
 
+/// The layout's shape without its windows, as sessions save it. Terminals
+/// come back as new shells.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum LayoutShape {
+    Buffer { top: usize },
+    Terminal,
+    Split {
+        direction: SplitDirection,
+        ratio: f32,
+        first: Box<LayoutShape>,
+        second: Box<LayoutShape>,
+    },
+}
+
 #[derive(Debug)]
 pub struct Layout {
     root: Option<Box<LayoutNode>>,
@@ -89,12 +146,6 @@ impl Window {
         }
     }
 
-    pub fn new_terminal(id: WindowId, dimensions: WindowDimensions) -> io::Result<Self> {
-        let terminal = Terminal::new();
-        let terminal = Arc::new(RwLock::new(terminal));
-        Ok(Self::new(id, WindowContent::Terminal(terminal), dimensions))
-    }
-
     pub fn resize(&mut self, new_dimensions: WindowDimensions) {
         if let WindowContent::Terminal(term) = &self.content {
             let mut term = term.write();
@@ -103,6 +154,26 @@ impl Window {
         self.dimensions = new_dimensions;
     }
 
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
+
+    pub fn content(&self) -> &WindowContent {
+        &self.content
+    }
+
+    pub fn content_mut(&mut self) -> &mut WindowContent {
+        &mut self.content
+    }
+
+    pub fn dimensions(&self) -> &WindowDimensions {
+        &self.dimensions
+    }
+
+    fn is_buffer(&self) -> bool {
+        matches!(self.content, WindowContent::Buffer { .. })
+    }
+
     pub fn focus(&mut self) {
         self.is_focused = true;
     }
@@ -117,18 +188,23 @@ impl Terminal {
         Self {
             pty: TerminalPty {
                 master: None,
+                writer: None,
                 child: None,
             },
-            scrollback: Vec::new(),
-            cursor: (0, 0),
+            screen: Screen::new(80, 24),
+            size: (80, 24),
+            output: None,
+            partial: Vec::new(),
+            copy: None,
         }
     }
 
+    // Start the shell at the size last given to `resize`
     pub fn spawn(&mut self) -> io::Result<()> {
         let pty_system = native_pty_system();
         let size = PtySize {
-            rows: 24,
-            cols: 80,
+            rows: self.size.1.max(1),
+            cols: self.size.0.max(1),
             pixel_width: 0,
             pixel_height: 0,
         };
@@ -143,35 +219,195 @@ impl Terminal {
         };
 
         let mut cmd_builder = CommandBuilder::new(cmd);
+        // Otherwise the shell would start in the home directory
+        if let Ok(cwd) = std::env::current_dir() {
+            cmd_builder.cwd(cwd);
+        }
         let child = pair.slave.spawn_command(cmd_builder)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
 
+        // Reads block, so they happen on their own thread, which ends when
+        // the shell exits or the terminal is dropped
+        let mut reader = pair.master.try_clone_reader()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            while let Ok(n @ 1..) = reader.read(&mut chunk) {
+                if sender.send(chunk[..n].to_vec()).is_err() {
+                    return;
+                }
+            }
+        });
+        self.output = Some(receiver);
+
+        let writer = pair.master.take_writer()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        self.pty.writer = Some(writer);
         self.pty.master = Some(pair.master);
         self.pty.child = Some(child);
         Ok(())
     }
 
     pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
-        if let Some(master) = &mut self.pty.master {
-            let mut writer = master.take_writer()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        if let Some(writer) = &mut self.pty.writer {
             writer.write_all(data)?;
             writer.flush()?;
         }
         Ok(())
     }
 
-    pub fn read(&mut self) -> io::Result<Vec<u8>> {
-        let mut buffer = Vec::new();
-        if let Some(master) = &mut self.pty.master {
-            let mut reader = master.try_clone_reader()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-            reader.read_to_end(&mut buffer)?;
+    // Pass a key on to the shell, as a terminal would send it. Keys with no
+    // encoding are dropped.
+    pub fn send_key(&mut self, key: KeyEvent) -> io::Result<()> {
+        match key_bytes(key) {
+            Some(bytes) => self.write(&bytes),
+            None => Ok(()),
+        }
+    }
+
+    pub fn paste(&mut self, text: &str) -> io::Result<()> {
+        let bytes = self.paste_bytes(text);
+        self.write(&bytes)
+    }
+
+    // Pasted text as a terminal sends it: line breaks as Enter, wrapped in
+    // bracketed-paste markers when the shell turned them on, so it takes
+    // the lines as text rather than running each one
+    fn paste_bytes(&self, text: &str) -> Vec<u8> {
+        let text = text.replace("\r\n", "\r").replace('\n', "\r");
+        match self.screen.bracketed_paste {
+            true => format!("\x1b[200~{}\x1b[201~", text.replace("\x1b[201~", "")).into_bytes(),
+            false => text.into_bytes(),
+        }
+    }
+
+    // The scrollback and screen as the shell drew them
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    // Where the shell's cursor is within the window, unless it's hidden or
+    // copy mode has taken over
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        (self.copy.is_none() && self.screen.cursor_visible).then(|| self.screen.cursor())
+    }
+
+    // Take in what the shell printed since the last call; true if it
+    // printed anything
+    pub fn poll_output(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(bytes) = self.output.as_ref().and_then(|output| output.try_recv().ok()) {
+            self.push_output(&bytes);
+            changed = true;
+        }
+        changed
+    }
+
+    fn push_output(&mut self, bytes: &[u8]) {
+        self.partial.extend_from_slice(bytes);
+        let complete = match std::str::from_utf8(&self.partial) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => self.partial.len(),
+        };
+        let text = String::from_utf8_lossy(&self.partial[..complete]).into_owned();
+        self.partial.drain(..complete);
+
+        // Copy mode stays on the same lines as old ones are dropped
+        let excess = self.screen.feed(&text);
+        if excess > 0 {
+            if let Some(copy) = &mut self.copy {
+                copy.line = copy.line.saturating_sub(excess);
+                copy.anchor = copy.anchor.map(|anchor| anchor.saturating_sub(excess));
+                copy.top = copy.top.saturating_sub(excess);
+            }
+        }
+    }
+
+    // First line of output to show: the latest screenful, or wherever
+    // copy mode has scrolled to
+    pub fn view_top(&self) -> usize {
+        match self.copy {
+            Some(copy) => copy.top,
+            None => self.screen.scrollback_len(),
+        }
+    }
+
+    pub fn copy_mode(&self) -> Option<CopyMode> {
+        self.copy
+    }
+
+    // Start copy mode on the shell's cursor line
+    pub fn enter_copy_mode(&mut self) {
+        self.copy = Some(CopyMode {
+            line: self.screen.scrollback_len() + self.screen.cursor().0,
+            anchor: None,
+            top: self.view_top(),
+        });
+    }
+
+    pub fn exit_copy_mode(&mut self) {
+        self.copy = None;
+    }
+
+    // Move the copy-mode cursor `delta` lines, scrolling to keep it in view
+    pub fn copy_move(&mut self, delta: isize) {
+        let last = self.screen.line_count().saturating_sub(1);
+        let height = (self.size.1 as usize).max(1);
+        if let Some(copy) = &mut self.copy {
+            copy.line = copy.line.saturating_add_signed(delta).min(last);
+            if copy.line < copy.top {
+                copy.top = copy.line;
+            } else if copy.line >= copy.top + height {
+                copy.top = copy.line + 1 - height;
+            }
+        }
+    }
+
+    // `v`: start a selection at the cursor, or drop the one there is
+    pub fn toggle_selection(&mut self) {
+        if let Some(copy) = &mut self.copy {
+            copy.anchor = match copy.anchor {
+                Some(_) => None,
+                None => Some(copy.line),
+            };
         }
-        Ok(buffer)
     }
 
+    // Lines from the selection start to the cursor, or just the cursor line
+    pub fn selected_lines(&self) -> Option<RangeInclusive<usize>> {
+        self.copy.map(|copy| {
+            let anchor = copy.anchor.unwrap_or(copy.line);
+            anchor.min(copy.line)..=anchor.max(copy.line)
+        })
+    }
+
+    // `y`: the selected lines, leaving copy mode
+    pub fn yank_selection(&mut self) -> Option<String> {
+        let lines = self.selected_lines()?;
+        self.copy = None;
+        let text: Vec<String> = lines.map(|line| self.screen.line_text(line)).collect();
+        Some(text.join("\n"))
+    }
+
+    // End the shell, as closing its window does. It's reaped in the
+    // background so a shell slow to exit doesn't hold up the editor.
+    pub fn kill(&mut self) {
+        if let Some(mut child) = self.pty.child.take() {
+            let _ = child.kill();
+            thread::spawn(move || child.wait());
+        }
+        self.pty.writer = None;
+    }
+
+    // Every layout change resizes every window; the shell only hears about
+    // actual changes. A window squeezed to nothing keeps the last real size.
     pub fn resize(&mut self, width: u16, height: u16) -> io::Result<()> {
+        if (width, height) == self.size || width == 0 || height == 0 {
+            return Ok(());
+        }
+        self.size = (width, height);
+        self.screen.resize(width as usize, height as usize);
         if let Some(master) = &mut self.pty.master {
             master.resize(PtySize {
                 rows: height,
@@ -184,14 +420,53 @@ impl Terminal {
     }
 }
 
+// Bytes a terminal sends the shell for `key`
+fn key_bytes(key: KeyEvent) -> Option<Vec<u8>> {
+    let mut bytes = match key.code {
+        // Ctrl with a letter or one of @[\]^_ is the matching C0 control
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            match c.to_ascii_uppercase() {
+                c @ ('@'..='_') => vec![c as u8 - b'@'],
+                ' ' => vec![0],
+                '?' => vec![0x7f],
+                _ => return None,
+            }
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        _ => return None,
+    };
+    // Alt sends Esc first
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        bytes.insert(0, 0x1b);
+    }
+    Some(bytes)
+}
+
 impl Layout {
     pub fn new() -> Self {
         Self { root: None }
     }
 
+    // The two sides of a split of `dimensions`, leaving a column between
+    // them for the separator
     pub fn split_vertical(&mut self, dimensions: WindowDimensions, ratio: f32) -> (WindowDimensions, WindowDimensions) {
         let left_width = (dimensions.width as f32 * ratio) as u16;
-        let right_width = dimensions.width - left_width;
+        let right_width = dimensions.width.saturating_sub(left_width + 1);
 
         (
             WindowDimensions {
@@ -201,7 +476,7 @@ impl Layout {
                 height: dimensions.height,
             },
             WindowDimensions {
-                x: dimensions.x + left_width,
+                x: dimensions.x + left_width + 1,
                 y: dimensions.y,
                 width: right_width,
                 height: dimensions.height,
@@ -209,9 +484,10 @@ impl Layout {
         )
     }
 
+    // Likewise with a row for the separator
     pub fn split_horizontal(&mut self, dimensions: WindowDimensions, ratio: f32) -> (WindowDimensions, WindowDimensions) {
         let top_height = (dimensions.height as f32 * ratio) as u16;
-        let bottom_height = dimensions.height - top_height;
+        let bottom_height = dimensions.height.saturating_sub(top_height + 1);
 
         (
             WindowDimensions {
@@ -222,19 +498,76 @@ impl Layout {
             },
             WindowDimensions {
                 x: dimensions.x,
-                y: dimensions.y + top_height,
+                y: dimensions.y + top_height + 1,
                 width: dimensions.width,
                 height: bottom_height,
             },
         )
     }
+}
 
-    pub fn add_bottom_panel(&mut self, total_height: u16, panel_height: u16) -> WindowDimensions {
-        WindowDimensions {
-            x: 0,
-            y: total_height - panel_height,
-            width: 0,
-            height: panel_height,
+impl LayoutNode {
+    fn dimensions(&self) -> &WindowDimensions {
+        match self {
+            LayoutNode::Leaf { dimensions, .. } | LayoutNode::Split { dimensions, .. } => dimensions,
+        }
+    }
+
+    // The gaps left between the two sides of each split, and whether
+    // they're vertical
+    fn collect_separators(&self, lines: &mut Vec<(WindowDimensions, bool)>) {
+        let LayoutNode::Split { direction, left, right, dimensions, .. } = self else {
+            return;
+        };
+        let first = left.dimensions();
+        let line = match direction {
+            SplitDirection::Vertical => WindowDimensions {
+                x: first.x + first.width,
+                y: dimensions.y,
+                width: 1,
+                height: dimensions.height,
+            },
+            SplitDirection::Horizontal => WindowDimensions {
+                x: dimensions.x,
+                y: first.y + first.height,
+                width: dimensions.width,
+                height: 1,
+            },
+        };
+        lines.push((line, *direction == SplitDirection::Vertical));
+        left.collect_separators(lines);
+        right.collect_separators(lines);
+    }
+
+    // Windows from left to right and top to bottom
+    fn collect_windows(&self, ids: &mut Vec<WindowId>) {
+        match self {
+            LayoutNode::Leaf { window_id, .. } => ids.push(*window_id),
+            LayoutNode::Split { left, right, .. } => {
+                left.collect_windows(ids);
+                right.collect_windows(ids);
+            }
+        }
+    }
+
+    // Replace the leaf of `target` with a split between it and `new`
+    fn split_leaf(self, target: WindowId, new: WindowId, direction: SplitDirection) -> LayoutNode {
+        match self {
+            LayoutNode::Leaf { window_id, dimensions } if window_id == target => LayoutNode::Split {
+                direction,
+                ratio: 0.5,
+                left: Box::new(LayoutNode::Leaf { window_id, dimensions: dimensions.clone() }),
+                right: Box::new(LayoutNode::Leaf { window_id: new, dimensions: dimensions.clone() }),
+                dimensions,
+            },
+            LayoutNode::Split { direction: split, ratio, left, right, dimensions } => LayoutNode::Split {
+                direction: split,
+                ratio,
+                left: Box::new(left.split_leaf(target, new, direction)),
+                right: Box::new(right.split_leaf(target, new, direction)),
+                dimensions,
+            },
+            leaf => leaf,
         }
     }
 }
@@ -244,7 +577,7 @@ pub struct WindowManager {
     layout: Layout,
     next_id: usize,
     active_window: Option<WindowId>,
-    terminal_window: Option<WindowId>,
+    buffer_window: Option<WindowId>, // Last focused buffer window, which has the buffer's viewport
     total_dimensions: WindowDimensions,
 }
 
@@ -262,20 +595,141 @@ impl WindowManager {
             layout: Layout::new(),
             next_id: 0,
             active_window: None,
-            terminal_window: None,
+            buffer_window: None,
             total_dimensions: dimensions.clone(),
         };
 
-        let initial_buffer = Arc::new(RwLock::new(Buffer::new()));
         let window_id = wm.create_window(
-            WindowContent::Buffer(initial_buffer),
-            dimensions,
+            WindowContent::Buffer { top: 0 },
+            dimensions.clone(),
         );
-        wm.active_window = Some(window_id);
+        wm.layout.root = Some(Box::new(LayoutNode::Leaf { window_id, dimensions }));
+        wm.focus_window(window_id);
 
         wm
     }
 
+    pub fn window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    // Windows onto the buffer, leaving out terminals
+    pub fn buffer_window_count(&self) -> usize {
+        self.windows.values().filter(|window| window.is_buffer()).count()
+    }
+
+    pub fn total_dimensions(&self) -> &WindowDimensions {
+        &self.total_dimensions
+    }
+
+    pub fn active_id(&self) -> Option<WindowId> {
+        self.active_window
+    }
+
+    pub fn active_window(&self) -> Option<&Window> {
+        self.active_window.and_then(|id| self.windows.get(&id))
+    }
+
+    // The window showing the editor's buffer at its cursor: the focused
+    // one, or while a terminal has focus, the buffer window focused last
+    pub fn buffer_window(&self) -> Option<&Window> {
+        self.buffer_window.and_then(|id| self.windows.get(&id))
+    }
+
+    pub fn buffer_window_mut(&mut self) -> Option<&mut Window> {
+        self.buffer_window.and_then(|id| self.windows.get_mut(&id))
+    }
+
+    // Windows in screen order, left to right and top to bottom
+    pub fn layout_windows(&self) -> Vec<&Window> {
+        let mut ids = Vec::new();
+        if let Some(root) = &self.layout.root {
+            root.collect_windows(&mut ids);
+        }
+        ids.iter().filter_map(|id| self.windows.get(id)).collect()
+    }
+
+    // The window covering screen cell (`x`, `y`)
+    pub fn window_at(&self, x: u16, y: u16) -> Option<WindowId> {
+        self.layout_windows().into_iter()
+            .find(|window| {
+                let d = &window.dimensions;
+                (d.x..d.x + d.width).contains(&x) && (d.y..d.y + d.height).contains(&y)
+            })
+            .map(Window::id)
+    }
+
+    // Where a floating panel of `width` by `height` cells goes for a cursor
+    // at (`x`, `y`): on the rows below it, or above when there's more room
+    // there, moved left as far as it must be to fit on screen
+    pub fn popup_area(&self, x: u16, y: u16, width: u16, height: u16) -> WindowDimensions {
+        let total = &self.total_dimensions;
+        let bottom = total.y + total.height;
+        let below = bottom.saturating_sub(y + 1);
+        let above = y.saturating_sub(total.y);
+        let (y, height) = if height <= below || below >= above {
+            (y + 1, height.min(below))
+        } else {
+            let height = height.min(above);
+            (y - height, height)
+        };
+        let width = width.min(total.width);
+        let x = x.min(total.x + total.width - width);
+        WindowDimensions { x, y, width, height }
+    }
+
+    // The lines between windows as (x, y, box-drawing character) cells, row
+    // by row. Where a line ends against another they join with a tee.
+    pub fn separators(&self) -> Vec<(u16, u16, char)> {
+        let mut lines = Vec::new();
+        if let Some(root) = &self.layout.root {
+            root.collect_separators(&mut lines);
+        }
+
+        let mut cells: BTreeMap<(u16, u16), u8> = BTreeMap::new();
+        for (line, vertical) in &lines {
+            let joins = if *vertical { UP | DOWN } else { LEFT | RIGHT };
+            for y in line.y..line.y + line.height {
+                for x in line.x..line.x + line.width {
+                    *cells.entry((y, x)).or_default() |= joins;
+                }
+            }
+        }
+        for (line, vertical) in &lines {
+            let ends = match vertical {
+                true => [(line.x, line.y.wrapping_sub(1), DOWN), (line.x, line.y + line.height, UP)],
+                false => [(line.x.wrapping_sub(1), line.y, RIGHT), (line.x + line.width, line.y, LEFT)],
+            };
+            for (x, y, join) in ends {
+                if let Some(joins) = cells.get_mut(&(y, x)) {
+                    *joins |= join;
+                }
+            }
+        }
+
+        cells.into_iter()
+            .map(|((y, x), joins)| {
+                let glyph = match joins {
+                    j if j == UP | DOWN => '│',
+                    j if j == LEFT | RIGHT => '─',
+                    j if j == UP | DOWN | RIGHT => '├',
+                    j if j == UP | DOWN | LEFT => '┤',
+                    j if j == LEFT | RIGHT | DOWN => '┬',
+                    j if j == LEFT | RIGHT | UP => '┴',
+                    _ => '┼',
+                };
+                (x, y, glyph)
+            })
+            .collect()
+    }
+
+    // The window after the focused one in screen order, wrapping around
+    pub fn next_window(&self) -> Option<WindowId> {
+        let ids: Vec<WindowId> = self.layout_windows().iter().map(|window| window.id).collect();
+        let current = ids.iter().position(|&id| Some(id) == self.active_window)?;
+        ids.get((current + 1) % ids.len()).copied()
+    }
+
     fn create_window(&mut self, content: WindowContent, dimensions: WindowDimensions) -> WindowId {
         let id = WindowId(self.next_id);
         self.next_id += 1;
@@ -285,46 +739,53 @@ impl WindowManager {
         id
     }
 
-    pub fn split(&mut self, direction: SplitDirection) -> io::Result<()> {
-        if let Some(active_id) = self.active_window {
-            if let Some(active_window) = self.windows.get(&active_id) {
-                let dimensions = active_window.dimensions.clone();
-                let (first_dims, second_dims) = match direction {
-                    SplitDirection::Vertical => self.layout.split_vertical(dimensions.clone(), 0.5),
-                    SplitDirection::Horizontal => self.layout.split_horizontal(dimensions.clone(), 0.5),
-                };
-
-                if let Some(window) = self.windows.get_mut(&active_id) {
-                    window.resize(first_dims.clone());
-                }
+    // Halve the focused window, giving the new half to another view of the
+    // buffer scrolled to `top`, and focus it
+    pub fn split(&mut self, direction: SplitDirection, top: usize) -> io::Result<()> {
+        self.split_with(direction, WindowContent::Buffer { top })
+    }
 
-                let new_buffer = Arc::new(RwLock::new(Buffer::new()));
-                let new_window_id = self.create_window(
-                    WindowContent::Buffer(new_buffer),
-                    second_dims.clone(),
-                );
-
-                let new_node = LayoutNode::Split {
-                    direction,
-                    ratio: 0.5,
-                    left: Box::new(LayoutNode::Leaf {
-                        window_id: active_id,
-                        dimensions: first_dims,
-                    }),
-                    right: Box::new(LayoutNode::Leaf {
-                        window_id: new_window_id,
-                        dimensions: second_dims,
-                    }),
-                    dimensions,
-                };
+    // :terminal: halve the focused window for a new shell
+    pub fn open_terminal(&mut self, direction: SplitDirection) -> io::Result<()> {
+        self.split_with(direction, terminal_content())?;
+        self.spawn_active_terminal()
+    }
 
-                self.layout.root = Some(Box::new(new_node));
-                self.focus_window(new_window_id);
-            }
+    // Start the shell of a terminal window just added, now that it has its
+    // size, so programs in it fit the window from the start
+    fn spawn_active_terminal(&mut self) -> io::Result<()> {
+        let Some(window) = self.active_window() else {
+            return Ok(());
+        };
+        let (id, WindowContent::Terminal(terminal)) = (window.id, &window.content) else {
+            return Ok(());
+        };
+        let spawned = terminal.write().spawn();
+        if spawned.is_err() {
+            self.close_window(id)?;
         }
+        spawned
+    }
+
+    fn split_with(&mut self, direction: SplitDirection, content: WindowContent) -> io::Result<()> {
+        let Some(active_id) = self.active_window else {
+            return Ok(());
+        };
+        let dimensions = self.windows[&active_id].dimensions.clone();
+        let new_window_id = self.create_window(content, dimensions);
+        self.layout.root = self.layout.root.take()
+            .map(|root| Box::new(root.split_leaf(active_id, new_window_id, direction)));
+        self.relayout()?;
+        self.focus_window(new_window_id);
         Ok(())
     }
 
+    // Fit every window to the layout again after it changed shape
+    fn relayout(&mut self) -> io::Result<()> {
+        let WindowDimensions { width, height, .. } = self.total_dimensions;
+        self.resize(width, height)
+    }
+
     pub fn focus_window(&mut self, id: WindowId) {
         if let Some(current_id) = self.active_window {
             if let Some(window) = self.windows.get_mut(&current_id) {
@@ -335,25 +796,39 @@ impl WindowManager {
         if let Some(window) = self.windows.get_mut(&id) {
             window.focus();
             self.active_window = Some(id);
+            if window.is_buffer() {
+                self.buffer_window = Some(id);
+            }
         }
     }
 
     pub fn close_window(&mut self, id: WindowId) -> io::Result<()> {
-        if self.windows.remove(&id).is_some() {
+        if let Some(window) = self.windows.remove(&id) {
+            if let WindowContent::Terminal(terminal) = &window.content {
+                terminal.write().kill();
+            }
             self.layout.root = self.layout.root.take().map(|node| {
                 self.remove_window_from_layout(*node, id)
             }).flatten().map(Box::new);
 
-            if Some(id) == self.active_window {
-                self.active_window = self.windows.keys().next().copied();
-                if let Some(new_active) = self.active_window {
-                    self.focus_window(new_active);
-                }
+            if Some(id) == self.buffer_window {
+                self.buffer_window = None;
             }
 
-            if Some(id) == self.terminal_window {
-                self.terminal_window = None;
+            // Focus passes to the first window left, preferring one that
+            // shows the buffer
+            if Some(id) == self.active_window || self.buffer_window.is_none() {
+                let windows = self.layout_windows();
+                let next = windows.iter()
+                    .find(|window| window.is_buffer())
+                    .or(windows.first())
+                    .map(|window| window.id);
+                self.active_window = None;
+                if let Some(next) = next {
+                    self.focus_window(next);
+                }
             }
+            self.relayout()?;
         }
         Ok(())
     }
@@ -420,32 +895,323 @@ impl WindowManager {
         Ok(())
     }
 
+    // Go to a terminal, starting one if there's none; from a terminal, go
+    // back to the buffer
     pub fn toggle_terminal(&mut self) -> io::Result<()> {
-        if let Some(term_id) = self.terminal_window {
-            self.close_window(term_id)?;
+        if self.active_window().is_some_and(|window| !window.is_buffer()) {
+            if let Some(id) = self.buffer_window {
+                self.focus_window(id);
+            }
+        } else if let Some(id) = self.find_terminal_window() {
+            self.focus_window(id);
         } else {
-            let term_height = 10;
-            let term_dims = self.layout.add_bottom_panel(
-                self.total_dimensions.height,
-                term_height,
-            );
-            
-            let window = Window::new_terminal(
-                WindowId(self.next_id),
-                term_dims,
-            )?;
-            
-            let window_id = window.id;
-            self.windows.insert(window_id, window);
-            self.next_id += 1;
-
-            self.terminal_window = Some(window_id);
-            self.focus_window(window_id);
+            self.add_panel(terminal_content())?;
+            self.spawn_active_terminal()?;
         }
         Ok(())
     }
 
+    // A panel along the bottom, under every other window, which takes focus
+    fn add_panel(&mut self, content: WindowContent) -> io::Result<()> {
+        let panel_height = 10;
+        let total = self.total_dimensions.clone();
+        let window_id = self.create_window(content, total.clone());
+        let ratio = match total.height > panel_height * 2 {
+            true => 1.0 - f32::from(panel_height) / f32::from(total.height),
+            false => 0.5,
+        };
+        self.layout.root = self.layout.root.take().map(|root| Box::new(LayoutNode::Split {
+            direction: SplitDirection::Horizontal,
+            ratio,
+            left: root,
+            right: Box::new(LayoutNode::Leaf { window_id, dimensions: total.clone() }),
+            dimensions: total,
+        }));
+        self.relayout()?;
+        self.focus_window(window_id);
+        Ok(())
+    }
+
+    // Every terminal window, for reading their output
+    pub fn terminals(&self) -> impl Iterator<Item = &Arc<RwLock<Terminal>>> + '_ {
+        self.windows.values().filter_map(|window| match &window.content {
+            WindowContent::Terminal(terminal) => Some(terminal),
+            WindowContent::Buffer { .. } => None,
+        })
+    }
+
+    pub fn shape(&self) -> Option<LayoutShape> {
+        self.layout.root.as_ref().map(|root| self.node_shape(root))
+    }
+
+    fn node_shape(&self, node: &LayoutNode) -> LayoutShape {
+        match node {
+            LayoutNode::Leaf { window_id, .. } => match self.windows.get(window_id).map(Window::content) {
+                Some(&WindowContent::Buffer { top }) => LayoutShape::Buffer { top },
+                _ => LayoutShape::Terminal,
+            },
+            LayoutNode::Split { direction, ratio, left, right, .. } => LayoutShape::Split {
+                direction: *direction,
+                ratio: *ratio,
+                first: Box::new(self.node_shape(left)),
+                second: Box::new(self.node_shape(right)),
+            },
+        }
+    }
+
+    // Replace every window with ones laid out as `shape`, focusing the
+    // `focus`th in screen order
+    pub fn restore(&mut self, shape: &LayoutShape, focus: usize) -> io::Result<()> {
+        for terminal in self.terminals() {
+            terminal.write().kill();
+        }
+        self.windows.clear();
+        self.active_window = None;
+        self.buffer_window = None;
+        let root = self.build_node(shape);
+        self.layout.root = Some(Box::new(root));
+        self.relayout()?;
+
+        let windows: Vec<(WindowId, bool)> = self.layout_windows().iter()
+            .map(|window| (window.id, window.is_buffer()))
+            .collect();
+        if let Some(&(id, _)) = windows.iter().find(|(_, is_buffer)| *is_buffer) {
+            self.focus_window(id);
+        }
+        if let Some(&(id, _)) = windows.get(focus).or(windows.first()) {
+            self.focus_window(id);
+        }
+
+        // Shells start once their windows have a size
+        let mut result = Ok(());
+        for (id, _) in windows.into_iter().filter(|(_, is_buffer)| !is_buffer) {
+            let Some(WindowContent::Terminal(terminal)) = self.windows.get(&id).map(Window::content) else {
+                continue;
+            };
+            let spawned = terminal.write().spawn();
+            if let Err(e) = spawned {
+                self.close_window(id)?;
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn build_node(&mut self, shape: &LayoutShape) -> LayoutNode {
+        let dimensions = self.total_dimensions.clone();
+        match shape {
+            LayoutShape::Buffer { top } => LayoutNode::Leaf {
+                window_id: self.create_window(WindowContent::Buffer { top: *top }, dimensions.clone()),
+                dimensions,
+            },
+            LayoutShape::Terminal => LayoutNode::Leaf {
+                window_id: self.create_window(terminal_content(), dimensions.clone()),
+                dimensions,
+            },
+            LayoutShape::Split { direction, ratio, first, second } => LayoutNode::Split {
+                direction: *direction,
+                ratio: ratio.clamp(0.1, 0.9),
+                left: Box::new(self.build_node(first)),
+                right: Box::new(self.build_node(second)),
+                dimensions,
+            },
+        }
+    }
+
+    // The first terminal window in screen order
     pub fn find_terminal_window(&self) -> Option<WindowId> {
-        self.terminal_window
+        self.layout_windows().into_iter()
+            .find(|window| !window.is_buffer())
+            .map(|window| window.id)
+    }
+}
+
+// A terminal whose shell isn't started yet
+fn terminal_content() -> WindowContent {
+    WindowContent::Terminal(Arc::new(RwLock::new(Terminal::new())))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn areas(wm: &WindowManager) -> Vec<(u16, u16, u16, u16)> {
+        wm.layout_windows().iter()
+            .map(|window| {
+                let d = window.dimensions();
+                (d.x, d.y, d.width, d.height)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_layout() {
+        let mut wm = WindowManager::new(80, 24);
+        assert_eq!(areas(&wm), vec![(0, 0, 80, 24)]);
+
+        // A column between the two halves is left for the separator
+        wm.split(SplitDirection::Vertical, 0).unwrap();
+        assert_eq!(areas(&wm), vec![(0, 0, 40, 24), (41, 0, 39, 24)]);
+
+        // Splitting again only divides the focused window
+        wm.split(SplitDirection::Horizontal, 0).unwrap();
+        assert_eq!(areas(&wm), vec![(0, 0, 40, 24), (41, 0, 39, 12), (41, 13, 39, 11)]);
+        assert_eq!(wm.window_at(50, 20), wm.active_id());
+        assert_eq!(wm.window_at(40, 20), None);
+
+        wm.resize(100, 30).unwrap();
+        assert_eq!(areas(&wm), vec![(0, 0, 50, 30), (51, 0, 49, 15), (51, 16, 49, 14)]);
+    }
+
+    #[test]
+    fn test_separators() {
+        let mut wm = WindowManager::new(5, 5);
+        wm.split(SplitDirection::Vertical, 0).unwrap();
+        wm.split(SplitDirection::Horizontal, 0).unwrap();
+        let drawn: String = wm.separators().iter().map(|&(_, _, glyph)| glyph).collect();
+        assert_eq!(drawn, "││├──││");
+        assert!(wm.separators().contains(&(2, 2, '├')));
+
+        let window = wm.layout_windows()[2].dimensions().clone();
+        assert!(window.borders_on(2, 4));
+        assert!(window.borders_on(4, 2));
+        assert!(!window.borders_on(1, 4));
+    }
+
+    #[test]
+    fn test_popup_area() {
+        let wm = WindowManager::new(80, 24);
+        let area = |x, y, width, height| {
+            let d = wm.popup_area(x, y, width, height);
+            (d.x, d.y, d.width, d.height)
+        };
+        assert_eq!(area(10, 5, 12, 4), (10, 6, 12, 4));
+        // No room below: it goes above, and left to fit
+        assert_eq!(area(75, 22, 12, 4), (68, 18, 12, 4));
+        assert_eq!(area(0, 20, 12, 10), (0, 10, 12, 10));
+    }
+
+    #[test]
+    fn test_close_window() {
+        let mut wm = WindowManager::new(80, 24);
+        let first = wm.active_id().unwrap();
+        wm.split(SplitDirection::Vertical, 5).unwrap();
+        let second = wm.active_id().unwrap();
+        assert_eq!(wm.next_window(), Some(first));
+        assert_eq!(wm.buffer_window().map(Window::id), Some(second));
+
+        // The window left fills the space again and takes focus
+        wm.close_window(second).unwrap();
+        assert_eq!(areas(&wm), vec![(0, 0, 80, 24)]);
+        assert_eq!(wm.active_id(), Some(first));
+        assert_eq!(wm.buffer_window().map(Window::id), Some(first));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_several_terminals() {
+        let shell = terminal_content;
+        let mut wm = WindowManager::new(80, 24);
+        let buffer = wm.active_id().unwrap();
+        wm.add_panel(shell()).unwrap();
+        let panel = wm.active_id().unwrap();
+        wm.split_with(SplitDirection::Vertical, shell()).unwrap();
+        let second = wm.active_id().unwrap();
+        assert_eq!(wm.terminals().count(), 2);
+        assert_eq!(wm.buffer_window_count(), 1);
+        assert_eq!(wm.find_terminal_window(), Some(panel));
+
+        // Ctrl-W t goes back to the buffer, then to the first terminal
+        wm.toggle_terminal().unwrap();
+        assert_eq!(wm.active_id(), Some(buffer));
+        wm.toggle_terminal().unwrap();
+        assert_eq!(wm.active_id(), Some(panel));
+
+        wm.close_window(panel).unwrap();
+        assert_eq!(wm.find_terminal_window(), Some(second));
+        assert_eq!(wm.active_id(), Some(buffer));
+    }
+
+    #[test]
+    fn test_terminal_fits_its_window() {
+        let mut wm = WindowManager::new(80, 24);
+        wm.add_panel(terminal_content()).unwrap();
+        let sizes = |wm: &WindowManager| {
+            let window = wm.active_window().unwrap();
+            let WindowContent::Terminal(terminal) = window.content() else { unreachable!() };
+            (terminal.read().size, (window.dimensions().width, window.dimensions().height))
+        };
+        assert_eq!(sizes(&wm), ((80, 9), (80, 9)));
+
+        wm.split_with(SplitDirection::Vertical, WindowContent::Buffer { top: 0 }).unwrap();
+        wm.focus_window(wm.find_terminal_window().unwrap());
+        wm.resize(100, 40).unwrap();
+        let (size, area) = sizes(&wm);
+        assert_eq!(size, area);
+        assert!(size.0 < 100);
+    }
+
+    #[test]
+    fn test_restore_shape() {
+        let mut wm = WindowManager::new(80, 24);
+        wm.split(SplitDirection::Vertical, 7).unwrap();
+        wm.split(SplitDirection::Horizontal, 9).unwrap();
+        let shape = wm.shape().unwrap();
+
+        let mut restored = WindowManager::new(80, 24);
+        restored.restore(&shape, 2).unwrap();
+        assert_eq!(restored.shape(), Some(shape));
+        assert_eq!(areas(&restored), areas(&wm));
+        assert_eq!(restored.active_id(), Some(restored.layout_windows()[2].id));
+        assert_eq!(restored.buffer_window().map(Window::id), restored.active_id());
+    }
+
+    #[test]
+    fn test_terminal_copy_mode() {
+        let mut terminal = Terminal::new();
+        terminal.resize(20, 3).unwrap();
+        terminal.push_output("one\r\ntwo\r\nthr".as_bytes());
+        // A read can end part way through a character
+        let rest = "ee\r\nfour\r\nfünf".as_bytes();
+        terminal.push_output(&rest[..12]);
+        terminal.push_output(&rest[12..]);
+        let screen = terminal.screen();
+        let lines: Vec<String> = (0..screen.line_count()).map(|line| screen.line_text(line)).collect();
+        assert_eq!(lines, ["one", "two", "three", "four", "fünf"]);
+        assert_eq!(terminal.view_top(), 2);
+
+        terminal.enter_copy_mode();
+        terminal.toggle_selection();
+        terminal.copy_move(-3);
+        assert_eq!(terminal.view_top(), 1);
+        assert_eq!(terminal.selected_lines(), Some(1..=4));
+
+        // Output arriving meanwhile doesn't move the view
+        terminal.push_output(b"\r\nsix");
+        assert_eq!(terminal.view_top(), 1);
+        assert_eq!(terminal.yank_selection().as_deref(), Some("two\nthree\nfour\nfünf"));
+        assert_eq!(terminal.copy_mode(), None);
+        assert_eq!(terminal.view_top(), 3);
+    }
+
+    #[test]
+    fn test_key_bytes() {
+        let key = |code, modifiers| key_bytes(KeyEvent::new(code, modifiers));
+        assert_eq!(key(KeyCode::Char('é'), KeyModifiers::NONE), Some("é".as_bytes().to_vec()));
+        assert_eq!(key(KeyCode::Char('c'), KeyModifiers::CONTROL), Some(vec![3]));
+        assert_eq!(key(KeyCode::Char('b'), KeyModifiers::ALT), Some(b"\x1bb".to_vec()));
+        assert_eq!(key(KeyCode::Enter, KeyModifiers::NONE), Some(b"\r".to_vec()));
+        assert_eq!(key(KeyCode::Up, KeyModifiers::NONE), Some(b"\x1b[A".to_vec()));
+        assert_eq!(key(KeyCode::F(1), KeyModifiers::NONE), None);
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        let mut terminal = Terminal::new();
+        assert_eq!(terminal.paste_bytes("ls\npwd"), b"ls\rpwd");
+
+        // Marked only while the shell has it turned on
+        terminal.push_output(b"\x1b[?2004h$ ");
+        assert_eq!(terminal.paste_bytes("ls\r\npwd"), b"\x1b[200~ls\rpwd\x1b[201~");
+        terminal.push_output(b"\x1b[?2004l");
+        assert_eq!(terminal.paste_bytes("ls"), b"ls");
+    }
+}
@@ -0,0 +1,568 @@
+// src/ui/windows/screen.rs
+use crossterm::style::Color;
+use crate::editor::buffer::cell_width;
+
+/// Lines scrolled off the top kept for scrolling back through
+const MAX_SCROLLBACK: usize = 10_000;
+
+/// Tab stops, as terminals set them
+const TAB_STOP: usize = 8;
+
+/// Longest escape sequence parameter list taken before giving up on it
+const MAX_PARAMS_LEN: usize = 64;
+
+/// How a cell is drawn. No color means the theme's.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CellStyle {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+/// One character cell of the terminal. The cell after a double-width
+/// character holds '\0'.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub c: char,
+    pub style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { c: ' ', style: CellStyle::default() }
+    }
+}
+
+/// Rows of cells, top first
+type Grid = Vec<Vec<Cell>>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    Csi(String), // Parameters so far
+    Osc,          // A title or similar, ignored up to its terminator
+    OscEscape,
+    Charset,      // `ESC (` and friends: the next character picks a charset
+}
+
+/// The screen of a terminal at its size, kept up to date from the shell's
+/// output: enough of a VT100/xterm to run a shell, `ls --color`, `less`
+/// and full-screen programs
+#[derive(Debug)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    grid: Grid,
+    scrollback: Grid,               // Oldest first
+    cursor: (usize, usize),         // Row and column in `grid`
+    wrap_pending: bool,             // The last column was written; the next character wraps
+    style: CellStyle,
+    saved_cursor: (usize, usize),
+    scroll_region: (usize, usize),  // First and last rows that scroll
+    main_screen: Option<(Grid, (usize, usize))>, // Put aside while a program uses the alternate screen
+    state: State,
+    dropped: usize,                 // Scrollback lines dropped during a `feed`
+    pub bracketed_paste: bool,      // The shell asked for pastes to be marked
+    pub cursor_visible: bool,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        Self {
+            width,
+            height,
+            grid: vec![vec![Cell::default(); width]; height],
+            scrollback: Vec::new(),
+            cursor: (0, 0),
+            wrap_pending: false,
+            style: CellStyle::default(),
+            saved_cursor: (0, 0),
+            scroll_region: (0, height - 1),
+            main_screen: None,
+            state: State::Ground,
+            dropped: 0,
+            bracketed_paste: false,
+            cursor_visible: true,
+        }
+    }
+
+    /// Lines in the scrollback followed by the rows of the screen
+    pub fn line_count(&self) -> usize {
+        self.scrollback.len() + self.height
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// Line `index` counting from the oldest scrollback line
+    pub fn line(&self, index: usize) -> &[Cell] {
+        match index.checked_sub(self.scrollback.len()) {
+            None => &self.scrollback[index],
+            Some(row) => self.grid.get(row).map_or(&[], Vec::as_slice),
+        }
+    }
+
+    /// The characters of a line, without trailing blanks
+    pub fn line_text(&self, index: usize) -> String {
+        let text: String = self.line(index).iter()
+            .map(|cell| cell.c)
+            .filter(|&c| c != '\0')
+            .collect();
+        text.trim_end().to_string()
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// Interpret output from the shell. Returns how many of the oldest
+    /// scrollback lines were dropped to stay under the limit.
+    pub fn feed(&mut self, text: &str) -> usize {
+        self.dropped = 0;
+        for c in text.chars() {
+            self.feed_char(c);
+        }
+        self.dropped
+    }
+
+    fn feed_char(&mut self, c: char) {
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(c),
+            State::Escape => self.escape(c),
+            State::Csi(mut params) => match c {
+                '\x1b' => self.state = State::Escape,
+                // Controls in the middle of a sequence still take effect
+                c if c.is_control() => {
+                    self.ground(c);
+                    self.state = State::Csi(params);
+                }
+                '\x20'..='\x3f' if params.len() < MAX_PARAMS_LEN => {
+                    params.push(c);
+                    self.state = State::Csi(params);
+                }
+                '\x40'..='\x7e' => self.csi(&params, c),
+                // Too long or malformed: dropped
+                _ => {}
+            },
+            State::Osc => match c {
+                '\x07' => {}
+                '\x1b' => self.state = State::OscEscape,
+                _ => self.state = State::Osc,
+            },
+            // ESC \ ends it; anything else is a new escape sequence
+            State::OscEscape if c != '\\' => self.escape(c),
+            State::OscEscape | State::Charset => {}
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\x1b' => self.state = State::Escape,
+            '\r' => self.move_to(self.cursor.0, 0),
+            '\n' | '\x0b' | '\x0c' => self.line_feed(),
+            '\x08' => self.move_to(self.cursor.0, self.cursor.1.saturating_sub(1)),
+            '\t' => {
+                let stop = (self.cursor.1 / TAB_STOP + 1) * TAB_STOP;
+                self.move_to(self.cursor.0, stop.min(self.width - 1));
+            }
+            c if c.is_control() => {}
+            c => self.print(c),
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        match c {
+            '[' => self.state = State::Csi(String::new()),
+            ']' => self.state = State::Osc,
+            '(' | ')' | '*' | '+' => self.state = State::Charset,
+            '7' => self.saved_cursor = self.cursor,
+            '8' => self.move_to(self.saved_cursor.0, self.saved_cursor.1),
+            'D' => self.line_feed(),
+            'E' => {
+                self.move_to(self.cursor.0, 0);
+                self.line_feed();
+            }
+            // Reverse index: up a row, scrolling down at the top
+            'M' if self.cursor.0 == self.scroll_region.0 => self.scroll_down(1),
+            'M' => self.move_to(self.cursor.0.saturating_sub(1), self.cursor.1),
+            'c' => {
+                let scrollback = std::mem::take(&mut self.scrollback);
+                *self = Self::new(self.width, self.height);
+                self.scrollback = scrollback;
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        let width = cell_width(c, 0, TAB_STOP);
+        if width == 0 {
+            return;
+        }
+        if self.wrap_pending || self.cursor.1 + width > self.width {
+            self.move_to(self.cursor.0, 0);
+            self.line_feed();
+        }
+        let (row, col) = self.cursor;
+        let style = self.style;
+        self.grid[row][col] = Cell { c, style };
+        if width == 2 && col + 1 < self.width {
+            self.grid[row][col + 1] = Cell { c: '\0', style };
+        }
+        if col + width >= self.width {
+            self.cursor.1 = self.width - 1;
+            self.wrap_pending = true;
+        } else {
+            self.cursor.1 = col + width;
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.cursor = (row.min(self.height - 1), col.min(self.width - 1));
+        self.wrap_pending = false;
+    }
+
+    fn line_feed(&mut self) {
+        if self.cursor.0 == self.scroll_region.1 {
+            self.scroll_up(1);
+        } else {
+            self.move_to(self.cursor.0 + 1, self.cursor.1);
+        }
+        self.wrap_pending = false;
+    }
+
+    // An empty cell, in the current background as terminals erase
+    fn blank(&self) -> Cell {
+        Cell { c: ' ', style: CellStyle { bg: self.style.bg, ..CellStyle::default() } }
+    }
+
+    fn blank_row(&self) -> Vec<Cell> {
+        vec![self.blank(); self.width]
+    }
+
+    // Scroll the scroll region up `n` rows. Rows leaving the top of the
+    // whole main screen go to the scrollback.
+    fn scroll_up(&mut self, n: usize) {
+        let (top, bottom) = self.scroll_region;
+        for _ in 0..n.min(bottom + 1 - top) {
+            let row = self.grid.remove(top);
+            self.grid.insert(bottom, self.blank_row());
+            if top == 0 && self.main_screen.is_none() {
+                self.push_scrollback(row);
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let (top, bottom) = self.scroll_region;
+        for _ in 0..n.min(bottom + 1 - top) {
+            self.grid.remove(bottom);
+            self.grid.insert(top, self.blank_row());
+        }
+    }
+
+    fn push_scrollback(&mut self, row: Vec<Cell>) {
+        self.scrollback.push(row);
+        if self.scrollback.len() > MAX_SCROLLBACK {
+            self.scrollback.remove(0);
+            self.dropped += 1;
+        }
+    }
+
+    // Control sequences: ESC [, parameters, then `action`
+    fn csi(&mut self, params: &str, action: char) {
+        let private = params.starts_with('?');
+        let args: Vec<usize> = params.trim_start_matches(['?', '>', '='])
+            .split(';')
+            .map(|arg| arg.split(':').next().and_then(|arg| arg.parse().ok()).unwrap_or(0))
+            .collect();
+        // Counts and positions: missing or 0 means 1, and none reach past
+        // the screen
+        let limit = self.width.max(self.height);
+        let arg = |i: usize| args.get(i).copied().filter(|&n| n > 0).unwrap_or(1).min(limit);
+        let mode = args.first().copied().unwrap_or(0);
+        let (row, col) = self.cursor;
+
+        match action {
+            'A' => self.move_to(row.saturating_sub(arg(0)), col),
+            'B' | 'e' => self.move_to(row.saturating_add(arg(0)), col),
+            'C' | 'a' => self.move_to(row, col.saturating_add(arg(0))),
+            'D' => self.move_to(row, col.saturating_sub(arg(0))),
+            'E' => self.move_to(row.saturating_add(arg(0)), 0),
+            'F' => self.move_to(row.saturating_sub(arg(0)), 0),
+            'G' | '`' => self.move_to(row, arg(0) - 1),
+            'd' => self.move_to(arg(0) - 1, col),
+            'H' | 'f' => self.move_to(arg(0) - 1, arg(1) - 1),
+            'J' => self.erase_display(mode),
+            'K' => self.erase_line(mode),
+            'X' => {
+                let blank = self.blank();
+                let end = col.saturating_add(arg(0)).min(self.width);
+                self.grid[row][col..end].fill(blank);
+            }
+            'P' => {
+                let blank = self.blank();
+                let line = &mut self.grid[row];
+                let n = arg(0).min(line.len().saturating_sub(col));
+                line.drain(col..col + n);
+                line.extend(std::iter::repeat_n(blank, n));
+            }
+            '@' => {
+                let blank = self.blank();
+                let width = self.width;
+                let line = &mut self.grid[row];
+                let n = arg(0).min(width.saturating_sub(col));
+                line.splice(col..col, std::iter::repeat_n(blank, n));
+                line.truncate(width);
+            }
+            'L' | 'M' if (self.scroll_region.0..=self.scroll_region.1).contains(&row) => {
+                // Insert or delete lines by scrolling the rows from the cursor down
+                let region = self.scroll_region;
+                self.scroll_region.0 = row;
+                if action == 'L' {
+                    self.scroll_down(arg(0));
+                } else {
+                    self.scroll_up_in_place(arg(0));
+                }
+                self.scroll_region = region;
+                self.move_to(row, 0);
+            }
+            'S' => self.scroll_up(arg(0)),
+            'T' => self.scroll_down(arg(0)),
+            'm' => self.select_graphic_rendition(&args),
+            'r' => {
+                let top = arg(0) - 1;
+                let bottom = args.get(1).copied().filter(|&n| n > 0).unwrap_or(self.height).min(self.height) - 1;
+                if top < bottom {
+                    self.scroll_region = (top, bottom);
+                    self.move_to(0, 0);
+                }
+            }
+            's' if !private => self.saved_cursor = self.cursor,
+            'u' if !private => self.move_to(self.saved_cursor.0, self.saved_cursor.1),
+            'h' | 'l' if private => {
+                for &mode in &args {
+                    self.set_private_mode(mode, action == 'h');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Like `scroll_up`, but what leaves the region is gone for good
+    fn scroll_up_in_place(&mut self, n: usize) {
+        let (top, bottom) = self.scroll_region;
+        for _ in 0..n.min(bottom + 1 - top) {
+            self.grid.remove(top);
+            self.grid.insert(bottom, self.blank_row());
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let blank = self.blank();
+        let (row, col) = self.cursor;
+        match mode {
+            0 => {
+                self.grid[row][col..].fill(blank);
+                self.grid[row + 1..].iter_mut().for_each(|line| line.fill(blank));
+            }
+            1 => {
+                self.grid[..row].iter_mut().for_each(|line| line.fill(blank));
+                self.grid[row][..=col].fill(blank);
+            }
+            2 => self.grid.iter_mut().for_each(|line| line.fill(blank)),
+            3 => self.scrollback.clear(),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let blank = self.blank();
+        let (row, col) = self.cursor;
+        let line = &mut self.grid[row];
+        match mode {
+            0 => line[col..].fill(blank),
+            1 => line[..=col].fill(blank),
+            2 => line.fill(blank),
+            _ => {}
+        }
+    }
+
+    // SGR: colors and attributes for the text that follows
+    fn select_graphic_rendition(&mut self, args: &[usize]) {
+        let mut args = args.iter().copied();
+        while let Some(code) = args.next() {
+            let style = &mut self.style;
+            match code {
+                0 => *style = CellStyle::default(),
+                1 => style.bold = true,
+                22 => style.bold = false,
+                4 => style.underline = true,
+                24 => style.underline = false,
+                7 => style.reverse = true,
+                27 => style.reverse = false,
+                30..=37 => style.fg = Some(Color::AnsiValue((code - 30) as u8)),
+                90..=97 => style.fg = Some(Color::AnsiValue((code - 90 + 8) as u8)),
+                40..=47 => style.bg = Some(Color::AnsiValue((code - 40) as u8)),
+                100..=107 => style.bg = Some(Color::AnsiValue((code - 100 + 8) as u8)),
+                39 => style.fg = None,
+                49 => style.bg = None,
+                38 => style.fg = extended_color(&mut args),
+                48 => style.bg = extended_color(&mut args),
+                _ => {}
+            }
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: usize, on: bool) {
+        match mode {
+            25 => self.cursor_visible = on,
+            2004 => self.bracketed_paste = on,
+            // The alternate screen of full-screen programs, which leaves the
+            // shell's screen as it was when they exit
+            47 | 1047 | 1049 if on && self.main_screen.is_none() => {
+                let blank = vec![vec![Cell::default(); self.width]; self.height];
+                let main = std::mem::replace(&mut self.grid, blank);
+                self.main_screen = Some((main, self.cursor));
+            }
+            47 | 1047 | 1049 if !on => {
+                if let Some((grid, cursor)) = self.main_screen.take() {
+                    self.grid = grid;
+                    self.move_to(cursor.0, cursor.1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Change size, keeping the cursor's row on screen. Lines don't reflow:
+    /// they're cut or padded at the right.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let (width, height) = (width.max(1), height.max(1));
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        let fit = |grid: &mut Grid| {
+            for line in grid.iter_mut() {
+                line.resize(width, Cell::default());
+            }
+        };
+        fit(&mut self.grid);
+        fit(&mut self.scrollback);
+        if let Some((grid, _)) = &mut self.main_screen {
+            fit(grid);
+            grid.resize(height, vec![Cell::default(); width]);
+        }
+        self.width = width;
+
+        // Shrinking pushes rows above the cursor into the scrollback, then
+        // cuts blank rows from the bottom; growing adds them there
+        let excess = self.height.saturating_sub(height);
+        let off_top = excess.min((self.cursor.0 + 1).saturating_sub(height));
+        for row in self.grid.drain(..off_top).collect::<Vec<_>>() {
+            if self.main_screen.is_none() {
+                self.push_scrollback(row);
+            }
+        }
+        self.grid.resize(height, vec![Cell::default(); width]);
+        self.height = height;
+        self.scroll_region = (0, height - 1);
+        let cursor = self.cursor;
+        self.move_to(cursor.0 - off_top, cursor.1);
+    }
+}
+
+// The color after a 38 or 48 in SGR: `5;N` picks from the 256-color
+// palette, `2;R;G;B` is RGB
+fn extended_color(args: &mut impl Iterator<Item = usize>) -> Option<Color> {
+    let mut next = || args.next().map(|n| n.min(255) as u8);
+    match next()? {
+        5 => next().map(Color::AnsiValue),
+        2 => Some(Color::Rgb { r: next()?, g: next()?, b: next()? }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fed(width: usize, height: usize, output: &str) -> Screen {
+        let mut screen = Screen::new(width, height);
+        screen.feed(output);
+        screen
+    }
+
+    fn rows(screen: &Screen) -> Vec<String> {
+        (0..screen.line_count()).map(|i| screen.line_text(i)).collect()
+    }
+
+    #[test]
+    fn test_text_and_scrolling() {
+        let screen = fed(10, 2, "one\r\ntwo\r\nthree");
+        assert_eq!(rows(&screen), ["one", "two", "three"]);
+        assert_eq!(screen.scrollback_len(), 1);
+        assert_eq!(screen.cursor(), (1, 5));
+
+        // Long lines wrap at the edge
+        assert_eq!(rows(&fed(4, 3, "abcdef")), ["abcd", "ef", ""]);
+        assert_eq!(rows(&fed(4, 3, "abcd\r\nef")), ["abcd", "ef", ""]);
+    }
+
+    #[test]
+    fn test_cursor_movement_and_erase() {
+        let screen = fed(10, 3, "hello\r\nworld\x1b[1;2HA\x1b[2;4H\x1b[K\x1b[3;1Hx\x1b[2D\x1b[1C!");
+        assert_eq!(rows(&screen), ["hAllo", "wor", "x!"]);
+
+        let screen = fed(10, 3, "abc\r\ndef\x1b[2J");
+        assert_eq!(rows(&screen), ["", "", ""]);
+        assert_eq!(screen.cursor(), (1, 3));
+    }
+
+    #[test]
+    fn test_colors() {
+        let screen = fed(10, 1, "\x1b[1;31ma\x1b[0;38;5;208;48;2;1;2;3mb\x1b[mc");
+        let cells = screen.line(0);
+        assert_eq!(cells[0].style, CellStyle { fg: Some(Color::AnsiValue(1)), bold: true, ..CellStyle::default() });
+        assert_eq!(cells[1].style.fg, Some(Color::AnsiValue(208)));
+        assert_eq!(cells[1].style.bg, Some(Color::Rgb { r: 1, g: 2, b: 3 }));
+        assert_eq!(cells[2].style, CellStyle::default());
+    }
+
+    #[test]
+    fn test_huge_parameters() {
+        let huge = usize::MAX.to_string();
+        for action in ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'd', 'H', 'X', 'P', '@', 'L', 'M', 'S', 'T', 'r', 'e', 'a'] {
+            let mut screen = fed(10, 3, "abc\r\ndef");
+            screen.feed(&format!("\x1b[{};{}{}", huge, huge, action));
+            screen.feed(&format!("\x1b[99999999999999999999999{}x", action));
+            let (row, col) = screen.cursor();
+            assert!(row < 3 && col < 10, "{}", action);
+        }
+        assert_eq!(rows(&fed(10, 2, &format!("abc\x1b[{}Cx", usize::MAX)))[0], "abc      x");
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut screen = fed(10, 2, "$ vim\r\n");
+        screen.feed("\x1b[?1049h\x1b[Hfull\x1b]0;title\x07");
+        assert_eq!(rows(&screen), ["full", ""]);
+        screen.feed("\x1b[?1049l");
+        assert_eq!(rows(&screen), ["$ vim", ""]);
+        assert_eq!(screen.cursor(), (1, 0));
+    }
+
+    #[test]
+    fn test_resize_keeps_cursor_row() {
+        let mut screen = fed(10, 4, "a\r\nb\r\nc");
+        screen.resize(3, 2);
+        assert_eq!(rows(&screen), ["a", "b", "c"]);
+        assert_eq!(screen.scrollback_len(), 1);
+        assert_eq!(screen.cursor(), (1, 1));
+    }
+}